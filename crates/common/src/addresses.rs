@@ -208,3 +208,32 @@ impl AddressMapping {
         }
     }
 }
+
+/// Encodes a recipient into the envelope sender using VERP, for example
+/// `bounce@example.org` sending to `jane@example.com` becomes
+/// `bounce+jane=example.com@example.org`.
+pub fn verp_encode(return_path: &str, recipient: &str) -> Option<String> {
+    let (local_part, domain_part) = return_path.rsplit_once('@')?;
+    let (rcpt_local, rcpt_domain) = recipient.rsplit_once('@')?;
+    if !local_part.is_empty() && !rcpt_local.is_empty() && !rcpt_domain.is_empty() {
+        Some(format!(
+            "{local_part}+{rcpt_local}={rcpt_domain}@{domain_part}"
+        ))
+    } else {
+        None
+    }
+}
+
+/// Obtains the original recipient from a VERP encoded address. The address
+/// is split from the right so that return paths containing a `+` in their
+/// local part, such as `list+bounces@example.org`, are decoded correctly.
+pub fn verp_decode(address: &str) -> Option<String> {
+    let (local_part, _) = address.rsplit_once('@')?;
+    let (encoded, rcpt_domain) = local_part.rsplit_once('=')?;
+    let (return_path, rcpt_local) = encoded.rsplit_once('+')?;
+    if !return_path.is_empty() && !rcpt_local.is_empty() && rcpt_domain.contains('.') {
+        Some(format!("{rcpt_local}@{rcpt_domain}"))
+    } else {
+        None
+    }
+}
@@ -64,6 +64,9 @@ pub struct JmapConfig {
     pub fallback_admin: Option<(String, String)>,
 
     pub spam_header: Option<(HeaderName<'static>, String)>,
+    pub spam_learn: bool,
+    pub spam_learn_store: Option<String>,
+    pub spam_learn_ham_folder: String,
 
     pub http_headers: Vec<(hyper::header::HeaderName, hyper::header::HeaderValue)>,
     pub http_use_forwarded: bool,
@@ -275,6 +278,14 @@ impl JmapConfig {
                         )
                     })
                 }),
+            spam_learn: config
+                .property_or_default("spam.learning.enable", "false")
+                .unwrap_or(false),
+            spam_learn_store: config.value("spam.learning.store").map(|s| s.to_string()),
+            spam_learn_ham_folder: config
+                .value("spam.learning.ham-folder")
+                .unwrap_or("NotJunk")
+                .to_string(),
             http_use_forwarded: config
                 .property("server.http.use-x-forwarded")
                 .unwrap_or(false),
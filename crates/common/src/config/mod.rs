@@ -8,8 +8,11 @@ use utils::config::Config;
 use crate::{expr::*, listener::tls::TlsManager, manager::config::ConfigManager, Core, Network};
 
 use self::{
-    imap::ImapConfig, jmap::settings::JmapConfig, scripts::Scripting, smtp::SmtpConfig,
-    storage::Storage,
+    imap::ImapConfig,
+    jmap::settings::JmapConfig,
+    scripts::Scripting,
+    smtp::SmtpConfig,
+    storage::{QueueStorage, Storage},
 };
 
 pub mod imap;
@@ -123,6 +126,8 @@ impl Core {
             )
         }
 
+        let queue = QueueStorage::parse(config, &stores, &data, &blob);
+
         Self {
             sieve: Scripting::parse(config, &stores).await,
             network: Network::parse(config),
@@ -135,6 +140,7 @@ impl Core {
                 blob,
                 fts,
                 lookup,
+                queue,
                 directory,
                 directories: directories.directories,
                 purge_schedules: stores.purge_schedules,
@@ -9,9 +9,17 @@ use nlp::bayes::cache::BayesTokenCache;
 use parking_lot::RwLock;
 use sieve::{compiler::grammar::Capability, Compiler, Runtime, Sieve};
 use store::Stores;
-use utils::config::Config;
+use utils::config::{ipmask::IpAddrMask, Config};
 
-use crate::scripts::{functions::register_functions, plugins::RegisterSievePlugins};
+use crate::scripts::{
+    functions::register_functions,
+    plugins::{
+        rules::{Ruleset, ScoreWeights},
+        signatures::ByteSignatures,
+        yara::YaraRules,
+        RegisterSievePlugins,
+    },
+};
 
 use super::{if_block::IfBlock, smtp::SMTP_RCPT_TO_VARS, tokenizer::TokenMap};
 
@@ -23,11 +31,37 @@ pub struct Scripting {
     pub from_name: IfBlock,
     pub return_path: IfBlock,
     pub sign: IfBlock,
+    pub hostname: String,
+    pub reputation_half_life: Duration,
+    pub rotation_window: Duration,
+    pub first_contact_ttl: Duration,
+    pub rdap_url: String,
+    pub rdap_client: reqwest::Client,
+    pub timeout: Duration,
+    pub cache_store: Option<String>,
+    pub cache_fail_open: bool,
+    pub allowlist_tiers: Vec<String>,
+    pub internal_networks: Vec<IpAddrMask>,
+    pub spam_headers: Option<SpamHeaders>,
     pub scripts: AHashMap<String, Arc<Sieve>>,
+    pub rulesets: AHashMap<String, Arc<Ruleset>>,
+    pub yara: Option<Arc<YaraRules>>,
+    pub byte_signatures: AHashMap<String, Arc<ByteSignatures>>,
+    pub score_weights: ScoreWeights,
     pub bayes_cache: BayesTokenCache,
     pub remote_lists: RwLock<AHashMap<String, RemoteList>>,
 }
 
+#[derive(Clone)]
+pub struct SpamHeaders {
+    pub variable: String,
+    pub threshold: f64,
+    pub status: String,
+    pub score: String,
+    pub level: String,
+    pub flag: String,
+}
+
 #[derive(Clone)]
 pub struct RemoteList {
     pub entries: HashSet<String>,
@@ -293,6 +327,74 @@ impl Scripting {
             }
         }
 
+        // Parse scoring rulesets
+        let mut rulesets = AHashMap::new();
+        for id in config
+            .sub_keys("sieve.trusted.rules", ".contents")
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+        {
+            let timeout = config
+                .property_or_default::<Duration>(
+                    ("sieve.trusted.rules", id.as_str(), "timeout"),
+                    "500ms",
+                )
+                .unwrap_or_else(|| Duration::from_millis(500));
+            let (ruleset, errors) = Ruleset::parse(
+                config
+                    .value(("sieve.trusted.rules", id.as_str(), "contents"))
+                    .unwrap(),
+                timeout,
+            );
+            for error in errors {
+                config.new_build_error(("sieve.trusted.rules", id.as_str(), "contents"), error);
+            }
+            rulesets.insert(id, ruleset.into());
+        }
+
+        // Parse YARA rules
+        let yara = YaraRules::parse(config).map(Arc::new);
+
+        // Parse byte signatures
+        let byte_signatures = ByteSignatures::parse(config)
+            .into_iter()
+            .map(|(id, signatures)| (id, Arc::new(signatures)))
+            .collect();
+
+        // Parse spam headers
+        let spam_headers = if config
+            .property_or_default("sieve.trusted.spam-headers.enable", "false")
+            .unwrap_or(false)
+        {
+            let mut headers = SpamHeaders {
+                variable: config
+                    .value("sieve.trusted.spam-headers.variable")
+                    .unwrap_or("score")
+                    .to_string(),
+                threshold: config
+                    .property_or_default("sieve.trusted.spam-headers.threshold", "5.0")
+                    .unwrap_or(5.0),
+                status: String::new(),
+                score: String::new(),
+                level: String::new(),
+                flag: String::new(),
+            };
+            for (value, key, default) in [
+                (&mut headers.status, "status", "X-Spam-Status"),
+                (&mut headers.score, "score", "X-Spam-Score"),
+                (&mut headers.level, "level", "X-Spam-Level"),
+                (&mut headers.flag, "flag", "X-Spam-Flag"),
+            ] {
+                *value = config
+                    .value(("sieve.trusted.spam-headers.names", key))
+                    .unwrap_or(default)
+                    .to_string();
+            }
+            Some(headers)
+        } else {
+            None
+        };
+
         let token_map = TokenMap::default().with_variables(SMTP_RCPT_TO_VARS);
 
         Scripting {
@@ -329,7 +431,50 @@ impl Scripting {
                         "['rsa-' + key_get('default', 'domain'), 'ed25519-' + key_get('default', 'domain')]",
                     )
                 }),
+            hostname,
+            reputation_half_life: config
+                .property_or_default::<Duration>("sieve.trusted.reputation.half-life", "30d")
+                .unwrap_or_else(|| Duration::from_secs(30 * 86400)),
+            rotation_window: config
+                .property_or_default::<Duration>("sieve.trusted.rotation.window", "1h")
+                .unwrap_or_else(|| Duration::from_secs(3600)),
+            first_contact_ttl: config
+                .property_or_default::<Duration>("sieve.trusted.first-contact.ttl", "90d")
+                .unwrap_or_else(|| Duration::from_secs(90 * 86400)),
+            rdap_url: config
+                .value("sieve.trusted.rdap.url")
+                .unwrap_or("https://rdap.org/domain/")
+                .to_string(),
+            rdap_client: rdap_client(
+                config
+                    .property_or_default::<Duration>("sieve.trusted.rdap.timeout", "10s")
+                    .unwrap_or_else(|| Duration::from_secs(10)),
+            ),
+            timeout: config
+                .property_or_default::<Duration>("sieve.trusted.limits.timeout", "1m")
+                .unwrap_or_else(|| Duration::from_secs(60)),
+            cache_store: config
+                .value("sieve.trusted.cache.store")
+                .filter(|v| !v.is_empty())
+                .map(|v| v.to_string()),
+            cache_fail_open: config
+                .property_or_default("sieve.trusted.cache.fail-open", "true")
+                .unwrap_or(true),
+            allowlist_tiers: config
+                .values("sieve.trusted.allowlist.tiers")
+                .map(|(_, v)| v.to_string())
+                .collect(),
+            internal_networks: config
+                .properties::<IpAddrMask>("sieve.trusted.internal-networks")
+                .into_iter()
+                .map(|(_, network)| network)
+                .collect(),
+            spam_headers,
             scripts,
+            rulesets,
+            yara,
+            byte_signatures,
+            score_weights: ScoreWeights::parse(config),
             bayes_cache: BayesTokenCache::new(
                 config
                     .property_or_default("cache.bayes.capacity", "8192")
@@ -364,7 +509,23 @@ impl Default for Scripting {
                 [],
                 "['rsa-' + key_get('default', 'domain'), 'ed25519-' + key_get('default', 'domain')]",
             ),
+            hostname: "localhost".to_string(),
+            reputation_half_life: Duration::from_secs(30 * 86400),
+            rotation_window: Duration::from_secs(3600),
+            first_contact_ttl: Duration::from_secs(90 * 86400),
+            rdap_url: "https://rdap.org/domain/".to_string(),
+            rdap_client: rdap_client(Duration::from_secs(10)),
+            timeout: Duration::from_secs(60),
+            cache_store: None,
+            cache_fail_open: true,
+            allowlist_tiers: vec![],
+            internal_networks: vec![],
+            spam_headers: None,
             scripts: AHashMap::new(),
+            rulesets: AHashMap::new(),
+            yara: None,
+            byte_signatures: AHashMap::new(),
+            score_weights: ScoreWeights::default(),
             bayes_cache: BayesTokenCache::new(
                 8192,
                 Duration::from_secs(3600),
@@ -385,9 +546,32 @@ impl Clone for Scripting {
             from_name: self.from_name.clone(),
             return_path: self.return_path.clone(),
             sign: self.sign.clone(),
+            hostname: self.hostname.clone(),
+            reputation_half_life: self.reputation_half_life,
+            rotation_window: self.rotation_window,
+            first_contact_ttl: self.first_contact_ttl,
+            rdap_url: self.rdap_url.clone(),
+            rdap_client: self.rdap_client.clone(),
+            timeout: self.timeout,
+            cache_store: self.cache_store.clone(),
+            cache_fail_open: self.cache_fail_open,
+            allowlist_tiers: self.allowlist_tiers.clone(),
+            internal_networks: self.internal_networks.clone(),
+            spam_headers: self.spam_headers.clone(),
             scripts: self.scripts.clone(),
+            rulesets: self.rulesets.clone(),
+            yara: self.yara.clone(),
+            byte_signatures: self.byte_signatures.clone(),
+            score_weights: self.score_weights.clone(),
             bayes_cache: self.bayes_cache.clone(),
             remote_lists: RwLock::new(self.remote_lists.read().clone()),
         }
     }
 }
+
+fn rdap_client(timeout: Duration) -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .build()
+        .unwrap_or_default()
+}
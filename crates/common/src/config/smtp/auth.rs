@@ -25,6 +25,7 @@ pub struct MailAuthConfig {
     pub spf: SpfAuthConfig,
     pub dmarc: DmarcAuthConfig,
     pub iprev: IpRevAuthConfig,
+    pub require_pass: RequirePassConfig,
 
     pub signers: AHashMap<String, Arc<DkimSigner>>,
     pub sealers: AHashMap<String, Arc<ArcSealer>>,
@@ -51,6 +52,8 @@ pub struct SpfAuthConfig {
 #[derive(Clone)]
 pub struct DmarcAuthConfig {
     pub verify: IfBlock,
+    pub enforce: IfBlock,
+    pub allow_list: String,
 }
 
 #[derive(Clone)]
@@ -58,6 +61,12 @@ pub struct IpRevAuthConfig {
     pub verify: IfBlock,
 }
 
+#[derive(Clone)]
+pub struct RequirePassConfig {
+    pub enable: IfBlock,
+    pub allow_list: String,
+}
+
 #[derive(Debug, Clone, Copy, Default)]
 pub enum VerifyStrategy {
     #[default]
@@ -131,6 +140,8 @@ impl Default for MailAuthConfig {
                     #[cfg(feature = "test_mode")]
                     "relaxed",
                 ),
+                enforce: IfBlock::new::<()>("auth.dmarc.enforce", [], "false"),
+                allow_list: "dmarc-allow".to_string(),
             },
             iprev: IpRevAuthConfig {
                 verify: IfBlock::new::<VerifyStrategy>(
@@ -142,6 +153,10 @@ impl Default for MailAuthConfig {
                     "relaxed",
                 ),
             },
+            require_pass: RequirePassConfig {
+                enable: IfBlock::new::<()>("auth.require-pass.enable", [], "false"),
+                allow_list: "spf-dkim-allow".to_string(),
+            },
             signers: Default::default(),
             sealers: Default::default(),
         }
@@ -174,13 +189,30 @@ impl MailAuthConfig {
                 &conn_vars,
             ),
             (&mut mail_auth.dmarc.verify, "auth.dmarc.verify", &rcpt_vars),
+            (
+                &mut mail_auth.dmarc.enforce,
+                "auth.dmarc.enforce",
+                &rcpt_vars,
+            ),
             (&mut mail_auth.iprev.verify, "auth.iprev.verify", &conn_vars),
+            (
+                &mut mail_auth.require_pass.enable,
+                "auth.require-pass.enable",
+                &rcpt_vars,
+            ),
         ] {
             if let Some(if_block) = IfBlock::try_parse(config, key, token_map) {
                 *value = if_block;
             }
         }
 
+        if let Some(allow_list) = config.value("auth.dmarc.allow-list") {
+            mail_auth.dmarc.allow_list = allow_list.to_string();
+        }
+        if let Some(allow_list) = config.value("auth.require-pass.allow-list") {
+            mail_auth.require_pass.allow_list = allow_list.to_string();
+        }
+
         // Parse signatures
         for id in config
             .sub_keys("signature", ".algorithm")
@@ -334,6 +366,14 @@ fn parse_signature<T: SigningKey, U: SigningKey<Hasher = Sha256>>(
         ];
     }
 
+    // Signature settings take precedence over the per-domain canonicalization
+    let canonicalization = config
+        .property::<DkimCanonicalization>(("signature", id, "canonicalization"))
+        .or_else(|| {
+            config.property::<DkimCanonicalization>(("auth.dkim.canonicalization", domain.as_str()))
+        })
+        .unwrap_or_default();
+
     let mut signer = mail_auth::dkim::DkimSigner::from_key(key_dkim)
         .domain(&domain)
         .selector(&selector)
@@ -345,19 +385,16 @@ fn parse_signature<T: SigningKey, U: SigningKey<Hasher = Sha256>>(
         headers.push("DKIM-Signature".to_string());
     }
     let mut sealer = mail_auth::arc::ArcSealer::from_key(key_arc)
-        .domain(domain)
+        .domain(&domain)
         .selector(selector)
         .headers(headers);
 
-    if let Some(c) = config.property::<DkimCanonicalization>(("signature", id, "canonicalization"))
-    {
-        signer = signer
-            .body_canonicalization(c.body)
-            .header_canonicalization(c.headers);
-        sealer = sealer
-            .body_canonicalization(c.body)
-            .header_canonicalization(c.headers);
-    }
+    signer = signer
+        .body_canonicalization(canonicalization.body)
+        .header_canonicalization(canonicalization.headers);
+    sealer = sealer
+        .body_canonicalization(canonicalization.body)
+        .header_canonicalization(canonicalization.headers);
 
     if let Some(c) = config.property::<Duration>(("signature", id, "expire")) {
         signer = signer.expiration(c.as_secs());
@@ -373,8 +410,19 @@ fn parse_signature<T: SigningKey, U: SigningKey<Hasher = Sha256>>(
         signer = signer.reporting(true);
     }
 
-    if let Some(auid) = config.property::<String>(("signature", id, "auid")) {
-        signer = signer.agent_user_identifier(auid);
+    // Signature settings take precedence over the per-domain identity
+    if let Some(auid) = config
+        .property::<String>(("signature", id, "auid"))
+        .or_else(|| config.property::<String>(("auth.dkim.auid", domain.as_str())))
+    {
+        if is_valid_auid(&auid, &domain) {
+            signer = signer.agent_user_identifier(auid);
+        } else {
+            config.new_build_error(
+                ("signature", id, "auid"),
+                format!("Identity {auid:?} does not belong to the signing domain {domain:?}."),
+            );
+        }
     }
 
     if let Some(atps) = config.property::<String>(("signature", id, "third-party")) {
@@ -388,6 +436,18 @@ fn parse_signature<T: SigningKey, U: SigningKey<Hasher = Sha256>>(
     Some((signer, sealer))
 }
 
+/// The AUID domain has to be the signing domain or one of its subdomains (RFC 6376, 3.5).
+fn is_valid_auid(auid: &str, domain: &str) -> bool {
+    auid.rsplit_once('@').map_or(false, |(_, auid_domain)| {
+        let auid_domain = auid_domain.to_lowercase();
+        let domain = domain.to_lowercase();
+        auid_domain == domain
+            || auid_domain
+                .strip_suffix(domain.as_str())
+                .map_or(false, |prefix| prefix.ends_with('.'))
+    })
+}
+
 impl<'x> TryFrom<expr::Variable<'x>> for VerifyStrategy {
     type Error = ();
 
@@ -4,6 +4,7 @@ pub mod auth;
 pub mod queue;
 pub mod report;
 pub mod resolver;
+pub mod schedule;
 pub mod session;
 pub mod throttle;
 
@@ -47,6 +48,16 @@ pub const THROTTLE_HELO_DOMAIN: u16 = 1 << 9;
 
 pub(crate) const RCPT_DOMAIN_VARS: &[u32; 1] = &[V_RECIPIENT_DOMAIN];
 
+pub(crate) const SMTP_GREETING_VARS: &[u32; 8] = &[
+    V_LISTENER,
+    V_REMOTE_IP,
+    V_REMOTE_PORT,
+    V_LOCAL_IP,
+    V_LOCAL_PORT,
+    V_PROTOCOL,
+    V_TLS,
+    V_IPREV_RESULT,
+];
 pub(crate) const SMTP_EHLO_VARS: &[u32; 8] = &[
     V_LISTENER,
     V_REMOTE_IP,
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use ahash::AHashMap;
 use mail_auth::IpLookupStrategy;
 use mail_send::Credentials;
@@ -7,7 +9,7 @@ use utils::config::{
 };
 
 use crate::{
-    config::server::ServerProtocol,
+    config::{server::ServerProtocol, smtp::session::TlsVersion},
     expr::{if_block::IfBlock, *},
 };
 
@@ -21,17 +23,28 @@ pub struct QueueConfig {
     pub retry: IfBlock,
     pub notify: IfBlock,
     pub expire: IfBlock,
+    pub max_attempts: IfBlock,
 
     // Outbound
     pub hostname: IfBlock,
     pub next_hop: IfBlock,
     pub max_mx: IfBlock,
+    pub implicit_mx: IfBlock,
     pub max_multihomed: IfBlock,
+    pub max_messages_per_connection: IfBlock,
     pub ip_strategy: IfBlock,
     pub source_ip: QueueOutboundSourceIp,
     pub tls: QueueOutboundTls,
     pub dsn: Dsn,
 
+    // VERP encoding of the envelope sender, applied to the original
+    // return path before any other sender rewriting takes place.
+    pub verp: IfBlock,
+
+    // Address that replaces the envelope sender on outbound delivery and DSNs,
+    // VERP encoding is applied to the overridden address.
+    pub return_path: IfBlock,
+
     // Timeouts
     pub timeout: QueueOutboundTimeout,
 
@@ -41,6 +54,21 @@ pub struct QueueConfig {
 
     // Relay hosts
     pub relay_hosts: AHashMap<String, RelayHost>,
+
+    // Per-domain pinned TLS certificates
+    pub tls_pins: AHashMap<String, Vec<TlsPin>>,
+
+    // Deduplication of identical messages submitted for delivery
+    pub dedup: QueueDedup,
+
+    // Maximum time to wait for sessions and deliveries to finish when draining
+    pub drain_timeout: Duration,
+}
+
+#[derive(Clone)]
+pub struct QueueDedup {
+    pub enable: IfBlock,
+    pub ttl: Duration,
 }
 
 #[derive(Clone)]
@@ -54,6 +82,27 @@ pub struct Dsn {
     pub name: IfBlock,
     pub address: IfBlock,
     pub sign: IfBlock,
+    pub locale: IfBlock,
+    pub locale_from_header: bool,
+    pub catalogs: AHashMap<String, DsnCatalog>,
+}
+
+// Human-readable texts included in delivery status notifications.
+#[derive(Debug, Clone)]
+pub struct DsnCatalog {
+    pub subject_success: String,
+    pub subject_delay: String,
+    pub subject_failure: String,
+    pub subject_partial: String,
+    pub subject_mixed: String,
+    pub summary_success: String,
+    pub summary_delay: String,
+    pub summary_failure: String,
+    pub summary_partial: String,
+    pub summary_mixed: String,
+    pub section_success: String,
+    pub section_delay: String,
+    pub section_failure: String,
 }
 
 #[derive(Clone)]
@@ -62,6 +111,9 @@ pub struct QueueOutboundTls {
     pub mta_sts: IfBlock,
     pub start: IfBlock,
     pub invalid_certs: IfBlock,
+    pub min_version: IfBlock,
+    pub resumption: IfBlock,
+    pub resumption_cache_size: usize,
 }
 
 #[derive(Clone)]
@@ -108,7 +160,15 @@ pub struct RelayHost {
     pub tls_allow_invalid_certs: bool,
 }
 
-#[derive(Debug, Clone, Copy, Default)]
+/// SHA-256 fingerprint of either the DER encoded certificate or its
+/// SubjectPublicKeyInfo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsPin {
+    pub is_spki: bool,
+    pub hash: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub enum RequireOptional {
     #[default]
     Optional,
@@ -126,6 +186,7 @@ impl Default for QueueConfig {
             ),
             notify: IfBlock::new::<()>("queue.schedule.notify", [], "[1d, 3d]"),
             expire: IfBlock::new::<()>("queue.schedule.expire", [], "5d"),
+            max_attempts: IfBlock::empty("queue.schedule.max-attempts"),
             hostname: IfBlock::new::<()>(
                 "queue.outbound.hostname",
                 [],
@@ -140,7 +201,13 @@ impl Default for QueueConfig {
                 "false",
             ),
             max_mx: IfBlock::new::<()>("queue.outbound.limits.mx", [], "5"),
+            implicit_mx: IfBlock::new::<()>("queue.outbound.implicit-mx", [], "true"),
             max_multihomed: IfBlock::new::<()>("queue.outbound.limits.multihomed", [], "2"),
+            max_messages_per_connection: IfBlock::new::<()>(
+                "queue.outbound.limits.messages-per-connection",
+                [],
+                "1",
+            ),
             ip_strategy: IfBlock::new::<IpLookupStrategy>(
                 "queue.outbound.ip-strategy",
                 [],
@@ -170,6 +237,13 @@ impl Default for QueueConfig {
                     [],
                     "false",
                 ),
+                min_version: IfBlock::new::<()>("queue.outbound.tls.min-version", [], "'TLSv1.2'"),
+                resumption: IfBlock::new::<()>(
+                    "queue.outbound.tls.resumption.enable",
+                    [],
+                    "false",
+                ),
+                resumption_cache_size: 256,
             },
             dsn: Dsn {
                 name: IfBlock::new::<()>("report.dsn.from-name", [], "'Mail Delivery Subsystem'"),
@@ -183,7 +257,17 @@ impl Default for QueueConfig {
                     [],
                     "['rsa-' + key_get('default', 'domain'), 'ed25519-' + key_get('default', 'domain')]",
                 ),
+                locale: IfBlock::new::<()>("report.dsn.locale", [], "'en'"),
+                locale_from_header: false,
+                catalogs: DsnCatalog::builtin_locales()
+                    .iter()
+                    .filter_map(|locale| {
+                        DsnCatalog::builtin(locale).map(|catalog| (locale.to_string(), catalog))
+                    })
+                    .collect(),
             },
+            verp: IfBlock::new::<()>("queue.outbound.verp", [], "false"),
+            return_path: IfBlock::empty("queue.outbound.return-path"),
             timeout: QueueOutboundTimeout {
                 connect: IfBlock::new::<()>("queue.outbound.timeouts.connect", [], "5m"),
                 greeting: IfBlock::new::<()>("queue.outbound.timeouts.greeting", [], "5m"),
@@ -205,6 +289,12 @@ impl Default for QueueConfig {
                 rcpt_domain: Default::default(),
             },
             relay_hosts: Default::default(),
+            tls_pins: Default::default(),
+            dedup: QueueDedup {
+                enable: IfBlock::new::<()>("queue.dedup.enable", [], "false"),
+                ttl: Duration::from_secs(3600),
+            },
+            drain_timeout: Duration::from_secs(120),
         }
     }
 }
@@ -224,13 +314,28 @@ impl QueueConfig {
             (&mut queue.retry, "queue.schedule.retry", &host_vars),
             (&mut queue.notify, "queue.schedule.notify", &rcpt_vars),
             (&mut queue.expire, "queue.schedule.expire", &rcpt_vars),
+            (
+                &mut queue.max_attempts,
+                "queue.schedule.max-attempts",
+                &rcpt_vars,
+            ),
             (&mut queue.hostname, "queue.outbound.hostname", &sender_vars),
             (&mut queue.max_mx, "queue.outbound.limits.mx", &rcpt_vars),
+            (
+                &mut queue.implicit_mx,
+                "queue.outbound.implicit-mx",
+                &rcpt_vars,
+            ),
             (
                 &mut queue.max_multihomed,
                 "queue.outbound.limits.multihomed",
                 &rcpt_vars,
             ),
+            (
+                &mut queue.max_messages_per_connection,
+                "queue.outbound.limits.messages-per-connection",
+                &host_vars,
+            ),
             (
                 &mut queue.ip_strategy,
                 "queue.outbound.ip-strategy",
@@ -247,6 +352,12 @@ impl QueueConfig {
                 &mx_vars,
             ),
             (&mut queue.next_hop, "queue.outbound.next-hop", &rcpt_vars),
+            (&mut queue.verp, "queue.outbound.verp", &sender_vars),
+            (
+                &mut queue.return_path,
+                "queue.outbound.return-path",
+                &sender_vars,
+            ),
             (&mut queue.tls.dane, "queue.outbound.tls.dane", &dane_vars),
             (
                 &mut queue.tls.mta_sts,
@@ -263,6 +374,16 @@ impl QueueConfig {
                 "queue.outbound.tls.allow-invalid-certs",
                 &mx_vars,
             ),
+            (
+                &mut queue.tls.min_version,
+                "queue.outbound.tls.min-version",
+                &mx_vars,
+            ),
+            (
+                &mut queue.tls.resumption,
+                "queue.outbound.tls.resumption.enable",
+                &mx_vars,
+            ),
             (
                 &mut queue.timeout.connect,
                 "queue.outbound.timeouts.connect",
@@ -310,11 +431,19 @@ impl QueueConfig {
                 &sender_vars,
             ),
             (&mut queue.dsn.sign, "report.dsn.sign", &sender_vars),
+            (&mut queue.dsn.locale, "report.dsn.locale", &sender_vars),
+            (&mut queue.dedup.enable, "queue.dedup.enable", &sender_vars),
         ] {
             if let Some(if_block) = IfBlock::try_parse(config, key, token_map) {
                 *value = if_block;
             }
         }
+        TlsVersion::validate_min_version(config, &mut queue.tls.min_version);
+        if let Some(cache_size) =
+            config.property_or_default("queue.outbound.tls.resumption.cache-size", "256")
+        {
+            queue.tls.resumption_cache_size = cache_size;
+        }
 
         // Parse queue quotas and throttles
         queue.throttle = parse_queue_throttle(config);
@@ -342,10 +471,226 @@ impl QueueConfig {
             },
         );
 
+        // Parse pinned TLS certificates
+        queue.tls_pins = parse_tls_pins(config);
+
+        // Parse deduplication window
+        if let Some(ttl) = config.property_or_default::<Duration>("queue.dedup.ttl", "1h") {
+            queue.dedup.ttl = ttl;
+        }
+
+        // Parse drain timeout
+        if let Some(timeout) = config.property_or_default::<Duration>("queue.drain.timeout", "2m") {
+            queue.drain_timeout = timeout;
+        }
+
+        // Parse DSN locales
+        queue.dsn.locale_from_header = config
+            .property_or_default("report.dsn.locale-from-header", "false")
+            .unwrap_or(false);
+        parse_dsn_catalogs(config, &mut queue.dsn.catalogs);
+
         queue
     }
 }
 
+impl Dsn {
+    /// Returns the catalog for a language tag such as "pt-BR", falling back
+    /// to the primary language subtag.
+    pub fn catalog(&self, tag: &str) -> Option<&DsnCatalog> {
+        let tag = tag.trim().to_lowercase();
+        self.catalogs.get(&tag).or_else(|| {
+            tag.split_once(['-', '_'])
+                .and_then(|(lang, _)| self.catalogs.get(lang))
+        })
+    }
+}
+
+impl DsnCatalog {
+    const FIELDS: [&'static str; 13] = [
+        "subject.success",
+        "subject.delay",
+        "subject.failure",
+        "subject.partial",
+        "subject.mixed",
+        "summary.success",
+        "summary.delay",
+        "summary.failure",
+        "summary.partial",
+        "summary.mixed",
+        "section.success",
+        "section.delay",
+        "section.failure",
+    ];
+
+    pub fn builtin_locales() -> &'static [&'static str] {
+        &["en", "es", "fr", "de"]
+    }
+
+    pub fn builtin(locale: &str) -> Option<Self> {
+        let texts: [&str; 13] = match locale {
+            "en" => [
+                "Successfully delivered message",
+                "Warning: Delay in message delivery",
+                "Failed to deliver message",
+                "Partially delivered message",
+                "Warning: Temporary and permanent failures during message delivery",
+                "Your message has been successfully delivered to the following recipients:",
+                "There was a temporary problem delivering your message to the following recipients:",
+                "Your message could not be delivered to the following recipients:",
+                "Your message has been partially delivered:",
+                "Your message could not be delivered to some recipients:",
+                "Delivery to the following addresses was successful",
+                "There was a temporary problem delivering to these addresses",
+                "Delivery to the following addresses failed",
+            ],
+            "es" => [
+                "Mensaje entregado correctamente",
+                "Advertencia: Retraso en la entrega del mensaje",
+                "No se pudo entregar el mensaje",
+                "Mensaje entregado parcialmente",
+                "Advertencia: Fallos temporales y permanentes durante la entrega del mensaje",
+                "Su mensaje ha sido entregado correctamente a los siguientes destinatarios:",
+                "Hubo un problema temporal al entregar su mensaje a los siguientes destinatarios:",
+                "Su mensaje no pudo ser entregado a los siguientes destinatarios:",
+                "Su mensaje ha sido entregado parcialmente:",
+                "Su mensaje no pudo ser entregado a algunos destinatarios:",
+                "La entrega a las siguientes direcciones fue exitosa",
+                "Hubo un problema temporal al entregar a estas direcciones",
+                "La entrega a las siguientes direcciones ha fallado",
+            ],
+            "fr" => [
+                "Message remis avec succès",
+                "Avertissement : Retard dans la remise du message",
+                "Échec de la remise du message",
+                "Message partiellement remis",
+                "Avertissement : Échecs temporaires et permanents lors de la remise du message",
+                "Votre message a été remis avec succès aux destinataires suivants :",
+                "Un problème temporaire est survenu lors de la remise de votre message aux destinataires suivants :",
+                "Votre message n'a pas pu être remis aux destinataires suivants :",
+                "Votre message a été partiellement remis :",
+                "Votre message n'a pas pu être remis à certains destinataires :",
+                "La remise aux adresses suivantes a réussi",
+                "Un problème temporaire est survenu lors de la remise à ces adresses",
+                "La remise aux adresses suivantes a échoué",
+            ],
+            "de" => [
+                "Nachricht erfolgreich zugestellt",
+                "Warnung: Verzögerung bei der Nachrichtenzustellung",
+                "Nachricht konnte nicht zugestellt werden",
+                "Nachricht teilweise zugestellt",
+                "Warnung: Vorübergehende und dauerhafte Fehler bei der Nachrichtenzustellung",
+                "Ihre Nachricht wurde erfolgreich an die folgenden Empfänger zugestellt:",
+                "Bei der Zustellung Ihrer Nachricht an die folgenden Empfänger ist ein vorübergehendes Problem aufgetreten:",
+                "Ihre Nachricht konnte an die folgenden Empfänger nicht zugestellt werden:",
+                "Ihre Nachricht wurde teilweise zugestellt:",
+                "Ihre Nachricht konnte an einige Empfänger nicht zugestellt werden:",
+                "Die Zustellung an die folgenden Adressen war erfolgreich",
+                "Bei der Zustellung an diese Adressen ist ein vorübergehendes Problem aufgetreten",
+                "Die Zustellung an die folgenden Adressen ist fehlgeschlagen",
+            ],
+            _ => return None,
+        };
+
+        Some(Self::from_texts(texts.map(|text| text.to_string())))
+    }
+
+    fn from_texts(texts: [String; 13]) -> Self {
+        let mut texts = texts.into_iter();
+        let mut next = || texts.next().unwrap_or_default();
+        DsnCatalog {
+            subject_success: next(),
+            subject_delay: next(),
+            subject_failure: next(),
+            subject_partial: next(),
+            subject_mixed: next(),
+            summary_success: next(),
+            summary_delay: next(),
+            summary_failure: next(),
+            summary_partial: next(),
+            summary_mixed: next(),
+            section_success: next(),
+            section_delay: next(),
+            section_failure: next(),
+        }
+    }
+
+    fn texts(&self) -> [&str; 13] {
+        [
+            &self.subject_success,
+            &self.subject_delay,
+            &self.subject_failure,
+            &self.subject_partial,
+            &self.subject_mixed,
+            &self.summary_success,
+            &self.summary_delay,
+            &self.summary_failure,
+            &self.summary_partial,
+            &self.summary_mixed,
+            &self.section_success,
+            &self.section_delay,
+            &self.section_failure,
+        ]
+    }
+}
+
+fn parse_dsn_catalogs(config: &mut Config, catalogs: &mut AHashMap<String, DsnCatalog>) {
+    let mut locales = Vec::new();
+    for key in config.keys.keys() {
+        if let Some(key) = key.strip_prefix("report.dsn.catalog.") {
+            if let Some((locale, field)) = key.split_once('.') {
+                if DsnCatalog::FIELDS.contains(&field) && !locales.iter().any(|l| l == locale) {
+                    locales.push(locale.to_string());
+                }
+            }
+        }
+    }
+
+    for locale in locales {
+        // Missing texts are taken from the built-in catalog for the same
+        // language or, if there is none, from the English catalog.
+        let base = catalogs
+            .get(&locale.to_lowercase())
+            .or_else(|| catalogs.get("en"))
+            .cloned()
+            .or_else(|| DsnCatalog::builtin("en"))
+            .unwrap();
+        let mut texts = base.texts().map(|text| text.to_string());
+        for (text, field) in texts.iter_mut().zip(DsnCatalog::FIELDS) {
+            if let Some(value) = config.value(("report.dsn.catalog", locale.as_str(), field)) {
+                *text = value.to_string();
+            }
+        }
+        catalogs.insert(locale.to_lowercase(), DsnCatalog::from_texts(texts));
+    }
+}
+
+fn parse_tls_pins(config: &mut Config) -> AHashMap<String, Vec<TlsPin>> {
+    let mut values = Vec::new();
+    for (key, value) in config.iterate_prefix("queue.outbound.tls.pin") {
+        // Remove array indexes
+        let domain = match key.rsplit_once('.') {
+            Some((domain, index)) if index.chars().all(|ch| ch.is_ascii_digit()) => domain,
+            _ => key,
+        };
+        values.push((
+            format!("queue.outbound.tls.pin.{key}"),
+            domain.to_lowercase(),
+            value.to_string(),
+        ));
+    }
+
+    let mut pins: AHashMap<String, Vec<TlsPin>> = AHashMap::new();
+    for (key, domain, value) in values {
+        match TlsPin::parse_value(&value) {
+            Ok(pin) => pins.entry(domain).or_default().push(pin),
+            Err(err) => config.new_parse_error(key, err),
+        }
+    }
+
+    pins
+}
+
 fn parse_relay_host(config: &mut Config, id: &str) -> Option<RelayHost> {
     Some(RelayHost {
         address: config.property_require(("remote", id, "address"))?,
@@ -524,6 +869,33 @@ fn parse_queue_quota_item(config: &mut Config, prefix: impl AsKey) -> Option<Que
     }
 }
 
+impl ParseValue for TlsPin {
+    fn parse_value(value: &str) -> utils::config::Result<Self> {
+        let (is_spki, fingerprint) = match value.trim().split_once(':') {
+            Some((kind, fingerprint)) if kind.eq_ignore_ascii_case("spki") => (true, fingerprint),
+            Some((kind, fingerprint)) if kind.eq_ignore_ascii_case("cert") => (false, fingerprint),
+            _ => {
+                return Err(format!(
+                    "Invalid TLS pin {value:?}, expected 'spki:<sha256>' or 'cert:<sha256>'."
+                ))
+            }
+        };
+        let fingerprint = fingerprint.replace(':', "");
+        let hash = (0..fingerprint.len())
+            .step_by(2)
+            .map(|pos| {
+                fingerprint
+                    .get(pos..pos + 2)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            })
+            .collect::<Option<Vec<_>>>()
+            .filter(|hash| hash.len() == 32)
+            .ok_or_else(|| format!("Invalid SHA-256 fingerprint in TLS pin {value:?}."))?;
+
+        Ok(TlsPin { is_spki, hash })
+    }
+}
+
 impl ParseValue for RequireOptional {
     fn parse_value(value: &str) -> utils::config::Result<Self> {
         match value {
@@ -18,6 +18,9 @@ use utils::{config::Config, suffixlist::PublicSuffix};
 pub struct Resolvers {
     pub dns: Resolver,
     pub dnssec: DnssecResolver,
+    /// Non-caching resolver returning TXT records individually, `dns` only
+    /// exposes them concatenated.
+    pub txt: TokioAsyncResolver,
     pub cache: DnsRecordCache,
     pub psl: PublicSuffix,
 }
@@ -30,6 +33,8 @@ pub struct DnssecResolver {
 pub struct DnsRecordCache {
     pub tlsa: LruCache<String, Arc<Tlsa>>,
     pub mta_sts: LruCache<String, Arc<Policy>>,
+    pub dkim_key: LruCache<String, (&'static str, i64)>,
+    pub spf_record: LruCache<String, String>,
 }
 
 #[derive(Debug, Hash, PartialEq, Eq)]
@@ -176,6 +181,7 @@ impl Resolvers {
         let config_dnssec = resolver_config.clone();
         let mut opts_dnssec = opts.clone();
         opts_dnssec.validate = true;
+        let txt = AsyncResolver::tokio(resolver_config.clone(), opts.clone());
 
         let mut capacities = [1024usize; 5];
         for (pos, key) in ["txt", "mx", "ipv4", "ipv6", "ptr"].into_iter().enumerate() {
@@ -198,6 +204,7 @@ impl Resolvers {
             dnssec: DnssecResolver {
                 resolver: AsyncResolver::tokio(config_dnssec, opts_dnssec),
             },
+            txt,
             cache: DnsRecordCache {
                 tlsa: LruCache::with_capacity(
                     config.property("cache.resolver.tlsa.size").unwrap_or(1024),
@@ -207,6 +214,12 @@ impl Resolvers {
                         .property("cache.resolver.mta-sts.size")
                         .unwrap_or(1024),
                 ),
+                dkim_key: LruCache::with_capacity(
+                    config.property("cache.resolver.dkim.size").unwrap_or(1024),
+                ),
+                spf_record: LruCache::with_capacity(
+                    config.property("cache.resolver.spf.size").unwrap_or(1024),
+                ),
             },
             psl: PublicSuffix::parse(config, "resolver.public-suffix").await,
         }
@@ -223,6 +236,7 @@ impl Default for Resolvers {
         let config_dnssec = config.clone();
         let mut opts_dnssec = opts.clone();
         opts_dnssec.validate = true;
+        let txt = AsyncResolver::tokio(config.clone(), opts.clone());
 
         Self {
             dns: Resolver::with_capacities(config, opts, 1024, 1024, 1024, 1024, 1024)
@@ -230,9 +244,12 @@ impl Default for Resolvers {
             dnssec: DnssecResolver {
                 resolver: AsyncResolver::tokio(config_dnssec, opts_dnssec),
             },
+            txt,
             cache: DnsRecordCache {
                 tlsa: LruCache::with_capacity(1024),
                 mta_sts: LruCache::with_capacity(1024),
+                dkim_key: LruCache::with_capacity(1024),
+                spf_record: LruCache::with_capacity(1024),
             },
             psl: PublicSuffix::default(),
         }
@@ -244,6 +261,7 @@ impl Clone for Resolvers {
         Self {
             dns: self.dns.clone(),
             dnssec: self.dnssec.clone(),
+            txt: self.txt.clone(),
             cache: self.cache.clone(),
             psl: self.psl.clone(),
         }
@@ -255,6 +273,8 @@ impl Clone for DnsRecordCache {
         Self {
             tlsa: Mutex::new(self.tlsa.lock().clone()),
             mta_sts: Mutex::new(self.mta_sts.lock().clone()),
+            dkim_key: Mutex::new(self.dkim_key.lock().clone()),
+            spf_record: Mutex::new(self.spf_record.lock().clone()),
         }
     }
 }
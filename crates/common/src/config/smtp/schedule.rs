@@ -0,0 +1,248 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use ahash::AHashMap;
+use chrono::{Datelike, FixedOffset, TimeZone, Timelike, Utc};
+use utils::config::{utils::ParseValue, Config};
+
+use crate::expr::{if_block::IfBlock, Constant, ExpressionItem};
+
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// A weekly time window such as `mon-fri 09:00-17:00 +02:00`.
+///
+/// The days are optional and default to the whole week, the timezone is an optional
+/// fixed UTC offset that defaults to UTC. Named zones such as `Europe/Madrid` are not
+/// supported, so windows do not follow daylight saving time changes and have to be
+/// updated when the offset changes. Windows ending before they start cross midnight
+/// and belong to the day they start on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeWindow {
+    pub days: u8,
+    pub start: u32,
+    pub end: u32,
+    pub offset: FixedOffset,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, timestamp: u64) -> bool {
+        let Some(time) = Utc.timestamp_opt(timestamp as i64, 0).single() else {
+            return false;
+        };
+        let time = time.with_timezone(&self.offset);
+        let day = time.weekday().num_days_from_monday();
+        let minute = time.hour() * 60 + time.minute();
+
+        if self.start <= self.end {
+            self.has_day(day) && (self.start..self.end).contains(&minute)
+        } else {
+            (minute >= self.start && self.has_day(day))
+                || (minute < self.end && self.has_day((day + 6) % 7))
+        }
+    }
+
+    fn has_day(&self, day: u32) -> bool {
+        self.days & (1 << day) != 0
+    }
+
+    /// Parses all the time windows that can be returned by an expression, reporting
+    /// any invalid window as a configuration error.
+    pub fn parse_if_block(config: &mut Config, if_block: &IfBlock) -> AHashMap<String, TimeWindow> {
+        let mut windows = AHashMap::new();
+        for item in if_block
+            .if_then
+            .iter()
+            .map(|if_then| &if_then.then)
+            .chain([&if_block.default])
+            .flat_map(|expr| expr.items.iter())
+        {
+            if let ExpressionItem::Constant(Constant::String(value)) = item {
+                match TimeWindow::parse_value(value) {
+                    Ok(window) => {
+                        windows.insert(value.to_string(), window);
+                    }
+                    Err(err) => {
+                        config.new_parse_error(if_block.key.as_str(), err);
+                    }
+                }
+            }
+        }
+        windows
+    }
+}
+
+impl ParseValue for TimeWindow {
+    fn parse_value(value: &str) -> utils::config::Result<Self> {
+        let mut tokens = value.split_whitespace().peekable();
+        let days = if tokens.peek().map_or(false, |token| {
+            token.starts_with(|ch: char| ch.is_alphabetic())
+        }) {
+            parse_days(tokens.next().unwrap())
+        } else {
+            Some(0x7f)
+        };
+        let hours = tokens
+            .next()
+            .and_then(|token| token.split_once('-'))
+            .and_then(|(start, end)| Some((parse_time(start)?, parse_time(end)?)));
+        let offset = match tokens.next() {
+            Some(token) if token.contains('/') => {
+                return Err(format!(
+                    "Invalid time window {:?}: named time zones are not supported, use a fixed UTC offset such as +02:00.",
+                    value
+                ));
+            }
+            Some(token) => parse_offset(token),
+            None => FixedOffset::east_opt(0),
+        };
+
+        match (days, hours, offset, tokens.next()) {
+            (Some(days), Some((start, end)), Some(offset), None) if start < 24 * 60 => {
+                Ok(TimeWindow {
+                    days,
+                    start,
+                    end,
+                    offset,
+                })
+            }
+            _ => Err(format!("Invalid time window {:?}.", value)),
+        }
+    }
+}
+
+fn parse_days(value: &str) -> Option<u8> {
+    let mut days = 0;
+    for range in value.split(',') {
+        let (from, to) = range.split_once('-').unwrap_or((range, range));
+        let from = parse_day(from)?;
+        let to = parse_day(to)?;
+        let mut day = from;
+        loop {
+            days |= 1 << day;
+            if day == to {
+                break;
+            }
+            day = (day + 1) % 7;
+        }
+    }
+    Some(days)
+}
+
+fn parse_day(value: &str) -> Option<usize> {
+    let value = value.to_ascii_lowercase();
+    let prefix = value.get(..3)?;
+    DAYS.iter().position(|day| day.starts_with(prefix))
+}
+
+fn parse_time(value: &str) -> Option<u32> {
+    let (hour, minute) = value.split_once(':')?;
+    let hour = hour.parse::<u32>().ok()?;
+    let minute = minute.parse::<u32>().ok()?;
+    if minute < 60 && (hour < 24 || (hour == 24 && minute == 0)) {
+        Some(hour * 60 + minute)
+    } else {
+        None
+    }
+}
+
+fn parse_offset(value: &str) -> Option<FixedOffset> {
+    let value = value
+        .strip_prefix("UTC")
+        .or_else(|| value.strip_prefix("GMT"))
+        .unwrap_or(value);
+    if value.is_empty() {
+        return FixedOffset::east_opt(0);
+    }
+    let (sign, value) = match value.as_bytes()[0] {
+        b'+' => (1, &value[1..]),
+        b'-' => (-1, &value[1..]),
+        _ => return None,
+    };
+    let (hours, minutes) = if let Some((hours, minutes)) = value.split_once(':') {
+        (hours, minutes)
+    } else if value.len() == 4 {
+        value.split_at(2)
+    } else {
+        (value, "0")
+    };
+    let hours = hours.parse::<i32>().ok()?;
+    let minutes = minutes.parse::<i32>().ok()?;
+    if hours <= 14 && minutes < 60 {
+        FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use utils::config::utils::ParseValue;
+
+    use super::TimeWindow;
+
+    #[test]
+    fn time_window() {
+        // 2024-01-01 was a Monday
+        const MONDAY: u64 = 1704067200;
+        const HOUR: u64 = 3600;
+        const DAY: u64 = 24 * HOUR;
+
+        let window = TimeWindow::parse_value("mon-fri 09:00-17:00").unwrap();
+        assert!(!window.contains(MONDAY + 8 * HOUR));
+        assert!(window.contains(MONDAY + 9 * HOUR));
+        assert!(window.contains(MONDAY + 4 * DAY + 16 * HOUR));
+        assert!(!window.contains(MONDAY + 4 * DAY + 17 * HOUR));
+        assert!(!window.contains(MONDAY + 5 * DAY + 10 * HOUR));
+
+        // Offsets are applied before comparing
+        let window = TimeWindow::parse_value("mon 09:00-17:00 +02:00").unwrap();
+        assert!(window.contains(MONDAY + 7 * HOUR));
+        assert!(!window.contains(MONDAY + 15 * HOUR));
+        let window = TimeWindow::parse_value("sat,sun 00:00-24:00 UTC-0500").unwrap();
+        assert!(window.contains(MONDAY + 4 * HOUR));
+        assert!(!window.contains(MONDAY + 5 * HOUR));
+
+        // Windows crossing midnight belong to the day they start on
+        let window = TimeWindow::parse_value("fri 22:00-06:00").unwrap();
+        assert!(window.contains(MONDAY + 4 * DAY + 23 * HOUR));
+        assert!(window.contains(MONDAY + 5 * DAY + 5 * HOUR));
+        assert!(!window.contains(MONDAY + 4 * DAY + 5 * HOUR));
+        assert!(!window.contains(MONDAY + 5 * DAY + 23 * HOUR));
+
+        // Days default to the whole week
+        let window = TimeWindow::parse_value("01:00-05:00").unwrap();
+        assert!(window.contains(MONDAY + 6 * DAY + 2 * HOUR));
+
+        for invalid in [
+            "",
+            "mon-fri",
+            "someday 09:00-17:00",
+            "mon-fri 09:00-25:00",
+            "mon-fri 09:00-17:00 +25:00",
+            "mon-fri 09:00-17:00 UTC extra",
+            "mon-fri 09:00-17:00 Europe/Madrid",
+        ] {
+            assert!(TimeWindow::parse_value(invalid).is_err(), "{invalid}");
+        }
+    }
+}
@@ -1,8 +1,10 @@
 use std::{
     net::{SocketAddr, ToSocketAddrs},
+    path::PathBuf,
     time::Duration,
 };
 
+use ahash::{AHashMap, AHashSet};
 use smtp_proto::*;
 use utils::config::{utils::ParseValue, Config};
 
@@ -11,7 +13,7 @@ use crate::{
     expr::{if_block::IfBlock, tokenizer::TokenMap, *},
 };
 
-use self::throttle::parse_throttle;
+use self::{schedule::TimeWindow, throttle::parse_throttle};
 
 use super::*;
 
@@ -21,6 +23,7 @@ pub struct SessionConfig {
     pub duration: IfBlock,
     pub transfer_limit: IfBlock,
     pub throttle: SessionThrottle,
+    pub transcript: Transcript,
 
     pub connect: Connect,
     pub ehlo: Ehlo,
@@ -29,6 +32,9 @@ pub struct SessionConfig {
     pub rcpt: Rcpt,
     pub data: Data,
     pub extensions: Extensions,
+
+    // Commands disabled on each listener, keyed by listener id
+    pub disabled_commands: AHashMap<String, AHashSet<String>>,
 }
 
 #[derive(Default, Debug, Clone)]
@@ -38,11 +44,20 @@ pub struct SessionThrottle {
     pub rcpt_to: Vec<Throttle>,
 }
 
+#[derive(Clone)]
+pub struct Transcript {
+    pub enable: IfBlock,
+    pub capacity: usize,
+    pub max_size: usize,
+}
+
 #[derive(Clone)]
 pub struct Connect {
     pub hostname: IfBlock,
     pub script: IfBlock,
     pub greeting: IfBlock,
+    pub help: IfBlock,
+    pub tls_min_version: IfBlock,
 }
 
 #[derive(Clone)]
@@ -50,6 +65,7 @@ pub struct Ehlo {
     pub script: IfBlock,
     pub require: IfBlock,
     pub reject_non_fqdn: IfBlock,
+    pub require_iprev: IfBlock,
 }
 
 #[derive(Clone)]
@@ -80,6 +96,11 @@ pub struct Auth {
 pub struct Mail {
     pub script: IfBlock,
     pub rewrite: IfBlock,
+    pub require_starttls: IfBlock,
+    pub schedule: IfBlock,
+
+    // Time windows returned by the schedule expression, parsed in advance
+    pub schedule_windows: AHashMap<String, TimeWindow>,
 }
 
 #[derive(Clone)]
@@ -89,6 +110,9 @@ pub struct Rcpt {
     pub directory: IfBlock,
     pub rewrite: IfBlock,
 
+    // Set ORCPT to the address received in RCPT TO when missing
+    pub generate_orcpt: IfBlock,
+
     // Errors
     pub errors_max: IfBlock,
     pub errors_wait: IfBlock,
@@ -99,6 +123,27 @@ pub struct Rcpt {
     // Catch-all and sub-adressing
     pub catch_all: AddressMapping,
     pub subaddressing: AddressMapping,
+
+    // Greylisting
+    pub greylist: Greylist,
+
+    // Per-recipient rate limit
+    pub rate_limit: RcptRateLimit,
+}
+
+#[derive(Clone)]
+pub struct Greylist {
+    pub enable: IfBlock,
+    pub store: Option<String>,
+    pub delay: Duration,
+    pub window: Duration,
+    pub lifetime: Duration,
+}
+
+#[derive(Clone)]
+pub struct RcptRateLimit {
+    pub rate: IfBlock,
+    pub store: Option<String>,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -114,11 +159,42 @@ pub struct Data {
     pub script: IfBlock,
     pub pipe_commands: Vec<Pipe>,
     pub milters: Vec<Milter>,
+    pub icap: Vec<Icap>,
 
     // Limits
     pub max_messages: IfBlock,
     pub max_message_size: IfBlock,
+    pub max_sender_message_size: IfBlock,
     pub max_received_headers: IfBlock,
+    pub max_header_size: IfBlock,
+    pub max_headers: IfBlock,
+
+    // Messages above this size are spooled to disk while being received
+    pub spool_threshold: IfBlock,
+    pub spool_path: PathBuf,
+    // Maximum bytes of spooled messages loaded back into memory at once
+    pub spool_max_memory: usize,
+
+    // Handling of bare CR and LF characters
+    pub line_endings: IfBlock,
+
+    // Duplicate Message-ID detection
+    pub duplicate: DuplicateMessageId,
+
+    // Removal of tracking pixels from HTML parts
+    pub tracking_pixels: TrackingPixels,
+
+    // Removal of scripts and other active content from HTML parts
+    pub html_sanitize: HtmlSanitize,
+
+    // Post-acceptance scanning of messages above the spool threshold
+    pub deferred_scan: DeferredScan,
+
+    // Plain text alternative for HTML-only messages
+    pub text_alternative: TextAlternative,
+
+    // Copies of accepted messages sent to a journaling address
+    pub journal: Journal,
 
     // Headers
     pub add_received: IfBlock,
@@ -127,6 +203,95 @@ pub struct Data {
     pub add_auth_results: IfBlock,
     pub add_message_id: IfBlock,
     pub add_date: IfBlock,
+
+    // Message-ID format, empty to use the default format
+    pub message_id_format: Vec<MessageIdToken>,
+
+    // Order and casing of the headers added to incoming messages, the casing
+    // of DKIM-Signature headers is not changed
+    pub header_order: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MessageIdToken {
+    Text(String),
+    Hostname,
+    SenderDomain,
+    Timestamp,
+    Random(usize),
+    Unique,
+}
+
+#[derive(Clone)]
+pub struct TrackingPixels {
+    pub enable: IfBlock,
+    pub sign: IfBlock,
+    pub max_area: u32,
+    pub lookup: String,
+}
+
+#[derive(Clone)]
+pub struct HtmlSanitize {
+    pub enable: IfBlock,
+    pub sign: IfBlock,
+}
+
+#[derive(Clone)]
+pub struct TextAlternative {
+    pub enable: IfBlock,
+    pub sign: IfBlock,
+}
+
+/// Accepted messages are copied to the journaling address of each recipient
+/// domain, wrapped together with their original envelope, once they are
+/// queued. Journaling failures only reject the message when configured as
+/// fatal, or reschedule its scan when the scan is deferred.
+#[derive(Clone)]
+pub struct Journal {
+    pub address: IfBlock,
+    pub fatal: IfBlock,
+}
+
+/// Messages above the spool threshold can be queued before the DATA script
+/// runs, keeping the SMTP transaction short. The script then runs from the
+/// queue and a bad verdict can no longer be rejected at SMTP time: the
+/// message is redirected to the quarantine address instead, or bounced to
+/// the sender when none is configured.
+#[derive(Clone)]
+pub struct DeferredScan {
+    pub enable: IfBlock,
+    pub quarantine: IfBlock,
+}
+
+#[derive(Clone)]
+pub struct DuplicateMessageId {
+    pub action: IfBlock,
+    pub window: Duration,
+    pub max_count: u64,
+    pub header: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineEndings {
+    #[default]
+    Allow,
+    Strict,
+    Lenient,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum TlsVersion {
+    #[default]
+    Tls12,
+    Tls13,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateAction {
+    #[default]
+    Accept,
+    Tag,
+    Reject,
 }
 
 // Ceci n'est pas une pipe
@@ -153,6 +318,7 @@ pub struct Milter {
     pub protocol_version: MilterVersion,
     pub flags_actions: Option<u32>,
     pub flags_protocol: Option<u32>,
+    pub circuit_breaker: Option<CircuitBreaker>,
 }
 
 #[derive(Clone, Copy)]
@@ -161,18 +327,86 @@ pub enum MilterVersion {
     V6,
 }
 
+#[derive(Clone)]
+pub struct Icap {
+    pub enable: IfBlock,
+    pub addrs: Vec<SocketAddr>,
+    pub hostname: String,
+    pub port: u16,
+    pub service: String,
+    pub timeout_connect: Duration,
+    pub timeout_data: Duration,
+    pub tls: bool,
+    pub tls_allow_invalid_certs: bool,
+    pub tempfail_on_error: bool,
+    pub max_response_size: usize,
+    pub infected_action: IcapInfectedAction,
+    pub allow_modifications: bool,
+    pub circuit_breaker: Option<CircuitBreaker>,
+}
+
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    pub id: String,
+    pub max_failures: u32,
+    pub cooldown: Duration,
+    pub action: CircuitBreakerAction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitBreakerAction {
+    Accept,
+    TempFail,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IcapInfectedAction {
+    Reject,
+    Quarantine,
+}
+
 impl SessionConfig {
     pub fn parse(config: &mut Config) -> Self {
         let has_conn_vars = TokenMap::default().with_variables(CONNECTION_VARS);
+        let has_greeting_vars = TokenMap::default().with_variables(SMTP_GREETING_VARS);
         let has_ehlo_hars = TokenMap::default().with_variables(SMTP_EHLO_VARS);
         let has_sender_vars = TokenMap::default().with_variables(SMTP_MAIL_FROM_VARS);
         let has_rcpt_vars = TokenMap::default().with_variables(SMTP_RCPT_TO_VARS);
         let mt_priority_vars = has_sender_vars.clone().with_constants::<MtPriority>();
         let mechanisms_vars = has_ehlo_hars.clone().with_constants::<Mechanism>();
+        let duplicate_vars = has_rcpt_vars.clone().with_constants::<DuplicateAction>();
+        let line_endings_vars = has_rcpt_vars.clone().with_constants::<LineEndings>();
 
         let mut session = SessionConfig::default();
         session.rcpt.catch_all = AddressMapping::parse(config, "session.rcpt.catch-all");
         session.rcpt.subaddressing = AddressMapping::parse(config, "session.rcpt.sub-addressing");
+        session.rcpt.greylist.store = config
+            .value("session.rcpt.greylist.store")
+            .map(|s| s.to_string());
+        session.rcpt.rate_limit.store = config
+            .value("session.rcpt.rate-limit.store")
+            .map(|s| s.to_string());
+        for (value, key, default) in [
+            (
+                &mut session.rcpt.greylist.delay,
+                "session.rcpt.greylist.delay",
+                "5m",
+            ),
+            (
+                &mut session.rcpt.greylist.window,
+                "session.rcpt.greylist.window",
+                "1d",
+            ),
+            (
+                &mut session.rcpt.greylist.lifetime,
+                "session.rcpt.greylist.lifetime",
+                "35d",
+            ),
+        ] {
+            if let Some(duration) = config.property_or_default::<Duration>(key, default) {
+                *value = duration;
+            }
+        }
         session.data.milters = config
             .sub_keys("session.data.milter", "")
             .map(|s| s.to_string())
@@ -180,6 +414,13 @@ impl SessionConfig {
             .into_iter()
             .filter_map(|id| parse_milter(config, &id, &has_rcpt_vars))
             .collect();
+        session.data.icap = config
+            .sub_keys("session.data.icap", "")
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+            .filter_map(|id| parse_icap(config, &id, &has_rcpt_vars))
+            .collect();
         session.data.pipe_commands = config
             .sub_keys("session.data.pipe", "")
             .map(|s| s.to_string())
@@ -188,7 +429,63 @@ impl SessionConfig {
             .filter_map(|id| parse_pipe(config, &id, &has_rcpt_vars))
             .collect();
         session.throttle = SessionThrottle::parse(config);
+        session.disabled_commands = parse_disabled_commands(config);
+        if let Some(capacity) = config.property_or_default("session.transcript.capacity", "100") {
+            session.transcript.capacity = capacity;
+        }
+        if let Some(max_size) = config.property_or_default("session.transcript.max-size", "65536") {
+            session.transcript.max_size = max_size;
+        }
+        if let Some(path) = config.value("session.data.spool-path") {
+            session.data.spool_path = PathBuf::from(path);
+        }
+        if let Some(max_memory) =
+            config.property_or_default("session.data.spool-max-memory", "1073741824")
+        {
+            session.data.spool_max_memory = max_memory;
+        }
+        if let Some(window) =
+            config.property_or_default::<Duration>("session.data.duplicate.window", "1h")
+        {
+            session.data.duplicate.window = window;
+        }
+        if let Some(max_count) = config.property_or_default("session.data.duplicate.max-count", "5")
+        {
+            session.data.duplicate.max_count = max_count;
+        }
+        if let Some(header) = config.property_or_default::<String>(
+            "session.data.duplicate.header",
+            "X-Duplicate-Message-Id",
+        ) {
+            session.data.duplicate.header = header;
+        }
+        if let Some(max_area) =
+            config.property_or_default("session.data.tracking-pixels.max-area", "4")
+        {
+            session.data.tracking_pixels.max_area = max_area;
+        }
+        if let Some(lookup) = config.value("session.data.tracking-pixels.lookup") {
+            session.data.tracking_pixels.lookup = lookup.to_string();
+        }
 
+        // The Message-ID settings can be either a single value or a table
+        // containing both the 'enable' and 'format' properties
+        let message_id_key = if config.keys.keys().any(|k| {
+            k.strip_prefix("session.data.add-headers.message-id.")
+                .map_or(false, |k| {
+                    k.starts_with("enable") || k.starts_with("format")
+                })
+        }) {
+            "session.data.add-headers.message-id.enable"
+        } else {
+            "session.data.add-headers.message-id"
+        };
+        session.data.message_id_format = parse_message_id_format(config);
+        session.data.header_order = config
+            .values("session.data.add-headers.order")
+            .map(|(_, v)| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .collect();
         for (value, key, token_map) in [
             (&mut session.duration, "session.duration", &has_conn_vars),
             (
@@ -197,6 +494,11 @@ impl SessionConfig {
                 &has_conn_vars,
             ),
             (&mut session.timeout, "session.timeout", &has_conn_vars),
+            (
+                &mut session.transcript.enable,
+                "session.transcript.enable",
+                &has_conn_vars,
+            ),
             (
                 &mut session.connect.script,
                 "session.connect.script",
@@ -210,6 +512,16 @@ impl SessionConfig {
             (
                 &mut session.connect.greeting,
                 "session.connect.greeting",
+                &has_greeting_vars,
+            ),
+            (
+                &mut session.connect.help,
+                "session.connect.help",
+                &has_conn_vars,
+            ),
+            (
+                &mut session.connect.tls_min_version,
+                "session.connect.tls.min-version",
                 &has_conn_vars,
             ),
             (
@@ -277,6 +589,11 @@ impl SessionConfig {
                 "session.ehlo.reject-non-fqdn",
                 &has_conn_vars,
             ),
+            (
+                &mut session.ehlo.require_iprev,
+                "session.ehlo.require-iprev",
+                &has_conn_vars,
+            ),
             (
                 &mut session.auth.directory,
                 "session.auth.directory",
@@ -317,6 +634,16 @@ impl SessionConfig {
                 "session.mail.rewrite",
                 &has_sender_vars,
             ),
+            (
+                &mut session.mail.require_starttls,
+                "session.mail.require-starttls",
+                &has_conn_vars,
+            ),
+            (
+                &mut session.mail.schedule,
+                "session.mail.schedule",
+                &has_sender_vars,
+            ),
             (
                 &mut session.rcpt.script,
                 "session.rcpt.script",
@@ -352,6 +679,21 @@ impl SessionConfig {
                 "session.rcpt.rewrite",
                 &has_rcpt_vars,
             ),
+            (
+                &mut session.rcpt.generate_orcpt,
+                "session.rcpt.generate-orcpt",
+                &has_rcpt_vars,
+            ),
+            (
+                &mut session.rcpt.greylist.enable,
+                "session.rcpt.greylist.enable",
+                &has_rcpt_vars,
+            ),
+            (
+                &mut session.rcpt.rate_limit.rate,
+                "session.rcpt.rate-limit.rate",
+                &has_rcpt_vars,
+            ),
             (
                 &mut session.data.script,
                 "session.data.script",
@@ -367,11 +709,91 @@ impl SessionConfig {
                 "session.data.limits.size",
                 &has_rcpt_vars,
             ),
+            (
+                &mut session.data.max_sender_message_size,
+                "session.data.limits.sender-size",
+                &has_sender_vars,
+            ),
             (
                 &mut session.data.max_received_headers,
                 "session.data.limits.received-headers",
                 &has_rcpt_vars,
             ),
+            (
+                &mut session.data.max_header_size,
+                "session.data.limits.max-header-size",
+                &has_rcpt_vars,
+            ),
+            (
+                &mut session.data.max_headers,
+                "session.data.limits.max-headers",
+                &has_rcpt_vars,
+            ),
+            (
+                &mut session.data.spool_threshold,
+                "session.data.spool-threshold",
+                &has_rcpt_vars,
+            ),
+            (
+                &mut session.data.line_endings,
+                "session.data.bare-line-endings",
+                &line_endings_vars,
+            ),
+            (
+                &mut session.data.duplicate.action,
+                "session.data.duplicate.action",
+                &duplicate_vars,
+            ),
+            (
+                &mut session.data.tracking_pixels.enable,
+                "session.data.tracking-pixels.enable",
+                &has_rcpt_vars,
+            ),
+            (
+                &mut session.data.tracking_pixels.sign,
+                "session.data.tracking-pixels.sign",
+                &has_rcpt_vars,
+            ),
+            (
+                &mut session.data.html_sanitize.enable,
+                "session.data.html-sanitize.enable",
+                &has_rcpt_vars,
+            ),
+            (
+                &mut session.data.html_sanitize.sign,
+                "session.data.html-sanitize.sign",
+                &has_rcpt_vars,
+            ),
+            (
+                &mut session.data.text_alternative.enable,
+                "session.data.text-alternative.enable",
+                &has_rcpt_vars,
+            ),
+            (
+                &mut session.data.text_alternative.sign,
+                "session.data.text-alternative.sign",
+                &has_rcpt_vars,
+            ),
+            (
+                &mut session.data.journal.address,
+                "session.data.journal.address",
+                &has_rcpt_vars,
+            ),
+            (
+                &mut session.data.journal.fatal,
+                "session.data.journal.fatal",
+                &has_rcpt_vars,
+            ),
+            (
+                &mut session.data.deferred_scan.enable,
+                "session.data.deferred-scan.enable",
+                &has_rcpt_vars,
+            ),
+            (
+                &mut session.data.deferred_scan.quarantine,
+                "session.data.deferred-scan.quarantine",
+                &has_rcpt_vars,
+            ),
             (
                 &mut session.data.add_received,
                 "session.data.add-headers.received",
@@ -394,7 +816,7 @@ impl SessionConfig {
             ),
             (
                 &mut session.data.add_message_id,
-                "session.data.add-headers.message-id",
+                message_id_key,
                 &has_rcpt_vars,
             ),
             (
@@ -407,6 +829,8 @@ impl SessionConfig {
                 *value = if_block;
             }
         }
+        TlsVersion::validate_min_version(config, &mut session.connect.tls_min_version);
+        session.mail.schedule_windows = TimeWindow::parse_if_block(config, &session.mail.schedule);
 
         session
     }
@@ -540,9 +964,217 @@ fn parse_milter(config: &mut Config, id: &str, token_map: &TokenMap) -> Option<M
         },
         flags_actions: config.property(("session.data.milter", id, "options.flags.actions")),
         flags_protocol: config.property(("session.data.milter", id, "options.flags.protocol")),
+        circuit_breaker: parse_circuit_breaker(config, "session.data.milter", id),
     })
 }
 
+fn parse_circuit_breaker(config: &mut Config, prefix: &str, id: &str) -> Option<CircuitBreaker> {
+    let max_failures = config
+        .property::<u32>((prefix, id, "options.circuit-breaker.failures"))
+        .filter(|failures| *failures > 0)?;
+
+    Some(CircuitBreaker {
+        id: format!("{prefix}.{id}"),
+        max_failures,
+        cooldown: config
+            .property_or_default((prefix, id, "options.circuit-breaker.cooldown"), "5m")
+            .unwrap_or_else(|| Duration::from_secs(300)),
+        action: config
+            .property_or_default((prefix, id, "options.circuit-breaker.action"), "tempfail")
+            .unwrap_or(CircuitBreakerAction::TempFail),
+    })
+}
+
+fn parse_message_id_format(config: &mut Config) -> Vec<MessageIdToken> {
+    let key = "session.data.add-headers.message-id.format";
+    let format = if let Some(format) = config.value(key) {
+        format.to_string()
+    } else {
+        return vec![];
+    };
+
+    let mut tokens = Vec::new();
+    let mut text = String::new();
+    let mut rest = format.as_str();
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            config.new_parse_error(key, "Unterminated placeholder in Message-ID format");
+            return vec![];
+        };
+        let token = match &rest[start + 1..start + end] {
+            "hostname" => MessageIdToken::Hostname,
+            "domain" => MessageIdToken::SenderDomain,
+            "timestamp" => MessageIdToken::Timestamp,
+            "unique" => MessageIdToken::Unique,
+            "random" => MessageIdToken::Random(16),
+            name => {
+                match name
+                    .strip_prefix("random:")
+                    .and_then(|len| len.parse::<usize>().ok())
+                    .filter(|len| (1..=64).contains(len))
+                {
+                    Some(len) => MessageIdToken::Random(len),
+                    None => {
+                        config.new_parse_error(
+                            key,
+                            format!("Invalid placeholder {name:?} in Message-ID format"),
+                        );
+                        return vec![];
+                    }
+                }
+            }
+        };
+        if !text.is_empty() {
+            tokens.push(MessageIdToken::Text(std::mem::take(&mut text)));
+        }
+        tokens.push(token);
+        rest = &rest[start + end + 1..];
+    }
+    text.push_str(rest);
+    if !text.is_empty() {
+        tokens.push(MessageIdToken::Text(text));
+    }
+
+    // Make sure that generated identifiers are valid and unique
+    let texts = tokens.iter().filter_map(|token| match token {
+        MessageIdToken::Text(text) => Some(text.as_str()),
+        _ => None,
+    });
+    if texts.clone().filter(|text| text.contains('@')).count() != 1
+        || texts
+            .clone()
+            .any(|text| text.contains(|ch: char| ch.is_whitespace() || "<>\"".contains(ch)))
+    {
+        config.new_parse_error(
+            key,
+            "Message-ID format must contain a single '@' and no whitespace or '<', '>', '\"'",
+        );
+        vec![]
+    } else if !tokens
+        .iter()
+        .any(|token| matches!(token, MessageIdToken::Unique | MessageIdToken::Random(16..)))
+    {
+        config.new_parse_error(
+            key,
+            "Message-ID format must include {unique} or a random part of at least 16 characters",
+        );
+        vec![]
+    } else {
+        tokens
+    }
+}
+
+fn parse_icap(config: &mut Config, id: &str, token_map: &TokenMap) -> Option<Icap> {
+    let hostname = config
+        .value_require(("session.data.icap", id, "hostname"))?
+        .to_string();
+    let port = config
+        .property_or_default(("session.data.icap", id, "port"), "1344")
+        .unwrap_or(1344);
+    Some(Icap {
+        enable: IfBlock::try_parse(config, ("session.data.icap", id, "enable"), token_map)
+            .unwrap_or_else(|| {
+                IfBlock::new::<()>(format!("session.data.icap.{id}.enable"), [], "false")
+            }),
+        addrs: format!("{}:{}", hostname, port)
+            .to_socket_addrs()
+            .map_err(|err| {
+                config.new_build_error(
+                    ("session.data.icap", id, "hostname"),
+                    format!("Unable to resolve ICAP hostname {hostname}: {err}"),
+                )
+            })
+            .ok()?
+            .collect(),
+        service: config
+            .value(("session.data.icap", id, "service"))
+            .unwrap_or("respmod")
+            .trim_start_matches('/')
+            .to_string(),
+        hostname,
+        port,
+        timeout_connect: config
+            .property_or_default(("session.data.icap", id, "timeout.connect"), "30s")
+            .unwrap_or_else(|| Duration::from_secs(30)),
+        timeout_data: config
+            .property_or_default(("session.data.icap", id, "timeout.data"), "60s")
+            .unwrap_or_else(|| Duration::from_secs(60)),
+        tls: config
+            .property_or_default(("session.data.icap", id, "tls"), "false")
+            .unwrap_or_default(),
+        tls_allow_invalid_certs: config
+            .property_or_default(("session.data.icap", id, "allow-invalid-certs"), "false")
+            .unwrap_or_default(),
+        tempfail_on_error: config
+            .property_or_default(
+                ("session.data.icap", id, "options.tempfail-on-error"),
+                "true",
+            )
+            .unwrap_or(true),
+        max_response_size: config
+            .property_or_default(
+                ("session.data.icap", id, "options.max-response-size"),
+                "52428800",
+            )
+            .unwrap_or(52428800),
+        infected_action: config
+            .property_or_default(("session.data.icap", id, "options.infected"), "reject")
+            .unwrap_or(IcapInfectedAction::Reject),
+        allow_modifications: config
+            .property_or_default(
+                ("session.data.icap", id, "options.allow-modifications"),
+                "true",
+            )
+            .unwrap_or(true),
+        circuit_breaker: parse_circuit_breaker(config, "session.data.icap", id),
+    })
+}
+
+// Commands required to complete a mail transaction, BDAT is included as its
+// chunk data cannot be discarded once the command is refused.
+const MANDATORY_COMMANDS: [&str; 8] = [
+    "ehlo", "lhlo", "mail", "rcpt", "data", "bdat", "quit", "rset",
+];
+const OPTIONAL_COMMANDS: [&str; 10] = [
+    "helo", "auth", "starttls", "vrfy", "expn", "noop", "help", "etrn", "atrn", "burl",
+];
+
+fn parse_disabled_commands(config: &mut Config) -> AHashMap<String, AHashSet<String>> {
+    let mut disabled_commands = AHashMap::new();
+
+    for listener_id in config
+        .sub_keys("session.disabled-commands", "")
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>()
+    {
+        let mut commands = AHashSet::new();
+        let mut errors = Vec::new();
+        for (key, value) in config.values(("session.disabled-commands", listener_id.as_str())) {
+            let command = value.trim().to_ascii_lowercase();
+            if OPTIONAL_COMMANDS.contains(&command.as_str()) {
+                commands.insert(command);
+            } else if MANDATORY_COMMANDS.contains(&command.as_str()) {
+                errors.push((
+                    key.to_string(),
+                    format!("Command {value:?} is mandatory and cannot be disabled"),
+                ));
+            } else {
+                errors.push((key.to_string(), format!("Unknown SMTP command {value:?}")));
+            }
+        }
+
+        for (key, error) in errors {
+            config.new_parse_error(key, error);
+        }
+        if !commands.is_empty() {
+            disabled_commands.insert(listener_id, commands);
+        }
+    }
+
+    disabled_commands
+}
+
 impl Default for SessionConfig {
     fn default() -> Self {
         Self {
@@ -554,6 +1186,11 @@ impl Default for SessionConfig {
                 mail_from: Default::default(),
                 rcpt_to: Default::default(),
             },
+            transcript: Transcript {
+                enable: IfBlock::new::<()>("session.transcript.enable", [], "false"),
+                capacity: 100,
+                max_size: 65536,
+            },
             connect: Connect {
                 hostname: IfBlock::new::<()>(
                     "server.connect.hostname",
@@ -566,6 +1203,16 @@ impl Default for SessionConfig {
                     [],
                     "'Stalwart ESMTP at your service'",
                 ),
+                help: IfBlock::new::<()>(
+                    "session.connect.help",
+                    [],
+                    "'Help can be found at https://stalw.art/smtp/'",
+                ),
+                tls_min_version: IfBlock::new::<()>(
+                    "session.connect.tls.min-version",
+                    [],
+                    "'TLSv1.2'",
+                ),
             },
             ehlo: Ehlo {
                 script: IfBlock::empty("session.ehlo.script"),
@@ -575,6 +1222,7 @@ impl Default for SessionConfig {
                     [("local_port == 25", "true")],
                     "false",
                 ),
+                require_iprev: IfBlock::new::<()>("session.ehlo.require-iprev", [], "false"),
             },
             auth: Auth {
                 directory: IfBlock::new::<()>(
@@ -605,6 +1253,9 @@ impl Default for SessionConfig {
             mail: Mail {
                 script: IfBlock::empty("session.mail.script"),
                 rewrite: IfBlock::empty("session.mail.rewrite"),
+                require_starttls: IfBlock::new::<()>("session.mail.require-starttls", [], "false"),
+                schedule: IfBlock::empty("session.mail.schedule"),
+                schedule_windows: AHashMap::new(),
             },
             rcpt: Rcpt {
                 script: IfBlock::empty("session.rcpt."),
@@ -622,11 +1273,23 @@ impl Default for SessionConfig {
                     "'*'",
                 ),
                 rewrite: IfBlock::empty("session.rcpt.rewrite"),
+                generate_orcpt: IfBlock::new::<()>("session.rcpt.generate-orcpt", [], "false"),
                 errors_max: IfBlock::new::<()>("session.rcpt.errors.total", [], "5"),
                 errors_wait: IfBlock::new::<()>("session.rcpt.errors.wait", [], "5s"),
                 max_recipients: IfBlock::new::<()>("session.rcpt.max-recipients", [], "100"),
                 catch_all: AddressMapping::Enable,
                 subaddressing: AddressMapping::Enable,
+                greylist: Greylist {
+                    enable: IfBlock::new::<()>("session.rcpt.greylist.enable", [], "false"),
+                    store: None,
+                    delay: Duration::from_secs(5 * 60),
+                    window: Duration::from_secs(86400),
+                    lifetime: Duration::from_secs(35 * 86400),
+                },
+                rate_limit: RcptRateLimit {
+                    rate: IfBlock::empty("session.rcpt.rate-limit.rate"),
+                    store: None,
+                },
             },
             data: Data {
                 #[cfg(feature = "test_mode")]
@@ -639,13 +1302,61 @@ impl Default for SessionConfig {
                 ),
                 pipe_commands: Default::default(),
                 milters: Default::default(),
+                icap: Default::default(),
                 max_messages: IfBlock::new::<()>("session.data.limits.messages", [], "10"),
                 max_message_size: IfBlock::new::<()>("session.data.limits.size", [], "104857600"),
+                max_sender_message_size: IfBlock::empty("session.data.limits.sender-size"),
                 max_received_headers: IfBlock::new::<()>(
                     "session.data.limits.received-headers",
                     [],
                     "50",
                 ),
+                max_header_size: IfBlock::new::<()>(
+                    "session.data.limits.max-header-size",
+                    [],
+                    "1048576",
+                ),
+                max_headers: IfBlock::new::<()>("session.data.limits.max-headers", [], "1000"),
+                spool_threshold: IfBlock::new::<()>("session.data.spool-threshold", [], "false"),
+                spool_path: std::env::temp_dir().join("stalwart-spool"),
+                spool_max_memory: 1073741824,
+                line_endings: IfBlock::new::<LineEndings>(
+                    "session.data.bare-line-endings",
+                    [],
+                    "allow",
+                ),
+                duplicate: DuplicateMessageId {
+                    action: IfBlock::new::<DuplicateAction>(
+                        "session.data.duplicate.action",
+                        [],
+                        "accept",
+                    ),
+                    window: Duration::from_secs(3600),
+                    max_count: 5,
+                    header: "X-Duplicate-Message-Id".to_string(),
+                },
+                tracking_pixels: TrackingPixels {
+                    enable: IfBlock::new::<()>("session.data.tracking-pixels.enable", [], "false"),
+                    sign: IfBlock::empty("session.data.tracking-pixels.sign"),
+                    max_area: 4,
+                    lookup: "spam-trackers".to_string(),
+                },
+                html_sanitize: HtmlSanitize {
+                    enable: IfBlock::new::<()>("session.data.html-sanitize.enable", [], "false"),
+                    sign: IfBlock::empty("session.data.html-sanitize.sign"),
+                },
+                deferred_scan: DeferredScan {
+                    enable: IfBlock::new::<()>("session.data.deferred-scan.enable", [], "false"),
+                    quarantine: IfBlock::empty("session.data.deferred-scan.quarantine"),
+                },
+                text_alternative: TextAlternative {
+                    enable: IfBlock::new::<()>("session.data.text-alternative.enable", [], "false"),
+                    sign: IfBlock::empty("session.data.text-alternative.sign"),
+                },
+                journal: Journal {
+                    address: IfBlock::empty("session.data.journal.address"),
+                    fatal: IfBlock::new::<()>("session.data.journal.fatal", [], "false"),
+                },
                 add_received: IfBlock::new::<()>(
                     "session.data.add-headers.received",
                     [("local_port == 25", "true")],
@@ -676,6 +1387,8 @@ impl Default for SessionConfig {
                     [("local_port == 25", "true")],
                     "false",
                 ),
+                message_id_format: vec![],
+                header_order: vec![],
             },
             extensions: Extensions {
                 pipelining: IfBlock::new::<()>("session.extensions.pipelining", [], "true"),
@@ -713,6 +1426,7 @@ impl Default for SessionConfig {
                     "false",
                 ),
             },
+            disabled_commands: AHashMap::new(),
         }
     }
 }
@@ -856,3 +1570,182 @@ impl ConstantValue for MtPriority {
             .add_constant("nsep", MtPriority::Nsep);
     }
 }
+
+impl ParseValue for IcapInfectedAction {
+    fn parse_value(value: &str) -> utils::config::Result<Self> {
+        match value {
+            "reject" => Ok(IcapInfectedAction::Reject),
+            "quarantine" => Ok(IcapInfectedAction::Quarantine),
+            _ => Err(format!("Invalid ICAP infected action value {:?}.", value)),
+        }
+    }
+}
+
+impl ParseValue for CircuitBreakerAction {
+    fn parse_value(value: &str) -> utils::config::Result<Self> {
+        match value {
+            "accept" => Ok(CircuitBreakerAction::Accept),
+            "tempfail" => Ok(CircuitBreakerAction::TempFail),
+            _ => Err(format!("Invalid circuit breaker action value {:?}.", value)),
+        }
+    }
+}
+
+impl ParseValue for LineEndings {
+    fn parse_value(value: &str) -> utils::config::Result<Self> {
+        match value {
+            "allow" => Ok(LineEndings::Allow),
+            "strict" => Ok(LineEndings::Strict),
+            "lenient" => Ok(LineEndings::Lenient),
+            _ => Err(format!("Invalid bare line endings value {:?}.", value)),
+        }
+    }
+}
+
+impl<'x> TryFrom<Variable<'x>> for LineEndings {
+    type Error = ();
+
+    fn try_from(value: Variable<'x>) -> Result<Self, Self::Error> {
+        match value {
+            Variable::Integer(value) => match value {
+                2 => Ok(LineEndings::Allow),
+                3 => Ok(LineEndings::Strict),
+                4 => Ok(LineEndings::Lenient),
+                _ => Err(()),
+            },
+            Variable::String(value) => LineEndings::parse_value(&value).map_err(|_| ()),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<LineEndings> for Constant {
+    fn from(value: LineEndings) -> Self {
+        Constant::Integer(match value {
+            LineEndings::Allow => 2,
+            LineEndings::Strict => 3,
+            LineEndings::Lenient => 4,
+        })
+    }
+}
+
+impl ConstantValue for LineEndings {
+    fn add_constants(token_map: &mut TokenMap) {
+        token_map
+            .add_constant("allow", LineEndings::Allow)
+            .add_constant("strict", LineEndings::Strict)
+            .add_constant("lenient", LineEndings::Lenient);
+    }
+}
+
+impl TlsVersion {
+    pub fn from_protocol(version: rustls::ProtocolVersion) -> Option<Self> {
+        match version {
+            rustls::ProtocolVersion::TLSv1_2 => Some(TlsVersion::Tls12),
+            rustls::ProtocolVersion::TLSv1_3 => Some(TlsVersion::Tls13),
+            _ => None,
+        }
+    }
+
+    /// Validates the versions of a minimum TLS version block, resetting the
+    /// block to its default if any of them is invalid.
+    pub fn validate_min_version(config: &mut Config, if_block: &mut IfBlock) {
+        let invalid = if_block
+            .if_then
+            .iter()
+            .map(|if_then| &if_then.then)
+            .chain([&if_block.default])
+            .flat_map(|expr| expr.items.iter())
+            .find_map(|item| match item {
+                ExpressionItem::Constant(Constant::String(value)) => {
+                    TlsVersion::parse_value(value).err()
+                }
+                _ => None,
+            });
+
+        if let Some(err) = invalid {
+            config.new_parse_error(if_block.key.as_str(), err);
+            *if_block = IfBlock::new::<()>(if_block.key.clone(), [], "'TLSv1.2'");
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TlsVersion::Tls12 => "TLSv1.2",
+            TlsVersion::Tls13 => "TLSv1.3",
+        }
+    }
+}
+
+impl ParseValue for TlsVersion {
+    fn parse_value(value: &str) -> utils::config::Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "tlsv1.2" | "tls1.2" | "0x0303" => Ok(TlsVersion::Tls12),
+            "tlsv1.3" | "tls1.3" | "0x0304" => Ok(TlsVersion::Tls13),
+            // rustls only negotiates TLS 1.2 and 1.3
+            "sslv3" | "ssl3" | "0x0300" | "tlsv1" | "tlsv1.0" | "tls1.0" | "0x0301" | "tlsv1.1"
+            | "tls1.1" | "0x0302" => Err(format!(
+                "{value} is not supported, the minimum TLS version must be TLSv1.2 or TLSv1.3"
+            )),
+            _ => Err(format!("Invalid TLS version {:?}.", value)),
+        }
+    }
+}
+
+impl<'x> TryFrom<Variable<'x>> for TlsVersion {
+    type Error = ();
+
+    fn try_from(value: Variable<'x>) -> Result<Self, Self::Error> {
+        match value {
+            Variable::String(value) => TlsVersion::parse_value(&value).map_err(|_| ()),
+            _ => Err(()),
+        }
+    }
+}
+
+impl ParseValue for DuplicateAction {
+    fn parse_value(value: &str) -> utils::config::Result<Self> {
+        match value {
+            "accept" => Ok(DuplicateAction::Accept),
+            "tag" => Ok(DuplicateAction::Tag),
+            "reject" => Ok(DuplicateAction::Reject),
+            _ => Err(format!("Invalid duplicate action value {:?}.", value)),
+        }
+    }
+}
+
+impl<'x> TryFrom<Variable<'x>> for DuplicateAction {
+    type Error = ();
+
+    fn try_from(value: Variable<'x>) -> Result<Self, Self::Error> {
+        match value {
+            Variable::Integer(value) => match value {
+                2 => Ok(DuplicateAction::Accept),
+                3 => Ok(DuplicateAction::Tag),
+                4 => Ok(DuplicateAction::Reject),
+                _ => Err(()),
+            },
+            Variable::String(value) => DuplicateAction::parse_value(&value).map_err(|_| ()),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<DuplicateAction> for Constant {
+    fn from(value: DuplicateAction) -> Self {
+        Constant::Integer(match value {
+            DuplicateAction::Accept => 2,
+            DuplicateAction::Tag => 3,
+            DuplicateAction::Reject => 4,
+        })
+    }
+}
+
+impl ConstantValue for DuplicateAction {
+    fn add_constants(token_map: &mut TokenMap) {
+        token_map
+            .add_constant("accept", DuplicateAction::Accept)
+            .add_constant("tag", DuplicateAction::Tag)
+            .add_constant("reject", DuplicateAction::Reject);
+    }
+}
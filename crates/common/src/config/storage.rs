@@ -2,7 +2,8 @@ use std::sync::Arc;
 
 use ahash::AHashMap;
 use directory::Directory;
-use store::{write::purge::PurgeSchedule, BlobStore, FtsStore, LookupStore, Store};
+use store::{write::purge::PurgeSchedule, BlobStore, FtsStore, LookupStore, Store, Stores};
+use utils::config::Config;
 
 use crate::manager::config::ConfigManager;
 
@@ -12,6 +13,7 @@ pub struct Storage {
     pub blob: BlobStore,
     pub fts: FtsStore,
     pub lookup: LookupStore,
+    pub queue: QueueStorage,
     pub directory: Arc<Directory>,
     pub directories: AHashMap<String, Arc<Directory>>,
     pub purge_schedules: Vec<PurgeSchedule>,
@@ -22,3 +24,70 @@ pub struct Storage {
     pub lookups: AHashMap<String, LookupStore>,
     pub ftss: AHashMap<String, FtsStore>,
 }
+
+#[derive(Default, Clone)]
+pub struct QueueStorage {
+    pub data: Store,
+    pub blob: BlobStore,
+}
+
+impl QueueStorage {
+    pub fn parse(config: &mut Config, stores: &Stores, data: &Store, blob: &BlobStore) -> Self {
+        let queue_data = config
+            .value("queue.storage.data")
+            .map(|id| id.to_string())
+            .and_then(|id| {
+                if let Some(store) = stores.stores.get(&id) {
+                    store.clone().into()
+                } else {
+                    config.new_parse_error(
+                        "queue.storage.data",
+                        format!("Data store {id:?} not found"),
+                    );
+                    None
+                }
+            });
+        let queue_blob = config
+            .value("queue.storage.blob")
+            .map(|id| id.to_string())
+            .and_then(|id| {
+                if let Some(store) = stores.blob_stores.get(&id) {
+                    store.clone().into()
+                } else {
+                    config.new_parse_error(
+                        "queue.storage.blob",
+                        format!("Blob store {id:?} not found"),
+                    );
+                    None
+                }
+            });
+
+        // Blobs are purged based on the links held by the data store, so the queue blobs
+        // can only be purged when both the data and blob stores are separate.
+        let separate_data = config
+            .value("queue.storage.data")
+            .map_or(false, |id| Some(id) != config.value("storage.data"));
+        let separate_blob = config
+            .value("queue.storage.blob")
+            .map_or(false, |id| Some(id) != config.value("storage.blob"));
+        if separate_data != separate_blob {
+            config.new_build_error(
+                "queue.storage",
+                "The queue requires both a separate data and blob store, using default stores",
+            );
+        }
+
+        match (queue_data, queue_blob) {
+            (Some(queue_data), Some(queue_blob)) if separate_data == separate_blob => {
+                QueueStorage {
+                    data: queue_data,
+                    blob: queue_blob,
+                }
+            }
+            _ => QueueStorage {
+                data: data.clone(),
+                blob: blob.clone(),
+            },
+        }
+    }
+}
@@ -229,6 +229,12 @@ impl Expression {
     pub fn items(&self) -> &[ExpressionItem] {
         &self.items
     }
+
+    pub fn has_variable(&self, variable: u32) -> bool {
+        self.items
+            .iter()
+            .any(|item| matches!(item, ExpressionItem::Variable(v) if *v == variable))
+    }
 }
 
 impl<'x> Variable<'x> {
@@ -82,6 +82,14 @@ impl IfBlock {
     pub fn is_empty(&self) -> bool {
         self.default.is_empty() && self.if_then.is_empty()
     }
+
+    pub fn has_variable(&self, variable: u32) -> bool {
+        self.default.has_variable(variable)
+            || self
+                .if_then
+                .iter()
+                .any(|i| i.expr.has_variable(variable) || i.then.has_variable(variable))
+    }
 }
 
 impl Expression {
@@ -43,6 +43,7 @@ pub const V_PRIORITY: u32 = 12;
 pub const V_PROTOCOL: u32 = 13;
 pub const V_TLS: u32 = 14;
 pub const V_RECIPIENTS: u32 = 15;
+pub const V_IPREV_RESULT: u32 = 16;
 
 pub const VARIABLES_MAP: &[(&str, u32)] = &[
     ("rcpt", V_RECIPIENT),
@@ -61,6 +62,7 @@ pub const VARIABLES_MAP: &[(&str, u32)] = &[
     ("protocol", V_PROTOCOL),
     ("is_tls", V_TLS),
     ("recipients", V_RECIPIENTS),
+    ("iprev_result", V_IPREV_RESULT),
 ];
 
 use regex::Regex;
@@ -365,6 +365,7 @@ impl TokenMap {
             V_PRIORITY,
             V_PROTOCOL,
             V_TLS,
+            V_IPREV_RESULT,
         ])
     }
 
@@ -64,6 +64,12 @@ pub fn fn_dedup<'x>(_: &'x Context<'x>, v: Vec<Variable>) -> Variable {
 }
 
 pub fn fn_cosine_similarity<'x>(_: &'x Context<'x>, v: Vec<Variable>) -> Variable {
+    cosine_similarity(v).into()
+}
+
+/// Returns the cosine similarity between the word frequencies of two arrays,
+/// or the character frequencies of two strings.
+pub fn cosine_similarity(v: Vec<Variable>) -> f64 {
     let mut word_freq: HashMap<Variable, [u32; 2]> = HashMap::new();
 
     for (idx, var) in v.into_iter().enumerate() {
@@ -96,7 +102,6 @@ pub fn fn_cosine_similarity<'x>(_: &'x Context<'x>, v: Vec<Variable>) -> Variabl
     } else {
         0.0
     }
-    .into()
 }
 
 pub fn fn_jaccard_similarity<'x>(_: &'x Context<'x>, v: Vec<Variable>) -> Variable {
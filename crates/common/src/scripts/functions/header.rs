@@ -21,7 +21,10 @@
  * for more details.
 */
 
-use mail_parser::{parsers::fields::thread::thread_name, HeaderName, HeaderValue, MimeHeaders};
+use mail_parser::{
+    parsers::{fields::thread::thread_name, MessageStream},
+    HeaderName, HeaderValue, Message, MessagePart, MimeHeaders, PartType,
+};
 use sieve::{compiler::ReceivedPart, runtime::Variable, Context};
 
 use super::ApplyString;
@@ -78,6 +81,613 @@ pub fn fn_mime_part_len<'x>(ctx: &'x Context<'x>, _: Vec<Variable>) -> Variable
         .into()
 }
 
+/// Returns `[total_parts, max_depth, attachments, text_parts, inline_images]`,
+/// including the parts of any nested messages.
+pub fn fn_mime_stats<'x>(ctx: &'x Context<'x>, _: Vec<Variable>) -> Variable {
+    let message = ctx.message();
+    let mut stats = MimeStats {
+        attachments: message.attachments.len(),
+        ..Default::default()
+    };
+    walk_parts(message, &mut |_, part, depth| {
+        stats.add_part(part, depth);
+        true
+    });
+
+    Variable::Array(
+        vec![
+            Variable::from(stats.parts),
+            Variable::from(stats.max_depth),
+            Variable::from(stats.attachments),
+            Variable::from(stats.text_parts),
+            Variable::from(stats.inline_images),
+        ]
+        .into(),
+    )
+}
+
+#[derive(Default)]
+struct MimeStats {
+    parts: usize,
+    max_depth: usize,
+    attachments: usize,
+    text_parts: usize,
+    inline_images: usize,
+}
+
+impl MimeStats {
+    fn add_part(&mut self, part: &MessagePart<'_>, depth: usize) {
+        self.parts += 1;
+        self.max_depth = std::cmp::max(self.max_depth, depth);
+
+        match &part.body {
+            PartType::Text(_) | PartType::Html(_) => {
+                self.text_parts += 1;
+            }
+            PartType::Binary(_) | PartType::InlineBinary(_) => {
+                let is_image = part
+                    .content_type()
+                    .map_or(false, |ct| ct.ctype().eq_ignore_ascii_case("image"));
+                let is_inline = matches!(part.body, PartType::InlineBinary(_))
+                    || part.content_id().is_some()
+                    || part
+                        .content_disposition()
+                        .map_or(false, |cd| cd.ctype().eq_ignore_ascii_case("inline"));
+                if is_image && is_inline {
+                    self.inline_images += 1;
+                }
+            }
+            PartType::Message(nested) => {
+                self.attachments += nested.attachments.len();
+            }
+            PartType::Multipart(_) => (),
+        }
+    }
+}
+
+/// Visits every part of `message` depth first, descending into multiparts
+/// and nested messages. `visit` receives the message owning the part and the
+/// nesting depth, and returns `false` to stop the walk.
+fn walk_parts<'x>(
+    message: &'x Message<'x>,
+    visit: &mut impl FnMut(&'x Message<'x>, &'x MessagePart<'x>, usize) -> bool,
+) -> bool {
+    walk_part(message, 0, 0, visit)
+}
+
+fn walk_part<'x>(
+    message: &'x Message<'x>,
+    part_id: usize,
+    depth: usize,
+    visit: &mut impl FnMut(&'x Message<'x>, &'x MessagePart<'x>, usize) -> bool,
+) -> bool {
+    let Some(part) = message.parts.get(part_id) else {
+        return true;
+    };
+    if !visit(message, part, depth) {
+        return false;
+    }
+
+    match &part.body {
+        PartType::Message(nested) => walk_part(nested, 0, depth + 1, visit),
+        PartType::Multipart(children) => children
+            .iter()
+            // Part ids always increase, which rules out loops
+            .filter(|&&child_id| child_id > part_id)
+            .all(|&child_id| walk_part(message, child_id, depth + 1, visit)),
+        _ => true,
+    }
+}
+
+/// Returns `[boundaries, anomalies]`, where `boundaries` lists the multipart
+/// boundaries in the order they appear (including nested messages) and
+/// `anomalies` contains `duplicate_boundary` and/or `invalid_syntax`.
+pub fn fn_mime_boundaries<'x>(ctx: &'x Context<'x>, _: Vec<Variable>) -> Variable {
+    let mut boundaries = MimeBoundaries::default();
+    walk_parts(ctx.message(), &mut |_, part, depth| {
+        boundaries.add_part(part, depth);
+        true
+    });
+
+    let mut anomalies = Vec::new();
+    if boundaries.duplicate {
+        anomalies.push(Variable::from("duplicate_boundary"));
+    }
+    if boundaries.invalid {
+        anomalies.push(Variable::from("invalid_syntax"));
+    }
+
+    Variable::Array(
+        vec![
+            Variable::Array(
+                boundaries
+                    .boundaries
+                    .into_iter()
+                    .map(|(boundary, _)| Variable::from(boundary))
+                    .collect::<Vec<_>>()
+                    .into(),
+            ),
+            Variable::Array(anomalies.into()),
+        ]
+        .into(),
+    )
+}
+
+#[derive(Default)]
+struct MimeBoundaries {
+    boundaries: Vec<(String, usize)>,
+    duplicate: bool,
+    invalid: bool,
+}
+
+impl MimeBoundaries {
+    fn add_part(&mut self, part: &MessagePart<'_>, depth: usize) {
+        if let PartType::Multipart(_) = &part.body {
+            if let Some(boundary) = part.content_type().and_then(|ct| ct.attribute("boundary")) {
+                self.add_boundary(boundary, depth);
+            }
+        }
+    }
+
+    fn add_boundary(&mut self, boundary: &str, depth: usize) {
+        if self
+            .boundaries
+            .iter()
+            .any(|(b, d)| *d != depth && b == boundary)
+        {
+            self.duplicate = true;
+        }
+        if !is_valid_boundary(boundary) {
+            self.invalid = true;
+        }
+        self.boundaries.push((boundary.to_string(), depth));
+    }
+}
+
+/// Validates a boundary against the RFC 2046 `boundary` syntax.
+fn is_valid_boundary(boundary: &str) -> bool {
+    (1..=70).contains(&boundary.len())
+        && !boundary.ends_with(' ')
+        && boundary.bytes().all(|ch| {
+            ch.is_ascii_alphanumeric()
+                || matches!(
+                    ch,
+                    b'\''
+                        | b'('
+                        | b')'
+                        | b'+'
+                        | b'_'
+                        | b','
+                        | b'-'
+                        | b'.'
+                        | b'/'
+                        | b':'
+                        | b'='
+                        | b'?'
+                        | b' '
+                )
+        })
+}
+
+/// Returns the anomalies found in `multipart/alternative` parts (including nested
+/// messages): `alternative_order` when a part is less rich than the one before it
+/// (RFC 2046 requires increasing faithfulness) and `duplicate_alternative` when
+/// two alternatives share the same content type.
+pub fn fn_part_order_anomaly<'x>(ctx: &'x Context<'x>, _: Vec<Variable>) -> Variable {
+    let mut anomalies = PartOrderAnomalies::default();
+    walk_parts(ctx.message(), &mut |message, part, _| {
+        anomalies.add_part(message, part);
+        true
+    });
+
+    let mut result = Vec::new();
+    if anomalies.out_of_order {
+        result.push(Variable::from("alternative_order"));
+    }
+    if anomalies.duplicate {
+        result.push(Variable::from("duplicate_alternative"));
+    }
+
+    Variable::Array(result.into())
+}
+
+#[derive(Default)]
+struct PartOrderAnomalies {
+    out_of_order: bool,
+    duplicate: bool,
+}
+
+impl PartOrderAnomalies {
+    fn add_part(&mut self, message: &Message<'_>, part: &MessagePart<'_>) {
+        if let PartType::Multipart(children) = &part.body {
+            if part.content_type().map_or(false, |ct| {
+                ct.subtype()
+                    .map_or(false, |st| st.eq_ignore_ascii_case("alternative"))
+            }) {
+                self.add_alternatives(message, children);
+            }
+        }
+    }
+
+    fn add_alternatives(&mut self, message: &Message<'_>, children: &[usize]) {
+        let mut content_types: Vec<String> = Vec::with_capacity(children.len());
+        let mut last_rank = 0;
+
+        for part in children
+            .iter()
+            .filter_map(|&child_id| message.parts.get(child_id))
+        {
+            let (ctype, subtype) = part.content_type().map_or_else(
+                || ("text".to_string(), "plain".to_string()),
+                |ct| {
+                    (
+                        ct.ctype().to_ascii_lowercase(),
+                        ct.subtype().unwrap_or_default().to_ascii_lowercase(),
+                    )
+                },
+            );
+
+            // Types without a known richness, such as calendar invites, are not ranked
+            if let Some(rank) = alternative_rank(&ctype, &subtype) {
+                if rank < last_rank {
+                    self.out_of_order = true;
+                }
+                last_rank = rank;
+            }
+
+            let content_type = format!("{ctype}/{subtype}");
+            if content_types.contains(&content_type) {
+                self.duplicate = true;
+            } else {
+                content_types.push(content_type);
+            }
+        }
+    }
+}
+
+/// Ranks the richness of an alternative, from plain text to HTML.
+fn alternative_rank(ctype: &str, subtype: &str) -> Option<u32> {
+    match (ctype, subtype) {
+        ("text", "plain") => Some(0),
+        ("text", "enriched" | "richtext") => Some(1),
+        ("text", "html") | ("multipart", "related") => Some(2),
+        _ => None,
+    }
+}
+
+/// Returns `[signed_parts, anomalies]`, where `signed_parts` is the number of
+/// `multipart/signed` parts (including nested messages) and `anomalies` lists
+/// `missing_protocol`, `unknown_protocol`, `missing_micalg`, `invalid_part_count`
+/// and/or `missing_signature`. The signatures themselves are not verified.
+pub fn fn_signed_structure<'x>(ctx: &'x Context<'x>, _: Vec<Variable>) -> Variable {
+    let mut signed = SignedStructure::default();
+    walk_parts(ctx.message(), &mut |message, part, _| {
+        signed.add_part(message, part);
+        true
+    });
+
+    Variable::Array(
+        vec![
+            Variable::from(signed.parts),
+            Variable::Array(
+                signed
+                    .anomalies
+                    .into_iter()
+                    .map(Variable::from)
+                    .collect::<Vec<_>>()
+                    .into(),
+            ),
+        ]
+        .into(),
+    )
+}
+
+#[derive(Default)]
+struct SignedStructure {
+    parts: usize,
+    anomalies: Vec<&'static str>,
+}
+
+impl SignedStructure {
+    fn add_part(&mut self, message: &Message<'_>, part: &MessagePart<'_>) {
+        let PartType::Multipart(children) = &part.body else {
+            return;
+        };
+        if let Some(ct) = part.content_type().filter(|ct| {
+            ct.ctype().eq_ignore_ascii_case("multipart")
+                && ct
+                    .subtype()
+                    .map_or(false, |st| st.eq_ignore_ascii_case("signed"))
+        }) {
+            self.parts += 1;
+            self.check_signed(
+                message,
+                ct.attribute("protocol"),
+                ct.attribute("micalg"),
+                children,
+            );
+        }
+    }
+
+    fn check_signed(
+        &mut self,
+        message: &Message<'_>,
+        protocol: Option<&str>,
+        micalg: Option<&str>,
+        children: &[usize],
+    ) {
+        // RFC 1847 requires both the protocol and micalg parameters
+        match protocol {
+            Some(protocol)
+                if [
+                    "application/pkcs7-signature",
+                    "application/x-pkcs7-signature",
+                    "application/pgp-signature",
+                ]
+                .iter()
+                .any(|p| p.eq_ignore_ascii_case(protocol.trim())) => {}
+            Some(_) => self.add_anomaly("unknown_protocol"),
+            None => self.add_anomaly("missing_protocol"),
+        }
+        if micalg.map_or(true, |m| m.trim().is_empty()) {
+            self.add_anomaly("missing_micalg");
+        }
+        if children.len() != 2 {
+            self.add_anomaly("invalid_part_count");
+        }
+
+        // The signature must be the second part and match the declared protocol
+        let has_signature = children
+            .get(1)
+            .and_then(|&child_id| message.parts.get(child_id))
+            .map_or(false, |signature| {
+                let ctype = signature
+                    .content_type()
+                    .map(|ct| format!("{}/{}", ct.ctype(), ct.subtype().unwrap_or_default()));
+                !signature.contents().is_empty()
+                    && matches!((ctype, protocol), (Some(ctype), Some(protocol))
+                        if ctype.eq_ignore_ascii_case(protocol.trim()))
+            });
+        if !has_signature {
+            self.add_anomaly("missing_signature");
+        }
+    }
+
+    fn add_anomaly(&mut self, anomaly: &'static str) {
+        if !self.anomalies.contains(&anomaly) {
+            self.anomalies.push(anomaly);
+        }
+    }
+}
+
+/// Returns the type of encrypted content found in the message (including nested
+/// messages): `pgp_mime` for `multipart/encrypted` parts, `smime` for S/MIME
+/// enveloped data or `pgp_inline` for armored PGP blocks in text parts. An empty
+/// string is returned when the message contains no encrypted content.
+pub fn fn_has_encrypted_content<'x>(ctx: &'x Context<'x>, _: Vec<Variable>) -> Variable {
+    let mut result = None;
+    walk_parts(ctx.message(), &mut |_, part, _| {
+        result = encrypted_content(part);
+        result.is_none()
+    });
+
+    result.unwrap_or_default().into()
+}
+
+fn encrypted_content(part: &MessagePart<'_>) -> Option<&'static str> {
+    let content_type = part.content_type();
+    let is_type = |ctype: &str, subtypes: &[&str]| {
+        content_type.map_or(false, |ct| {
+            ct.ctype().eq_ignore_ascii_case(ctype)
+                && ct.subtype().map_or(false, |st| {
+                    subtypes.iter().any(|s| st.eq_ignore_ascii_case(s))
+                })
+        })
+    };
+
+    match &part.body {
+        PartType::Text(text) | PartType::Html(text)
+            if text.contains("-----BEGIN PGP MESSAGE-----") =>
+        {
+            Some("pgp_inline")
+        }
+        PartType::Binary(_) | PartType::InlineBinary(_)
+            if is_type("application", &["pkcs7-mime", "x-pkcs7-mime"])
+                && content_type
+                    .and_then(|ct| ct.attribute("smime-type"))
+                    .map_or(true, |st| st.eq_ignore_ascii_case("enveloped-data")) =>
+        {
+            Some("smime")
+        }
+        PartType::Multipart(_) if is_type("multipart", &["encrypted"]) => Some("pgp_mime"),
+        _ => None,
+    }
+}
+
+/// Returns a `[filename, flags]` pair for each attachment (including those of
+/// nested messages) with a suspicious name, where `flags` contains
+/// `double_extension`, `rtl_override` and/or `long_name`. Names are checked
+/// after decoding any RFC 2231 or RFC 2047 encoding.
+pub fn fn_filename_risk<'x>(ctx: &'x Context<'x>, _: Vec<Variable>) -> Variable {
+    let mut results = Vec::new();
+    filename_risk(ctx.message(), &mut results);
+    Variable::Array(results.into())
+}
+
+const MAX_FILENAME_LEN: usize = 100;
+const DOCUMENT_EXTENSIONS: &[&str] = &[
+    "pdf", "doc", "docx", "xls", "xlsx", "ppt", "pptx", "odt", "ods", "rtf", "txt", "csv", "jpg",
+    "jpeg", "png", "gif", "htm", "html", "zip",
+];
+const TRAILING_EXTENSIONS: &[&str] = &["gz", "bz2", "xz", "zip", "7z", "rar", "sig", "asc"];
+
+fn filename_risk(message: &Message<'_>, results: &mut Vec<Variable>) {
+    for part in &message.parts {
+        if let PartType::Message(nested) = &part.body {
+            filename_risk(nested, results);
+        }
+        let Some(name) = part.attachment_name() else {
+            continue;
+        };
+
+        let mut flags = Vec::new();
+        let mut extensions = name
+            .rsplit('.')
+            .map(|ext| ext.trim().to_ascii_lowercase())
+            .take(3);
+        if let (Some(last), Some(prev), Some(_)) =
+            (extensions.next(), extensions.next(), extensions.next())
+        {
+            if DOCUMENT_EXTENSIONS.contains(&prev.as_str())
+                && !DOCUMENT_EXTENSIONS.contains(&last.as_str())
+                && !TRAILING_EXTENSIONS.contains(&last.as_str())
+            {
+                flags.push(Variable::from("double_extension"));
+            }
+        }
+        if name
+            .chars()
+            .any(|ch| matches!(ch, '\u{202E}' | '\u{202B}' | '\u{2067}'))
+        {
+            flags.push(Variable::from("rtl_override"));
+        }
+        if name.chars().count() > MAX_FILENAME_LEN {
+            flags.push(Variable::from("long_name"));
+        }
+
+        if !flags.is_empty() {
+            results.push(Variable::Array(
+                vec![
+                    Variable::from(name.to_string()),
+                    Variable::Array(flags.into()),
+                ]
+                .into(),
+            ));
+        }
+    }
+}
+
+/// Returns the `Authentication-Results` headers of the message whose `authserv-id`
+/// does not match the hostname passed as an argument.
+pub fn fn_foreign_auth_results<'x>(ctx: &'x Context<'x>, v: Vec<Variable>) -> Variable {
+    let hostname = v[0].to_string();
+    let hostname = hostname.trim().trim_end_matches('.');
+    let raw_message = ctx.message().raw_message();
+
+    ctx.message()
+        .root_part()
+        .headers()
+        .iter()
+        .filter(|header| {
+            header
+                .name
+                .as_str()
+                .eq_ignore_ascii_case("Authentication-Results")
+        })
+        .filter_map(|header| {
+            let value = raw_message
+                .get(header.offset_start()..header.offset_end())
+                .map(String::from_utf8_lossy)?;
+            if !authserv_id(&value)
+                .trim_end_matches('.')
+                .eq_ignore_ascii_case(hostname)
+            {
+                Some(Variable::from(value.trim().to_string()))
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>()
+        .into()
+}
+
+fn authserv_id(value: &str) -> &str {
+    let mut value = value.trim_start();
+
+    // Skip leading comments
+    while let Some(comment) = value.strip_prefix('(') {
+        value = comment
+            .split_once(')')
+            .map_or("", |(_, rest)| rest)
+            .trim_start();
+    }
+
+    value
+        .split(|ch: char| ch == ';' || ch == '(' || ch.is_whitespace())
+        .next()
+        .unwrap_or_default()
+}
+
+/// Returns `[over_folded, excessive_whitespace, duplicated]`, the number of top-level
+/// headers containing near-empty continuation lines, the number of headers with
+/// excessive leading whitespace and the number of repeated single-instance headers.
+pub fn fn_header_anomalies<'x>(ctx: &'x Context<'x>, _: Vec<Variable>) -> Variable {
+    const MAX_LEADING_WHITESPACE: usize = 2;
+    const SINGLE_INSTANCE: [&str; 11] = [
+        "Date",
+        "From",
+        "Sender",
+        "Reply-To",
+        "To",
+        "Cc",
+        "Bcc",
+        "Message-ID",
+        "In-Reply-To",
+        "References",
+        "Subject",
+    ];
+
+    let raw_message = ctx.message().raw_message();
+    let mut over_folded: usize = 0;
+    let mut excessive_whitespace: usize = 0;
+    let mut seen = [0usize; SINGLE_INSTANCE.len()];
+
+    for header in ctx.message().root_part().headers() {
+        if let Some(idx) = SINGLE_INSTANCE
+            .iter()
+            .position(|name| header.name.as_str().eq_ignore_ascii_case(name))
+        {
+            seen[idx] += 1;
+        }
+
+        let value = raw_message
+            .get(header.offset_start()..header.offset_end())
+            .unwrap_or_default();
+        let value = value.strip_suffix(b"\n").unwrap_or(value);
+        if value.split(|&ch| ch == b'\n').skip(1).any(|line| {
+            line.iter()
+                .filter(|ch| !ch.is_ascii_whitespace())
+                .take(2)
+                .count()
+                < 2
+        }) {
+            over_folded += 1;
+        }
+        if value
+            .iter()
+            .take_while(|ch| ch.is_ascii_whitespace())
+            .filter(|ch| !matches!(ch, b'\r' | b'\n'))
+            .count()
+            > MAX_LEADING_WHITESPACE
+        {
+            excessive_whitespace += 1;
+        }
+    }
+
+    Variable::Array(
+        vec![
+            Variable::from(over_folded),
+            Variable::from(excessive_whitespace),
+            Variable::from(
+                seen.iter()
+                    .map(|&count| count.saturating_sub(1))
+                    .sum::<usize>(),
+            ),
+        ]
+        .into(),
+    )
+}
+
 pub fn fn_thread_name<'x>(_: &'x Context<'x>, v: Vec<Variable>) -> Variable {
     v[0].transform(|s| thread_name(s).into())
 }
@@ -111,3 +721,67 @@ pub fn fn_is_header_utf8_valid<'x>(ctx: &'x Context<'x>, v: Vec<Variable>) -> Va
         })
         .unwrap_or(Variable::Integer(1))
 }
+
+/// Returns the names of the top-level headers containing an injection attempt:
+/// bare CR characters, line breaks hidden inside RFC 2047 encoded words or
+/// URL-encoded line breaks, as produced by web forms that pass user input
+/// straight into a header field.
+pub fn fn_header_injection<'x>(ctx: &'x Context<'x>, _: Vec<Variable>) -> Variable {
+    let raw_message = ctx.message().raw_message();
+    let mut flagged: Vec<Variable> = Vec::new();
+
+    for header in ctx.message().root_part().headers() {
+        let name = header.name.as_str();
+        if flagged
+            .iter()
+            .any(|v| v.to_string().eq_ignore_ascii_case(name))
+        {
+            continue;
+        }
+
+        // Line breaks other than folding in the raw value
+        let raw_value = raw_message
+            .get(header.offset_start()..header.offset_end())
+            .unwrap_or_default();
+        let mut is_injection = raw_value
+            .iter()
+            .enumerate()
+            .any(|(pos, &ch)| ch == b'\r' && raw_value.get(pos + 1) != Some(&b'\n'));
+
+        // Line breaks in the decoded value, custom headers are stored undecoded
+        if !is_injection {
+            let decoded;
+            let value = if matches!(header.name, HeaderName::Other(_)) {
+                decoded = MessageStream::new(raw_value).parse_unstructured();
+                &decoded
+            } else {
+                &header.value
+            };
+            is_injection = match value {
+                HeaderValue::Text(text) => has_line_break(text),
+                HeaderValue::TextList(list) => list.iter().any(|text| has_line_break(text)),
+                HeaderValue::Address(address) => address.iter().any(|addr| {
+                    addr.name().map_or(false, has_line_break)
+                        || addr.address().map_or(false, has_line_break)
+                }),
+                _ => false,
+            };
+        }
+
+        // URL-encoded line breaks, excluding headers that contain URLs
+        if !is_injection && !name.to_ascii_lowercase().starts_with("list-") {
+            let raw_value = String::from_utf8_lossy(raw_value).to_ascii_lowercase();
+            is_injection = raw_value.contains("%0a") || raw_value.contains("%0d");
+        }
+
+        if is_injection {
+            flagged.push(Variable::from(name.to_string()));
+        }
+    }
+
+    Variable::Array(flagged.into())
+}
+
+fn has_line_break(text: &str) -> bool {
+    text.contains(['\r', '\n'])
+}
@@ -21,8 +21,16 @@
  * for more details.
 */
 
+use image::imageops::FilterType;
+use mail_parser::{decoders::html::html_to_text, MimeHeaders, PartType};
 use sieve::{runtime::Variable, Context};
 
+use super::html::{get_attribute, html_img_area, html_to_tokens};
+
+const IMAGE_MAX_COUNT: usize = 10;
+const IMAGE_MAX_DIMENSION: usize = 2048;
+const IMAGE_ONLY_MAX_TEXT: usize = 100;
+
 pub fn fn_img_metadata<'x>(ctx: &'x Context<'x>, v: Vec<Variable>) -> Variable {
     ctx.message()
         .part(ctx.part())
@@ -72,3 +80,183 @@ pub fn fn_img_metadata<'x>(ctx: &'x Context<'x>, v: Vec<Variable>) -> Variable {
         })
         .unwrap_or_default()
 }
+
+/// Decodes the QR codes found in the image attachments of the message and
+/// returns the URLs they contain.
+pub fn fn_qr_decode<'x>(ctx: &'x Context<'x>, _: Vec<Variable>) -> Variable {
+    let mut urls = Vec::new();
+
+    for bytes in decodable_images(ctx, true) {
+        let image = match image::load_from_memory(bytes) {
+            Ok(image) => image.to_luma8(),
+            Err(_) => continue,
+        };
+        let mut image = rqrr::PreparedImage::prepare_from_greyscale(
+            image.width() as usize,
+            image.height() as usize,
+            |x, y| image.get_pixel(x as u32, y as u32).0[0],
+        );
+        for grid in image.detect_grids() {
+            if let Ok((_, content)) = grid.decode() {
+                let content = content.trim();
+                if content.contains("://") || content.starts_with("www.") {
+                    let url = Variable::from(content.to_string());
+                    if !urls.contains(&url) {
+                        urls.push(url);
+                    }
+                }
+            }
+        }
+    }
+
+    urls.into()
+}
+
+/// Returns the perceptual hashes of the image parts of the message as
+/// hexadecimal strings, which can be compared using `phash_distance`.
+pub fn fn_image_phash<'x>(ctx: &'x Context<'x>, _: Vec<Variable>) -> Variable {
+    decodable_images(ctx, false)
+        .filter_map(|bytes| image::load_from_memory(bytes).ok())
+        .map(|image| Variable::from(format!("{:016x}", perceptual_hash(&image))))
+        .collect::<Vec<_>>()
+        .into()
+}
+
+/// Returns the number of differing bits between two perceptual hashes,
+/// or -1 if either hash is invalid.
+pub fn fn_phash_distance<'x>(_: &'x Context<'x>, v: Vec<Variable>) -> Variable {
+    match (
+        u64::from_str_radix(v[0].to_string().as_ref(), 16),
+        u64::from_str_radix(v[1].to_string().as_ref(), 16),
+    ) {
+        (Ok(a), Ok(b)) => Variable::Integer((a ^ b).count_ones() as i64),
+        _ => Variable::Integer(-1),
+    }
+}
+
+/// Returns the ratio of image bytes to text bytes in the message and whether
+/// it is effectively image-only. The text is taken from the richest
+/// displayable body, so the HTML alternative is preferred over the plain text
+/// one. Remote images referenced from the HTML body are accounted for by
+/// their displayed area.
+pub fn fn_image_text_ratio<'x>(ctx: &'x Context<'x>, _: Vec<Variable>) -> Variable {
+    let message = ctx.message();
+    let mut image_len = message
+        .parts
+        .iter()
+        .filter(|p| {
+            p.content_type()
+                .map_or(false, |ct| ct.ctype().eq_ignore_ascii_case("image"))
+        })
+        .map(|p| p.contents().len())
+        .sum::<usize>();
+    let mut text_len = 0;
+
+    for part in message.html_body.iter().filter_map(|id| message.part(*id)) {
+        let text = match &part.body {
+            PartType::Html(html) => {
+                let remote_images = html_to_tokens(html)
+                    .into_iter()
+                    .filter(|tag| {
+                        let tag = tag.to_string();
+                        tag.starts_with("<img")
+                            && get_attribute(tag.as_ref(), "src").map_or(false, |src| {
+                                !src.trim_start().to_ascii_lowercase().starts_with("cid:")
+                            })
+                    })
+                    .collect::<Vec<_>>();
+                image_len += html_img_area(&remote_images) as usize;
+                html_to_text(html)
+            }
+            PartType::Text(text) => text.to_string(),
+            _ => continue,
+        };
+        text_len += text.chars().filter(|ch| !ch.is_whitespace()).count();
+    }
+
+    Variable::Array(
+        vec![
+            Variable::Float(image_len as f64 / text_len.max(1) as f64),
+            Variable::from(image_len > 0 && text_len < IMAGE_ONLY_MAX_TEXT),
+        ]
+        .into(),
+    )
+}
+
+/// Returns the contents of the image parts that can be decoded, optionally
+/// restricted to attachments. Only PNG, JPEG, GIF and BMP images are
+/// considered, images exceeding the maximum dimensions are skipped and at
+/// most `IMAGE_MAX_COUNT` images are returned.
+fn decodable_images<'x>(
+    ctx: &'x Context<'x>,
+    attachments_only: bool,
+) -> impl Iterator<Item = &'x [u8]> {
+    let message = ctx.message();
+    message
+        .parts
+        .iter()
+        .enumerate()
+        .filter(move |(part_id, part)| {
+            !attachments_only
+                || (message.attachments.contains(part_id)
+                    && !matches!(part.body, PartType::Message(_)))
+        })
+        .map(|(_, part)| part.contents())
+        .filter(|bytes| {
+            matches!(
+                imagesize::image_type(bytes),
+                Ok(imagesize::ImageType::Png
+                    | imagesize::ImageType::Jpeg
+                    | imagesize::ImageType::Gif
+                    | imagesize::ImageType::Bmp)
+            ) && imagesize::blob_size(bytes).map_or(false, |s| {
+                s.width <= IMAGE_MAX_DIMENSION && s.height <= IMAGE_MAX_DIMENSION
+            })
+        })
+        .take(IMAGE_MAX_COUNT)
+}
+
+/// DCT based perceptual hash: the image is reduced to 32x32 grayscale pixels
+/// and each of the 64 lowest frequency coefficients is compared against
+/// their median.
+fn perceptual_hash(image: &image::DynamicImage) -> u64 {
+    const SIZE: usize = 32;
+    const HASH_SIZE: usize = 8;
+
+    let pixels = image
+        .resize_exact(SIZE as u32, SIZE as u32, FilterType::Triangle)
+        .to_luma8()
+        .pixels()
+        .map(|p| p.0[0] as f64)
+        .collect::<Vec<_>>();
+
+    let mut cos_table = [[0f64; SIZE]; HASH_SIZE];
+    for (u, row) in cos_table.iter_mut().enumerate() {
+        for (x, value) in row.iter_mut().enumerate() {
+            *value = (((2 * x + 1) * u) as f64 * std::f64::consts::PI / (2 * SIZE) as f64).cos();
+        }
+    }
+
+    let mut dct = [0f64; HASH_SIZE * HASH_SIZE];
+    for v in 0..HASH_SIZE {
+        for u in 0..HASH_SIZE {
+            let mut sum = 0.0;
+            for y in 0..SIZE {
+                for x in 0..SIZE {
+                    sum += pixels[y * SIZE + x] * cos_table[u][x] * cos_table[v][y];
+                }
+            }
+            dct[v * HASH_SIZE + u] = sum;
+        }
+    }
+
+    // Exclude the DC coefficient when computing the median
+    let mut sorted = dct[1..].to_vec();
+    sorted.sort_unstable_by(|a, b| a.total_cmp(b));
+    let median = sorted[sorted.len() / 2];
+
+    dct.iter()
+        .enumerate()
+        .filter(|(_, value)| **value > median)
+        .fold(0u64, |hash, (pos, _)| hash | (1 << pos))
+}
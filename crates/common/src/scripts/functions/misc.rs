@@ -111,6 +111,42 @@ pub fn fn_hash<'x>(_: &'x Context<'x>, v: Vec<Variable>) -> Variable {
     })
 }
 
+pub fn fn_message_hash<'x>(ctx: &'x Context<'x>, v: Vec<Variable>) -> Variable {
+    use sha1::Digest;
+    let raw_message = ctx.message().raw_message();
+    let raw_message = match v[1].to_string().as_ref() {
+        "full" | "" => raw_message,
+        "body" => raw_message
+            .get(ctx.message().root_part().raw_body_offset()..)
+            .unwrap_or_default(),
+        _ => return Variable::default(),
+    };
+
+    // Canonicalize line endings and ignore trailing empty lines
+    let mut canonical = Vec::with_capacity(raw_message.len());
+    let mut last_ch = 0;
+    for &ch in raw_message {
+        if ch == b'\n' && last_ch != b'\r' {
+            canonical.push(b'\r');
+        }
+        canonical.push(ch);
+        last_ch = ch;
+    }
+    while canonical.ends_with(b"\r\n") {
+        canonical.truncate(canonical.len() - 2);
+    }
+
+    match v[0].to_string().as_ref() {
+        "sha256" => {
+            let mut hasher = Sha256::new();
+            hasher.update(&canonical);
+            format!("{:x}", hasher.finalize()).into()
+        }
+        "blake3" => blake3::hash(&canonical).to_hex().to_string().into(),
+        _ => Variable::default(),
+    }
+}
+
 pub fn fn_is_var_names<'x>(ctx: &'x Context<'x>, _: Vec<Variable>) -> Variable {
     Variable::Array(
         ctx.global_variable_names()
@@ -21,7 +21,7 @@
  * for more details.
 */
 
-mod array;
+pub mod array;
 mod email;
 mod header;
 pub mod html;
@@ -50,6 +50,7 @@ pub fn register_functions() -> FunctionMap {
         .with_function("to_lowercase", fn_to_lowercase)
         .with_function("to_uppercase", fn_to_uppercase)
         .with_function("detect_language", fn_detect_language)
+        .with_function("detect_language_score", fn_detect_language_score)
         .with_function("is_email", fn_is_email)
         .with_function("thread_name", fn_thread_name)
         .with_function("html_to_text", fn_html_to_text)
@@ -73,9 +74,13 @@ pub fn register_functions() -> FunctionMap {
         .with_function("has_obscured", fn_has_obscured)
         .with_function("is_single_script", fn_is_single_script)
         .with_function("puny_decode", fn_puny_decode)
+        .with_function("url_params", fn_url_params)
         .with_function("unicode_skeleton", fn_unicode_skeleton)
         .with_function("cure_text", fn_cure_text)
+        .with_function("deobfuscate_name", fn_deobfuscate_name)
         .with_function("detect_file_type", fn_detect_file_type)
+        .with_function("extract_phones", fn_extract_phones)
+        .with_function("extract_crypto", fn_extract_crypto)
         .with_function_args("sort", fn_sort, 2)
         .with_function_args("email_part", fn_email_part, 2)
         .with_function_args("eq_ignore_case", fn_eq_ignore_case, 2)
@@ -101,12 +106,26 @@ pub fn register_functions() -> FunctionMap {
         .with_function_args("strip_suffix", fn_strip_suffix, 2)
         .with_function_args("is_intersect", fn_is_intersect, 2)
         .with_function_args("hash", fn_hash, 2)
+        .with_function_args("message_hash", fn_message_hash, 2)
+        .with_function_args("phash_distance", fn_phash_distance, 2)
         .with_function_no_args("is_encoding_problem", fn_is_encoding_problem)
         .with_function_no_args("is_attachment", fn_is_attachment)
         .with_function_no_args("is_body", fn_is_body)
         .with_function_no_args("var_names", fn_is_var_names)
         .with_function_no_args("attachment_name", fn_attachment_name)
         .with_function_no_args("mime_part_len", fn_mime_part_len)
+        .with_function_no_args("mime_stats", fn_mime_stats)
+        .with_function_no_args("mime_boundaries", fn_mime_boundaries)
+        .with_function_no_args("part_order_anomaly", fn_part_order_anomaly)
+        .with_function_no_args("header_anomalies", fn_header_anomalies)
+        .with_function_no_args("signed_structure", fn_signed_structure)
+        .with_function_no_args("has_encrypted_content", fn_has_encrypted_content)
+        .with_function_no_args("filename_risk", fn_filename_risk)
+        .with_function_no_args("header_injection", fn_header_injection)
+        .with_function("foreign_auth_results", fn_foreign_auth_results)
+        .with_function_no_args("qr_decode", fn_qr_decode)
+        .with_function_no_args("image_phash", fn_image_phash)
+        .with_function_no_args("image_text_ratio", fn_image_text_ratio)
 }
 
 pub trait ApplyString<'x> {
@@ -299,8 +299,325 @@ pub fn fn_levenshtein_distance<'x>(_: &'x Context<'x>, v: Vec<Variable>) -> Vari
 }
 
 pub fn fn_detect_language<'x>(_: &'x Context<'x>, v: Vec<Variable>) -> Variable {
-    whatlang::detect_lang(v[0].to_string().as_ref())
-        .map(|l| l.code())
+    detect_dominant_language(v[0].to_string().as_ref())
+        .map(|(lang, _)| lang.code())
         .unwrap_or("unknown")
         .into()
 }
+
+/// Returns `[language, confidence]`, where `language` is the ISO 639-3 code of
+/// the dominant language or `unknown` when the text is too short or ambiguous.
+pub fn fn_detect_language_score<'x>(_: &'x Context<'x>, v: Vec<Variable>) -> Variable {
+    let (code, confidence) = detect_dominant_language(v[0].to_string().as_ref())
+        .map(|(lang, confidence)| (lang.code(), confidence))
+        .unwrap_or(("unknown", 0.0));
+
+    Variable::Array(vec![Variable::from(code), Variable::Float(confidence)].into())
+}
+
+const MIN_LANGUAGE_CHARS: usize = 20;
+const MIN_LANGUAGE_CONFIDENCE: f64 = 0.5;
+
+/// Detects the language of each paragraph and returns the one covering most
+/// of the text, along with its confidence weighted by the text it covers.
+fn detect_dominant_language(text: &str) -> Option<(whatlang::Lang, f64)> {
+    let mut languages: Vec<(whatlang::Lang, usize, f64)> = Vec::new();
+    let mut total_chars = 0;
+    let mut chunk = String::new();
+
+    for line in text.lines().chain([""]) {
+        let line = line.trim();
+        if !line.is_empty() {
+            if !chunk.is_empty() {
+                chunk.push(' ');
+            }
+            chunk.push_str(line);
+            continue;
+        }
+
+        // Paragraphs that are too short are merged with the next one
+        let chars = chunk.chars().filter(|ch| ch.is_alphabetic()).count();
+        if chars < MIN_LANGUAGE_CHARS {
+            continue;
+        }
+        if let Some(info) = whatlang::detect(&chunk) {
+            if let Some(entry) = languages.iter_mut().find(|(l, _, _)| *l == info.lang()) {
+                entry.1 += chars;
+                entry.2 += info.confidence() * chars as f64;
+            } else {
+                languages.push((info.lang(), chars, info.confidence() * chars as f64));
+            }
+        }
+        total_chars += chars;
+        chunk.clear();
+    }
+
+    // Mixed-language text lowers the confidence of the dominant language
+    let (lang, _, confidence) = languages.into_iter().max_by_key(|(_, chars, _)| *chars)?;
+    let confidence = confidence / total_chars as f64;
+
+    (confidence >= MIN_LANGUAGE_CONFIDENCE).then_some((lang, confidence))
+}
+
+/// Extracts phone numbers from text and returns them in E.164 format.
+/// Numbers written with a `+` or `00` international prefix are accepted
+/// as-is, while national numbers are only accepted when written in groups
+/// following the North American numbering plan (such as `(555) 123-4567`)
+/// to avoid matching order or reference numbers.
+pub fn fn_extract_phones<'x>(_: &'x Context<'x>, v: Vec<Variable>) -> Variable {
+    let text = v[0].to_string();
+    let mut phones: Vec<Variable> = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    let mut prev_ch = ' ';
+
+    while let Some((pos, ch)) = chars.next() {
+        if !(ch == '+' || ch == '(' || ch.is_ascii_digit()) || prev_ch.is_alphanumeric() {
+            prev_ch = ch;
+            continue;
+        }
+
+        // Collect the candidate number
+        let mut end = pos + ch.len_utf8();
+        prev_ch = ch;
+        while let Some((next_pos, next_ch)) = chars.peek() {
+            if next_ch.is_ascii_digit() || matches!(next_ch, ' ' | '-' | '.' | '(' | ')' | '/') {
+                end = next_pos + next_ch.len_utf8();
+                prev_ch = *next_ch;
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        if prev_ch.is_ascii_digit() && chars.peek().map_or(false, |(_, ch)| ch.is_alphanumeric()) {
+            // Digits followed by a word are not a phone number
+            continue;
+        }
+
+        if let Some(phone) = normalize_phone(&text[pos..end]) {
+            let phone = Variable::from(phone);
+            if !phones.contains(&phone) {
+                phones.push(phone);
+            }
+        }
+        prev_ch = ' ';
+    }
+
+    phones.into()
+}
+
+fn normalize_phone(candidate: &str) -> Option<String> {
+    let candidate = candidate.trim_end_matches(|c: char| !c.is_ascii_digit());
+    let digits = candidate
+        .chars()
+        .filter(|c| c.is_ascii_digit())
+        .collect::<String>();
+    let groups = candidate
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|g| !g.is_empty())
+        .collect::<Vec<_>>();
+    let is_grouped = groups.len() > 1 && groups.iter().all(|g| g.len() <= 4);
+    let is_nanp = matches!(
+        groups
+            .iter()
+            .map(|g| g.len())
+            .collect::<Vec<_>>()
+            .as_slice(),
+        [3, 3, 4] | [1, 3, 3, 4]
+    );
+
+    if candidate.starts_with('+') {
+        if (8..=15).contains(&digits.len()) && !digits.starts_with('0') {
+            return Some(format!("+{digits}"));
+        }
+    } else if let Some(digits) = digits.strip_prefix("00") {
+        if is_grouped && (8..=15).contains(&digits.len()) && !digits.starts_with('0') {
+            return Some(format!("+{digits}"));
+        }
+    } else if is_nanp {
+        if digits.len() == 10 && !digits.starts_with(['0', '1']) {
+            return Some(format!("+1{digits}"));
+        } else if digits.len() == 11 && digits.starts_with('1') {
+            return Some(format!("+{digits}"));
+        }
+    }
+
+    None
+}
+
+/// Extracts cryptocurrency wallet addresses from text. Legacy Bitcoin
+/// addresses are validated using their Base58Check checksum, SegWit
+/// addresses using their Bech32 or Bech32m checksum and mixed-case Ethereum
+/// addresses using their EIP-55 checksum, while Monero addresses are matched
+/// on their format only.
+pub fn fn_extract_crypto<'x>(_: &'x Context<'x>, v: Vec<Variable>) -> Variable {
+    let text = v[0].to_string();
+    let mut addresses: Vec<Variable> = Vec::new();
+
+    for token in text
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|t| (14..=95).contains(&t.len()))
+    {
+        let address =
+            if is_eth_address(token) || is_xmr_address(token) || is_btc_base58_address(token) {
+                token.to_string()
+            } else if is_btc_bech32_address(token) {
+                token.to_ascii_lowercase()
+            } else {
+                continue;
+            };
+
+        let address = Variable::from(address);
+        if !addresses.contains(&address) {
+            addresses.push(address);
+        }
+    }
+
+    addresses.into()
+}
+
+fn is_eth_address(token: &str) -> bool {
+    use tiny_keccak::{Hasher, Keccak};
+
+    let Some(hex) = token.strip_prefix("0x") else {
+        return false;
+    };
+    if hex.len() != 40 || !hex.bytes().all(|ch| ch.is_ascii_hexdigit()) {
+        return false;
+    }
+
+    // Single-case addresses carry no EIP-55 checksum
+    if hex.bytes().all(|ch| !ch.is_ascii_uppercase())
+        || hex.bytes().all(|ch| !ch.is_ascii_lowercase())
+    {
+        return true;
+    }
+
+    // Letters are uppercase when the matching nibble of the Keccak-256 hash
+    // of the lowercase address is 8 or higher
+    let mut hash = [0u8; 32];
+    let mut hasher = Keccak::v256();
+    hasher.update(hex.to_ascii_lowercase().as_bytes());
+    hasher.finalize(&mut hash);
+
+    hex.bytes().enumerate().all(|(i, ch)| {
+        let nibble = (hash[i / 2] >> if i % 2 == 0 { 4 } else { 0 }) & 0x0f;
+        !ch.is_ascii_alphabetic() || ch.is_ascii_uppercase() == (nibble >= 8)
+    })
+}
+
+fn is_xmr_address(token: &str) -> bool {
+    token.len() == 95
+        && token.starts_with(['4', '8'])
+        && token.bytes().all(|ch| BASE58_ALPHABET.contains(&ch))
+}
+
+fn is_btc_base58_address(token: &str) -> bool {
+    use sha2::{Digest, Sha256};
+
+    if !(26..=35).contains(&token.len()) || !token.starts_with(['1', '3']) {
+        return false;
+    }
+
+    // Decode Base58 into a big-endian byte array
+    let mut bytes: Vec<u8> = Vec::with_capacity(25);
+    for ch in token.bytes() {
+        let Some(mut carry) = BASE58_ALPHABET
+            .iter()
+            .position(|&b| b == ch)
+            .map(|pos| pos as u32)
+        else {
+            return false;
+        };
+        for byte in bytes.iter_mut().rev() {
+            carry += (*byte as u32) * 58;
+            *byte = (carry & 0xff) as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.insert(0, (carry & 0xff) as u8);
+            carry >>= 8;
+        }
+    }
+    let leading_zeros = token.bytes().take_while(|&ch| ch == b'1').count();
+    let mut decoded = vec![0u8; leading_zeros];
+    decoded.extend_from_slice(&bytes);
+
+    // Version byte, 20-byte hash and 4-byte checksum
+    if decoded.len() != 25 || !matches!(decoded[0], 0x00 | 0x05) {
+        return false;
+    }
+    let (payload, checksum) = decoded.split_at(21);
+    Sha256::digest(Sha256::digest(payload))[..4] == *checksum
+}
+
+fn is_btc_bech32_address(token: &str) -> bool {
+    if !(14..=74).contains(&token.len())
+        || !(token.bytes().all(|ch| !ch.is_ascii_uppercase())
+            || token.bytes().all(|ch| !ch.is_ascii_lowercase()))
+    {
+        return false;
+    }
+    let token = token.to_ascii_lowercase();
+    let Some(data) = token.strip_prefix("bc1") else {
+        return false;
+    };
+
+    let mut values = Vec::with_capacity(data.len() + 5);
+    values.extend([3, 3, 0, 2, 3]); // Expanded human-readable part "bc"
+    for ch in data.bytes() {
+        match BECH32_CHARSET.iter().position(|&b| b == ch) {
+            Some(pos) => values.push(pos as u32),
+            None => return false,
+        }
+    }
+
+    let mut chk: u32 = 1;
+    for value in values {
+        let top = chk >> 25;
+        chk = ((chk & 0x1ffffff) << 5) ^ value;
+        for (i, generator) in [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3]
+            .into_iter()
+            .enumerate()
+        {
+            if (top >> i) & 1 == 1 {
+                chk ^= generator;
+            }
+        }
+    }
+
+    // Bech32 is used by witness version 0, Bech32m by later versions
+    chk == 1 || chk == 0x2bc830a3
+}
+
+const BASE58_ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const BECH32_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+#[cfg(test)]
+mod tests {
+    use super::is_eth_address;
+
+    #[test]
+    fn eth_address_checksum() {
+        for address in [
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+            "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+            "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed",
+            "0x5AAEB6053F3E94C9B9A09F33669435E7EF1BEAED",
+        ] {
+            assert!(is_eth_address(address), "{address}");
+        }
+
+        for address in [
+            "0x5aaeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d35A",
+            "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9adB",
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAe",
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeg",
+            "5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed00",
+        ] {
+            assert!(!is_eth_address(address), "{address}");
+        }
+    }
+}
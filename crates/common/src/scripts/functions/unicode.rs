@@ -22,6 +22,7 @@
 */
 
 use sieve::{runtime::Variable, Context};
+use unicode_normalization::UnicodeNormalization;
 use unicode_security::MixedScript;
 
 pub fn fn_is_ascii<'x>(_: &'x Context<'x>, v: Vec<Variable>) -> Variable {
@@ -60,6 +61,66 @@ pub fn fn_has_obscured<'x>(_: &'x Context<'x>, v: Vec<Variable>) -> Variable {
     .into()
 }
 
+/// Returns the original display name along with a cleaned form suitable for
+/// comparing against brand or executive lists. The cleaned form is NFKC
+/// normalized, has decorative symbols and invisible characters removed,
+/// collapses runs of separators and joins letters spaced out one at a time
+/// (such as `P.a.y.P.a.l`).
+pub fn fn_deobfuscate_name<'x>(_: &'x Context<'x>, v: Vec<Variable>) -> Variable {
+    let name = v[0].to_string();
+    let cleaned = deobfuscate_name(name.as_ref());
+
+    Variable::Array(vec![name.into_owned().into(), cleaned.into()].into())
+}
+
+fn deobfuscate_name(name: &str) -> String {
+    let mut tokens: Vec<String> = Vec::new();
+    let mut token = String::new();
+
+    for ch in name.nfkc() {
+        if ch.is_alphanumeric() || matches!(ch, '\'' | '&' | ',') {
+            token.push(ch);
+        } else if ch.is_whitespace()
+            || ch.is_ascii_punctuation()
+            || matches!(
+                ch,
+                '\u{00B7}' | '\u{2013}' | '\u{2014}' | '\u{2022}' | '\u{2219}'
+            )
+        {
+            if !token.is_empty() {
+                tokens.push(std::mem::take(&mut token));
+            }
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+
+    // Join runs of three or more single characters
+    let mut cleaned = String::with_capacity(name.len());
+    let mut pos = 0;
+    while pos < tokens.len() {
+        let run = tokens[pos..]
+            .iter()
+            .take_while(|t| t.chars().count() == 1)
+            .count();
+        if !cleaned.is_empty() {
+            cleaned.push(' ');
+        }
+        if run >= 3 {
+            for token in &tokens[pos..pos + run] {
+                cleaned.push_str(token);
+            }
+            pos += run;
+        } else {
+            cleaned.push_str(&tokens[pos]);
+            pos += 1;
+        }
+    }
+
+    cleaned
+}
+
 trait CharUtils {
     fn is_zwsp(&self) -> bool;
     fn is_obscured(&self) -> bool;
@@ -106,3 +167,34 @@ pub fn fn_is_single_script<'x>(_: &'x Context<'x>, v: Vec<Variable>) -> Variable
     }
     .into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::deobfuscate_name;
+
+    #[test]
+    fn deobfuscate_names() {
+        for (name, expected) in [
+            ("John Smith", "John Smith"),
+            ("P.a.y.P.a.l Support", "PayPal Support"),
+            ("A m a z o n", "Amazon"),
+            ("N_e_t_f_l_i_x Billing", "Netflix Billing"),
+            (
+                "\u{FF2D}\u{FF49}\u{FF43}\u{FF52}\u{FF4F}\u{FF53}\u{FF4F}\u{FF46}\u{FF54}",
+                "Microsoft",
+            ),
+            (
+                "\u{1D400}\u{1D429}\u{1D429}\u{1D425}\u{1D41E} \u{2605} Support",
+                "Apple Support",
+            ),
+            ("J\u{200B}o\u{200B}h\u{200B}n Smith", "John Smith"),
+            ("CEO   ..  John -- Smith", "CEO John Smith"),
+            ("Bank__of__America", "Bank of America"),
+            ("\u{2022} DHL \u{2022} Express \u{2022}", "DHL Express"),
+            ("O'Brien, Kate", "O'Brien, Kate"),
+            ("J R Tolkien", "J R Tolkien"),
+        ] {
+            assert_eq!(deobfuscate_name(name), expected, "{name:?}");
+        }
+    }
+}
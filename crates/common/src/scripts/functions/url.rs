@@ -73,3 +73,107 @@ pub fn fn_puny_decode<'x>(_: &'x Context<'x>, v: Vec<Variable>) -> Variable {
         }
     })
 }
+
+/// Returns, for each URL, an array containing the names of its query parameters,
+/// the names of known tracking parameters, the names of parameters with long
+/// encoded values and the highest Shannon entropy found in a parameter value.
+pub fn fn_url_params<'x>(_: &'x Context<'x>, v: Vec<Variable>) -> Variable {
+    let urls = match &v[0] {
+        Variable::Array(urls) => urls.iter().map(|url| url.to_string()).collect::<Vec<_>>(),
+        url => vec![url.to_string()],
+    };
+
+    urls.iter()
+        .map(|url| {
+            let mut names = Vec::new();
+            let mut tracking = Vec::new();
+            let mut encoded = Vec::new();
+            let mut max_entropy: f64 = 0.0;
+
+            let query = url
+                .split_once('?')
+                .map_or("", |(_, query)| query.split('#').next().unwrap_or_default());
+            for param in query.split(['&', ';']).filter(|param| !param.is_empty()) {
+                let (name, value) = param.split_once('=').unwrap_or((param, ""));
+                let name_var = Variable::from(name.to_string());
+                if is_tracking_param(name) {
+                    tracking.push(name_var.clone());
+                }
+                if is_encoded_value(value) {
+                    encoded.push(name_var.clone());
+                }
+                max_entropy = max_entropy.max(shannon_entropy(value));
+                names.push(name_var);
+            }
+
+            Variable::Array(
+                vec![
+                    Variable::Array(names.into()),
+                    Variable::Array(tracking.into()),
+                    Variable::Array(encoded.into()),
+                    Variable::Float(max_entropy),
+                ]
+                .into(),
+            )
+        })
+        .collect::<Vec<_>>()
+        .into()
+}
+
+fn is_tracking_param(name: &str) -> bool {
+    const TRACKING_PARAMS: &[&str] = &[
+        "fbclid",
+        "gclid",
+        "dclid",
+        "gbraid",
+        "wbraid",
+        "msclkid",
+        "yclid",
+        "twclid",
+        "ttclid",
+        "igshid",
+        "mc_cid",
+        "mc_eid",
+        "_hsenc",
+        "_hsmi",
+        "mkt_tok",
+        "vero_id",
+        "oly_anon_id",
+        "oly_enc_id",
+        "rb_clickid",
+        "s_cid",
+        "wickedid",
+    ];
+
+    let name = name.to_ascii_lowercase();
+    name.starts_with("utm_") || TRACKING_PARAMS.contains(&name.as_str())
+}
+
+fn is_encoded_value(value: &str) -> bool {
+    const MIN_ENCODED_LEN: usize = 64;
+
+    value.len() >= MIN_ENCODED_LEN
+        && value.bytes().all(|ch| {
+            ch.is_ascii_alphanumeric() || matches!(ch, b'%' | b'+' | b'/' | b'=' | b'-' | b'_')
+        })
+}
+
+fn shannon_entropy(value: &str) -> f64 {
+    if value.is_empty() {
+        return 0.0;
+    }
+
+    let mut counts = [0u32; 256];
+    for ch in value.bytes() {
+        counts[ch as usize] += 1;
+    }
+    let len = value.len() as f64;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
@@ -18,6 +18,9 @@ pub enum ScriptModification {
         name: Arc<String>,
         value: Arc<String>,
     },
+    DeleteRecipient {
+        address: String,
+    },
 }
 
 pub fn into_sieve_value(value: Value) -> Variable {
@@ -32,6 +32,8 @@ use sieve::{runtime::Variable, FunctionMap};
 use store::{write::key::KeySerializer, LookupStore, U64_LEN};
 use tokio::runtime::Handle;
 
+use crate::Core;
+
 use super::PluginContext;
 
 pub fn register_train(plugin_id: u32, fnc_map: &mut FunctionMap) {
@@ -82,82 +84,92 @@ fn train(ctx: PluginContext<'_>, is_train: bool) -> Variable {
     if text.is_empty() {
         return false.into();
     }
-    let handle = ctx.handle;
 
+    match ctx.handle.block_on(bayes_train(
+        ctx.core,
+        store,
+        text.as_ref(),
+        is_spam,
+        is_train,
+    )) {
+        Ok(result) => result.into(),
+        Err(err) => {
+            tracing::warn!(
+                parent: span,
+                context = "sieve:bayes_train",
+                event = "failed",
+                reason = ?err,
+            );
+            false.into()
+        }
+    }
+}
+
+/// Trains or untrains the Bayes model stored in `store` with the provided text.
+/// Untraining reverts a previous training with the same text and label.
+pub async fn bayes_train(
+    core: &Core,
+    store: &LookupStore,
+    text: &str,
+    is_spam: bool,
+    is_train: bool,
+) -> store::Result<bool> {
     // Train the model
     let mut model = BayesModel::default();
     model.train(
-        OsbTokenizer::new(
-            BayesTokenizer::new(text.as_ref(), &ctx.core.smtp.resolvers.psl),
-            5,
-        ),
+        OsbTokenizer::new(BayesTokenizer::new(text, &core.smtp.resolvers.psl), 5),
         is_spam,
     );
     if model.weights.is_empty() {
-        return false.into();
+        return Ok(false);
     }
 
     tracing::debug!(
-        parent: span,
-        context = "sieve:bayes_train",
-        event = "train",
+        context = "bayes_train",
+        event = if is_train { "train" } else { "untrain" },
         is_spam = is_spam,
         num_tokens = model.weights.len(),
     );
 
-    // Update weight and invalidate cache
-    let bayes_cache = &ctx.core.sieve.bayes_cache;
-    if is_train {
-        for (hash, weights) in model.weights {
-            if handle
-                .block_on(
-                    store.counter_incr(
-                        KeySerializer::new(U64_LEN)
-                            .write(hash.h1)
-                            .write(hash.h2)
-                            .finalize(),
-                        weights.into(),
-                        None,
-                        false,
-                    ),
-                )
-                .is_err()
-            {
-                return false.into();
-            }
-            bayes_cache.invalidate(&hash);
-        }
-
-        // Update training counts
-        let weights = if is_spam {
-            Weights { spam: 1, ham: 0 }
-        } else {
-            Weights { spam: 0, ham: 1 }
-        };
-        if handle
-            .block_on(
-                store.counter_incr(
-                    KeySerializer::new(U64_LEN)
-                        .write(0u64)
-                        .write(0u64)
-                        .finalize(),
-                    weights.into(),
-                    None,
-                    false,
-                ),
+    // Update weights and invalidate cache
+    let bayes_cache = &core.sieve.bayes_cache;
+    for (hash, weights) in model.weights {
+        let weights = i64::from(weights);
+        store
+            .counter_incr(
+                KeySerializer::new(U64_LEN)
+                    .write(hash.h1)
+                    .write(hash.h2)
+                    .finalize(),
+                if is_train { weights } else { -weights },
+                None,
+                false,
             )
-            .is_err()
-        {
-            return false.into();
-        }
-    } else {
-        //TODO: Implement untrain
-        return false.into();
+            .await?;
+        bayes_cache.invalidate(&hash);
     }
 
+    // Update training counts
+    let weights = i64::from(if is_spam {
+        Weights { spam: 1, ham: 0 }
+    } else {
+        Weights { spam: 0, ham: 1 }
+    });
+    store
+        .counter_incr(
+            KeySerializer::new(U64_LEN)
+                .write(0u64)
+                .write(0u64)
+                .finalize(),
+            if is_train { weights } else { -weights },
+            None,
+            false,
+        )
+        .await?;
+
     bayes_cache.invalidate(&TokenHash::default());
 
-    true.into()
+    Ok(true)
 }
 
 pub fn exec_classify(ctx: PluginContext<'_>) -> Variable {
@@ -0,0 +1,142 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use sieve::{runtime::Variable, FunctionMap};
+use store::LookupStore;
+
+use super::{
+    lookup::{key_get, key_set},
+    PluginContext,
+};
+
+pub fn register_get(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("cache_get", plugin_id, 1);
+}
+
+pub fn register_set(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("cache_set", plugin_id, 3);
+}
+
+pub fn register_incr(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("cache_incr", plugin_id, 3);
+}
+
+// The cache is backed by a lookup store, usually a Redis store shared by all
+// nodes. When the store is unreachable, a fail-open cache behaves as if it
+// was empty: lookups return nothing, writes report success and counters
+// report zero so that rate checks pass. A fail-closed cache reports keys as
+// present, writes as failed and counters as exceeding any limit.
+
+pub fn exec_get(ctx: PluginContext<'_>) -> Variable {
+    let result = cache_store(&ctx).and_then(|store| {
+        key_get(&ctx, store, &ctx.arguments[0]).map_err(|err| {
+            cache_error(&ctx, "sieve:cache_get", err);
+        })
+    });
+
+    match result {
+        Ok(value) => value,
+        Err(_) if ctx.core.sieve.cache_fail_open => Variable::default(),
+        Err(_) => true.into(),
+    }
+}
+
+pub fn exec_set(ctx: PluginContext<'_>) -> Variable {
+    let result = cache_store(&ctx).and_then(|store| {
+        key_set(
+            &ctx,
+            store,
+            &ctx.arguments[0],
+            &ctx.arguments[1],
+            expires(&ctx.arguments[2]),
+        )
+        .map_err(|err| {
+            cache_error(&ctx, "sieve:cache_set", err);
+        })
+    });
+
+    (result.is_ok() || ctx.core.sieve.cache_fail_open).into()
+}
+
+pub fn exec_incr(ctx: PluginContext<'_>) -> Variable {
+    let delta = match &ctx.arguments[1] {
+        Variable::Integer(v) => *v,
+        Variable::Float(v) => *v as i64,
+        Variable::String(v) => v.parse().unwrap_or(1),
+        _ => 1,
+    };
+
+    let result = cache_store(&ctx).and_then(|store| {
+        ctx.handle
+            .block_on(store.counter_incr(
+                ctx.arguments[0].to_string().into_owned().into_bytes(),
+                delta,
+                expires(&ctx.arguments[2]),
+                true,
+            ))
+            .map_err(|err| {
+                cache_error(&ctx, "sieve:cache_incr", err);
+            })
+    });
+
+    match result {
+        Ok(value) => value,
+        Err(_) if ctx.core.sieve.cache_fail_open => 0,
+        Err(_) => i64::MAX,
+    }
+    .into()
+}
+
+fn cache_store<'x>(ctx: &PluginContext<'x>) -> Result<&'x LookupStore, ()> {
+    match &ctx.core.sieve.cache_store {
+        Some(id) => ctx.core.storage.lookups.get(id).ok_or_else(|| {
+            tracing::warn!(
+                parent: ctx.span,
+                context = "sieve:cache",
+                event = "failed",
+                reason = "Unknown store id",
+                store_id = id.as_str(),
+                fail_open = ctx.core.sieve.cache_fail_open,
+            );
+        }),
+        None => Ok(&ctx.core.storage.lookup),
+    }
+}
+
+fn cache_error(ctx: &PluginContext<'_>, context: &str, err: store::Error) {
+    tracing::warn!(
+        parent: ctx.span,
+        context = context,
+        event = "error",
+        fail_open = ctx.core.sieve.cache_fail_open,
+        reason = %err,
+    );
+}
+
+fn expires(value: &Variable) -> Option<u64> {
+    match value {
+        Variable::Integer(v) if *v > 0 => Some(*v as u64),
+        Variable::Float(v) if *v > 0.0 => Some(*v as u64),
+        _ => None,
+    }
+}
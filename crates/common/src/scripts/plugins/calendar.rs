@@ -0,0 +1,194 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use mail_parser::{MessagePart, MimeHeaders};
+use sieve::{runtime::Variable, FunctionMap};
+
+use super::PluginContext;
+
+// Maximum number of bytes inspected on each calendar part
+const MAX_CALENDAR_LEN: usize = 64 * 1024;
+
+pub fn register(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("calendar_parts", plugin_id, 0);
+}
+
+/// Returns the iCalendar parts of the message, whether attached or sent as an alternative,
+/// as an array of `[part_id, method, organizer, summary]` entries. The method is uppercased,
+/// the organizer is the lowercased address without the `mailto:` scheme and the summary
+/// is taken from the first event. Missing properties are returned as empty strings.
+pub fn exec(ctx: PluginContext<'_>) -> Variable {
+    let mut results = Vec::new();
+
+    for (part_id, part) in ctx.message.parts.iter().enumerate() {
+        if !is_calendar(part) {
+            continue;
+        }
+
+        let contents = part.contents();
+        let contents =
+            String::from_utf8_lossy(&contents[..std::cmp::min(contents.len(), MAX_CALENDAR_LEN)]);
+        let calendar = parse_calendar(&contents);
+        results.push(Variable::Array(
+            vec![
+                Variable::Integer(part_id as i64),
+                Variable::from(calendar.method),
+                Variable::from(calendar.organizer),
+                Variable::from(calendar.summary),
+            ]
+            .into(),
+        ));
+    }
+
+    Variable::Array(results.into())
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Calendar {
+    method: String,
+    organizer: String,
+    summary: String,
+}
+
+fn is_calendar(part: &MessagePart<'_>) -> bool {
+    part.content_type().map_or(false, |ct| {
+        let subtype = ct.subtype().unwrap_or_default();
+        (ct.ctype().eq_ignore_ascii_case("text") && subtype.eq_ignore_ascii_case("calendar"))
+            || (ct.ctype().eq_ignore_ascii_case("application")
+                && subtype.eq_ignore_ascii_case("ics"))
+    }) || part.attachment_name().map_or(false, |name| {
+        name.rsplit_once('.')
+            .map_or(false, |(_, ext)| ext.eq_ignore_ascii_case("ics"))
+    })
+}
+
+fn parse_calendar(contents: &str) -> Calendar {
+    let mut calendar = Calendar::default();
+    let mut components = Vec::new();
+
+    for line in unfold_lines(contents) {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let name = name
+            .split_once(';')
+            .map_or(name, |(name, _)| name)
+            .trim()
+            .to_ascii_uppercase();
+        let value = value.trim();
+
+        match name.as_str() {
+            "BEGIN" => components.push(value.to_ascii_uppercase()),
+            "END" => {
+                components.pop();
+            }
+            "METHOD" if calendar.method.is_empty() && components.len() == 1 => {
+                calendar.method = value.to_ascii_uppercase();
+            }
+            "ORGANIZER" if calendar.organizer.is_empty() && is_in_event(&components) => {
+                let address = value
+                    .get(..7)
+                    .filter(|scheme| scheme.eq_ignore_ascii_case("mailto:"))
+                    .map_or(value, |_| &value[7..]);
+                calendar.organizer = address.trim().to_lowercase();
+            }
+            "SUMMARY" if calendar.summary.is_empty() && is_in_event(&components) => {
+                calendar.summary = unescape_text(value);
+            }
+            _ => {}
+        }
+    }
+
+    calendar
+}
+
+fn is_in_event(components: &[String]) -> bool {
+    components.last().map_or(false, |c| c == "VEVENT")
+}
+
+/// Joins folded content lines, which continue on the next line after a space or tab.
+fn unfold_lines(contents: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in contents.lines() {
+        if let Some(continuation) = line.strip_prefix([' ', '\t']) {
+            if let Some(last) = lines.last_mut() {
+                last.push_str(continuation);
+                continue;
+            }
+        }
+        lines.push(line.to_string());
+    }
+    lines
+}
+
+fn unescape_text(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\\' {
+            match chars.next() {
+                Some('n' | 'N') => result.push('\n'),
+                Some(ch) => result.push(ch),
+                None => {}
+            }
+        } else {
+            result.push(ch);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_calendar, Calendar};
+
+    #[test]
+    fn calendar_parts() {
+        assert_eq!(
+            parse_calendar(concat!(
+                "BEGIN:VCALENDAR\r\n",
+                "VERSION:2.0\r\n",
+                "METHOD:request\r\n",
+                "BEGIN:VEVENT\r\n",
+                "ORGANIZER;CN=Prize Department:MAILTO:Winner@Example.com\r\n",
+                "SUMMARY:You have won\\, claim your\r\n",
+                "  prize now\r\n",
+                "BEGIN:VALARM\r\n",
+                "SUMMARY:Reminder\r\n",
+                "END:VALARM\r\n",
+                "END:VEVENT\r\n",
+                "END:VCALENDAR\r\n",
+            )),
+            Calendar {
+                method: "REQUEST".to_string(),
+                organizer: "winner@example.com".to_string(),
+                summary: "You have won, claim your prize now".to_string(),
+            }
+        );
+
+        assert_eq!(
+            parse_calendar("BEGIN:VCALENDAR\nBEGIN:VTODO\nSUMMARY:Task\nEND:VTODO\nEND:VCALENDAR"),
+            Calendar::default()
+        );
+    }
+}
@@ -0,0 +1,319 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::borrow::Cow;
+
+use mail_parser::Message;
+use sha2::{Digest, Sha256};
+use sieve::{runtime::Variable, FunctionMap};
+
+use super::PluginContext;
+
+pub fn register(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("normalized_checksum", plugin_id, 1);
+}
+
+pub fn register_count(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("checksum_count", plugin_id, 3);
+}
+
+/// Computes a SHA-256 checksum of the message body after removing the
+/// content that usually varies between the copies of a campaign:
+///
+/// - All text parts are concatenated (HTML parts are converted to text) and lowercased.
+/// - The recipient addresses passed as argument, as well as their local parts,
+///   are removed.
+/// - Any other e-mail addresses are removed.
+/// - URLs are reduced to their hostname, dropping paths and query strings.
+/// - Tokens that look random (8 or more characters containing digits,
+///   or 20 or more alphanumeric characters) are removed.
+/// - Punctuation is stripped and whitespace is collapsed.
+pub fn exec(ctx: PluginContext<'_>) -> Variable {
+    let recipients = ctx.arguments[0].to_array();
+    body_checksum(
+        ctx.message,
+        &recipient_tokens(recipients.iter().map(|rcpt| rcpt.to_string())),
+    )
+    .map(Variable::from)
+    .unwrap_or_default()
+}
+
+pub fn exec_count(ctx: PluginContext<'_>) -> Variable {
+    let store = match &ctx.arguments[0] {
+        Variable::String(v) if !v.is_empty() => ctx.core.storage.lookups.get(v.as_ref()),
+        _ => Some(&ctx.core.storage.lookup),
+    };
+    let checksum = ctx.arguments[1].to_string();
+    let expires = expiry(&ctx.arguments[2]);
+
+    if let Some(store) = store {
+        if checksum.is_empty() {
+            return 0.into();
+        }
+
+        match ctx.handle.block_on(store.counter_incr(
+            format!("csum:{checksum}").into_bytes(),
+            1,
+            expires,
+            true,
+        )) {
+            Ok(count) => count.into(),
+            Err(err) => {
+                tracing::warn!(
+                    parent: ctx.span,
+                    context = "sieve:checksum_count",
+                    event = "failed",
+                    reason = %err,
+                );
+                Variable::default()
+            }
+        }
+    } else {
+        tracing::warn!(
+            parent: ctx.span,
+            context = "sieve:checksum_count",
+            event = "failed",
+            reason = "Unknown store id",
+            store_id = ctx.arguments[0].to_string().as_ref(),
+        );
+        Variable::default()
+    }
+}
+
+/// Returns the recipient addresses, their local parts and the components of
+/// the local parts, which are removed from the body before hashing.
+fn recipient_tokens<'x>(recipients: impl Iterator<Item = Cow<'x, str>>) -> Vec<String> {
+    let mut tokens = Vec::new();
+    for rcpt in recipients {
+        let rcpt = rcpt.trim().to_lowercase();
+        if let Some((local, _)) = rcpt.rsplit_once('@') {
+            tokens.extend(
+                local
+                    .split(['.', '_', '-', '+'])
+                    .filter(|p| p.len() > 2)
+                    .map(|p| p.to_string()),
+            );
+            tokens.push(local.to_string());
+        }
+        if !rcpt.is_empty() {
+            tokens.push(rcpt);
+        }
+    }
+    tokens
+}
+
+fn body_checksum(message: &Message, variables: &[String]) -> Option<String> {
+    let mut hasher = Sha256::new();
+    let mut has_content = false;
+    for pos in 0..message.text_body_count() {
+        if let Some(text) = message.body_text(pos) {
+            for token in text.split_whitespace() {
+                if let Some(token) = normalize_token(token, variables) {
+                    if has_content {
+                        hasher.update(b" ");
+                    }
+                    hasher.update(token.as_bytes());
+                    has_content = true;
+                }
+            }
+        }
+    }
+
+    if has_content {
+        Some(format!("{:x}", hasher.finalize()))
+    } else {
+        None
+    }
+}
+
+/// Counters only expire when a positive number of seconds is given.
+fn expiry(value: &Variable) -> Option<u64> {
+    match value {
+        Variable::Integer(v) if *v > 0 => Some(*v as u64),
+        Variable::Float(v) if *v >= 1.0 => Some(*v as u64),
+        _ => None,
+    }
+}
+
+fn normalize_token(token: &str, variables: &[String]) -> Option<String> {
+    let token = token.to_lowercase();
+    let token = token.trim_matches(|c: char| !c.is_alphanumeric() && c != '/' && c != ':');
+
+    if token.is_empty() || variables.iter().any(|v| token.contains(v.as_str())) {
+        return None;
+    }
+
+    // Reduce URLs to their hostname
+    if let Some(url) = token
+        .strip_prefix("https://")
+        .or_else(|| token.strip_prefix("http://"))
+        .or_else(|| token.strip_prefix("www."))
+    {
+        let host = url
+            .split(['/', '?', '#', ':'])
+            .next()
+            .unwrap_or_default()
+            .trim_start_matches("www.");
+        return if !host.is_empty() {
+            Some(host.to_string())
+        } else {
+            None
+        };
+    }
+
+    // Remove e-mail addresses
+    if token.contains('@') {
+        return None;
+    }
+
+    // Remove random looking tokens
+    let len = token.chars().count();
+    if (len >= 8 && token.chars().any(|c| c.is_ascii_digit()))
+        || (len >= 20 && token.chars().all(|c| c.is_alphanumeric()))
+    {
+        return None;
+    }
+
+    let token = token
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .collect::<String>();
+    if !token.is_empty() {
+        Some(token)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mail_parser::MessageParser;
+    use sieve::runtime::Variable;
+
+    fn checksum(message: &str, recipients: &[&str]) -> Option<String> {
+        super::body_checksum(
+            &MessageParser::new().parse(message.as_bytes()).unwrap(),
+            &super::recipient_tokens(recipients.iter().map(|rcpt| (*rcpt).into())),
+        )
+    }
+
+    #[test]
+    fn normalized_checksum() {
+        let expected = checksum(
+            "Subject: offer\r\n\r\nDear John, claim your prize at https://prize.example.org/claim\r\n",
+            &["john@example.org"],
+        )
+        .unwrap();
+
+        for (message, recipients) in [
+            // Case, punctuation and whitespace
+            (
+                "Subject: offer\r\n\r\ndear   JOHN\r\n  Claim your PRIZE at https://prize.example.org/claim!\r\n",
+                &["john@example.org"][..],
+            ),
+            // Personalized for another recipient
+            (
+                "Subject: offer\r\n\r\nDear Jane, claim your prize at https://prize.example.org/claim\r\n",
+                &["jane@example.org"][..],
+            ),
+            // Recipient order
+            (
+                "Subject: offer\r\n\r\nDear John, claim your prize at https://prize.example.org/claim\r\n",
+                &["jane@example.org", "john@example.org"][..],
+            ),
+            (
+                "Subject: offer\r\n\r\nDear John, claim your prize at https://prize.example.org/claim\r\n",
+                &["john@example.org", "jane@example.org"][..],
+            ),
+            // URL paths, addresses and random tokens
+            (
+                concat!(
+                    "Subject: offer\r\n\r\nDear John, claim your prize at ",
+                    "https://www.prize.example.org/claim?id=8f3a9c21 ",
+                    "8f3a9c21d0 bounce-8f3a@example.net\r\n"
+                ),
+                &["john@example.org"][..],
+            ),
+            // HTML parts are converted to text
+            (
+                concat!(
+                    "Content-Type: text/html\r\n\r\n",
+                    "<p>Dear <b>John</b>, claim your prize at https://prize.example.org/claim</p>\r\n"
+                ),
+                &["john@example.org"][..],
+            ),
+        ] {
+            assert_eq!(
+                checksum(message, recipients).as_deref(),
+                Some(expected.as_str()),
+                "{message:?} {recipients:?}"
+            );
+        }
+
+        // Different content
+        assert_ne!(
+            checksum(
+                "Subject: offer\r\n\r\nDear John, claim your refund at https://prize.example.org/claim\r\n",
+                &["john@example.org"],
+            )
+            .unwrap(),
+            expected
+        );
+        assert_ne!(
+            checksum(
+                "Subject: offer\r\n\r\nDear John, claim your prize at https://other.example.org/claim\r\n",
+                &["john@example.org"],
+            )
+            .unwrap(),
+            expected
+        );
+
+        // Nothing left to hash
+        for message in [
+            "Subject: empty\r\n\r\n",
+            "Subject: personal\r\n\r\nJohn john@example.org 12345678\r\n",
+        ] {
+            assert_eq!(
+                checksum(message, &["john@example.org"]),
+                None,
+                "{message:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn checksum_count_expiry() {
+        for (value, expected) in [
+            (Variable::Integer(86400), Some(86400)),
+            (Variable::Float(3600.5), Some(3600)),
+            (Variable::Integer(0), None),
+            (Variable::Integer(-1), None),
+            (Variable::Float(0.5), None),
+            (Variable::Float(-10.0), None),
+            (Variable::from("3600"), None),
+            (Variable::default(), None),
+        ] {
+            assert_eq!(super::expiry(&value), expected, "{value:?}");
+        }
+    }
+}
@@ -0,0 +1,66 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use sieve::{runtime::Variable, FunctionMap};
+
+use super::PluginContext;
+
+pub fn register(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("is_first_contact", plugin_id, 2);
+}
+
+/// Returns whether no mail from the sender domain has been seen for the recipient within
+/// the configured TTL, and records the contact. Each contact extends the TTL, so senders
+/// are only considered new again after a long silence.
+pub fn exec(ctx: PluginContext<'_>) -> Variable {
+    let sender = ctx.arguments[0].to_string().trim().to_lowercase();
+    let recipient = ctx.arguments[1].to_string().trim().to_lowercase();
+    let sender_domain = sender
+        .rsplit_once('@')
+        .map_or(sender.as_str(), |(_, domain)| domain);
+    if sender_domain.is_empty() || recipient.is_empty() {
+        return Variable::default();
+    }
+
+    let store = &ctx.core.storage.lookup;
+    let key = format!("fc:{recipient}:{sender_domain}").into_bytes();
+    let ttl = ctx.core.sieve.first_contact_ttl.as_secs();
+    let result = ctx.handle.block_on(async {
+        let is_known = store.key_exists(key.clone()).await?;
+        store.key_set(key, vec![], ttl.into()).await?;
+        Ok::<_, store::Error>(!is_known)
+    });
+
+    match result {
+        Ok(is_first_contact) => is_first_contact.into(),
+        Err(err) => {
+            tracing::warn!(
+                parent: ctx.span,
+                context = "sieve:is_first_contact",
+                event = "error",
+                reason = %err,
+            );
+            Variable::default()
+        }
+    }
+}
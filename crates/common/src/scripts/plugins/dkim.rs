@@ -0,0 +1,279 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::{Duration, Instant};
+
+use mail_auth::common::lru::DnsCache;
+use mail_parser::decoders::base64::base64_decode;
+use sieve::{runtime::Variable, FunctionMap};
+use store::write::now;
+
+use super::PluginContext;
+
+const KEY_CACHE_TTL: Duration = Duration::from_secs(3600);
+// Maximum clock skew tolerated before a signature is considered future-dated
+const MAX_CLOCK_SKEW: i64 = 300;
+
+pub fn register(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("dkim_key_bits", plugin_id, 0);
+}
+
+pub fn register_timestamps(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("dkim_timestamps", plugin_id, 0);
+}
+
+/// Returns, for each DKIM signature, an array containing the algorithm (`rsa` or
+/// `ed25519`) and the size in bits of the public key published for its selector, or
+/// an empty algorithm and zero if the key could not be retrieved or parsed.
+pub fn exec(ctx: PluginContext<'_>) -> Variable {
+    let raw_message = ctx.message.raw_message();
+    let mut results = Vec::new();
+
+    for header in ctx.message.root_part().headers() {
+        if !header.name.as_str().eq_ignore_ascii_case("DKIM-Signature") {
+            continue;
+        }
+        let signature = raw_message
+            .get(header.offset_start()..header.offset_end())
+            .map(String::from_utf8_lossy)
+            .unwrap_or_default();
+        let mut domain = "";
+        let mut selector = "";
+        for (tag, value) in tags(&signature) {
+            match tag {
+                "d" => domain = value,
+                "s" => selector = value,
+                _ => (),
+            }
+        }
+        if domain.is_empty() || selector.is_empty() {
+            results.push(key_variable(("", 0)));
+            continue;
+        }
+
+        let key = format!("{selector}._domainkey.{domain}.").to_lowercase();
+        let resolvers = &ctx.core.smtp.resolvers;
+        let key_info = if let Some(key_info) = resolvers.cache.dkim_key.get(&key) {
+            key_info
+        } else {
+            match ctx
+                .handle
+                .block_on(resolvers.dns.txt_raw_lookup(key.as_str()))
+            {
+                Ok(record) => resolvers.cache.dkim_key.insert(
+                    key,
+                    key_info(&String::from_utf8_lossy(&record)),
+                    Instant::now() + KEY_CACHE_TTL,
+                ),
+                Err(err) => {
+                    tracing::debug!(
+                        parent: ctx.span,
+                        context = "sieve:dkim_key_bits",
+                        event = "error",
+                        selector = key.as_str(),
+                        reason = %err,
+                        "Failed to retrieve DKIM key."
+                    );
+                    ("", 0)
+                }
+            }
+        };
+
+        results.push(key_variable(key_info));
+    }
+
+    Variable::Array(results.into())
+}
+
+/// Returns, for each DKIM signature, an array containing the signing domain, the
+/// `t=` and `x=` tags (zero when missing) and its status, which is one of `valid`,
+/// `expired`, `future` or `invalid` (expiration not later than the timestamp).
+/// Signatures are not verified.
+pub fn exec_timestamps(ctx: PluginContext<'_>) -> Variable {
+    let raw_message = ctx.message.raw_message();
+    let now = now() as i64;
+    let mut results = Vec::new();
+
+    for header in ctx.message.root_part().headers() {
+        if !header.name.as_str().eq_ignore_ascii_case("DKIM-Signature") {
+            continue;
+        }
+        let signature = raw_message
+            .get(header.offset_start()..header.offset_end())
+            .map(String::from_utf8_lossy)
+            .unwrap_or_default();
+        let mut domain = "";
+        let mut timestamp = None;
+        let mut expiration = None;
+        for (tag, value) in tags(&signature) {
+            match tag {
+                "d" => domain = value,
+                "t" => timestamp = value.parse::<i64>().ok(),
+                "x" => expiration = value.parse::<i64>().ok(),
+                _ => (),
+            }
+        }
+
+        let status = match (timestamp, expiration) {
+            (Some(timestamp), Some(expiration)) if expiration <= timestamp => "invalid",
+            (_, Some(expiration)) if expiration < now => "expired",
+            (Some(timestamp), _) if timestamp > now + MAX_CLOCK_SKEW => "future",
+            _ => "valid",
+        };
+
+        results.push(Variable::Array(
+            vec![
+                Variable::from(domain.to_lowercase()),
+                Variable::Integer(timestamp.unwrap_or_default()),
+                Variable::Integer(expiration.unwrap_or_default()),
+                Variable::from(status),
+            ]
+            .into(),
+        ));
+    }
+
+    Variable::Array(results.into())
+}
+
+fn tags(value: &str) -> impl Iterator<Item = (&str, &str)> {
+    value.split(';').filter_map(|tag| {
+        tag.split_once('=')
+            .map(|(name, value)| (name.trim(), value.trim()))
+    })
+}
+
+fn key_variable((algorithm, bits): (&'static str, i64)) -> Variable {
+    Variable::Array(vec![Variable::from(algorithm), Variable::Integer(bits)].into())
+}
+
+fn key_info(record: &str) -> (&'static str, i64) {
+    let mut is_ed25519 = false;
+    let mut public_key = None;
+    for (tag, value) in tags(record) {
+        match tag {
+            "k" => is_ed25519 = value.eq_ignore_ascii_case("ed25519"),
+            "p" => public_key = Some(value),
+            _ => (),
+        }
+    }
+
+    let Some(der) = public_key
+        .map(|public_key| {
+            public_key
+                .chars()
+                .filter(|ch| !ch.is_ascii_whitespace())
+                .collect::<String>()
+        })
+        .filter(|public_key| !public_key.is_empty())
+        .and_then(|public_key| base64_decode(public_key.as_bytes()))
+    else {
+        return ("", 0);
+    };
+
+    if is_ed25519 {
+        if der.len() == 32 {
+            ("ed25519", 256)
+        } else {
+            ("", 0)
+        }
+    } else {
+        rsa_modulus_bits(&der).map_or(("", 0), |bits| ("rsa", bits))
+    }
+}
+
+// Obtains the modulus size of an RSA key encoded either as a SubjectPublicKeyInfo or as
+// a PKCS#1 RSAPublicKey structure.
+fn rsa_modulus_bits(der: &[u8]) -> Option<i64> {
+    let (tag, contents, _) = der_element(der)?;
+    if tag != 0x30 {
+        return None;
+    }
+    let (tag, value, rest) = der_element(contents)?;
+    match tag {
+        // PKCS#1 RSAPublicKey
+        0x02 => {
+            let modulus = value
+                .iter()
+                .position(|byte| *byte != 0)
+                .map_or(&[][..], |pos| &value[pos..]);
+            let first = *modulus.first()?;
+            Some((modulus.len() * 8) as i64 - first.leading_zeros() as i64)
+        }
+        // SubjectPublicKeyInfo
+        0x30 => {
+            let (tag, value, _) = der_element(rest)?;
+            if tag == 0x03 {
+                rsa_modulus_bits(value.get(1..)?)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+fn der_element(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *der.first()?;
+    let len_byte = *der.get(1)?;
+    let (len, offset) = if len_byte & 0x80 == 0 {
+        (len_byte as usize, 2)
+    } else {
+        let num_bytes = (len_byte & 0x7f) as usize;
+        if num_bytes == 0 || num_bytes > 4 {
+            return None;
+        }
+        let len = der
+            .get(2..2 + num_bytes)?
+            .iter()
+            .fold(0usize, |acc, byte| (acc << 8) | *byte as usize);
+        (len, 2 + num_bytes)
+    };
+    let value = der.get(offset..offset + len)?;
+    Some((tag, value, &der[offset + len..]))
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn dkim_key_info() {
+        for (record, expected) in [
+            (
+                concat!(
+                    "v=DKIM1; k=rsa; p=MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQDwIRP/UC3SBsEmGqZ9ZJW3/DkM",
+                    "oGeLnQg1fWn7/zYtIxN2SnFCjxOCKG9v3b4jYfcTNh5ijSsq631uBItLa7od+v/RtdC2UzJ1lWT947qR+Rc",
+                    "ac2gbto/NMqJ0fzfVjH4OuKhitdY9tf6mcwGjaNBcWToIMmPSPDdQPNUYckcQ2QIDAQAB"
+                ),
+                ("rsa", 1024),
+            ),
+            (
+                "v=DKIM1; k=ed25519; p=11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo=",
+                ("ed25519", 256),
+            ),
+            ("v=DKIM1; k=ed25519; p=11qYAYKxCrfVS/7TyWQHOg==", ("", 0)),
+            ("v=DKIM1; k=rsa; p=", ("", 0)),
+            ("v=DKIM1; k=rsa; p=invalid", ("", 0)),
+        ] {
+            assert_eq!(super::key_info(record), expected, "{record}");
+        }
+    }
+}
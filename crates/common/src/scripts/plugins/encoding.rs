@@ -0,0 +1,371 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use mail_parser::{
+    decoders::{
+        base64::base64_decode, charsets::map::charset_decoder,
+        quoted_printable::quoted_printable_decode,
+    },
+    Encoding, MessagePart, MimeHeaders, PartType,
+};
+use sieve::{runtime::Variable, FunctionMap};
+
+use super::PluginContext;
+
+// Maximum number of bytes inspected on each part
+const MAX_PREFIX_LEN: usize = 4096;
+
+pub fn register(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("cte_anomalies", plugin_id, 0);
+}
+
+pub fn register_anomaly(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("encoding_anomaly", plugin_id, 0);
+}
+
+pub fn register_charset_validity(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("charset_validity", plugin_id, 0);
+}
+
+/// Returns the parts whose contents do not match their declared Content-Transfer-Encoding,
+/// as an array of `[part_id, declared_encoding, anomaly]` entries.
+pub fn exec(ctx: PluginContext<'_>) -> Variable {
+    let raw_message = ctx.message.raw_message();
+    let mut results = Vec::new();
+
+    for (part_id, part) in ctx.message.parts.iter().enumerate() {
+        let declared = part
+            .content_transfer_encoding()
+            .map(|cte| cte.trim().to_ascii_lowercase())
+            .unwrap_or_default();
+        let contents = raw_message
+            .get(part.offset_body..part.offset_end)
+            .unwrap_or_default();
+        let contents = &contents[..std::cmp::min(contents.len(), MAX_PREFIX_LEN)];
+
+        let is_container = matches!(part.body, PartType::Multipart(_) | PartType::Message(_));
+        let anomaly = match declared.as_str() {
+            "" | "7bit" | "8bit" | "binary" if is_container => None,
+            _ if is_container => Some("encoded_container"),
+            "" | "7bit" => has_8bit(contents).then_some("8bit_in_7bit"),
+            "8bit" | "binary" => None,
+            "base64" => base64_anomaly(contents),
+            "quoted-printable" => qp_anomaly(contents),
+            _ => Some("unknown_encoding"),
+        };
+
+        if let Some(anomaly) = anomaly {
+            results.push(Variable::Array(
+                vec![
+                    Variable::Integer(part_id as i64),
+                    Variable::from(declared),
+                    Variable::from(anomaly.to_string()),
+                ]
+                .into(),
+            ));
+        }
+    }
+
+    Variable::Array(results.into())
+}
+
+/// Returns the text/plain and text/html body parts that are base64 encoded even though
+/// their decoded contents are plain ASCII, as an array of `[part_id, content_type, size]` entries.
+pub fn exec_anomaly(ctx: PluginContext<'_>) -> Variable {
+    let message = ctx.message;
+    let mut results = Vec::new();
+
+    // Parts may be listed in both the text and html bodies
+    let mut part_ids = message
+        .text_body
+        .iter()
+        .chain(message.html_body.iter())
+        .copied()
+        .collect::<Vec<_>>();
+    part_ids.sort_unstable();
+    part_ids.dedup();
+
+    for part_id in part_ids {
+        if let Some((content_type, size)) = message.parts.get(part_id).and_then(unnecessary_base64)
+        {
+            results.push(Variable::Array(
+                vec![
+                    Variable::Integer(part_id as i64),
+                    Variable::from(content_type.to_string()),
+                    Variable::Integer(size as i64),
+                ]
+                .into(),
+            ));
+        }
+    }
+
+    Variable::Array(results.into())
+}
+
+/// Returns the text parts whose declared charset is unknown or whose decoded contents
+/// are not valid in that charset, as an array of `[part_id, charset, problem]` entries.
+pub fn exec_charset_validity(ctx: PluginContext<'_>) -> Variable {
+    let raw_message = ctx.message.raw_message();
+    let mut results = Vec::new();
+
+    for (part_id, part) in ctx.message.parts.iter().enumerate() {
+        if !matches!(part.body, PartType::Text(_) | PartType::Html(_)) {
+            continue;
+        }
+        let Some(charset) = part
+            .content_type()
+            .and_then(|ct| ct.attribute("charset"))
+            .map(|charset| charset.trim().to_ascii_lowercase())
+            .filter(|charset| !charset.is_empty())
+        else {
+            continue;
+        };
+
+        let problem = decoded_prefix(part, raw_message).and_then(|(contents, is_truncated)| {
+            charset_problem(&charset, &contents, is_truncated)
+        });
+        if let Some(problem) = problem {
+            results.push(Variable::Array(
+                vec![
+                    Variable::Integer(part_id as i64),
+                    Variable::from(charset),
+                    Variable::from(problem.to_string()),
+                ]
+                .into(),
+            ));
+        }
+    }
+
+    Variable::Array(results.into())
+}
+
+/// Returns the transfer decoded prefix of a part, along with whether it was truncated.
+fn decoded_prefix(part: &MessagePart<'_>, raw_message: &[u8]) -> Option<(Vec<u8>, bool)> {
+    let contents = raw_message.get(part.offset_body..part.offset_end)?;
+
+    match part.encoding {
+        Encoding::None => Some((
+            contents[..std::cmp::min(contents.len(), MAX_PREFIX_LEN)].to_vec(),
+            contents.len() > MAX_PREFIX_LEN,
+        )),
+        Encoding::Base64 => {
+            let mut encoded = contents
+                .iter()
+                .copied()
+                .filter(|ch| ch.is_ascii_alphanumeric() || matches!(ch, b'+' | b'/' | b'='))
+                .collect::<Vec<_>>();
+            let max_len = MAX_PREFIX_LEN / 3 * 4;
+            let is_truncated = encoded.len() > max_len;
+            encoded.truncate(max_len);
+            base64_decode(&encoded).map(|decoded| (decoded, is_truncated))
+        }
+        Encoding::QuotedPrintable => {
+            let mut encoded = &contents[..std::cmp::min(contents.len(), MAX_PREFIX_LEN)];
+            let is_truncated = contents.len() > MAX_PREFIX_LEN;
+            if is_truncated {
+                // Do not cut an escape sequence in half
+                if let Some(pos) = encoded.iter().rev().take(2).position(|&ch| ch == b'=') {
+                    encoded = &encoded[..encoded.len() - pos - 1];
+                }
+            }
+            quoted_printable_decode(encoded).map(|decoded| (decoded, is_truncated))
+        }
+    }
+}
+
+fn charset_problem(charset: &str, contents: &[u8], is_truncated: bool) -> Option<&'static str> {
+    match charset {
+        "us-ascii" | "ascii" | "ansi_x3.4-1968" => has_8bit(contents).then_some("invalid_bytes"),
+        "utf-8" | "utf8" => match std::str::from_utf8(contents) {
+            Ok(_) => None,
+            // A multi-byte sequence cut by the end of the prefix is not an error
+            Err(err) if err.error_len().is_none() && is_truncated => None,
+            Err(_) => Some("invalid_bytes"),
+        },
+        _ => {
+            let Some(decoder) = charset_decoder(charset.as_bytes()) else {
+                return Some("unknown_charset");
+            };
+            let decoded = decoder(contents);
+            let decoded = if is_truncated {
+                decoded.trim_end_matches('\u{fffd}')
+            } else {
+                decoded.as_str()
+            };
+            decoded.contains('\u{fffd}').then_some("invalid_bytes")
+        }
+    }
+}
+
+/// Returns the content type and decoded size of a base64 encoded text part
+/// whose contents are plain ASCII.
+fn unnecessary_base64(part: &MessagePart<'_>) -> Option<(&'static str, usize)> {
+    let (text, content_type) = match &part.body {
+        PartType::Text(text) => (text, "text/plain"),
+        PartType::Html(html) => (html, "text/html"),
+        _ => return None,
+    };
+
+    (!text.is_empty()
+        && text.is_ascii()
+        && part
+            .content_transfer_encoding()
+            .map_or(false, |cte| cte.trim().eq_ignore_ascii_case("base64")))
+    .then_some((content_type, text.len()))
+}
+
+fn has_8bit(contents: &[u8]) -> bool {
+    contents.iter().any(|ch| !ch.is_ascii())
+}
+
+fn base64_anomaly(contents: &[u8]) -> Option<&'static str> {
+    if has_8bit(contents) {
+        return Some("8bit_in_base64");
+    }
+
+    // Decode the prefix, truncated to a whole number of base64 quantums
+    let mut encoded = Vec::with_capacity(contents.len());
+    for &ch in contents {
+        match ch {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'+' | b'/' | b'=' => encoded.push(ch),
+            b'\r' | b'\n' | b' ' | b'\t' => (),
+            _ => return Some("invalid_base64"),
+        }
+    }
+    if contents.len() == MAX_PREFIX_LEN {
+        encoded.truncate(encoded.len() - encoded.len() % 4);
+    }
+
+    if encoded.is_empty() || base64_decode(&encoded).is_some() {
+        None
+    } else {
+        Some("invalid_base64")
+    }
+}
+
+fn qp_anomaly(contents: &[u8]) -> Option<&'static str> {
+    if has_8bit(contents) {
+        return Some("8bit_in_qp");
+    }
+
+    let mut iter = contents.iter().copied().peekable();
+    while let Some(ch) = iter.next() {
+        if ch == b'=' {
+            match (iter.next(), iter.peek().copied()) {
+                // Soft line break, or escape truncated by the end of the prefix
+                (Some(b'\n') | None, _) => (),
+                (Some(b'\r'), Some(b'\n')) => {
+                    iter.next();
+                }
+                (Some(_), None) if contents.len() == MAX_PREFIX_LEN => (),
+                (Some(hex1), Some(hex2))
+                    if hex1.is_ascii_hexdigit() && hex2.is_ascii_hexdigit() =>
+                {
+                    iter.next();
+                }
+                _ => return Some("invalid_qp"),
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use mail_parser::MessageParser;
+
+    use super::{base64_anomaly, charset_problem, qp_anomaly, unnecessary_base64};
+
+    #[test]
+    fn cte_anomalies() {
+        for (contents, expected) in [
+            ("SGVsbG8gd29ybGQh\r\n", None),
+            ("SGVsbG8gd29y\r\nbGQh\r\n", None),
+            ("SGVsbG8gd29ybGQh\r\n<script>\r\n", Some("invalid_base64")),
+            ("SGVsbG8g\u{e9}d29ybGQh\r\n", Some("8bit_in_base64")),
+        ] {
+            assert_eq!(
+                base64_anomaly(contents.as_bytes()),
+                expected,
+                "{contents:?}"
+            );
+        }
+
+        for (contents, expected) in [
+            ("Hello =C3=A9 world=\r\n", None),
+            ("Hello world=\n", None),
+            ("Hello =XY world", Some("invalid_qp")),
+            ("Hello world=", None),
+            ("Hello =A", Some("invalid_qp")),
+            ("Hello \u{e9} world", Some("8bit_in_qp")),
+        ] {
+            assert_eq!(qp_anomaly(contents.as_bytes()), expected, "{contents:?}");
+        }
+    }
+
+    #[test]
+    fn unnecessary_base64_parts() {
+        for (message, expected) in [
+            (
+                "Content-Type: text/plain\r\nContent-Transfer-Encoding: base64\r\n\r\nSGVsbG8gd29ybGQh\r\n",
+                Some(("text/plain", 12)),
+            ),
+            (
+                "Content-Type: text/html\r\nContent-Transfer-Encoding: base64\r\n\r\nPGI+SGk8L2I+\r\n",
+                Some(("text/html", 9)),
+            ),
+            (
+                "Content-Type: text/plain; charset=iso-8859-1\r\nContent-Transfer-Encoding: base64\r\n\r\nSOlsbG8=\r\n",
+                None,
+            ),
+            (
+                "Content-Type: text/plain\r\nContent-Transfer-Encoding: 7bit\r\n\r\nHello world!\r\n",
+                None,
+            ),
+        ] {
+            let message = MessageParser::new().parse(message).unwrap();
+            assert_eq!(unnecessary_base64(&message.parts[0]), expected);
+        }
+    }
+
+    #[test]
+    fn charset_problems() {
+        for (charset, contents, is_truncated, expected) in [
+            ("us-ascii", &b"Hello world"[..], false, None),
+            ("us-ascii", &b"H\xe9llo"[..], false, Some("invalid_bytes")),
+            ("utf-8", "H\u{e9}llo".as_bytes(), false, None),
+            ("utf-8", &b"H\xe9llo"[..], false, Some("invalid_bytes")),
+            ("utf-8", &b"Hello \xc3"[..], true, None),
+            ("utf-8", &b"Hello \xc3"[..], false, Some("invalid_bytes")),
+            ("iso-8859-1", &b"H\xe9llo"[..], false, None),
+            ("x-made-up", &b"Hello"[..], false, Some("unknown_charset")),
+        ] {
+            assert_eq!(
+                charset_problem(charset, contents, is_truncated),
+                expected,
+                "{charset} {contents:?}"
+            );
+        }
+    }
+}
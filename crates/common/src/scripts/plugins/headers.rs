@@ -21,6 +21,8 @@
  * for more details.
 */
 
+use std::net::IpAddr;
+
 use sieve::{runtime::Variable, FunctionMap};
 
 use crate::scripts::ScriptModification;
@@ -31,6 +33,18 @@ pub fn register(plugin_id: u32, fnc_map: &mut FunctionMap) {
     fnc_map.set_external_function("add_header", plugin_id, 2);
 }
 
+pub fn register_suspicious(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("suspicious_headers", plugin_id, 2);
+}
+
+const INTERNAL_HEADERS: &[&str] = &[
+    "x-originating-ip",
+    "x-ms-exchange-organization-*",
+    "x-ms-exchange-crosstenant-*",
+    "x-internal-*",
+    "x-auth-*",
+];
+
 pub fn exec(ctx: PluginContext<'_>) -> Variable {
     if let (Variable::String(name), Variable::String(value)) =
         (&ctx.arguments[0], &ctx.arguments[1])
@@ -45,3 +59,61 @@ pub fn exec(ctx: PluginContext<'_>) -> Variable {
     }
     .into()
 }
+
+/// Returns the names of the headers matching the given patterns that claim an
+/// internal origin, either by referencing an address from the configured internal
+/// networks or by declaring themselves internal, on messages received from an
+/// external address. Patterns ending in `*` match any header with that prefix.
+pub fn exec_suspicious(ctx: PluginContext<'_>) -> Variable {
+    let networks = &ctx.core.sieve.internal_networks;
+    let is_internal = |ip: &IpAddr| networks.iter().any(|network| network.matches(ip));
+    if ctx.arguments[0]
+        .to_string()
+        .trim()
+        .parse::<IpAddr>()
+        .map_or(true, |ip| is_internal(&ip))
+    {
+        return Variable::Array(vec![].into());
+    }
+
+    let patterns = match &ctx.arguments[1] {
+        Variable::Array(patterns) if !patterns.is_empty() => patterns
+            .iter()
+            .map(|pattern| pattern.to_string().trim().to_lowercase())
+            .collect::<Vec<_>>(),
+        _ => INTERNAL_HEADERS.iter().map(|h| h.to_string()).collect(),
+    };
+    let raw_message = ctx.message.raw_message();
+    let mut flagged: Vec<Variable> = Vec::new();
+
+    for header in ctx.message.root_part().headers() {
+        let name = header.name.as_str();
+        let lcase_name = name.to_lowercase();
+        if !patterns.iter().any(|pattern| {
+            pattern.strip_suffix('*').map_or_else(
+                || lcase_name == *pattern,
+                |prefix| lcase_name.starts_with(prefix),
+            )
+        }) || flagged
+            .iter()
+            .any(|v| v.to_string().eq_ignore_ascii_case(name))
+        {
+            continue;
+        }
+
+        let value = raw_message
+            .get(header.offset_start()..header.offset_end())
+            .map(String::from_utf8_lossy)
+            .unwrap_or_default();
+        if value.trim().eq_ignore_ascii_case("internal")
+            || value
+                .split(|ch: char| !ch.is_ascii_hexdigit() && ch != '.' && ch != ':')
+                .filter_map(|token| token.trim_matches(':').parse::<IpAddr>().ok())
+                .any(|ip| is_internal(&ip))
+        {
+            flagged.push(Variable::from(name.to_string()));
+        }
+    }
+
+    Variable::Array(flagged.into())
+}
@@ -0,0 +1,247 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use mail_parser::{HeaderName, HeaderValue};
+use sieve::{runtime::Variable, FunctionMap};
+
+use super::{
+    replyto::{from_domain, reply_to_domains, FREEMAIL_LOOKUP},
+    text::domain_sld,
+    PluginContext,
+};
+
+pub fn register(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("impersonation_signals", plugin_id, 2);
+}
+
+pub fn register_envelope_mismatch(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("envelope_header_mismatch", plugin_id, 1);
+}
+
+pub fn register_self_spoof(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("self_spoof", plugin_id, 3);
+}
+
+/// Evaluates common business email compromise heuristics using the list of domains
+/// with a valid DKIM signature and the name of the lookup containing the display
+/// names of executives, or an empty string to skip that check. Returns an array
+/// containing the combined score and the names of the signals found.
+pub fn exec(ctx: PluginContext<'_>) -> Variable {
+    let psl = &ctx.core.smtp.resolvers.psl;
+    let dkim_domains = ctx.arguments[0]
+        .as_array()
+        .map(|domains| {
+            domains
+                .iter()
+                .map(|domain| domain.to_string().to_lowercase())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let executive_lookup = ctx.arguments[1].to_string();
+
+    let from_domain = from_domain(ctx.message);
+    let Some(from_sld) = domain_sld(psl, &from_domain) else {
+        return Variable::Array(vec![Variable::Float(0.0), Variable::Array(vec![].into())].into());
+    };
+    let from_name = ctx
+        .message
+        .from()
+        .and_then(|a| a.first())
+        .and_then(|a| a.name())
+        .map(normalize_name)
+        .unwrap_or_default();
+
+    let key_exists = |lookup: &str, key: &str| {
+        ctx.core.storage.lookups.get(lookup).map_or(false, |store| {
+            ctx.handle
+                .block_on(store.key_exists(key.as_bytes().to_vec()))
+                .unwrap_or(false)
+        })
+    };
+    let is_freemail_from = key_exists(FREEMAIL_LOOKUP, from_sld);
+
+    let mut score = 0.0;
+    let mut signals = Vec::new();
+
+    // Display name of a known executive sent from a freemail account
+    if is_freemail_from
+        && !from_name.is_empty()
+        && !executive_lookup.is_empty()
+        && key_exists(executive_lookup.as_ref(), &from_name)
+    {
+        score += 3.0;
+        signals.push(Variable::from("executive_freemail".to_string()));
+    }
+
+    // Replies are directed to a different domain
+    let mut has_reply_to_mismatch = false;
+    let mut has_corporate_reply_to = false;
+    for domain in reply_to_domains(ctx.message) {
+        let rto_sld = domain_sld(psl, &domain).unwrap_or(domain.as_str());
+        if rto_sld != from_sld {
+            has_reply_to_mismatch = true;
+            if is_freemail_from && !has_corporate_reply_to && !key_exists(FREEMAIL_LOOKUP, rto_sld)
+            {
+                has_corporate_reply_to = true;
+            }
+        }
+    }
+    if has_reply_to_mismatch {
+        score += 1.0;
+        signals.push(Variable::from("reply_to_mismatch".to_string()));
+    }
+    if has_corporate_reply_to {
+        score += 2.0;
+        signals.push(Variable::from("freemail_corporate_reply_to".to_string()));
+    }
+
+    // No DKIM signature aligned with the From domain
+    if !dkim_domains
+        .iter()
+        .any(|domain| domain_sld(psl, domain).unwrap_or(domain.as_str()) == from_sld)
+    {
+        score += 1.0;
+        signals.push(Variable::from("no_dkim_alignment".to_string()));
+    }
+
+    Variable::Array(vec![Variable::Float(score), Variable::Array(signals.into())].into())
+}
+
+/// Compares the organizational domain of the envelope sender against the domains
+/// of all addresses in the From header(s). Returns an array containing the result
+/// (`aligned`, `partial`, `mismatch`, `null_sender` or `no_from`), the envelope
+/// sender domain and the list of From domains.
+pub fn exec_envelope_mismatch(ctx: PluginContext<'_>) -> Variable {
+    let psl = &ctx.core.smtp.resolvers.psl;
+    let envelope_domain = ctx.arguments[0]
+        .to_string()
+        .trim()
+        .trim_start_matches('<')
+        .trim_end_matches('>')
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.trim().to_lowercase())
+        .unwrap_or_default();
+
+    let mut from_domains = Vec::new();
+    for header in ctx.message.root_part().headers() {
+        if let (HeaderName::From, HeaderValue::Address(address)) = (&header.name, &header.value) {
+            for addr in address.iter() {
+                if let Some(domain) = addr
+                    .address()
+                    .and_then(|a| a.rsplit_once('@'))
+                    .map(|(_, domain)| domain.trim().to_lowercase())
+                    .filter(|domain| !domain.is_empty())
+                {
+                    if !from_domains.contains(&domain) {
+                        from_domains.push(domain);
+                    }
+                }
+            }
+        }
+    }
+
+    let result = if envelope_domain.is_empty() {
+        "null_sender"
+    } else if from_domains.is_empty() {
+        "no_from"
+    } else {
+        let envelope_sld = domain_sld(psl, &envelope_domain).unwrap_or(envelope_domain.as_str());
+        let aligned = from_domains
+            .iter()
+            .filter(|domain| domain_sld(psl, domain).unwrap_or(domain.as_str()) == envelope_sld)
+            .count();
+        if aligned == from_domains.len() {
+            "aligned"
+        } else if aligned > 0 {
+            "partial"
+        } else {
+            "mismatch"
+        }
+    };
+
+    Variable::Array(
+        vec![
+            Variable::from(result.to_string()),
+            Variable::from(envelope_domain),
+            Variable::Array(
+                from_domains
+                    .into_iter()
+                    .map(Variable::from)
+                    .collect::<Vec<_>>()
+                    .into(),
+            ),
+        ]
+        .into(),
+    )
+}
+
+/// Returns true when the header From domain (or its registrable domain) is local to the
+/// given directory but the sender did not authenticate and the message did not pass DMARC.
+/// Expects the directory name (empty for the default directory), the authenticated
+/// account and the DMARC result.
+pub fn exec_self_spoof(ctx: PluginContext<'_>) -> Variable {
+    let is_authenticated = !ctx.arguments[1].to_string().is_empty();
+    let dmarc_pass = ctx.arguments[2]
+        .to_string()
+        .trim()
+        .eq_ignore_ascii_case("pass");
+    let from_domain = from_domain(ctx.message);
+    if is_authenticated || dmarc_pass || from_domain.is_empty() {
+        return false.into();
+    }
+
+    let directory = match &ctx.arguments[0] {
+        Variable::String(v) if !v.is_empty() => ctx.core.storage.directories.get(v.as_ref()),
+        _ => Some(&ctx.core.storage.directory),
+    };
+    let Some(directory) = directory else {
+        tracing::warn!(
+            parent: ctx.span,
+            context = "sieve:self_spoof",
+            event = "failed",
+            reason = "Unknown directory",
+            lookup_id = ctx.arguments[0].to_string().as_ref(),
+        );
+        return Variable::default();
+    };
+
+    let from_sld =
+        domain_sld(&ctx.core.smtp.resolvers.psl, &from_domain).filter(|sld| *sld != from_domain);
+    let is_local = [Some(from_domain.as_str()), from_sld]
+        .into_iter()
+        .flatten()
+        .any(|domain| {
+            ctx.handle
+                .block_on(directory.is_local_domain(domain))
+                .unwrap_or_default()
+        });
+    is_local.into()
+}
+
+fn normalize_name(name: &str) -> String {
+    name.split(|ch: char| ch.is_whitespace() || ch == '"' || ch == '\'')
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
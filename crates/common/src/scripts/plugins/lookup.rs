@@ -29,7 +29,7 @@ use std::{
 
 use mail_auth::flate2;
 use sieve::{runtime::Variable, FunctionMap};
-use store::{Deserialize, Value};
+use store::{Deserialize, LookupStore, Value};
 
 use crate::{config::scripts::RemoteList, scripts::into_sieve_value, USER_AGENT};
 
@@ -55,6 +55,33 @@ pub fn register_local_domain(plugin_id: u32, fnc_map: &mut FunctionMap) {
     fnc_map.set_external_function("is_local_domain", plugin_id, 2);
 }
 
+pub fn register_role_address(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("is_role_address", plugin_id, 1);
+}
+
+pub fn register_allowlist_tier(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("allowlist_tier", plugin_id, 1);
+}
+
+pub fn register_disposable(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("is_disposable", plugin_id, 1);
+}
+
+const ROLE_LOOKUP: &str = "spam-role";
+const DISPOSABLE_LOOKUP: &str = "spam-disposable";
+
+const DISPOSABLE_KEYWORDS: &[&str] = &[
+    "burnermail",
+    "disposable",
+    "fakemail",
+    "minutemail",
+    "temp-mail",
+    "tempmail",
+    "throwaway",
+    "tmpmail",
+    "trashmail",
+];
+
 pub fn exec(ctx: PluginContext<'_>) -> Variable {
     let store = match &ctx.arguments[0] {
         Variable::String(v) if !v.is_empty() => ctx.core.storage.lookups.get(v.as_ref()),
@@ -102,15 +129,7 @@ pub fn exec_get(ctx: PluginContext<'_>) -> Variable {
     };
 
     if let Some(store) = store {
-        ctx.handle
-            .block_on(
-                store.key_get::<VariableWrapper>(
-                    ctx.arguments[1].to_string().into_owned().into_bytes(),
-                ),
-            )
-            .unwrap_or_default()
-            .map(|v| v.into_inner())
-            .unwrap_or_default()
+        key_get(&ctx, store, &ctx.arguments[1]).unwrap_or_default()
     } else {
         tracing::debug!(
             parent: ctx.span,
@@ -136,16 +155,7 @@ pub fn exec_set(ctx: PluginContext<'_>) -> Variable {
             _ => None,
         };
 
-        ctx.handle
-            .block_on(store.key_set(
-                ctx.arguments[1].to_string().into_owned().into_bytes(),
-                if !ctx.arguments[2].is_empty() {
-                    bincode::serialize(&ctx.arguments[2]).unwrap_or_default()
-                } else {
-                    vec![]
-                },
-                expires,
-            ))
+        key_set(&ctx, store, &ctx.arguments[1], &ctx.arguments[2], expires)
             .is_ok()
             .into()
     } else {
@@ -160,6 +170,34 @@ pub fn exec_set(ctx: PluginContext<'_>) -> Variable {
     }
 }
 
+pub(super) fn key_get(
+    ctx: &PluginContext<'_>,
+    store: &LookupStore,
+    key: &Variable,
+) -> store::Result<Variable> {
+    ctx.handle
+        .block_on(store.key_get::<VariableWrapper>(key.to_string().into_owned().into_bytes()))
+        .map(|value| value.map(|v| v.into_inner()).unwrap_or_default())
+}
+
+pub(super) fn key_set(
+    ctx: &PluginContext<'_>,
+    store: &LookupStore,
+    key: &Variable,
+    value: &Variable,
+    expires: Option<u64>,
+) -> store::Result<()> {
+    ctx.handle.block_on(store.key_set(
+        key.to_string().into_owned().into_bytes(),
+        if !value.is_empty() {
+            bincode::serialize(value).unwrap_or_default()
+        } else {
+            vec![]
+        },
+        expires,
+    ))
+}
+
 pub fn exec_remote(ctx: PluginContext<'_>) -> Variable {
     let resource = ctx.arguments[0].to_string();
     let item = ctx.arguments[1].to_string();
@@ -401,6 +439,109 @@ pub fn exec_local_domain(ctx: PluginContext<'_>) -> Variable {
     Variable::default()
 }
 
+/// Returns whether the local part of an address, ignoring any subaddress, is listed
+/// in the role local parts lookup (`maps/localparts_role.list`).
+pub fn exec_role_address(ctx: PluginContext<'_>) -> Variable {
+    let address = ctx.arguments[0].to_string().trim().to_lowercase();
+    let local_part = address
+        .rsplit_once('@')
+        .map_or(address.as_str(), |(local_part, _)| local_part);
+    let local_part = local_part
+        .split_once('+')
+        .map_or(local_part, |(local_part, _)| local_part);
+
+    (!local_part.is_empty()
+        && ctx
+            .core
+            .storage
+            .lookups
+            .get(ROLE_LOOKUP)
+            .map_or(false, |store| {
+                ctx.handle
+                    .block_on(store.key_exists(local_part.as_bytes().to_vec()))
+                    .unwrap_or(false)
+            }))
+    .into()
+}
+
+pub fn exec_disposable(ctx: PluginContext<'_>) -> Variable {
+    let address = ctx.arguments[0].to_string().trim().to_lowercase();
+    let domain = address
+        .rsplit_once('@')
+        .map_or(address.as_str(), |(_, domain)| domain);
+    is_disposable_domain(&ctx, domain).into()
+}
+
+/// Returns whether a lowercase domain, or any of its parent domains, is listed as a
+/// disposable domain, falling back to keywords commonly found in disposable domain names.
+pub(super) fn is_disposable_domain(ctx: &PluginContext<'_>, domain: &str) -> bool {
+    if domain.is_empty() {
+        return false;
+    }
+
+    // Look up the domain and its parent domains in the disposable domains list
+    if let Some(store) = ctx.core.storage.lookups.get(DISPOSABLE_LOOKUP) {
+        let mut domain = domain;
+        loop {
+            if ctx
+                .handle
+                .block_on(store.key_exists(domain.as_bytes().to_vec()))
+                .unwrap_or(false)
+            {
+                return true;
+            }
+            match domain.split_once('.') {
+                Some((_, parent)) if parent.contains('.') => domain = parent,
+                _ => break,
+            }
+        }
+    }
+
+    // Fall back to keywords commonly found in disposable domain names
+    let name = domain.rsplit_once('.').map_or(domain, |(name, _)| name);
+    DISPOSABLE_KEYWORDS
+        .iter()
+        .any(|keyword| name.contains(keyword))
+}
+
+/// Returns the name of the first allowlist, in the configured order of precedence,
+/// that contains either the address or its domain, or an empty string if none do.
+pub fn exec_allowlist_tier(ctx: PluginContext<'_>) -> Variable {
+    let address = ctx.arguments[0].to_string().trim().to_lowercase();
+    if address.is_empty() {
+        return Variable::default();
+    }
+    let domain = address
+        .rsplit_once('@')
+        .map(|(_, domain)| domain)
+        .filter(|domain| !domain.is_empty());
+
+    for tier in &ctx.core.sieve.allowlist_tiers {
+        let Some(store) = ctx.core.storage.lookups.get(tier) else {
+            tracing::debug!(
+                parent: ctx.span,
+                context = "sieve:allowlist_tier",
+                event = "failed",
+                reason = "Unknown lookup id",
+                lookup_id = tier.as_str(),
+            );
+            continue;
+        };
+
+        for key in [Some(address.as_str()), domain].into_iter().flatten() {
+            if ctx
+                .handle
+                .block_on(store.key_exists(key.as_bytes().to_vec()))
+                .unwrap_or(false)
+            {
+                return Variable::from(tier.clone());
+            }
+        }
+    }
+
+    Variable::default()
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct VariableWrapper(Variable);
 
@@ -22,14 +22,30 @@
 */
 
 pub mod bayes;
+pub mod cache;
+pub mod calendar;
+pub mod checksum;
+pub mod contact;
+pub mod dkim;
 pub mod dns;
+pub mod encoding;
 pub mod exec;
 pub mod headers;
 pub mod http;
+pub mod impersonation;
 pub mod lookup;
 pub mod pyzor;
 pub mod query;
+pub mod rdap;
+pub mod recipients;
+pub mod replyto;
+pub mod reputation;
+pub mod rotation;
+pub mod rules;
+pub mod signatures;
+pub mod spf;
 pub mod text;
+pub mod yara;
 
 use mail_parser::Message;
 use sieve::{runtime::Variable, FunctionMap, Input};
@@ -51,7 +67,7 @@ pub struct PluginContext<'x> {
     pub arguments: Vec<Variable>,
 }
 
-const PLUGINS_EXEC: [ExecPluginFnc; 18] = [
+const PLUGINS_EXEC: [ExecPluginFnc; 56] = [
     query::exec,
     exec::exec,
     lookup::exec,
@@ -70,8 +86,46 @@ const PLUGINS_EXEC: [ExecPluginFnc; 18] = [
     headers::exec,
     text::exec_tokenize,
     text::exec_domain_part,
+    checksum::exec,
+    checksum::exec_count,
+    spf::exec_helo,
+    text::exec_hidden_urls,
+    reputation::exec_get,
+    reputation::exec_update,
+    rdap::exec_domain_age,
+    lookup::exec_role_address,
+    lookup::exec_disposable,
+    rules::exec,
+    spf::exec_explain,
+    replyto::exec,
+    recipients::exec,
+    dkim::exec,
+    encoding::exec,
+    impersonation::exec,
+    cache::exec_get,
+    cache::exec_set,
+    cache::exec_incr,
+    text::exec_link_density,
+    lookup::exec_allowlist_tier,
+    yara::exec,
+    impersonation::exec_envelope_mismatch,
+    rules::exec_weighted,
+    headers::exec_suspicious,
+    spf::exec_record_analysis,
+    text::exec_punycode_links,
+    signatures::exec,
+    dkim::exec_timestamps,
+    encoding::exec_anomaly,
+    rotation::exec,
+    encoding::exec_charset_validity,
+    replyto::exec_risk,
+    calendar::exec,
+    contact::exec,
+    text::exec_alternative_divergence,
+    impersonation::exec_self_spoof,
+    recipients::exec_delete,
 ];
-const PLUGINS_REGISTER: [RegisterPluginFnc; 18] = [
+const PLUGINS_REGISTER: [RegisterPluginFnc; 56] = [
     query::register,
     exec::register,
     lookup::register,
@@ -90,6 +144,44 @@ const PLUGINS_REGISTER: [RegisterPluginFnc; 18] = [
     headers::register,
     text::register_tokenize,
     text::register_domain_part,
+    checksum::register,
+    checksum::register_count,
+    spf::register_helo,
+    text::register_hidden_urls,
+    reputation::register_get,
+    reputation::register_update,
+    rdap::register_domain_age,
+    lookup::register_role_address,
+    lookup::register_disposable,
+    rules::register,
+    spf::register_explain,
+    replyto::register,
+    recipients::register,
+    dkim::register,
+    encoding::register,
+    impersonation::register,
+    cache::register_get,
+    cache::register_set,
+    cache::register_incr,
+    text::register_link_density,
+    lookup::register_allowlist_tier,
+    yara::register,
+    impersonation::register_envelope_mismatch,
+    rules::register_weighted,
+    headers::register_suspicious,
+    spf::register_record_analysis,
+    text::register_punycode_links,
+    signatures::register,
+    dkim::register_timestamps,
+    encoding::register_anomaly,
+    rotation::register,
+    encoding::register_charset_validity,
+    replyto::register_risk,
+    calendar::register,
+    contact::register,
+    text::register_alternative_divergence,
+    impersonation::register_self_spoof,
+    recipients::register_delete,
 ];
 
 pub trait RegisterSievePlugins {
@@ -0,0 +1,133 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use sieve::{runtime::Variable, FunctionMap};
+use store::write::now;
+
+use super::PluginContext;
+
+const CACHE_TTL: u64 = 30 * 86400;
+const CACHE_TTL_FAILURE: u64 = 3600;
+
+pub fn register_domain_age(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("domain_age", plugin_id, 1);
+}
+
+/// Returns the number of days since a domain was registered, as reported
+/// by the RDAP server configured in `sieve.trusted.rdap.url`. Results are
+/// cached in the lookup store and `-1` is returned when the registration
+/// date could not be obtained.
+pub fn exec_domain_age(ctx: PluginContext<'_>) -> Variable {
+    let domain = ctx.arguments[0].to_string().trim().to_lowercase();
+    if domain.is_empty() || !domain.contains('.') {
+        return Variable::Integer(-1);
+    }
+
+    // Check the cache first
+    let store = &ctx.core.storage.lookup;
+    let key = format!("rdap:{domain}").into_bytes();
+    let registered = match ctx.handle.block_on(store.key_get::<i64>(key.clone())) {
+        Ok(Some(registered)) => registered,
+        result => {
+            if let Err(err) = result {
+                tracing::debug!(
+                    parent: ctx.span,
+                    context = "sieve:domain_age",
+                    event = "cache-failed",
+                    reason = %err,
+                );
+            }
+
+            let registered = {
+                let _enter = ctx.handle.enter();
+                ctx.handle.block_on(registration_date(
+                    &ctx.core.sieve.rdap_client,
+                    &ctx.core.sieve.rdap_url,
+                    &domain,
+                ))
+            };
+            let registered = match registered {
+                Ok(registered) => registered,
+                Err(reason) => {
+                    tracing::debug!(
+                        parent: ctx.span,
+                        context = "sieve:domain_age",
+                        event = "lookup-failed",
+                        domain = domain,
+                        reason = reason,
+                    );
+                    -1
+                }
+            };
+            let _ = ctx.handle.block_on(store.key_set(
+                key,
+                registered.to_be_bytes().to_vec(),
+                Some(if registered >= 0 {
+                    CACHE_TTL
+                } else {
+                    CACHE_TTL_FAILURE
+                }),
+            ));
+            registered
+        }
+    };
+
+    if registered >= 0 {
+        Variable::Integer((now() as i64).saturating_sub(registered).max(0) / 86400)
+    } else {
+        Variable::Integer(-1)
+    }
+}
+
+async fn registration_date(
+    client: &reqwest::Client,
+    url: &str,
+    domain: &str,
+) -> Result<i64, String> {
+    let response = client
+        .get(format!("{url}{domain}"))
+        .header("Accept", "application/rdap+json")
+        .send()
+        .await
+        .map_err(|err| err.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("RDAP server returned {}", response.status()));
+    }
+    let response = serde_json::from_slice::<serde_json::Value>(
+        &response.bytes().await.map_err(|err| err.to_string())?,
+    )
+    .map_err(|err| err.to_string())?;
+
+    response
+        .get("events")
+        .and_then(|events| events.as_array())
+        .and_then(|events| {
+            events.iter().find(|event| {
+                event.get("eventAction").and_then(|a| a.as_str()) == Some("registration")
+            })
+        })
+        .and_then(|event| event.get("eventDate").and_then(|d| d.as_str()))
+        .and_then(|date| chrono::DateTime::parse_from_rfc3339(date).ok())
+        .map(|date| date.timestamp())
+        .ok_or_else(|| "Registration date not found".to_string())
+}
@@ -0,0 +1,115 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use ahash::AHashSet;
+use mail_parser::{Address, HeaderName, HeaderValue};
+use sieve::{runtime::Variable, FunctionMap};
+
+use crate::scripts::ScriptModification;
+
+use super::PluginContext;
+
+pub fn register(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("recipient_stats", plugin_id, 1);
+}
+
+pub fn register_delete(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("delete_rcpt", plugin_id, 1);
+}
+
+/// Compares the envelope recipients against the recipients listed in the To and Cc headers.
+/// Returns an array containing the number of envelope recipients, the number of visible
+/// recipients, the number of envelope recipients not listed in the headers and whether the
+/// message was sent to undisclosed recipients.
+pub fn exec(ctx: PluginContext<'_>) -> Variable {
+    // Obtain envelope recipients
+    let envelope = match &ctx.arguments[0] {
+        Variable::Array(list) => list
+            .iter()
+            .map(|rcpt| rcpt.to_string().trim().to_lowercase())
+            .filter(|rcpt| !rcpt.is_empty())
+            .collect::<AHashSet<_>>(),
+        rcpt => {
+            let rcpt = rcpt.to_string().trim().to_lowercase();
+            if !rcpt.is_empty() {
+                AHashSet::from_iter([rcpt])
+            } else {
+                AHashSet::new()
+            }
+        }
+    };
+
+    // Obtain visible recipients, including members of groups
+    let mut visible = AHashSet::new();
+    let mut has_to = false;
+    let mut has_empty_group = false;
+    for header in ctx.message.root_part().headers() {
+        if !matches!(header.name, HeaderName::To | HeaderName::Cc) {
+            continue;
+        }
+        if let HeaderValue::Address(address) = &header.value {
+            if let Address::Group(groups) = address {
+                has_empty_group |= groups.iter().any(|group| group.addresses.is_empty());
+            }
+            for addr in address.iter() {
+                if let Some(addr) = addr.address() {
+                    let addr = addr.trim().to_lowercase();
+                    if !addr.is_empty() {
+                        if header.name == HeaderName::To {
+                            has_to = true;
+                        }
+                        visible.insert(addr);
+                    }
+                }
+            }
+        }
+    }
+
+    let hidden = envelope
+        .iter()
+        .filter(|rcpt| !visible.contains(*rcpt))
+        .count();
+
+    Variable::Array(
+        vec![
+            Variable::Integer(envelope.len() as i64),
+            Variable::Integer(visible.len() as i64),
+            Variable::Integer(hidden as i64),
+            Variable::from(!has_to || has_empty_group),
+        ]
+        .into(),
+    )
+}
+
+/// Removes a recipient from the envelope, for example when it expands to no targets.
+pub fn exec_delete(ctx: PluginContext<'_>) -> Variable {
+    let address = ctx.arguments[0].to_string().trim().to_lowercase();
+    if !address.is_empty() {
+        ctx.modifications
+            .push(ScriptModification::DeleteRecipient { address });
+        true
+    } else {
+        false
+    }
+    .into()
+}
@@ -0,0 +1,210 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use mail_parser::{HeaderName, HeaderValue, Message};
+use sieve::{runtime::Variable, FunctionMap};
+
+use super::{lookup::is_disposable_domain, text::domain_sld, PluginContext};
+
+pub(super) const FREEMAIL_LOOKUP: &str = "spam-free";
+
+const NOREPLY_LOCAL_PARTS: &[&str] = &[
+    "donotreply",
+    "donotrespond",
+    "noreply",
+    "noresponse",
+    "noreplies",
+];
+
+pub fn register(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("replyto_mismatch", plugin_id, 0);
+}
+
+pub fn register_risk(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("replyto_risk", plugin_id, 0);
+}
+
+/// Compares the registrable domains of the Reply-To addresses against the From domain.
+/// Returns an array containing whether any Reply-To domain differs from the From domain,
+/// whether a Reply-To address points to a freemail provider while the From domain is not
+/// a freemail provider, and the list of mismatched Reply-To domains.
+pub fn exec(ctx: PluginContext<'_>) -> Variable {
+    let psl = &ctx.core.smtp.resolvers.psl;
+
+    let from_domain = from_domain(ctx.message);
+    let from_sld = domain_sld(psl, &from_domain);
+    let reply_to = reply_to_domains(ctx.message);
+
+    let mut mismatched = Vec::new();
+    let mut is_freemail_reply_to = false;
+    if let Some(from_sld) = from_sld {
+        let store = ctx.core.storage.lookups.get(FREEMAIL_LOOKUP);
+        let is_freemail = |domain: &str| {
+            store.map_or(false, |store| {
+                ctx.handle
+                    .block_on(store.key_exists(domain.as_bytes().to_vec()))
+                    .unwrap_or(false)
+            })
+        };
+        let is_corporate_from = !is_freemail(from_sld);
+
+        for domain in &reply_to {
+            let rto_sld = domain_sld(psl, domain).unwrap_or(domain.as_str());
+            if rto_sld != from_sld {
+                mismatched.push(Variable::from(rto_sld.to_string()));
+                if is_corporate_from && !is_freemail_reply_to && is_freemail(rto_sld) {
+                    is_freemail_reply_to = true;
+                }
+            }
+        }
+    }
+
+    Variable::Array(
+        vec![
+            Variable::from(!mismatched.is_empty()),
+            Variable::from(is_freemail_reply_to),
+            Variable::Array(mismatched.into()),
+        ]
+        .into(),
+    )
+}
+
+/// Scores a Reply-To pointing to a disposable or freemail domain, which is a common
+/// phishing pattern when the From address claims to be a no-reply address.
+/// Returns an array containing the risk score, whether the From address looks like a
+/// no-reply address, whether a Reply-To domain is disposable and whether a Reply-To
+/// domain is a freemail provider. Reply-To domains matching the From domain are ignored.
+/// The score is 2 for a disposable and 1 for a freemail Reply-To, doubled when the
+/// From address is a no-reply address.
+pub fn exec_risk(ctx: PluginContext<'_>) -> Variable {
+    let psl = &ctx.core.smtp.resolvers.psl;
+
+    let from = ctx
+        .message
+        .from()
+        .and_then(|a| a.first())
+        .and_then(|a| a.address())
+        .map(|a| a.trim().to_lowercase())
+        .unwrap_or_default();
+    let (from_local, from_domain) = addr_parts(&from);
+    let from_sld = domain_sld(psl, from_domain).unwrap_or(from_domain);
+    let is_noreply_from = is_noreply(from_local);
+
+    let store = ctx.core.storage.lookups.get(FREEMAIL_LOOKUP);
+    let mut is_disposable_reply_to = false;
+    let mut is_freemail_reply_to = false;
+    for domain in reply_to_domains(ctx.message) {
+        let rto_sld = domain_sld(psl, &domain).unwrap_or(domain.as_str());
+        if rto_sld == from_sld {
+            continue;
+        }
+        if !is_disposable_reply_to && is_disposable_domain(&ctx, &domain) {
+            is_disposable_reply_to = true;
+        }
+        if !is_freemail_reply_to
+            && store.map_or(false, |store| {
+                ctx.handle
+                    .block_on(store.key_exists(rto_sld.as_bytes().to_vec()))
+                    .unwrap_or(false)
+            })
+        {
+            is_freemail_reply_to = true;
+        }
+    }
+
+    let mut score = if is_disposable_reply_to {
+        2
+    } else if is_freemail_reply_to {
+        1
+    } else {
+        0
+    };
+    if is_noreply_from {
+        score *= 2;
+    }
+
+    Variable::Array(
+        vec![
+            Variable::Integer(score),
+            Variable::from(is_noreply_from),
+            Variable::from(is_disposable_reply_to),
+            Variable::from(is_freemail_reply_to),
+        ]
+        .into(),
+    )
+}
+
+/// Splits an address into its local part, without any subaddress, and its domain.
+pub(super) fn addr_parts(address: &str) -> (&str, &str) {
+    let (local_part, domain) = address.rsplit_once('@').unwrap_or((address, ""));
+    let local_part = local_part
+        .split_once('+')
+        .map_or(local_part, |(local_part, _)| local_part);
+    (local_part, domain)
+}
+
+/// Returns whether a lowercase local part looks like a no-reply address,
+/// ignoring separators such as `no-reply`, `no_reply` or `do.not.reply`.
+fn is_noreply(local_part: &str) -> bool {
+    let local_part = local_part
+        .chars()
+        .filter(|ch| !matches!(ch, '-' | '_' | '.'))
+        .collect::<String>();
+    NOREPLY_LOCAL_PARTS
+        .iter()
+        .any(|prefix| local_part.starts_with(prefix))
+}
+
+/// Returns the lowercased domain of the first From address.
+pub(super) fn from_domain(message: &Message<'_>) -> String {
+    message
+        .from()
+        .and_then(|a| a.first())
+        .and_then(|a| a.address())
+        .and_then(|a| a.rsplit_once('@'))
+        .map(|(_, domain)| domain.trim().to_lowercase())
+        .unwrap_or_default()
+}
+
+/// Returns the unique lowercased domains of all Reply-To addresses.
+pub(super) fn reply_to_domains(message: &Message<'_>) -> Vec<String> {
+    let mut reply_to = Vec::new();
+    for header in message.root_part().headers() {
+        if let (HeaderName::ReplyTo, HeaderValue::Address(address)) = (&header.name, &header.value)
+        {
+            for addr in address.iter() {
+                if let Some(domain) = addr
+                    .address()
+                    .and_then(|a| a.rsplit_once('@'))
+                    .map(|(_, domain)| domain.trim().to_lowercase())
+                    .filter(|domain| !domain.is_empty())
+                {
+                    if !reply_to.contains(&domain) {
+                        reply_to.push(domain);
+                    }
+                }
+            }
+        }
+    }
+    reply_to
+}
@@ -0,0 +1,130 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use sieve::{runtime::Variable, FunctionMap};
+use store::write::now;
+
+use super::{lookup::VariableWrapper, PluginContext};
+
+pub fn register_get(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("reputation_get", plugin_id, 1);
+}
+
+pub fn register_update(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("reputation_update", plugin_id, 2);
+}
+
+/// Returns the current reputation score for a key (such as a sender domain,
+/// DKIM signing domain or IP address) after applying time decay.
+pub fn exec_get(ctx: PluginContext<'_>) -> Variable {
+    let key = ctx.arguments[0].to_string();
+    if key.is_empty() {
+        return Variable::default();
+    }
+
+    match get_score(&ctx, key.as_ref()) {
+        Ok(score) => score.into(),
+        Err(err) => {
+            tracing::warn!(
+                parent: ctx.span,
+                context = "sieve:reputation_get",
+                event = "failed",
+                reason = %err,
+            );
+            Variable::default()
+        }
+    }
+}
+
+/// Decays the stored reputation score for a key and adds the provided delta,
+/// returning the updated score.
+pub fn exec_update(ctx: PluginContext<'_>) -> Variable {
+    let key = ctx.arguments[0].to_string();
+    let delta = to_float(&ctx.arguments[1]);
+    if key.is_empty() {
+        return Variable::default();
+    }
+
+    let result = get_score(&ctx, key.as_ref()).and_then(|score| {
+        let score = score + delta;
+        let half_life = ctx.core.sieve.reputation_half_life.as_secs().max(1);
+        ctx.handle
+            .block_on(
+                ctx.core.storage.lookup.key_set(
+                    format!("rep:{key}").into_bytes(),
+                    bincode::serialize(&Variable::from(vec![
+                        Variable::Float(score),
+                        Variable::Integer(now() as i64),
+                    ]))
+                    .unwrap_or_default(),
+                    // Old entries have decayed to a negligible score after 8 half-lives
+                    Some(half_life * 8),
+                ),
+            )
+            .map(|_| score)
+    });
+
+    match result {
+        Ok(score) => score.into(),
+        Err(err) => {
+            tracing::warn!(
+                parent: ctx.span,
+                context = "sieve:reputation_update",
+                event = "failed",
+                reason = %err,
+            );
+            Variable::default()
+        }
+    }
+}
+
+fn get_score(ctx: &PluginContext<'_>, key: &str) -> store::Result<f64> {
+    let entry = ctx
+        .handle
+        .block_on(
+            ctx.core
+                .storage
+                .lookup
+                .key_get::<VariableWrapper>(format!("rep:{key}").into_bytes()),
+        )?
+        .map(|v| v.into_inner());
+
+    if let Some(Variable::Array(entry)) = entry {
+        if let (Some(score), Some(Variable::Integer(updated))) = (entry.first(), entry.get(1)) {
+            let half_life = ctx.core.sieve.reputation_half_life.as_secs().max(1) as f64;
+            let elapsed = now().saturating_sub(*updated as u64) as f64;
+            return Ok(to_float(score) * 0.5f64.powf(elapsed / half_life));
+        }
+    }
+
+    Ok(0.0)
+}
+
+fn to_float(value: &Variable) -> f64 {
+    match value {
+        Variable::Float(v) => *v,
+        Variable::Integer(v) => *v as f64,
+        Variable::String(v) => v.trim().parse().unwrap_or_default(),
+        _ => 0.0,
+    }
+}
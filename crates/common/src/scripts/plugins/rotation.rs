@@ -0,0 +1,82 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use sieve::{runtime::Variable, FunctionMap};
+
+use super::{text::domain_sld, PluginContext};
+
+pub fn register(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("rotation_signals", plugin_id, 2);
+}
+
+/// Records the sender address used by an IP address and returns the number of distinct
+/// local parts and subdomains it has used on the sender's registrable domain within the
+/// configured window, as a `[local_parts, subdomains]` array.
+pub fn exec(ctx: PluginContext<'_>) -> Variable {
+    let sender = ctx.arguments[0].to_string().trim().to_lowercase();
+    let ip = ctx.arguments[1].to_string();
+    let Some((local_part, domain)) = sender
+        .rsplit_once('@')
+        .filter(|(local_part, domain)| !local_part.is_empty() && !domain.is_empty())
+    else {
+        return Variable::default();
+    };
+    if ip.is_empty() {
+        return Variable::default();
+    }
+
+    let sld = domain_sld(&ctx.core.smtp.resolvers.psl, domain).unwrap_or(domain);
+    let store = &ctx.core.storage.lookup;
+    let window = ctx.core.sieve.rotation_window.as_secs();
+    let mut counts = Vec::with_capacity(2);
+
+    for (class, value) in [("l", local_part), ("s", domain)] {
+        let seen_key = format!("rot:{class}:{ip}:{sld}:{value}").into_bytes();
+        let count_key = format!("rot:{class}:{ip}:{sld}").into_bytes();
+
+        // Only values not seen within the window increase the distinct count
+        let result = ctx.handle.block_on(async {
+            if store.key_exists(seen_key.clone()).await? {
+                store.counter_get(count_key).await
+            } else {
+                store.key_set(seen_key, vec![], window.into()).await?;
+                store.counter_incr(count_key, 1, window.into(), true).await
+            }
+        });
+
+        match result {
+            Ok(count) => counts.push(Variable::Integer(count)),
+            Err(err) => {
+                tracing::warn!(
+                    parent: ctx.span,
+                    context = "sieve:rotation_signals",
+                    event = "error",
+                    reason = %err,
+                );
+                return Variable::default();
+            }
+        }
+    }
+
+    Variable::Array(counts.into())
+}
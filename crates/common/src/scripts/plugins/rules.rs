@@ -0,0 +1,436 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::{Duration, Instant};
+
+use ahash::AHashMap;
+
+use regex::{Regex, RegexBuilder};
+use sieve::{runtime::Variable, FunctionMap};
+use utils::config::Config;
+
+use super::{lookup::VariableWrapper, PluginContext};
+
+const REGEX_SIZE_LIMIT: usize = 1024 * 1024;
+
+pub struct Ruleset {
+    pub rules: Vec<Rule>,
+    pub timeout: Duration,
+}
+
+pub struct Rule {
+    pub name: String,
+    pub score: f64,
+    pub target: RuleTarget,
+    pub regex: Regex,
+    pub negate: bool,
+}
+
+#[derive(Clone, Default)]
+pub struct ScoreWeights {
+    pub weights: AHashMap<String, f64>,
+    pub store: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RuleTarget {
+    Header(String),
+    AllHeaders,
+    Body,
+    RawBody,
+    Full,
+}
+
+pub fn register(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("score_rules", plugin_id, 1);
+}
+
+pub fn register_weighted(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("weighted_score", plugin_id, 1);
+}
+
+pub fn exec(ctx: PluginContext<'_>) -> Variable {
+    let id = ctx.arguments[0].to_string();
+    let ruleset = if let Some(ruleset) = ctx.core.sieve.rulesets.get(id.as_ref()) {
+        ruleset
+    } else {
+        tracing::warn!(
+            parent: ctx.span,
+            context = "sieve:score_rules",
+            event = "failed",
+            reason = "Unknown ruleset",
+            ruleset = id.as_ref(),
+        );
+        return Variable::default();
+    };
+
+    let message = ctx.message;
+    let raw_message = message.raw_message();
+    let root_part = message.root_part();
+    let mut body = None;
+    let mut raw_body = None;
+    let mut full = None;
+    let mut score = 0.0;
+    let mut matches = Vec::new();
+    let started = Instant::now();
+
+    for rule in &ruleset.rules {
+        if started.elapsed() > ruleset.timeout {
+            tracing::debug!(
+                parent: ctx.span,
+                context = "sieve:score_rules",
+                event = "timeout",
+                ruleset = id.as_ref(),
+                matched = matches.len(),
+                "Ruleset evaluation exceeded the time limit."
+            );
+            break;
+        }
+
+        let is_match = match &rule.target {
+            RuleTarget::Header(name) => root_part
+                .headers
+                .iter()
+                .filter(|header| header.name.as_str().eq_ignore_ascii_case(name))
+                .any(|header| {
+                    raw_message
+                        .get(header.offset_start()..header.offset_end())
+                        .map_or(false, |value| {
+                            rule.regex.is_match(String::from_utf8_lossy(value).trim())
+                        })
+                }),
+            RuleTarget::AllHeaders => raw_message
+                .get(root_part.raw_header_offset()..root_part.raw_body_offset())
+                .map_or(false, |headers| {
+                    rule.regex.is_match(&String::from_utf8_lossy(headers))
+                }),
+            RuleTarget::Body => rule.regex.is_match(body.get_or_insert_with(|| {
+                (0..message.text_body_count())
+                    .filter_map(|pos| message.body_text(pos))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })),
+            RuleTarget::RawBody => rule.regex.is_match(raw_body.get_or_insert_with(|| {
+                String::from_utf8_lossy(
+                    raw_message
+                        .get(root_part.raw_body_offset()..)
+                        .unwrap_or_default(),
+                )
+            })),
+            RuleTarget::Full => rule
+                .regex
+                .is_match(full.get_or_insert_with(|| String::from_utf8_lossy(raw_message))),
+        };
+
+        if is_match != rule.negate {
+            score += rule.score;
+            matches.push(Variable::from(rule.name.clone()));
+        }
+    }
+
+    Variable::Array(vec![Variable::Float(score), Variable::Array(matches.into())].into())
+}
+
+/// Combines a list of signals into a single score, weighting each one by its
+/// calibrated accuracy. Signals are either names, which contribute a value of 1.0,
+/// or `[name, value]` pairs. Weights stored in the configured lookup store take
+/// precedence over the static weights, and unknown signals have a weight of 1.0.
+pub fn exec_weighted(ctx: PluginContext<'_>) -> Variable {
+    let scoring = &ctx.core.sieve.score_weights;
+    let store = scoring.store.as_ref().and_then(|id| {
+        let store = ctx.core.storage.lookups.get(id);
+        if store.is_none() {
+            tracing::debug!(
+                parent: ctx.span,
+                context = "sieve:weighted_score",
+                event = "failed",
+                reason = "Unknown lookup id",
+                lookup_id = id.as_str(),
+            );
+        }
+        store
+    });
+    let mut score = 0.0;
+
+    for signal in ctx.arguments[0].as_array().into_iter().flatten() {
+        let (name, value) = match signal {
+            Variable::Array(pair) if pair.len() == 2 => (pair[0].to_string(), to_f64(&pair[1])),
+            signal => (signal.to_string(), Some(1.0)),
+        };
+        let Some(value) = value.filter(|_| !name.is_empty()) else {
+            continue;
+        };
+
+        let weight = store
+            .and_then(|store| {
+                ctx.handle
+                    .block_on(store.key_get::<VariableWrapper>(format!("w:{name}").into_bytes()))
+                    .unwrap_or_default()
+                    .and_then(|v| to_f64(&v.into_inner()))
+            })
+            .or_else(|| scoring.weights.get(name.as_ref()).copied())
+            .unwrap_or(1.0);
+        score += weight * value;
+    }
+
+    Variable::Float(score)
+}
+
+fn to_f64(value: &Variable) -> Option<f64> {
+    match value {
+        Variable::Integer(v) => Some(*v as f64),
+        Variable::Float(v) => Some(*v),
+        Variable::String(v) => v.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+impl Ruleset {
+    /// Parses a SpamAssassin style rule file. Only `header`, `body`, `rawbody`
+    /// and `full` regular expression tests are supported, other directives
+    /// such as `describe` or `meta` are ignored. Rules that fail to compile
+    /// are skipped and reported in the returned list of errors.
+    pub fn parse(contents: &str, timeout: Duration) -> (Self, Vec<String>) {
+        let mut rules: Vec<Rule> = Vec::new();
+        let mut scores = Vec::new();
+        let mut errors = Vec::new();
+
+        for (line_num, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (directive, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let (name, rest) = rest
+                .trim()
+                .split_once(char::is_whitespace)
+                .unwrap_or((rest.trim(), ""));
+            let rest = rest.trim();
+            if rest.contains("eval:") {
+                // Eval tests are not supported
+                continue;
+            }
+            let target = match directive {
+                "header" => {
+                    let Some((header, test)) = rest.split_once(char::is_whitespace) else {
+                        errors.push(format!("Invalid header rule at line {}", line_num + 1));
+                        continue;
+                    };
+                    let test = test.trim();
+                    let (negate, test) = if let Some(test) = test.strip_prefix("=~") {
+                        (false, test)
+                    } else if let Some(test) = test.strip_prefix("!~") {
+                        (true, test)
+                    } else {
+                        // Tests such as 'exists:' are not supported
+                        continue;
+                    };
+                    if header.contains(':') {
+                        // Header modifiers are not supported
+                        continue;
+                    }
+                    Some((
+                        if header == "ALL" {
+                            RuleTarget::AllHeaders
+                        } else {
+                            RuleTarget::Header(header.to_string())
+                        },
+                        negate,
+                        test.trim(),
+                    ))
+                }
+                "body" => Some((RuleTarget::Body, false, rest)),
+                "rawbody" => Some((RuleTarget::RawBody, false, rest)),
+                "full" => Some((RuleTarget::Full, false, rest)),
+                "score" => {
+                    match rest
+                        .split_whitespace()
+                        .next()
+                        .and_then(|score| score.parse::<f64>().ok())
+                    {
+                        Some(score) => scores.push((name.to_string(), score)),
+                        None => errors.push(format!("Invalid score at line {}", line_num + 1)),
+                    }
+                    None
+                }
+                _ => None,
+            };
+
+            if let Some((target, negate, test)) = target {
+                // Sub-rules are only used by meta rules, which are not supported
+                if name.is_empty() || name.starts_with("__") {
+                    continue;
+                }
+
+                let regex = match parse_regex(test) {
+                    Ok(regex) => regex,
+                    Err(err) => {
+                        errors.push(format!(
+                            "Invalid regular expression at line {}: {err}",
+                            line_num + 1
+                        ));
+                        continue;
+                    }
+                };
+                rules.retain(|rule| rule.name != name);
+                rules.push(Rule {
+                    name: name.to_string(),
+                    score: 1.0,
+                    target,
+                    regex,
+                    negate,
+                });
+            }
+        }
+
+        for (name, score) in scores {
+            if let Some(rule) = rules.iter_mut().find(|rule| rule.name == name) {
+                rule.score = score;
+            }
+        }
+
+        // Rules with a zero score are disabled
+        rules.retain(|rule| rule.score != 0.0);
+
+        (Ruleset { rules, timeout }, errors)
+    }
+}
+
+impl ScoreWeights {
+    pub fn parse(config: &mut Config) -> Self {
+        let mut weights = AHashMap::new();
+        for (signal, weight) in config
+            .iterate_prefix("sieve.trusted.scoring.weights")
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<Vec<_>>()
+        {
+            match weight.trim().parse::<f64>() {
+                Ok(weight) => {
+                    weights.insert(signal, weight);
+                }
+                Err(_) => config.new_parse_error(
+                    ("sieve.trusted.scoring.weights", signal.as_str()),
+                    format!("Invalid weight {weight:?}"),
+                ),
+            }
+        }
+
+        ScoreWeights {
+            weights,
+            store: config
+                .value("sieve.trusted.scoring.store")
+                .filter(|v| !v.is_empty())
+                .map(|v| v.to_string()),
+        }
+    }
+}
+
+fn parse_regex(test: &str) -> Result<Regex, String> {
+    let test = test.strip_prefix('m').unwrap_or(test);
+    let delimiter = test.chars().next().ok_or("Missing regular expression")?;
+    let end_delimiter = match delimiter {
+        '{' => '}',
+        '(' => ')',
+        '[' => ']',
+        '<' => '>',
+        ch if ch.is_ascii_punctuation() => ch,
+        _ => return Err("Missing regular expression delimiter".to_string()),
+    };
+    let (pattern, flags) = test[delimiter.len_utf8()..]
+        .rsplit_once(end_delimiter)
+        .ok_or("Unterminated regular expression")?;
+
+    let mut builder = RegexBuilder::new(pattern);
+    builder
+        .size_limit(REGEX_SIZE_LIMIT)
+        .dfa_size_limit(REGEX_SIZE_LIMIT);
+    for flag in flags.trim().chars() {
+        match flag {
+            'i' => builder.case_insensitive(true),
+            'm' => builder.multi_line(true),
+            's' => builder.dot_matches_new_line(true),
+            'x' => builder.ignore_whitespace(true),
+            _ => return Err(format!("Unsupported regular expression flag {flag:?}")),
+        };
+    }
+    builder.build().map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{RuleTarget, Ruleset};
+
+    #[test]
+    fn parse_ruleset() {
+        let (ruleset, errors) = Ruleset::parse(
+            r#"
+# Sample rules
+header   SUBJ_FREE      Subject =~ /free\s+money/i
+describe SUBJ_FREE      Subject offers free money
+header   NO_LIST_ID     List-Id !~ /./
+header   FROM_RAW       From:raw =~ /test/
+header   __SUB_RULE     Subject =~ /hello/
+body     BODY_PILLS     m{cheap\s+pills}i
+rawbody  RAW_BASE64     /base64/
+full     FULL_TEST      /X-Mailer/m
+body     BAD_REGEX      /(?<=a)b/
+meta     META_RULE      SUBJ_FREE && BODY_PILLS
+body     EVAL_RULE      eval:check_stuff()
+score    SUBJ_FREE      2.5
+score    BODY_PILLS     1.5 1.0 1.5 1.0
+score    FULL_TEST      0
+score    META_RULE      3.0
+"#,
+            Duration::from_millis(100),
+        );
+
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert_eq!(
+            ruleset
+                .rules
+                .iter()
+                .map(|rule| (rule.name.as_str(), rule.score, &rule.target, rule.negate))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "SUBJ_FREE",
+                    2.5,
+                    &RuleTarget::Header("Subject".to_string()),
+                    false
+                ),
+                (
+                    "NO_LIST_ID",
+                    1.0,
+                    &RuleTarget::Header("List-Id".to_string()),
+                    true
+                ),
+                ("BODY_PILLS", 1.5, &RuleTarget::Body, false),
+                ("RAW_BASE64", 1.0, &RuleTarget::RawBody, false),
+            ]
+        );
+        assert!(ruleset.rules[0].regex.is_match("FREE  Money"));
+        assert!(ruleset.rules[2].regex.is_match("Cheap pills"));
+    }
+}
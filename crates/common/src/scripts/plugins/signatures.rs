@@ -0,0 +1,141 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level store of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use ahash::AHashMap;
+use aho_corasick::AhoCorasick;
+use sieve::{runtime::Variable, FunctionMap};
+use utils::config::Config;
+
+use super::PluginContext;
+
+pub struct ByteSignatures {
+    pub automaton: AhoCorasick,
+    pub names: Vec<String>,
+}
+
+pub fn register(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("byte_signatures", plugin_id, 1);
+}
+
+pub fn exec(ctx: PluginContext<'_>) -> Variable {
+    let set_id = ctx.arguments[0].to_string();
+    let Some(signatures) = ctx.core.sieve.byte_signatures.get(set_id.as_ref()) else {
+        tracing::warn!(
+            parent: ctx.span,
+            context = "sieve:byte_signatures",
+            event = "failed",
+            reason = "Unknown signature set",
+            id = set_id.as_ref(),
+        );
+        return Variable::Array(vec![].into());
+    };
+    let mut matches: Vec<Variable> = Vec::new();
+
+    for pattern_id in signatures
+        .automaton
+        .find_overlapping_iter(ctx.message.raw_message())
+        .map(|m| m.pattern().as_usize())
+    {
+        let name = &signatures.names[pattern_id];
+        if !matches.iter().any(|v| v.to_string().as_ref() == name) {
+            matches.push(Variable::from(name.clone()));
+        }
+    }
+
+    Variable::Array(matches.into())
+}
+
+impl ByteSignatures {
+    /// Compiles the signature sets listed under `sieve.trusted.byte-signatures`,
+    /// where each key is `<set>.<name>` and each value is either a string or a
+    /// hexadecimal pattern prefixed with `hex:`.
+    pub fn parse(config: &mut Config) -> AHashMap<String, ByteSignatures> {
+        let mut patterns: AHashMap<String, (Vec<String>, Vec<Vec<u8>>)> = AHashMap::new();
+        for (key, value) in config
+            .iterate_prefix("sieve.trusted.byte-signatures")
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<Vec<_>>()
+        {
+            let Some((set_id, name)) = key.split_once('.') else {
+                config.new_parse_error(
+                    ("sieve.trusted.byte-signatures", key.as_str()),
+                    "Signatures must be defined as <set>.<name>",
+                );
+                continue;
+            };
+            let pattern = if let Some(hex) = value.strip_prefix("hex:") {
+                match decode_hex(hex) {
+                    Some(pattern) => pattern,
+                    None => {
+                        config.new_parse_error(
+                            ("sieve.trusted.byte-signatures", key.as_str()),
+                            format!("Invalid hexadecimal pattern {hex:?}"),
+                        );
+                        continue;
+                    }
+                }
+            } else {
+                value.into_bytes()
+            };
+            if pattern.is_empty() {
+                config.new_parse_error(
+                    ("sieve.trusted.byte-signatures", key.as_str()),
+                    "Empty patterns are not allowed",
+                );
+                continue;
+            }
+
+            let (names, set_patterns) = patterns.entry(set_id.to_string()).or_default();
+            names.push(name.to_string());
+            set_patterns.push(pattern);
+        }
+
+        let mut signatures = AHashMap::with_capacity(patterns.len());
+        for (set_id, (names, patterns)) in patterns {
+            match AhoCorasick::new(patterns) {
+                Ok(automaton) => {
+                    signatures.insert(set_id, ByteSignatures { automaton, names });
+                }
+                Err(err) => config.new_build_error(
+                    ("sieve.trusted.byte-signatures", set_id.as_str()),
+                    format!("Failed to build signature set: {err}"),
+                ),
+            }
+        }
+
+        signatures
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    let hex = hex
+        .bytes()
+        .filter(|ch| !ch.is_ascii_whitespace())
+        .collect::<Vec<_>>();
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    hex.chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,231 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use mail_auth::{common::lru::DnsCache, hickory_resolver::error::ResolveErrorKind};
+use sieve::{runtime::Variable, FunctionMap};
+
+use super::PluginContext;
+
+const SPF_LOOKUP_LIMIT: usize = 10;
+const SPF_CACHE_TTL: Duration = Duration::from_secs(3600);
+
+pub fn register_helo(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("spf_helo_check", plugin_id, 2);
+}
+
+pub fn exec_helo(ctx: PluginContext<'_>) -> Variable {
+    let ip = if let Ok(ip) = ctx.arguments[0].to_string().parse::<IpAddr>() {
+        ip
+    } else {
+        return Variable::default();
+    };
+    let helo_domain = ctx.arguments[1].to_string().trim().to_lowercase();
+    if helo_domain.is_empty() {
+        return Variable::default();
+    }
+
+    ctx.handle
+        .block_on(ctx.core.smtp.resolvers.dns.verify_spf_helo(
+            ip,
+            &helo_domain,
+            &ctx.core.sieve.hostname,
+        ))
+        .result()
+        .to_string()
+        .to_lowercase()
+        .into()
+}
+
+pub fn register_explain(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("spf_explain", plugin_id, 3);
+}
+
+/// Returns the macro-expanded explanation published using the `exp=` modifier
+/// when the SPF check of the sender fails. DNS lookups go through the shared
+/// resolver and its caches, and are subject to the same lookup limits as the
+/// regular SPF verification.
+pub fn exec_explain(ctx: PluginContext<'_>) -> Variable {
+    let ip = if let Ok(ip) = ctx.arguments[0].to_string().parse::<IpAddr>() {
+        ip
+    } else {
+        return Variable::default();
+    };
+    let helo_domain = ctx.arguments[1].to_string().trim().to_lowercase();
+    let sender = ctx.arguments[2].to_string().trim().to_lowercase();
+    if helo_domain.is_empty() || sender.is_empty() {
+        return Variable::default();
+    }
+
+    ctx.handle
+        .block_on(ctx.core.smtp.resolvers.dns.verify_spf_sender(
+            ip,
+            &helo_domain,
+            &ctx.core.sieve.hostname,
+            &sender,
+        ))
+        .explanation()
+        .map(|explanation| Variable::from(explanation.to_string()))
+        .unwrap_or_default()
+}
+
+pub fn register_record_analysis(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("spf_record_analysis", plugin_id, 1);
+}
+
+/// Analyzes the SPF record published by a domain. Returns an array containing
+/// the qualifier of the top-level `all` mechanism (`+`, `?`, `~`, `-` or empty
+/// if there is none), the number of mechanisms and modifiers requiring a DNS
+/// lookup, the list of included domains and the number of void lookups.
+/// Included and redirected records are followed until the lookup limit is
+/// exceeded, the `all` mechanism of a redirect target counts as top-level.
+pub fn exec_record_analysis(ctx: PluginContext<'_>) -> Variable {
+    let domain = ctx.arguments[0]
+        .to_string()
+        .trim()
+        .trim_end_matches('.')
+        .to_lowercase();
+    if domain.is_empty() {
+        return Variable::default();
+    }
+    let Some(record) = spf_record(&ctx, &domain) else {
+        return Variable::default();
+    };
+
+    let mut all = String::new();
+    let mut lookups = 0;
+    let mut void_lookups = 0;
+    let mut includes: Vec<String> = Vec::new();
+    let mut visited = vec![domain];
+    let mut pending = vec![(record, true)];
+
+    while let Some((record, is_top_level)) = pending.pop() {
+        for term in record.split_ascii_whitespace().skip(1) {
+            let (qualifier, term) = match term.as_bytes().first() {
+                Some(b'+' | b'-' | b'~' | b'?') => term.split_at(1),
+                _ => ("+", term),
+            };
+            let (name, target) = term.split_once([':', '=', '/']).unwrap_or((term, ""));
+
+            match name.to_ascii_lowercase().as_str() {
+                "all" if is_top_level => all = qualifier.to_string(),
+                "a" | "mx" | "ptr" | "exists" => lookups += 1,
+                name @ ("include" | "redirect") => {
+                    lookups += 1;
+                    let target = target.trim_end_matches('.').to_lowercase();
+                    if target.is_empty() {
+                        continue;
+                    }
+                    if name == "include" && !includes.contains(&target) {
+                        includes.push(target.clone());
+                    }
+                    if lookups <= SPF_LOOKUP_LIMIT
+                        && !target.contains('%')
+                        && !visited.contains(&target)
+                    {
+                        if let Some(record) = spf_record(&ctx, &target) {
+                            // The `all` of a redirect target replaces the one of the record
+                            pending.push((record, is_top_level && name == "redirect"));
+                        } else {
+                            void_lookups += 1;
+                        }
+                        visited.push(target);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    Variable::Array(
+        vec![
+            Variable::from(all),
+            Variable::from(lookups),
+            Variable::Array(
+                includes
+                    .into_iter()
+                    .map(Variable::from)
+                    .collect::<Vec<_>>()
+                    .into(),
+            ),
+            Variable::from(void_lookups),
+        ]
+        .into(),
+    )
+}
+
+/// Returns the SPF record published by a domain, caching both records and
+/// domains without one. Domains publishing more than one SPF record are
+/// treated as not having one.
+fn spf_record(ctx: &PluginContext<'_>, domain: &str) -> Option<String> {
+    let resolvers = &ctx.core.smtp.resolvers;
+    let record = if let Some(record) = resolvers.cache.spf_record.get(domain) {
+        record
+    } else {
+        let record = match ctx
+            .handle
+            .block_on(resolvers.txt.txt_lookup(format!("{domain}.")))
+        {
+            Ok(lookup) => {
+                let mut records = lookup.iter().filter_map(|txt| {
+                    let record = txt.txt_data().iter().fold(Vec::new(), |mut record, item| {
+                        record.extend_from_slice(item);
+                        record
+                    });
+                    let record = String::from_utf8_lossy(&record).into_owned();
+                    let version = record.split_ascii_whitespace().next()?;
+                    version.eq_ignore_ascii_case("v=spf1").then_some(record)
+                });
+                match (records.next(), records.next()) {
+                    (Some(record), None) => record,
+                    _ => String::new(),
+                }
+            }
+            Err(err) if matches!(err.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {
+                String::new()
+            }
+            Err(err) => {
+                tracing::debug!(
+                    parent: ctx.span,
+                    context = "sieve:spf_record_analysis",
+                    event = "error",
+                    domain = domain,
+                    reason = %err,
+                    "Failed to retrieve SPF record."
+                );
+                return None;
+            }
+        };
+        resolvers.cache.spf_record.insert(
+            domain.to_string(),
+            record,
+            Instant::now() + SPF_CACHE_TTL,
+        )
+    };
+
+    Some(record).filter(|record| !record.is_empty())
+}
@@ -21,13 +21,28 @@
  * for more details.
 */
 
+use hyper::Uri;
+use mail_parser::{
+    decoders::{base64::base64_decode, html::html_to_text},
+    PartType,
+};
 use nlp::tokenizers::types::{TokenType, TypesTokenizer};
 use sieve::{runtime::Variable, FunctionMap};
+use unicode_security::MixedScript;
+use utils::suffixlist::PublicSuffix;
 
-use crate::scripts::functions::{html::html_to_tokens, text::tokenize_words, ApplyString};
+use crate::scripts::functions::{
+    array::cosine_similarity,
+    html::{html_attr_tokens, html_to_tokens},
+    text::tokenize_words,
+    ApplyString,
+};
 
 use super::PluginContext;
 
+// Alternatives below this similarity are considered divergent
+const DIVERGENCE_THRESHOLD: f64 = 0.5;
+
 #[derive(PartialEq, Eq, Clone, Copy)]
 enum MatchPart {
     Sld,
@@ -43,6 +58,22 @@ pub fn register_domain_part(plugin_id: u32, fnc_map: &mut FunctionMap) {
     fnc_map.set_external_function("domain_part", plugin_id, 2);
 }
 
+pub fn register_hidden_urls(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("hidden_urls", plugin_id, 1);
+}
+
+pub fn register_link_density(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("link_density", plugin_id, 0);
+}
+
+pub fn register_punycode_links(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("punycode_links", plugin_id, 0);
+}
+
+pub fn register_alternative_divergence(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("alternative_divergence", plugin_id, 0);
+}
+
 pub fn exec_tokenize(ctx: PluginContext<'_>) -> Variable {
     let mut v = ctx.arguments;
     let (urls, urls_without_scheme, emails) = match v[1].to_string().as_ref() {
@@ -126,3 +157,303 @@ pub fn exec_domain_part(ctx: PluginContext<'_>) -> Variable {
         }
     })
 }
+
+/// Returns the registrable domain of a lowercase domain name.
+pub fn domain_sld<'x>(psl: &PublicSuffix, domain: &'x str) -> Option<&'x str> {
+    let mut seen_dot = false;
+    for (pos, ch) in domain.as_bytes().iter().enumerate().rev() {
+        if *ch == b'.' {
+            if seen_dot {
+                let maybe_domain = &domain[pos + 1..];
+                if !psl.contains(maybe_domain) {
+                    return Some(maybe_domain);
+                }
+            } else {
+                seen_dot = true;
+            }
+        }
+    }
+
+    if seen_dot {
+        Some(domain)
+    } else {
+        None
+    }
+}
+
+/// Extracts the URLs hidden inside `data:` URIs and base64 encoded blobs found
+/// in the text and HTML parts of the message. The argument limits the total
+/// number of decoded bytes (defaults to 64KB).
+pub fn exec_hidden_urls(ctx: PluginContext<'_>) -> Variable {
+    const MIN_BLOB_LEN: usize = 24;
+    const MAX_BLOB_LEN: usize = 16384;
+
+    let mut bytes_left = match &ctx.arguments[0] {
+        Variable::Integer(limit) if *limit > 0 => *limit as usize,
+        _ => 65536,
+    };
+    let mut urls: Vec<Variable> = Vec::new();
+
+    'outer: for part in &ctx.message.parts {
+        let text = match &part.body {
+            PartType::Text(text) | PartType::Html(text) => text.as_ref(),
+            _ => continue,
+        };
+
+        // Find candidate regions: data URIs and long runs of base64 characters
+        let bytes = text.as_bytes();
+        let mut pos = 0;
+        while pos < bytes.len() {
+            if !is_base64_char(bytes[pos]) {
+                pos += 1;
+                continue;
+            }
+            let start = pos;
+            while pos < bytes.len() && is_base64_char(bytes[pos]) {
+                pos += 1;
+            }
+            let mut blob = &text[start..pos];
+
+            // Skip the media type of data URIs
+            if let Some((_, data)) = blob.split_once(";base64,") {
+                blob = data;
+            } else if let Some(data) = blob.strip_prefix("base64,") {
+                blob = data;
+            }
+
+            if blob.len() < MIN_BLOB_LEN || blob.len() > MAX_BLOB_LEN || !is_plausible_base64(blob)
+            {
+                continue;
+            }
+            if blob.len() > bytes_left {
+                break 'outer;
+            }
+            bytes_left -= blob.len();
+
+            if let Some(decoded) =
+                base64_decode(blob.as_bytes()).and_then(|decoded| String::from_utf8(decoded).ok())
+            {
+                for token in TypesTokenizer::new(&decoded, &ctx.core.smtp.resolvers.psl)
+                    .tokenize_numbers(false)
+                    .tokenize_urls(true)
+                    .tokenize_urls_without_scheme(false)
+                    .tokenize_emails(false)
+                {
+                    if let TokenType::Url(url) = token.word {
+                        let url = Variable::from(url.to_string());
+                        if !urls.contains(&url) {
+                            urls.push(url);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    urls.into()
+}
+
+/// Returns `[domains, ratio]`, where `domains` is the number of distinct registrable
+/// domains linked from the HTML parts of the message and `ratio` is the number of
+/// link characters divided by the number of visible text characters.
+pub fn exec_link_density(ctx: PluginContext<'_>) -> Variable {
+    let psl = &ctx.core.smtp.resolvers.psl;
+    let mut domains: Vec<String> = Vec::new();
+    let mut link_chars = 0;
+    let mut text_chars = 0;
+
+    for part in &ctx.message.parts {
+        let PartType::Html(html) = &part.body else {
+            continue;
+        };
+
+        for href in html_attr_tokens(html, "a", vec!["href".into()]) {
+            let href = href.to_string();
+            let href = href.trim();
+            link_chars += href.chars().count();
+
+            if let Some(host) = href
+                .parse::<Uri>()
+                .ok()
+                .and_then(|uri| uri.host().map(|host| host.to_lowercase()))
+            {
+                if let Some(sld) = domain_sld(psl, &host) {
+                    if !domains.iter().any(|domain| domain == sld) {
+                        domains.push(sld.to_string());
+                    }
+                }
+            }
+        }
+
+        text_chars += html_to_text(html)
+            .chars()
+            .filter(|ch| !ch.is_whitespace())
+            .count();
+    }
+
+    Variable::Array(
+        vec![
+            Variable::from(domains.len()),
+            Variable::Float(link_chars as f64 / std::cmp::max(text_chars, 1) as f64),
+        ]
+        .into(),
+    )
+}
+
+/// Compares the words of the plain text alternative with those of the text extracted from
+/// the HTML alternative, returning `[similarity, is_divergent]` where `similarity` is their
+/// cosine similarity between 0 and 1. Returns an empty value when the message does not
+/// contain both alternatives.
+pub fn exec_alternative_divergence(ctx: PluginContext<'_>) -> Variable {
+    let message = ctx.message;
+    let mut text = String::new();
+    let mut html = String::new();
+
+    for part_id in &message.text_body {
+        if let Some(PartType::Text(contents)) = message.parts.get(*part_id).map(|p| &p.body) {
+            if !message.html_body.contains(part_id) {
+                text.push_str(contents);
+                text.push('\n');
+            }
+        }
+    }
+    for part_id in &message.html_body {
+        if let Some(PartType::Html(contents)) = message.parts.get(*part_id).map(|p| &p.body) {
+            if !message.text_body.contains(part_id) {
+                html.push_str(&html_to_text(contents));
+                html.push('\n');
+            }
+        }
+    }
+
+    let text = alternative_words(&text);
+    let html = alternative_words(&html);
+    if text.is_empty() || html.is_empty() {
+        return Variable::default();
+    }
+
+    let similarity = cosine_similarity(vec![
+        Variable::Array(text.into()),
+        Variable::Array(html.into()),
+    ]);
+
+    Variable::Array(
+        vec![
+            Variable::Float(similarity),
+            Variable::from(similarity < DIVERGENCE_THRESHOLD),
+        ]
+        .into(),
+    )
+}
+
+fn alternative_words(text: &str) -> Vec<Variable> {
+    text.split(|ch: char| !ch.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| Variable::from(word.to_lowercase()))
+        .collect()
+}
+
+/// Returns the links found in the text and HTML parts of the message whose
+/// hostnames are punycode encoded or mix several scripts. Each entry is an array
+/// containing the URL, its hostname, the Unicode form of the hostname and
+/// whether any label of the Unicode form mixes scripts.
+pub fn exec_punycode_links(ctx: PluginContext<'_>) -> Variable {
+    let mut urls: Vec<String> = Vec::new();
+
+    for part in &ctx.message.parts {
+        let text = match &part.body {
+            PartType::Text(text) => text.clone(),
+            PartType::Html(html) => {
+                for href in html_attr_tokens(html, "a", vec!["href".into()]) {
+                    let href = href.to_string().trim().to_string();
+                    if !urls.contains(&href) {
+                        urls.push(href);
+                    }
+                }
+                html_to_text(html).into()
+            }
+            _ => continue,
+        };
+
+        for token in TypesTokenizer::new(text.as_ref(), &ctx.core.smtp.resolvers.psl)
+            .tokenize_numbers(false)
+            .tokenize_urls(true)
+            .tokenize_urls_without_scheme(true)
+            .tokenize_emails(false)
+        {
+            if let TokenType::Url(url) | TokenType::UrlNoScheme(url) = token.word {
+                if !urls.iter().any(|u| u == url) {
+                    urls.push(url.to_string());
+                }
+            }
+        }
+    }
+
+    let mut links = Vec::new();
+    for url in urls {
+        let host = match url_host(&url) {
+            Some(host) => host.to_lowercase(),
+            None => continue,
+        };
+        let is_punycode = host.split('.').any(|label| label.starts_with("xn--"));
+        if !is_punycode && host.is_ascii() {
+            continue;
+        }
+        let unicode_host = host
+            .split('.')
+            .map(|label| {
+                label
+                    .strip_prefix("xn--")
+                    .and_then(idna::punycode::decode_to_string)
+                    .unwrap_or_else(|| label.to_string())
+            })
+            .collect::<Vec<_>>()
+            .join(".");
+        let is_mixed_script = unicode_host
+            .split('.')
+            .any(|label| !label.is_single_script());
+        if is_punycode || is_mixed_script {
+            links.push(Variable::Array(
+                vec![
+                    Variable::from(url),
+                    Variable::from(host),
+                    Variable::from(unicode_host),
+                    Variable::from(is_mixed_script),
+                ]
+                .into(),
+            ));
+        }
+    }
+
+    links.into()
+}
+
+fn is_base64_char(ch: u8) -> bool {
+    ch.is_ascii_alphanumeric() || matches!(ch, b'+' | b'/' | b'=' | b';' | b',' | b'-')
+}
+
+fn is_plausible_base64(blob: &str) -> bool {
+    let blob = blob.trim_end_matches('=');
+    let (mut upper, mut lower, mut digits) = (false, false, false);
+    for ch in blob.bytes() {
+        match ch {
+            b'A'..=b'Z' => upper = true,
+            b'a'..=b'z' => lower = true,
+            b'0'..=b'9' => digits = true,
+            b'+' | b'/' => {}
+            _ => return false,
+        }
+    }
+    upper && lower && (digits || blob.len() > 32)
+}
+
+fn url_host(url: &str) -> Option<&str> {
+    let authority = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = authority.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let host = host.split_once(':').map_or(host, |(host, _)| host);
+    let host = host.trim_end_matches('.');
+    (!host.is_empty()).then_some(host)
+}
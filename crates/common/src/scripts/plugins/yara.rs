@@ -0,0 +1,153 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level store of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+#[cfg(feature = "yara")]
+use mail_parser::PartType;
+use sieve::{runtime::Variable, FunctionMap};
+use utils::config::Config;
+
+use super::PluginContext;
+
+pub struct YaraRules {
+    #[cfg(feature = "yara")]
+    pub rules: yara_x::Rules,
+    pub timeout: Duration,
+    pub max_size: usize,
+}
+
+pub fn register(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("yara_scan", plugin_id, 0);
+}
+
+#[cfg(feature = "yara")]
+pub fn exec(ctx: PluginContext<'_>) -> Variable {
+    let Some(yara) = &ctx.core.sieve.yara else {
+        return Variable::Array(vec![].into());
+    };
+    let mut scanner = yara_x::Scanner::new(&yara.rules);
+    scanner.set_timeout(yara.timeout);
+    let mut matches: Vec<Variable> = Vec::new();
+
+    for part in &ctx.message.parts {
+        if !matches!(part.body, PartType::Binary(_) | PartType::InlineBinary(_))
+            && part.attachment_name().is_none()
+        {
+            continue;
+        }
+        let contents = part.contents();
+        let contents = contents.get(..yara.max_size).unwrap_or(contents);
+        if contents.is_empty() {
+            continue;
+        }
+
+        match scanner.scan(contents) {
+            Ok(results) => {
+                for rule in results.matching_rules() {
+                    let name = rule.identifier();
+                    if !matches.iter().any(|v| v.to_string().as_ref() == name) {
+                        matches.push(Variable::from(name.to_string()));
+                    }
+                }
+            }
+            Err(err) => {
+                tracing::debug!(
+                    parent: ctx.span,
+                    context = "sieve:yara_scan",
+                    event = "failed",
+                    reason = %err,
+                );
+            }
+        }
+    }
+
+    Variable::Array(matches.into())
+}
+
+#[cfg(not(feature = "yara"))]
+pub fn exec(_: PluginContext<'_>) -> Variable {
+    Variable::Array(vec![].into())
+}
+
+impl YaraRules {
+    /// Compiles the rules listed under `sieve.trusted.yara`, either inline
+    /// or from files. Rules are compiled once per configuration load, so
+    /// reloading the configuration also reloads the rule files.
+    pub fn parse(config: &mut Config) -> Option<Self> {
+        let mut sources = config
+            .values("sieve.trusted.yara.contents")
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<Vec<_>>();
+        for (key, path) in config
+            .values("sieve.trusted.yara.files")
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<Vec<_>>()
+        {
+            match std::fs::read_to_string(&path) {
+                Ok(contents) => sources.push((key, contents)),
+                Err(err) => config.new_build_error(
+                    key.as_str(),
+                    format!("Failed to read YARA rules from {path:?}: {err}"),
+                ),
+            }
+        }
+        if sources.is_empty() {
+            return None;
+        }
+
+        #[cfg(feature = "yara")]
+        {
+            let timeout = config
+                .property_or_default::<Duration>("sieve.trusted.yara.timeout", "5s")
+                .unwrap_or_else(|| Duration::from_secs(5));
+            let max_size = config
+                .property_or_default("sieve.trusted.yara.max-size", "10485760")
+                .unwrap_or(10485760);
+            let mut compiler = yara_x::Compiler::new();
+            for (key, contents) in sources {
+                if let Err(err) = compiler.add_source(contents.as_str()) {
+                    config.new_build_error(
+                        key.as_str(),
+                        format!("Failed to compile YARA rules: {err}"),
+                    );
+                }
+            }
+
+            Some(YaraRules {
+                rules: compiler.build(),
+                timeout,
+                max_size,
+            })
+        }
+
+        #[cfg(not(feature = "yara"))]
+        {
+            config.new_build_error(
+                "sieve.trusted.yara",
+                "YARA rules are configured but this build does not include YARA support",
+            );
+            None
+        }
+    }
+}
@@ -262,6 +262,19 @@ impl<T: SessionStream> SessionData<T> {
                                 .log_child_update(Collection::Mailbox, src_mailbox.id.mailbox_id);
                             did_move = true;
                         }
+
+                        // Learn from messages moved in or out of the Junk mailbox
+                        let removed = if is_move {
+                            vec![src_mailbox.id.mailbox_id]
+                        } else {
+                            vec![]
+                        };
+                        self.jmap.spam_learn(
+                            account_id,
+                            id,
+                            &[dest_mailbox_id.mailbox_id],
+                            &removed,
+                        );
                     }
                     Err(MethodError::ServerUnavailable) => {
                         response.rtype = ResponseType::No;
@@ -306,6 +319,12 @@ impl<T: SessionStream> SessionData<T> {
                             debug_assert!(*assigned_uid > 0);
                             copied_ids.push((imap_id.uid, *assigned_uid));
                         }
+                        self.jmap.spam_learn(
+                            dest_account_id,
+                            email.id.document_id(),
+                            &[dest_mailbox_id],
+                            &[],
+                        );
                     }
                     Ok(Err(err)) => {
                         if err.type_ != SetErrorType::NotFound {
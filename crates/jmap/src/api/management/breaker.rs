@@ -0,0 +1,65 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Instant;
+
+use hyper::Method;
+use jmap_proto::error::request::RequestError;
+use serde_json::json;
+
+use crate::{
+    api::{http::ToHttpResponse, HttpRequest, HttpResponse, JsonResponse},
+    JMAP,
+};
+
+impl JMAP {
+    pub async fn handle_manage_breaker(&self, req: &HttpRequest) -> HttpResponse {
+        match req.method() {
+            &Method::GET => {
+                let now = Instant::now();
+                let items = self
+                    .smtp
+                    .breaker_states()
+                    .into_iter()
+                    .map(|(id, state)| {
+                        let remaining = state
+                            .open_until
+                            .and_then(|open_until| open_until.checked_duration_since(now));
+                        json!({
+                            "id": id,
+                            "failures": state.failures,
+                            "open": remaining.is_some(),
+                            "retryIn": remaining.map_or(0, |remaining| remaining.as_secs()),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+
+                JsonResponse::new(json!({
+                        "data": items,
+                }))
+                .into_http_response()
+            }
+            _ => RequestError::not_found().into_http_response(),
+        }
+    }
+}
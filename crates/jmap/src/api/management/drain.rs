@@ -0,0 +1,54 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use hyper::Method;
+use jmap_proto::error::request::RequestError;
+use serde_json::json;
+
+use crate::{
+    api::{http::ToHttpResponse, HttpRequest, HttpResponse, JsonResponse},
+    JMAP,
+};
+
+impl JMAP {
+    pub async fn handle_manage_drain(&self, req: &HttpRequest) -> HttpResponse {
+        let drain = &self.smtp.inner.drain;
+        match req.method() {
+            &Method::GET => {}
+            &Method::POST => {
+                self.smtp.drain();
+            }
+            _ => return RequestError::not_found().into_http_response(),
+        }
+
+        JsonResponse::new(json!({
+            "data": {
+                "draining": drain.is_draining(),
+                "drained": drain.is_drained(),
+                "sessions": drain.active_sessions(),
+                "deliveries": drain.active_deliveries(),
+            },
+        }))
+        .into_http_response()
+    }
+}
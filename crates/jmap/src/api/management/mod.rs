@@ -21,8 +21,10 @@
  * for more details.
 */
 
+pub mod breaker;
 pub mod dkim;
 pub mod domain;
+pub mod drain;
 pub mod log;
 pub mod principal;
 pub mod queue;
@@ -30,6 +32,7 @@ pub mod reload;
 pub mod report;
 pub mod settings;
 pub mod stores;
+pub mod transcript;
 
 use std::{borrow::Cow, sync::Arc};
 
@@ -84,6 +87,9 @@ impl JMAP {
             "domain" if is_superuser => self.handle_manage_domain(req, path).await,
             "store" if is_superuser => self.handle_manage_store(req, path).await,
             "reload" if is_superuser => self.handle_manage_reload(req, path).await,
+            "transcript" if is_superuser => self.handle_manage_transcript(req, path).await,
+            "breaker" if is_superuser => self.handle_manage_breaker(req).await,
+            "drain" if is_superuser => self.handle_manage_drain(req).await,
             "dkim" if is_superuser => self.handle_manage_dkim(req, path, body).await,
             "update" if is_superuser => self.handle_manage_update(req, path).await,
             "logs" if is_superuser && req.method() == Method::GET => {
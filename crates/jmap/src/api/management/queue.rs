@@ -35,7 +35,7 @@ use serde::{Deserializer, Serializer};
 use serde_json::json;
 use smtp::queue::{self, ErrorDetails, HostResponse, QueueId, Status};
 use store::{
-    write::{key::DeserializeBigEndian, now, Bincode, QueueClass, ReportEvent, ValueClass},
+    write::{key::DeserializeBigEndian, now, QueueClass, ReportEvent, ValueClass},
     Deserialize, IterateParams, ValueKey,
 };
 use utils::url_params::UrlParams;
@@ -150,11 +150,12 @@ impl JMAP {
                 let _ = self
                     .core
                     .storage
+                    .queue
                     .data
                     .iterate(
                         IterateParams::new(from_key, to_key).ascending(),
                         |key, value| {
-                            let message = Bincode::<queue::Message>::deserialize(value)?.inner;
+                            let message = <queue::Message as Deserialize>::deserialize(value)?;
                             let matches = !has_filters
                                 || (text
                                     .as_ref()
@@ -395,6 +396,7 @@ impl JMAP {
                 let _ = self
                     .core
                     .storage
+                    .queue
                     .data
                     .iterate(
                         IterateParams::new(from_key, to_key).ascending().no_values(),
@@ -74,6 +74,9 @@ impl JMAP {
                             // Update core
                             if let Some(core) = result.new_core {
                                 self.shared_core.store(core.into());
+
+                                // Breaker thresholds may have changed
+                                self.smtp.reset_breakers();
                             }
                         }
 
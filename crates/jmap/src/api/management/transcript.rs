@@ -0,0 +1,86 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use hyper::Method;
+use jmap_proto::error::request::RequestError;
+use serde_json::json;
+use utils::url_params::UrlParams;
+
+use crate::{
+    api::{http::ToHttpResponse, HttpRequest, HttpResponse, JsonResponse},
+    JMAP,
+};
+
+impl JMAP {
+    pub async fn handle_manage_transcript(
+        &self,
+        req: &HttpRequest,
+        path: Vec<&str>,
+    ) -> HttpResponse {
+        match (path.get(1).copied(), req.method()) {
+            (None, &Method::GET) => {
+                let params = UrlParams::new(req.uri().query());
+                let remote_ip = params.get("remote_ip");
+                let page: usize = params.parse("page").unwrap_or_default();
+                let limit: usize = params.parse("limit").unwrap_or_default();
+
+                let transcripts = self
+                    .smtp
+                    .inner
+                    .transcripts
+                    .list()
+                    .into_iter()
+                    .filter(|entry| remote_ip.map_or(true, |ip| entry.remote_ip.to_string() == ip))
+                    .collect::<Vec<_>>();
+                let total = transcripts.len();
+                let items = transcripts
+                    .into_iter()
+                    .skip(page.saturating_sub(1) * limit)
+                    .take(if limit > 0 { limit } else { total })
+                    .collect::<Vec<_>>();
+
+                JsonResponse::new(json!({
+                        "data": {
+                            "items": items,
+                            "total": total,
+                        },
+                }))
+                .into_http_response()
+            }
+            (Some(correlation_id), &Method::GET) => {
+                if let Some(entry) = u64::from_str_radix(correlation_id, 16)
+                    .ok()
+                    .and_then(|id| self.smtp.inner.transcripts.get(id))
+                {
+                    JsonResponse::new(json!({
+                            "data": entry,
+                    }))
+                    .into_http_response()
+                } else {
+                    RequestError::not_found().into_http_response()
+                }
+            }
+            _ => RequestError::not_found().into_http_response(),
+        }
+    }
+}
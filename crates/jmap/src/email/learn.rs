@@ -0,0 +1,164 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use common::scripts::plugins::bayes::bayes_train;
+use jmap_proto::types::{collection::Collection, property::Property};
+use mail_parser::{parsers::fields::thread::thread_name, MessageParser};
+use store::{
+    write::{Bincode, ValueClass},
+    ValueKey,
+};
+
+use crate::{
+    mailbox::{INBOX_ID, JUNK_ID},
+    JMAP,
+};
+
+use super::metadata::MessageMetadata;
+
+const LEARN_EXPIRY: u64 = 90 * 24 * 60 * 60;
+
+impl JMAP {
+    /// Trains the Bayes classifier in the background after a message changed
+    /// mailboxes: messages added to the Junk mailbox are learned as spam,
+    /// while messages added to the ham folder or moved from Junk back to the
+    /// Inbox are learned as ham. The label learned for each message is
+    /// recorded per account so that a message is never learned twice, and a
+    /// previous opposite label is untrained first.
+    pub fn spam_learn(&self, account_id: u32, document_id: u32, added: &[u32], removed: &[u32]) {
+        if !self.core.jmap.spam_learn || added.is_empty() {
+            return;
+        }
+
+        let jmap = self.clone();
+        let added = added.to_vec();
+        let is_junk_removed = removed.contains(&JUNK_ID);
+        tokio::spawn(async move {
+            let is_spam = if added.contains(&JUNK_ID) {
+                true
+            } else if is_junk_removed && added.contains(&INBOX_ID) {
+                false
+            } else {
+                match jmap
+                    .mailbox_get_by_name(account_id, &jmap.core.jmap.spam_learn_ham_folder)
+                    .await
+                {
+                    Ok(Some(mailbox_id)) if added.contains(&mailbox_id) => false,
+                    _ => return,
+                }
+            };
+
+            if let Err(err) = jmap
+                .spam_learn_message(account_id, document_id, is_spam)
+                .await
+            {
+                tracing::warn!(
+                    context = "spam_learn",
+                    event = "error",
+                    account_id = account_id,
+                    document_id = document_id,
+                    reason = %err,
+                    "Failed to learn message."
+                );
+            }
+        });
+    }
+
+    async fn spam_learn_message(
+        &self,
+        account_id: u32,
+        document_id: u32,
+        is_spam: bool,
+    ) -> store::Result<()> {
+        let store = self
+            .core
+            .jmap
+            .spam_learn_store
+            .as_deref()
+            .map_or(&self.core.storage.lookup, |id| {
+                self.core.get_lookup_store(id)
+            });
+
+        // Obtain message
+        let metadata = match self
+            .core
+            .storage
+            .data
+            .get_value::<Bincode<MessageMetadata>>(ValueKey {
+                account_id,
+                collection: Collection::Email.into(),
+                document_id,
+                class: ValueClass::Property(Property::BodyStructure.into()),
+            })
+            .await?
+        {
+            Some(metadata) => metadata.inner,
+            None => return Ok(()),
+        };
+
+        // Skip messages that were already learned with the same label
+        let mut key = b"sl:".to_vec();
+        key.extend_from_slice(&account_id.to_be_bytes());
+        key.extend_from_slice(metadata.blob_hash.as_ref());
+        let prev_is_spam = store.key_get::<i64>(key.clone()).await?.map(|v| v == 1);
+        if prev_is_spam == Some(is_spam) {
+            return Ok(());
+        }
+
+        let raw_message = match self
+            .core
+            .storage
+            .blob
+            .get_blob(metadata.blob_hash.as_ref(), 0..usize::MAX)
+            .await?
+        {
+            Some(raw_message) => raw_message,
+            None => return Ok(()),
+        };
+        let message = match MessageParser::new().parse(&raw_message) {
+            Some(message) => message,
+            None => return Ok(()),
+        };
+        let text = format!(
+            "{} {}",
+            thread_name(message.subject().unwrap_or_default()),
+            message.body_text(0).unwrap_or_default()
+        );
+
+        // Revert the previous label before learning the new one
+        if let Some(prev_is_spam) = prev_is_spam {
+            bayes_train(&self.core, store, &text, prev_is_spam, false).await?;
+        }
+        if bayes_train(&self.core, store, &text, is_spam, true).await? {
+            store
+                .key_set(
+                    key,
+                    (is_spam as i64).to_be_bytes().to_vec(),
+                    LEARN_EXPIRY.into(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
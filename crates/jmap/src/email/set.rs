@@ -863,6 +863,7 @@ impl JMAP {
             // Log change
             batch.update_document(document_id);
             let mut changed_mailboxes = AHashSet::new();
+            let mut learn_mailboxes = (Vec::new(), Vec::new());
             changes.log_update(Collection::Email, id);
 
             // Process keywords
@@ -976,6 +977,12 @@ impl JMAP {
                     }
                 }
 
+                // Keep track of the mailbox changes for spam learning
+                learn_mailboxes = (
+                    mailboxes.added().iter().map(|m| m.mailbox_id).collect(),
+                    mailboxes.removed().iter().map(|m| m.mailbox_id).collect(),
+                );
+
                 // Update mailboxIds property
                 mailboxes.update_batch(&mut batch, Property::MailboxIds);
             }
@@ -991,6 +998,14 @@ impl JMAP {
                     Ok(_) => {
                         // Add to updated list
                         response.updated.append(id, None);
+
+                        // Learn from messages moved in or out of the Junk mailbox
+                        self.spam_learn(
+                            account_id,
+                            document_id,
+                            &learn_mailboxes.0,
+                            &learn_mailboxes.1,
+                        );
                     }
                     Err(store::Error::AssertValueFailed) => {
                         response.not_updated.append(
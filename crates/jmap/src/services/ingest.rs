@@ -35,6 +35,7 @@ impl JMAP {
         let raw_message = match self
             .core
             .storage
+            .queue
             .blob
             .get_blob(message.message_blob.as_slice(), 0..usize::MAX)
             .await
@@ -87,12 +87,20 @@ async fn main() -> std::io::Result<()> {
         };
     });
 
-    // Wait for shutdown signal
-    wait_for_shutdown(&format!(
-        "Shutting down Stalwart Mail Server v{}...",
-        env!("CARGO_PKG_VERSION")
-    ))
-    .await;
+    // Wait for shutdown signal or for a drain to complete
+    let mut drained_rx = smtp.inner.drain.subscribe_drained();
+    tokio::select! {
+        _ = wait_for_shutdown(&format!(
+            "Shutting down Stalwart Mail Server v{}...",
+            env!("CARGO_PKG_VERSION")
+        )) => {}
+        _ = drained_rx.wait_for(|drained| *drained) => {
+            tracing::info!(
+                "Drain completed, shutting down Stalwart Mail Server v{}...",
+                env!("CARGO_PKG_VERSION")
+            );
+        }
+    }
 
     // Stop services
     let _ = shutdown_tx.send(true);
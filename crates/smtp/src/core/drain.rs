@@ -0,0 +1,174 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use common::listener::{
+    limiter::{ConcurrencyLimiter, InFlight},
+    SessionStream,
+};
+use store::write::now;
+use tokio::sync::watch;
+
+use crate::queue;
+
+use super::{Session, State, SMTP};
+
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct Drain {
+    draining: watch::Sender<bool>,
+    drained: watch::Sender<bool>,
+    sessions: ConcurrencyLimiter,
+    deliveries: ConcurrencyLimiter,
+}
+
+impl Drain {
+    pub fn is_draining(&self) -> bool {
+        *self.draining.borrow()
+    }
+
+    pub fn is_drained(&self) -> bool {
+        *self.drained.borrow()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.draining.subscribe()
+    }
+
+    pub fn subscribe_drained(&self) -> watch::Receiver<bool> {
+        self.drained.subscribe()
+    }
+
+    pub fn session_in_flight(&self) -> Option<InFlight> {
+        self.sessions.is_allowed()
+    }
+
+    pub fn delivery_in_flight(&self) -> Option<InFlight> {
+        self.deliveries.is_allowed()
+    }
+
+    pub fn active_sessions(&self) -> u64 {
+        self.sessions.concurrent.load(Ordering::Relaxed)
+    }
+
+    pub fn active_deliveries(&self) -> u64 {
+        self.deliveries.concurrent.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for Drain {
+    fn default() -> Self {
+        Drain {
+            draining: watch::channel(false).0,
+            drained: watch::channel(false).0,
+            sessions: ConcurrencyLimiter::new(u64::MAX),
+            deliveries: ConcurrencyLimiter::new(u64::MAX),
+        }
+    }
+}
+
+impl SMTP {
+    /// Starts draining the server: new connections are refused, open sessions are closed
+    /// once their current transaction completes and due messages keep being delivered.
+    /// Once no sessions or deliveries are left, or the drain timeout expires, the queue
+    /// is stopped and the drain is reported as completed. Returns `false` when a drain
+    /// is already in progress.
+    pub fn drain(&self) -> bool {
+        if !self
+            .inner
+            .drain
+            .draining
+            .send_if_modified(|draining| !std::mem::replace(draining, true))
+        {
+            return false;
+        }
+
+        let timeout = self.core.smtp.queue.drain_timeout;
+        tracing::info!(
+            context = "drain",
+            event = "start",
+            timeout = timeout.as_secs(),
+            "Draining SMTP sessions and queued deliveries."
+        );
+
+        let core = self.clone();
+        tokio::spawn(async move {
+            let started = Instant::now();
+            while core.inner.drain.active_sessions() > 0
+                || core.inner.drain.active_deliveries() > 0
+                || core.has_due_events().await
+            {
+                if started.elapsed() >= timeout {
+                    tracing::warn!(
+                        context = "drain",
+                        event = "timeout",
+                        sessions = core.inner.drain.active_sessions(),
+                        deliveries = core.inner.drain.active_deliveries(),
+                        "Drain timeout reached with work still in progress."
+                    );
+                    break;
+                }
+                tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+            }
+
+            let _ = core.inner.queue_tx.send(queue::Event::Stop).await;
+            core.inner.drain.drained.send_replace(true);
+            tracing::info!(context = "drain", event = "completed", "Drain completed.");
+        });
+
+        true
+    }
+
+    async fn has_due_events(&self) -> bool {
+        let now = now();
+        self.next_event().await.iter().any(|event| event.due <= now)
+    }
+}
+
+impl<T: SessionStream> Session<T> {
+    /// Sends a 421 reply and returns `true` when the server is draining
+    /// and the session has no transaction in progress.
+    pub async fn close_if_draining(&mut self) -> bool {
+        if self.core.inner.drain.is_draining()
+            && self.data.mail_from.is_none()
+            && matches!(self.state, State::Request(_))
+        {
+            tracing::debug!(
+                parent: &self.span,
+                event = "disconnect",
+                reason = "drain",
+                "Server is draining connections."
+            );
+            self.write(b"421 4.3.2 Server shutting down, try again later.\r\n")
+                .await
+                .ok();
+            true
+        } else {
+            false
+        }
+    }
+}
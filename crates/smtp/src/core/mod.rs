@@ -24,7 +24,7 @@
 use std::{
     hash::Hash,
     net::IpAddr,
-    sync::Arc,
+    sync::{atomic::AtomicUsize, Arc},
     time::{Duration, Instant},
 };
 
@@ -39,6 +39,8 @@ use common::{
 use dashmap::DashMap;
 use directory::Directory;
 use mail_auth::{IprevOutput, SpfOutput};
+use parking_lot::Mutex;
+use rustls::client::Resumption;
 use smtp_proto::request::receiver::{
     BdatReceiver, DataReceiver, DummyDataReceiver, DummyLineReceiver, LineReceiver, RequestReceiver,
 };
@@ -48,16 +50,27 @@ use tokio::{
 };
 use tokio_rustls::TlsConnector;
 use tracing::Span;
-use utils::snowflake::SnowflakeIdGenerator;
+use utils::{rustls_client_config, snowflake::SnowflakeIdGenerator};
 
 use crate::{
-    inbound::auth::SaslToken,
+    inbound::{
+        auth::SaslToken,
+        breaker::BreakerState,
+        header_scanner::HeaderScanner,
+        spool::SpoolFile,
+        terminator::TerminatorScanner,
+        transcript::{Transcript, Transcripts},
+    },
     queue::{self, DomainPart, QueueId},
     reporting,
 };
 
-use self::throttle::{ThrottleKey, ThrottleKeyHasherBuilder};
+use self::{
+    drain::Drain,
+    throttle::{ThrottleKey, ThrottleKeyHasherBuilder},
+};
 
+pub mod drain;
 pub mod params;
 pub mod throttle;
 pub mod worker;
@@ -102,6 +115,10 @@ pub struct Inner {
     pub report_tx: mpsc::Sender<reporting::Event>,
     pub snowflake_id: SnowflakeIdGenerator,
     pub connectors: TlsConnectors,
+    pub transcripts: Arc<Transcripts>,
+    pub circuit_breakers: DashMap<String, BreakerState>,
+    pub drain: Drain,
+    pub spool_memory: AtomicUsize,
     #[cfg(feature = "local_delivery")]
     pub delivery_tx: mpsc::Sender<DeliveryEvent>,
 }
@@ -109,12 +126,66 @@ pub struct Inner {
 pub struct TlsConnectors {
     pub pki_verify: TlsConnector,
     pub dummy_verify: TlsConnector,
+
+    // Connectors that reuse TLS sessions, used for outbound deliveries to
+    // destinations without DANE, MTA-STS or pinned certificates. They are
+    // created on first use and rebuilt when the session cache size changes.
+    pub resume: Mutex<Option<ResumeConnectors>>,
+}
+
+pub struct ResumeConnectors {
+    pub cache_size: usize,
+    pub pki_verify: TlsConnector,
+    pub dummy_verify: TlsConnector,
+}
+
+impl TlsConnectors {
+    pub fn resume(&self, allow_invalid_certs: bool, cache_size: usize) -> TlsConnector {
+        let mut resume = self.resume.lock();
+        if resume
+            .as_ref()
+            .map_or(false, |resume| resume.cache_size != cache_size)
+        {
+            *resume = None;
+        }
+        let resume = resume.get_or_insert_with(|| ResumeConnectors {
+            cache_size,
+            pki_verify: build_tls_connector(false, cache_size),
+            dummy_verify: build_tls_connector(true, cache_size),
+        });
+
+        if allow_invalid_certs {
+            resume.dummy_verify.clone()
+        } else {
+            resume.pki_verify.clone()
+        }
+    }
+}
+
+impl Default for TlsConnectors {
+    fn default() -> Self {
+        TlsConnectors {
+            pki_verify: build_tls_connector(false, 0),
+            dummy_verify: build_tls_connector(true, 0),
+            resume: Mutex::new(None),
+        }
+    }
+}
+
+fn build_tls_connector(allow_invalid_certs: bool, session_cache_size: usize) -> TlsConnector {
+    let mut config = rustls_client_config(allow_invalid_certs);
+    config.resumption = if session_cache_size > 0 {
+        Resumption::in_memory_sessions(session_cache_size)
+    } else {
+        Resumption::disabled()
+    };
+    TlsConnector::from(Arc::new(config))
 }
 
 pub enum State {
     Request(RequestReceiver),
     Bdat(BdatReceiver),
-    Data(DataReceiver),
+    Data(DataReceiver, TerminatorScanner),
     Sasl(LineReceiver<SaslToken>),
     DataTooLarge(DummyDataReceiver),
     RequestTooLarge(DummyLineReceiver),
@@ -142,11 +213,14 @@ pub struct SessionData {
     pub remote_ip_str: String,
     pub remote_port: u16,
     pub helo_domain: String,
+    pub correlation_id: u64,
 
     pub mail_from: Option<SessionAddress>,
     pub rcpt_to: Vec<SessionAddress>,
     pub rcpt_errors: usize,
     pub message: Vec<u8>,
+    pub spool: Option<SpoolFile>,
+    pub headers: HeaderScanner,
 
     pub authenticated_as: String,
     pub authenticated_emails: Vec<String>,
@@ -164,6 +238,11 @@ pub struct SessionData {
     pub spf_ehlo: Option<SpfOutput>,
     pub spf_mail_from: Option<SpfOutput>,
     pub dnsbl_error: Option<Vec<u8>>,
+
+    pub transcript: Option<Transcript>,
+
+    #[cfg(feature = "test_mode")]
+    pub clock: Option<u64>,
 }
 
 #[derive(Clone)]
@@ -183,6 +262,7 @@ pub struct SessionParameters {
     // Ehlo parameters
     pub ehlo_require: bool,
     pub ehlo_reject_non_fqdn: bool,
+    pub ehlo_require_iprev: bool,
 
     // Auth parameters
     pub auth_directory: Option<Arc<Directory>>,
@@ -191,6 +271,9 @@ pub struct SessionParameters {
     pub auth_errors_wait: Duration,
     pub auth_match_sender: bool,
 
+    // Mail parameters
+    pub mail_require_starttls: bool,
+
     // Rcpt parameters
     pub rcpt_errors_max: usize,
     pub rcpt_errors_wait: Duration,
@@ -199,6 +282,9 @@ pub struct SessionParameters {
     pub can_expn: bool,
     pub can_vrfy: bool,
     pub max_message_size: usize,
+    pub max_header_size: usize,
+    pub max_headers: usize,
+    pub spool_threshold: usize,
 
     // Mail authentication parameters
     pub iprev: VerifyStrategy,
@@ -216,6 +302,7 @@ impl SessionData {
             remote_ip_str: remote_ip.to_string(),
             remote_port,
             helo_domain: String::new(),
+            correlation_id: 0,
             mail_from: None,
             rcpt_to: Vec::new(),
             authenticated_as: String::new(),
@@ -224,6 +311,8 @@ impl SessionData {
             valid_until: Instant::now(),
             rcpt_errors: 0,
             message: Vec::with_capacity(0),
+            spool: None,
+            headers: HeaderScanner::new(),
             auth_errors: 0,
             messages_sent: 0,
             bytes_left: 0,
@@ -233,6 +322,9 @@ impl SessionData {
             spf_ehlo: None,
             spf_mail_from: None,
             dnsbl_error: None,
+            transcript: None,
+            #[cfg(feature = "test_mode")]
+            clock: None,
         }
     }
 }
@@ -322,6 +414,7 @@ impl Session<common::listener::stream::NullIo> {
                 timeout: Default::default(),
                 ehlo_require: Default::default(),
                 ehlo_reject_non_fqdn: Default::default(),
+                ehlo_require_iprev: Default::default(),
                 auth_directory: Default::default(),
                 auth_require: Default::default(),
                 auth_errors_max: Default::default(),
@@ -331,7 +424,11 @@ impl Session<common::listener::stream::NullIo> {
                 rcpt_max: Default::default(),
                 rcpt_dsn: Default::default(),
                 max_message_size: Default::default(),
+                max_header_size: Default::default(),
+                max_headers: Default::default(),
+                spool_threshold: Default::default(),
                 auth_match_sender: false,
+                mail_require_starttls: false,
                 iprev: VerifyStrategy::Disable,
                 spf_ehlo: VerifyStrategy::Disable,
                 spf_mail_from: VerifyStrategy::Disable,
@@ -385,10 +482,13 @@ impl SessionData {
             remote_port: 0,
             local_port: 0,
             helo_domain: "localhost".into(),
+            correlation_id: 0,
             mail_from,
             rcpt_to,
             rcpt_errors: 0,
             message,
+            spool: None,
+            headers: HeaderScanner::new(),
             authenticated_as: "local".into(),
             authenticated_emails: vec![],
             auth_errors: 0,
@@ -402,6 +502,9 @@ impl SessionData {
             spf_ehlo: None,
             spf_mail_from: None,
             dnsbl_error: None,
+            transcript: None,
+            #[cfg(feature = "test_mode")]
+            clock: None,
         }
     }
 }
@@ -440,10 +543,11 @@ impl Default for Inner {
             queue_tx: mpsc::channel(1).0,
             report_tx: mpsc::channel(1).0,
             snowflake_id: Default::default(),
-            connectors: TlsConnectors {
-                pki_verify: mail_send::smtp::tls::build_tls_connector(false),
-                dummy_verify: mail_send::smtp::tls::build_tls_connector(true),
-            },
+            connectors: Default::default(),
+            transcripts: Default::default(),
+            circuit_breakers: Default::default(),
+            drain: Default::default(),
+            spool_memory: Default::default(),
             delivery_tx: mpsc::channel(1).0,
         }
     }
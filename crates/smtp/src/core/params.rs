@@ -82,6 +82,12 @@ impl<T: SessionStream> Session<T> {
             .eval_if(&ec.reject_non_fqdn, self)
             .await
             .unwrap_or(true);
+        self.params.ehlo_require_iprev = self
+            .core
+            .core
+            .eval_if(&ec.require_iprev, self)
+            .await
+            .unwrap_or(false);
 
         // Auth parameters
         let ac = &self.core.core.smtp.session.auth;
@@ -117,6 +123,14 @@ impl<T: SessionStream> Session<T> {
             .await
             .unwrap_or(true);
 
+        // Mail parameters
+        self.params.mail_require_starttls = self
+            .core
+            .core
+            .eval_if(&self.core.core.smtp.session.mail.require_starttls, self)
+            .await
+            .unwrap_or(false);
+
         // VRFY/EXPN parameters
         let ec = &self.core.core.smtp.session.extensions;
         self.params.can_expn = self
@@ -177,11 +191,49 @@ impl<T: SessionStream> Session<T> {
             .await
             .unwrap_or(true);
 
-        self.params.max_message_size = self
+        self.params.max_message_size = self.eval_max_message_size().await;
+        self.params.max_header_size = self
+            .core
+            .core
+            .eval_if(&self.core.core.smtp.session.data.max_header_size, self)
+            .await
+            .unwrap_or(1024 * 1024);
+        self.params.max_headers = self
+            .core
+            .core
+            .eval_if(&self.core.core.smtp.session.data.max_headers, self)
+            .await
+            .unwrap_or(1000);
+        self.params.spool_threshold = self
+            .core
+            .core
+            .eval_if(&self.core.core.smtp.session.data.spool_threshold, self)
+            .await
+            .unwrap_or(0);
+    }
+
+    pub async fn eval_max_message_size(&self) -> usize {
+        let max_size = self
             .core
             .core
             .eval_if(&self.core.core.smtp.session.data.max_message_size, self)
             .await
             .unwrap_or(25 * 1024 * 1024);
+
+        // Per-sender limits can only lower the global limit
+        match self
+            .core
+            .core
+            .eval_if::<usize, _>(
+                &self.core.core.smtp.session.data.max_sender_message_size,
+                self,
+            )
+            .await
+        {
+            Some(sender_size) if sender_size > 0 && (max_size == 0 || sender_size < max_size) => {
+                sender_size
+            }
+            _ => max_size,
+        }
     }
 }
@@ -0,0 +1,107 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use common::listener::SessionStream;
+use mail_builder::mime::make_boundary;
+use mail_parser::{decoders::html::html_to_text, MessageParser, PartType};
+
+use crate::core::Session;
+
+use super::tracking::quoted_printable_encode;
+
+impl<T: SessionStream> Session<T> {
+    /// Converts HTML-only messages into a multipart/alternative message that
+    /// includes a plain text rendering of the HTML body. The message is only
+    /// converted when the conversion is enabled for every recipient.
+    pub async fn add_text_alternative(&self, raw_message: &[u8]) -> Option<Vec<u8>> {
+        if !self
+            .is_enabled_for_all_rcpts(&self.core.core.smtp.session.data.text_alternative.enable)
+            .await
+        {
+            return None;
+        }
+
+        let message = MessageParser::new().parse(raw_message)?;
+        let part = message.parts.first().filter(|_| message.parts.len() == 1)?;
+        let PartType::Html(html) = &part.body else {
+            return None;
+        };
+
+        // Split the content headers from the rest of the message headers
+        let mut edited_message = Vec::with_capacity(raw_message.len() * 2);
+        let mut content_headers = Vec::new();
+        let mut has_mime_version = false;
+        for header in &part.headers {
+            let name = header.name.as_str();
+            let buf = if header.name.is_mime_header() || name.starts_with("Content-") {
+                &mut content_headers
+            } else {
+                has_mime_version |= name.eq_ignore_ascii_case("MIME-Version");
+                &mut edited_message
+            };
+            buf.extend_from_slice(name.as_bytes());
+            buf.push(b':');
+            buf.extend_from_slice(
+                raw_message
+                    .get(header.offset_start..header.offset_end)
+                    .unwrap_or_default(),
+            );
+        }
+
+        // Build the multipart/alternative message
+        let boundary = make_boundary("_");
+        if !has_mime_version {
+            edited_message.extend_from_slice(b"MIME-Version: 1.0\r\n");
+        }
+        edited_message.extend_from_slice(b"Content-Type: multipart/alternative;\r\n\tboundary=\"");
+        edited_message.extend_from_slice(boundary.as_bytes());
+        edited_message.extend_from_slice(b"\"\r\n\r\n--");
+        edited_message.extend_from_slice(boundary.as_bytes());
+        edited_message.extend_from_slice(
+            concat!(
+                "\r\nContent-Type: text/plain; charset=\"utf-8\"\r\n",
+                "Content-Transfer-Encoding: quoted-printable\r\n\r\n"
+            )
+            .as_bytes(),
+        );
+        edited_message.extend_from_slice(&quoted_printable_encode(html_to_text(html).as_bytes()));
+        edited_message.extend_from_slice(b"\r\n--");
+        edited_message.extend_from_slice(boundary.as_bytes());
+        edited_message.extend_from_slice(b"\r\n");
+        edited_message.extend_from_slice(&content_headers);
+        edited_message.extend_from_slice(b"\r\n");
+        edited_message.extend_from_slice(raw_message.get(part.offset_body..part.offset_end)?);
+        edited_message.extend_from_slice(b"\r\n--");
+        edited_message.extend_from_slice(boundary.as_bytes());
+        edited_message.extend_from_slice(b"--\r\n");
+
+        tracing::debug!(
+            parent: &self.span,
+            context = "data",
+            event = "text-alternative",
+            "Added plain text alternative to HTML-only message."
+        );
+
+        Some(edited_message)
+    }
+}
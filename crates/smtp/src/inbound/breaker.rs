@@ -0,0 +1,133 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    sync::{Arc, Weak},
+    time::Instant,
+};
+
+use common::config::smtp::session::CircuitBreaker;
+use opentelemetry::{global, KeyValue};
+
+use crate::core::{Inner, SMTP};
+
+const BREAKER_CLOSED: u64 = 0;
+const BREAKER_OPEN: u64 = 1;
+const BREAKER_HALF_OPEN: u64 = 2;
+
+#[derive(Debug, Default, Clone)]
+pub struct BreakerState {
+    pub failures: u32,
+    pub open_until: Option<Instant>,
+}
+
+impl SMTP {
+    /// Returns true while the breaker is open, meaning that the dependency
+    /// failed too many times in a row and should not be contacted.
+    pub fn is_breaker_open(&self, breaker: &CircuitBreaker) -> bool {
+        self.inner
+            .circuit_breakers
+            .get(&breaker.id)
+            .and_then(|state| state.open_until)
+            .map_or(false, |open_until| open_until > Instant::now())
+    }
+
+    pub fn breaker_success(&self, breaker: &CircuitBreaker) {
+        if let Some((_, state)) = self.inner.circuit_breakers.remove(&breaker.id) {
+            if state.open_until.is_some() {
+                tracing::info!(
+                    context = "circuit-breaker",
+                    event = "close",
+                    id = breaker.id.as_str(),
+                    "Dependency recovered, closing circuit breaker."
+                );
+            }
+        }
+    }
+
+    pub fn breaker_failure(&self, breaker: &CircuitBreaker) {
+        let mut state = self
+            .inner
+            .circuit_breakers
+            .entry(breaker.id.clone())
+            .or_default();
+        state.failures += 1;
+        if state.failures >= breaker.max_failures {
+            state.open_until = Some(Instant::now() + breaker.cooldown);
+            tracing::warn!(
+                context = "circuit-breaker",
+                event = "open",
+                id = breaker.id.as_str(),
+                failures = state.failures,
+                cooldown = ?breaker.cooldown,
+                action = ?breaker.action,
+                "Dependency failed too many times, opening circuit breaker."
+            );
+        }
+    }
+
+    /// Forgets the state of all breakers, used after a configuration reload
+    /// as breakers may have been removed or their thresholds changed.
+    pub fn reset_breakers(&self) {
+        self.inner.circuit_breakers.clear();
+    }
+
+    pub fn breaker_states(&self) -> Vec<(String, BreakerState)> {
+        self.inner
+            .circuit_breakers
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect()
+    }
+}
+
+impl BreakerState {
+    pub fn status(&self, now: Instant) -> u64 {
+        match self.open_until {
+            Some(open_until) if open_until > now => BREAKER_OPEN,
+            Some(_) => BREAKER_HALF_OPEN,
+            None => BREAKER_CLOSED,
+        }
+    }
+}
+
+/// Registers the `smtp.circuit_breaker.state` gauge, which reports for each breaker
+/// with recorded failures whether it is closed (0), open (1) or half-open (2).
+pub fn register_breaker_gauge(inner: &Arc<Inner>) {
+    let inner: Weak<Inner> = Arc::downgrade(inner);
+    global::meter("stalwart-smtp")
+        .u64_observable_gauge("smtp.circuit_breaker.state")
+        .with_description("Circuit breaker state: 0 = closed, 1 = open, 2 = half-open")
+        .with_callback(move |gauge| {
+            if let Some(inner) = inner.upgrade() {
+                let now = Instant::now();
+                for entry in inner.circuit_breakers.iter() {
+                    gauge.observe(
+                        entry.value().status(now),
+                        &[KeyValue::new("id", entry.key().clone())],
+                    );
+                }
+            }
+        })
+        .init();
+}
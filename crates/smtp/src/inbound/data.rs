@@ -24,18 +24,32 @@
 use std::{
     borrow::Cow,
     process::Stdio,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     time::{Duration, SystemTime},
 };
 
 use common::{
-    config::smtp::auth::VerifyStrategy, listener::SessionStream, scripts::ScriptModification,
+    addresses::verp_decode,
+    config::smtp::{
+        auth::VerifyStrategy,
+        session::{DuplicateAction, LineEndings, MessageIdToken},
+    },
+    listener::SessionStream,
+    scripts::ScriptModification,
 };
 use mail_auth::{
     common::{headers::HeaderWriter, verify::VerifySignature},
-    dmarc, AuthenticatedMessage, AuthenticationResults, DkimResult, DmarcResult, ReceivedSpf,
+    dmarc,
+    report::{self, PolicyPublished},
+    AuthenticatedMessage, AuthenticationResults, DkimResult, DmarcOutput, DmarcResult, ReceivedSpf,
+    SpfResult,
 };
 use mail_builder::headers::{date::Date, message_id::generate_message_id_header};
+use mail_parser::MessageParser;
+use rand::{distributions::Alphanumeric, Rng};
 use sieve::runtime::Variable;
 use smtp_proto::{
     MAIL_BY_RETURN, RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_NEVER, RCPT_NOTIFY_SUCCESS,
@@ -46,16 +60,40 @@ use utils::config::Rate;
 
 use crate::{
     core::{Session, SessionAddress, State},
-    queue::{self, Message, SimpleEnvelope},
-    scripts::ScriptResult,
+    queue::{self, scan::DeferredScan, Message, SimpleEnvelope},
+    scripts::{ScriptResult, StoredParameters},
 };
 
 use super::{ArcSeal, AuthResult, DkimSign};
 
 impl<T: SessionStream> Session<T> {
     pub async fn queue_message(&mut self) -> Cow<'static, [u8]> {
+        // Handle bare CR and LF characters before any DKIM verification takes place
+        let mut raw_message = std::mem::take(&mut self.data.message);
+        match self
+            .core
+            .core
+            .eval_if(&self.core.core.smtp.session.data.line_endings, self)
+            .await
+            .unwrap_or_default()
+        {
+            LineEndings::Strict if has_bare_line_endings(&raw_message) => {
+                tracing::info!(parent: &self.span,
+                    context = "data",
+                    event = "bare-line-endings",
+                    size = raw_message.len(),
+                    "Message rejected due to bare CR or LF characters.");
+
+                return (&b"500 5.5.2 Message contains bare CR or LF characters.\r\n"[..]).into();
+            }
+            LineEndings::Lenient if has_bare_line_endings(&raw_message) => {
+                raw_message = normalize_line_endings(&raw_message);
+            }
+            _ => (),
+        }
+
         // Authenticate message
-        let raw_message = Arc::new(std::mem::take(&mut self.data.message));
+        let raw_message = Arc::new(raw_message);
         let auth_message = if let Some(auth_message) = AuthenticatedMessage::parse(&raw_message) {
             auth_message
         } else {
@@ -235,7 +273,7 @@ impl<T: SessionStream> Session<T> {
         }
 
         // Verify DMARC
-        let (dmarc_result, dmarc_policy) = match &self.data.spf_mail_from {
+        let (dmarc_result, dmarc_policy, dmarc_alignment) = match &self.data.spf_mail_from {
             Some(spf_output) if dmarc.verify() => {
                 let dmarc_output = self
                     .core
@@ -255,10 +293,10 @@ impl<T: SessionStream> Session<T> {
                     )
                     .await;
 
-                let rejected = dmarc.is_strict()
-                    && dmarc_output.policy() == dmarc::Policy::Reject
+                let rejected = dmarc_output.policy() == dmarc::Policy::Reject
                     && !(matches!(dmarc_output.spf_result(), DmarcResult::Pass)
-                        || matches!(dmarc_output.dkim_result(), DmarcResult::Pass));
+                        || matches!(dmarc_output.dkim_result(), DmarcResult::Pass))
+                    && (dmarc.is_strict() || self.is_dmarc_enforced(&dmarc_output).await);
                 let is_temp_fail = rejected
                     && matches!(dmarc_output.spf_result(), DmarcResult::TempError(_))
                     || matches!(dmarc_output.dkim_result(), DmarcResult::TempError(_));
@@ -278,6 +316,25 @@ impl<T: SessionStream> Session<T> {
                 };
                 let dmarc_policy = dmarc_output.policy();
 
+                // Obtain aligned identifiers and alignment modes
+                let mut aligned = Vec::with_capacity(2);
+                if dmarc_output.spf_result() == &DmarcResult::Pass {
+                    aligned.push(Variable::from("spf"));
+                }
+                if dmarc_output.dkim_result() == &DmarcResult::Pass {
+                    aligned.push(Variable::from("dkim"));
+                }
+                let (aspf, adkim) = dmarc_output
+                    .dmarc_record_cloned()
+                    .map(|record| {
+                        let policy = PolicyPublished::from_record(
+                            dmarc_output.domain().to_string(),
+                            &record,
+                        );
+                        (alignment_mode(&policy.aspf), alignment_mode(&policy.adkim))
+                    })
+                    .unwrap_or_default();
+
                 if !rejected {
                     tracing::debug!(parent: &self.span,
                     context = "dmarc",
@@ -317,11 +374,69 @@ impl<T: SessionStream> Session<T> {
                     };
                 }
 
-                (dmarc_result.into(), dmarc_policy.into())
+                (
+                    dmarc_result.into(),
+                    dmarc_policy.into(),
+                    (aligned, aspf, adkim).into(),
+                )
             }
-            _ => (None, None),
+            _ => (None, None, None),
         };
 
+        // Reject messages that fail all authentication mechanisms
+        if self.data.authenticated_as.is_empty()
+            && self
+                .core
+                .core
+                .eval_if(&ac.require_pass.enable, self)
+                .await
+                .unwrap_or(false)
+        {
+            let spf_result = self.data.spf_mail_from.as_ref().map(|spf| spf.result());
+            let dkim_result = dkim_output
+                .iter()
+                .map(|d| d.result())
+                .find(|r| matches!(r, DkimResult::Pass))
+                .or_else(|| dkim_output.first().map(|d| d.result()));
+
+            if !matches!(spf_result, Some(SpfResult::Pass))
+                && !matches!(dkim_result, Some(DkimResult::Pass))
+                && !matches!(dmarc_result, Some(DmarcResult::Pass))
+                && !self.is_require_pass_allow_listed(&auth_message).await
+            {
+                let is_temp_fail = matches!(spf_result, Some(SpfResult::TempError))
+                    || matches!(dkim_result, Some(DkimResult::TempError(_)))
+                    || matches!(dmarc_result, Some(DmarcResult::TempError(_)));
+                let spf = spf_result.map_or_else(|| "none".to_string(), |r| r.to_string());
+                let dkim = dkim_result.map_or_else(|| "none".to_string(), |r| r.to_string());
+                let dmarc = dmarc_result
+                    .as_ref()
+                    .map_or_else(|| "none".to_string(), |r| r.to_string());
+
+                tracing::info!(parent: &self.span,
+                    context = "auth",
+                    event = "rejected",
+                    return_path = mail_from.address,
+                    from = auth_message.from(),
+                    spf_result = spf,
+                    dkim_result = dkim,
+                    dmarc_result = dmarc,
+                    "No authentication mechanism passed.");
+
+                return if is_temp_fail {
+                    format!(
+                        "451 4.7.26 Authentication temporarily failed (spf={spf}, dkim={dkim}, dmarc={dmarc}).\r\n"
+                    )
+                } else {
+                    format!(
+                        "550 5.7.26 No authentication mechanism passed (spf={spf}, dkim={dkim}, dmarc={dmarc}).\r\n"
+                    )
+                }
+                .into_bytes()
+                .into();
+            }
+        }
+
         // Analyze reports
         if self.is_report() {
             self.core.analyze_report(raw_message.clone());
@@ -359,6 +474,18 @@ impl<T: SessionStream> Session<T> {
             Err(response) => return response,
         };
 
+        // Run ICAP scanners
+        match self
+            .run_icap(edited_message.as_ref().unwrap_or(&raw_message))
+            .await
+        {
+            Ok(Some(modified_message)) => {
+                edited_message = Arc::new(modified_message).into();
+            }
+            Ok(None) => (),
+            Err(response) => return response,
+        }
+
         // Pipe message
         for pipe in &dc.pipe_commands {
             if let Some(command_) = self
@@ -462,14 +589,56 @@ impl<T: SessionStream> Session<T> {
             }
         }
 
+        // Attribute bounces addressed to VERP encoded return paths of local domains
+        let mut verp_recipients = vec![];
+        if self
+            .data
+            .mail_from
+            .as_ref()
+            .map_or(false, |m| m.address.is_empty())
+        {
+            if let Some(directory) = self
+                .core
+                .core
+                .eval_if::<String, _>(&self.core.core.smtp.session.rcpt.directory, self)
+                .await
+                .and_then(|name| self.core.core.get_directory(&name))
+            {
+                for rcpt in &self.data.rcpt_to {
+                    if let Some(verp_rcpt) = verp_decode(&rcpt.address_lcase) {
+                        if directory
+                            .is_local_domain(&rcpt.domain)
+                            .await
+                            .unwrap_or(false)
+                        {
+                            verp_recipients.push(verp_rcpt);
+                        }
+                    }
+                }
+            }
+        }
+        for rcpt in &verp_recipients {
+            tracing::info!(parent: &self.span,
+                context = "verp",
+                event = "bounce",
+                rcpt = rcpt,
+                "Received bounce for VERP encoded recipient.");
+        }
+
         // Sieve filtering
         let mut headers = Vec::with_capacity(64);
-        if let Some(script) = self
+        let mut deferred_scan = None;
+        if let Some((script_name, script)) = self
             .core
             .core
             .eval_if::<String, _>(&dc.script, self)
             .await
-            .and_then(|name| self.core.core.get_sieve_script(&name))
+            .and_then(|name| {
+                self.core
+                    .core
+                    .get_sieve_script(&name)
+                    .map(|script| (name, script))
+            })
         {
             let params = self
                 .build_script_parameters("data")
@@ -517,44 +686,165 @@ impl<T: SessionStream> Session<T> {
                         .as_ref()
                         .map(|a| a.as_str())
                         .unwrap_or_default(),
+                )
+                .set_variable(
+                    "dmarc.aligned",
+                    dmarc_alignment
+                        .as_ref()
+                        .map(|(aligned, _, _)| aligned.clone())
+                        .unwrap_or_default(),
+                )
+                .set_variable(
+                    "dmarc.aspf",
+                    dmarc_alignment
+                        .as_ref()
+                        .map(|(_, aspf, _)| *aspf)
+                        .unwrap_or_default(),
+                )
+                .set_variable(
+                    "dmarc.adkim",
+                    dmarc_alignment
+                        .as_ref()
+                        .map(|(_, _, adkim)| *adkim)
+                        .unwrap_or_default(),
+                )
+                .set_variable(
+                    "verp.recipients",
+                    verp_recipients
+                        .iter()
+                        .map(|rcpt| Variable::from(rcpt.clone()))
+                        .collect::<Vec<_>>(),
                 );
 
-            let modifications = match self.run_script(script.clone(), params).await {
-                ScriptResult::Accept { modifications } => modifications,
-                ScriptResult::Replace {
-                    message,
-                    modifications,
-                } => {
-                    edited_message = Arc::new(message).into();
-                    modifications
+            if self.is_deferred_scan(raw_message.len()).await {
+                let quarantine = self
+                    .core
+                    .core
+                    .eval_if::<String, _>(&dc.deferred_scan.quarantine, self)
+                    .await
+                    .filter(|addr| !addr.is_empty());
+                let params = params.with_envelope(&self.core.core, self).await;
+                deferred_scan = DeferredScan {
+                    script: script_name,
+                    quarantine,
+                    params: StoredParameters::from(&params),
+                    headers_len: 0,
+                    signers: vec![],
+                    journal: None,
                 }
-                ScriptResult::Reject(message) => {
-                    tracing::info!(parent: &self.span,
-                        context = "sieve",
-                        event = "reject",
-                        reason = message);
+                .into();
+            } else {
+                let modifications = match self.run_script(script.clone(), params).await {
+                    ScriptResult::Accept { modifications } => modifications,
+                    ScriptResult::Replace {
+                        message,
+                        modifications,
+                    } => {
+                        edited_message = Arc::new(message).into();
+                        modifications
+                    }
+                    ScriptResult::Reject(message) => {
+                        tracing::info!(parent: &self.span,
+                            context = "sieve",
+                            event = "reject",
+                            reason = message);
 
-                    return message.into_bytes().into();
-                }
-                ScriptResult::Discard => {
-                    return (b"250 2.0.0 Message queued for delivery.\r\n"[..]).into();
-                }
-            };
-
-            // Apply modifications
-            for modification in modifications {
-                match modification {
-                    ScriptModification::AddHeader { name, value } => {
-                        headers.extend_from_slice(name.as_bytes());
-                        headers.extend_from_slice(b": ");
-                        headers.extend_from_slice(value.as_bytes());
-                        if !value.ends_with('\n') {
-                            headers.extend_from_slice(b"\r\n");
+                        return message.into_bytes().into();
+                    }
+                    ScriptResult::Discard => {
+                        return (b"250 2.0.0 Message queued for delivery.\r\n"[..]).into();
+                    }
+                };
+
+                // Apply modifications
+                for modification in modifications {
+                    match modification {
+                        ScriptModification::AddHeader { name, value } => {
+                            headers.extend_from_slice(name.as_bytes());
+                            headers.extend_from_slice(b": ");
+                            headers.extend_from_slice(value.as_bytes());
+                            if !value.ends_with('\n') {
+                                headers.extend_from_slice(b"\r\n");
+                            }
+                        }
+                        ScriptModification::SetEnvelope { name, value } => {
+                            self.data.apply_envelope_modification(name, value);
+                        }
+                        ScriptModification::DeleteRecipient { address } => {
+                            self.data.delete_recipient(&address);
                         }
                     }
-                    ScriptModification::SetEnvelope { name, value } => {
-                        self.data.apply_envelope_modification(name, value);
+                }
+            }
+        }
+
+        // Remove active content from HTML parts
+        let mut html_sanitized = false;
+        if let Some(sanitized_message) = self
+            .strip_dangerous_html(edited_message.as_ref().unwrap_or(&raw_message))
+            .await
+        {
+            edited_message = Arc::new(sanitized_message).into();
+            html_sanitized = true;
+        }
+
+        // Remove tracking pixels
+        let mut tracking_pixels_removed = false;
+        if let Some(stripped_message) = self
+            .strip_tracking_pixels(edited_message.as_ref().unwrap_or(&raw_message))
+            .await
+        {
+            edited_message = Arc::new(stripped_message).into();
+            tracking_pixels_removed = true;
+        }
+
+        // Add a plain text alternative to HTML-only messages
+        let mut text_alternative_added = false;
+        if let Some(converted_message) = self
+            .add_text_alternative(edited_message.as_ref().unwrap_or(&raw_message))
+            .await
+        {
+            edited_message = Arc::new(converted_message).into();
+            text_alternative_added = true;
+        }
+
+        // Make sure there are recipients left after expansion
+        if self.data.rcpt_to.is_empty() {
+            tracing::info!(parent: &self.span,
+                context = "data",
+                event = "no-recipients",
+                return_path = self.data.mail_from.as_ref().unwrap().address,
+                "No valid recipients left after expansion.");
+
+            return (&b"554 5.5.1 No valid recipients.\r\n"[..]).into();
+        }
+
+        // Duplicate Message-ID detection
+        let duplicate_action: DuplicateAction = self
+            .core
+            .core
+            .eval_if(&dc.duplicate.action, self)
+            .await
+            .unwrap_or_default();
+        if duplicate_action != DuplicateAction::Accept {
+            if let Some(message_id) = MessageParser::new()
+                .parse_headers(raw_message.as_slice())
+                .and_then(|message| message.message_id().map(|id| id.to_string()))
+            {
+                if self.is_duplicate_message(&message_id).await {
+                    tracing::info!(parent: &self.span,
+                        context = "data",
+                        event = "duplicate",
+                        return_path = self.data.mail_from.as_ref().unwrap().address,
+                        message_id = message_id,
+                        action = ?duplicate_action,
+                        "Duplicate Message-ID detected.");
+
+                    if duplicate_action == DuplicateAction::Reject {
+                        return (&b"550 5.7.1 Duplicate message rejected.\r\n"[..]).into();
                     }
+                    headers.extend_from_slice(dc.duplicate.header.as_bytes());
+                    headers.extend_from_slice(b": yes\r\n");
                 }
             }
         }
@@ -647,7 +937,11 @@ impl<T: SessionStream> Session<T> {
                 .unwrap_or(true)
         {
             headers.extend_from_slice(b"Message-ID: ");
-            let _ = generate_message_id_header(&mut headers, &self.hostname);
+            if dc.message_id_format.is_empty() {
+                let _ = generate_message_id_header(&mut headers, &self.hostname);
+            } else {
+                self.write_message_id(&dc.message_id_format, &mut headers);
+            }
             headers.extend_from_slice(b"\r\n");
         }
 
@@ -664,16 +958,43 @@ impl<T: SessionStream> Session<T> {
             headers.extend_from_slice(b">\r\n");
         }
 
-        // DKIM sign
+        // Apply the configured header casing before signing, the signatures
+        // added below keep the casing written by the signer as changing it
+        // would break signatures using simple header canonicalization
+        if !dc.header_order.is_empty() {
+            headers = recase_headers(&headers, &dc.header_order);
+        }
+
+        // DKIM sign, re-signing messages that were modified
         let raw_message = edited_message.unwrap_or(raw_message);
-        for signer in self
+        let mut signers = self
             .core
             .core
             .eval_if::<Vec<String>, _>(&ac.dkim.sign, self)
             .await
-            .unwrap_or_default()
-        {
-            if let Some(signer) = self.core.core.get_dkim_signer(&signer) {
+            .unwrap_or_default();
+        for (is_modified, if_block) in [
+            (html_sanitized, &dc.html_sanitize.sign),
+            (tracking_pixels_removed, &dc.tracking_pixels.sign),
+            (text_alternative_added, &dc.text_alternative.sign),
+        ] {
+            if !is_modified {
+                continue;
+            }
+            for signer in self
+                .core
+                .core
+                .eval_if::<Vec<String>, _>(if_block, self)
+                .await
+                .unwrap_or_default()
+            {
+                if !signers.contains(&signer) {
+                    signers.push(signer);
+                }
+            }
+        }
+        for signer in &signers {
+            if let Some(signer) = self.core.core.get_dkim_signer(signer) {
                 match signer.sign_chained(&[headers.as_ref(), &raw_message]) {
                     Ok(signature) => {
                         signature.write_header(&mut headers);
@@ -689,20 +1010,86 @@ impl<T: SessionStream> Session<T> {
             }
         }
 
+        // Reorder the added headers, signatures are not affected as the relative
+        // order of headers with the same name is preserved.
+        if !dc.header_order.is_empty() {
+            headers = reorder_headers(&headers, &dc.header_order);
+        }
+
         // Update size
         message.size = raw_message.len() + headers.len();
+        if let Some(scan) = &mut deferred_scan {
+            scan.headers_len = headers.len();
+            scan.signers = signers;
+        }
+
+        // Drop exact duplicates of recently queued messages
+        let dedup_key = self.queue_dedup_key(&message, &raw_message).await;
+        if let Some(dedup_key) = &dedup_key {
+            if self.is_duplicate_submission(dedup_key).await {
+                tracing::info!(
+                    parent: &self.span,
+                    context = "queue",
+                    event = "duplicate",
+                    from = message.return_path,
+                    "Dropping duplicate of a recently queued message."
+                );
+                self.state = State::Accepted(message.id);
+                self.data.messages_sent += 1;
+                return (b"250 2.0.0 Message queued for delivery.\r\n"[..]).into();
+            }
+        }
 
         // Verify queue quota
         if self.core.has_quota(&mut message).await {
+            // Messages with a deferred scan are journaled once the scan accepts them
+            let mut journal = self.journal_reports(&message).await;
+            if let Some(scan) = &mut deferred_scan {
+                scan.journal = journal.take();
+            }
+
             let queue_id = message.id;
+            let correlation_id = message.correlation_id;
+            let rcpt_rates = self.rcpt_rates(&message).await;
             if message
-                .queue(Some(&headers), &raw_message, &self.core, &self.span)
+                .queue_with_scan(
+                    Some(&headers),
+                    &raw_message,
+                    deferred_scan,
+                    &self.core,
+                    &self.span,
+                )
                 .await
             {
+                // Send a copy to the journaling addresses
+                if let Some(journal) = journal {
+                    if !self
+                        .core
+                        .queue_journal(
+                            &journal,
+                            correlation_id,
+                            &[&headers, &raw_message],
+                            &self.span,
+                        )
+                        .await
+                    {
+                        // Journaling failures are fatal, remove the queued message
+                        if let Some(message) = self.core.read_message(queue_id).await {
+                            let due = message.next_event().unwrap_or_default();
+                            message.remove(&self.core, due).await;
+                        }
+                        self.release_dedup_key(dedup_key).await;
+                        return (b"451 4.3.5 Unable to accept message at this time.\r\n"[..])
+                            .into();
+                    }
+                }
+
+                self.count_rcpt_rates(rcpt_rates).await;
                 self.state = State::Accepted(queue_id);
                 self.data.messages_sent += 1;
                 (b"250 2.0.0 Message queued for delivery.\r\n"[..]).into()
             } else {
+                self.release_dedup_key(dedup_key).await;
                 (b"451 4.3.5 Unable to accept message at this time.\r\n"[..]).into()
             }
         } else {
@@ -713,10 +1100,22 @@ impl<T: SessionStream> Session<T> {
                 from = message.return_path,
                 "Queue quota exceeded, rejecting message."
             );
+            self.release_dedup_key(dedup_key).await;
             (b"452 4.3.1 Mail system full, try again later.\r\n"[..]).into()
         }
     }
 
+    async fn is_deferred_scan(&self, size: usize) -> bool {
+        self.params.spool_threshold > 0
+            && size >= self.params.spool_threshold
+            && self
+                .core
+                .core
+                .eval_if(&self.core.core.smtp.session.data.deferred_scan.enable, self)
+                .await
+                .unwrap_or(false)
+    }
+
     pub async fn build_message(
         &self,
         mail_from: SessionAddress,
@@ -740,6 +1139,7 @@ impl<T: SessionStream> Session<T> {
             env_id: mail_from.dsn_info,
             blob_hash: Default::default(),
             quota_keys: Vec::new(),
+            correlation_id: self.data.correlation_id,
         };
 
         // Add recipients
@@ -870,12 +1270,106 @@ impl<T: SessionStream> Session<T> {
                     .await?;
                 Ok(false)
             }
+        } else if self.data.mail_from.is_some() {
+            self.write(b"554 5.5.1 No valid recipients.\r\n").await?;
+            Ok(false)
         } else {
             self.write(b"503 5.5.1 RCPT is required first.\r\n").await?;
             Ok(false)
         }
     }
 
+    /// Returns true if a failed DMARC check with a reject policy has to be enforced,
+    /// unless the domain is allow-listed or excluded by the policy's sampling rate.
+    async fn is_dmarc_enforced(&self, dmarc_output: &DmarcOutput) -> bool {
+        let config = &self.core.core.smtp.mail_auth.dmarc;
+        if !self
+            .core
+            .core
+            .eval_if(&config.enforce, self)
+            .await
+            .unwrap_or(false)
+        {
+            return false;
+        }
+
+        // Local overrides
+        if self
+            .is_allow_listed(&config.allow_list, dmarc_output.domain())
+            .await
+        {
+            tracing::debug!(
+                parent: &self.span,
+                context = "dmarc",
+                event = "allow-listed",
+                domain = dmarc_output.domain(),
+                "DMARC policy not enforced for allow-listed domain."
+            );
+            return false;
+        }
+
+        // Apply the percentage of messages subjected to filtering
+        let pct = dmarc_output
+            .dmarc_record()
+            .map_or(100, |record| record.pct());
+        pct >= 100 || rand::thread_rng().gen_range(0..100) < pct
+    }
+
+    /// Returns true if either the envelope sender or the From header domain is
+    /// exempted from the requirement of passing at least one authentication check.
+    async fn is_require_pass_allow_listed(&self, auth_message: &AuthenticatedMessage<'_>) -> bool {
+        let allow_list = &self.core.core.smtp.mail_auth.require_pass.allow_list;
+        let mail_from = self.data.mail_from.as_ref().unwrap();
+        let sender_domain = if !mail_from.domain.is_empty() {
+            mail_from.domain.as_str()
+        } else {
+            self.data.helo_domain.as_str()
+        };
+        let from_domain = auth_message
+            .from()
+            .rsplit_once('@')
+            .map(|(_, domain)| domain)
+            .unwrap_or_default();
+
+        for domain in [sender_domain, from_domain] {
+            if !domain.is_empty() && self.is_allow_listed(allow_list, domain).await {
+                tracing::debug!(
+                    parent: &self.span,
+                    context = "auth",
+                    event = "allow-listed",
+                    domain = domain,
+                    "Authentication requirement not enforced for allow-listed domain."
+                );
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Returns true if the domain or any of its parent domains is present in the
+    /// lookup store `list`.
+    async fn is_allow_listed(&self, list: &str, domain: &str) -> bool {
+        if let Some(store) = self.core.core.storage.lookups.get(list) {
+            let mut domain = domain.to_lowercase();
+            loop {
+                if store
+                    .key_exists(domain.as_bytes().to_vec())
+                    .await
+                    .unwrap_or(false)
+                {
+                    return true;
+                }
+                match domain.split_once('.') {
+                    Some((_, parent)) if parent.contains('.') => domain = parent.to_string(),
+                    _ => break,
+                }
+            }
+        }
+
+        false
+    }
+
     fn write_received(&self, headers: &mut Vec<u8>, id: u64) {
         headers.extend_from_slice(b"Received: from ");
         headers.extend_from_slice(self.data.helo_domain.as_bytes());
@@ -918,3 +1412,138 @@ impl<T: SessionStream> Session<T> {
         headers.extend_from_slice(b"\r\n");
     }
 }
+
+impl<T: SessionStream> Session<T> {
+    fn write_message_id(&self, format: &[MessageIdToken], headers: &mut Vec<u8>) {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        headers.push(b'<');
+        for token in format {
+            match token {
+                MessageIdToken::Text(text) => headers.extend_from_slice(text.as_bytes()),
+                MessageIdToken::Hostname => headers.extend_from_slice(self.hostname.as_bytes()),
+                MessageIdToken::SenderDomain => headers.extend_from_slice(
+                    self.data
+                        .mail_from
+                        .as_ref()
+                        .map(|mail_from| mail_from.domain.as_str())
+                        .filter(|domain| !domain.is_empty())
+                        .unwrap_or(self.hostname.as_str())
+                        .as_bytes(),
+                ),
+                MessageIdToken::Timestamp => {
+                    headers.extend_from_slice(now().to_string().as_bytes())
+                }
+                MessageIdToken::Random(len) => {
+                    headers.extend(rand::thread_rng().sample_iter(Alphanumeric).take(*len))
+                }
+                MessageIdToken::Unique => {
+                    let nanos = SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .map_or(0, |d| d.as_nanos());
+                    headers.extend_from_slice(
+                        format!(
+                            "{:x}{:04x}",
+                            nanos,
+                            COUNTER.fetch_add(1, Ordering::Relaxed) & 0xffff
+                        )
+                        .as_bytes(),
+                    );
+                }
+            }
+        }
+        headers.push(b'>');
+    }
+}
+
+fn has_bare_line_endings(message: &[u8]) -> bool {
+    let mut last_ch = 0;
+    for (pos, &ch) in message.iter().enumerate() {
+        match ch {
+            b'\n' if last_ch != b'\r' => return true,
+            b'\r' if message.get(pos + 1) != Some(&b'\n') => return true,
+            _ => (),
+        }
+        last_ch = ch;
+    }
+    false
+}
+
+fn normalize_line_endings(message: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(message.len() + (message.len() / 32));
+    let mut iter = message.iter().peekable();
+    while let Some(&ch) = iter.next() {
+        match ch {
+            b'\r' => {
+                result.extend_from_slice(b"\r\n");
+                if iter.peek() == Some(&&b'\n') {
+                    iter.next();
+                }
+            }
+            b'\n' => result.extend_from_slice(b"\r\n"),
+            _ => result.push(ch),
+        }
+    }
+    result
+}
+
+/// Splits a block of headers into header fields, each including its folded lines.
+fn split_header_fields(headers: &[u8]) -> Vec<&[u8]> {
+    let mut fields = Vec::new();
+    let mut start = 0;
+    for (pos, &ch) in headers.iter().enumerate() {
+        if ch == b'\n' && !matches!(headers.get(pos + 1), Some(b' ' | b'\t')) {
+            fields.push(&headers[start..pos + 1]);
+            start = pos + 1;
+        }
+    }
+    if start < headers.len() {
+        fields.push(&headers[start..]);
+    }
+    fields
+}
+
+fn header_name(field: &[u8]) -> &[u8] {
+    field
+        .iter()
+        .position(|&ch| ch == b':')
+        .map_or(field, |pos| &field[..pos])
+}
+
+fn recase_headers(headers: &[u8], names: &[String]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(headers.len());
+    for field in split_header_fields(headers) {
+        let name = header_name(field);
+        if let Some(new_name) = names
+            .iter()
+            .find(|n| n.as_bytes().eq_ignore_ascii_case(name))
+        {
+            result.extend_from_slice(new_name.as_bytes());
+            result.extend_from_slice(&field[name.len()..]);
+        } else {
+            result.extend_from_slice(field);
+        }
+    }
+    result
+}
+
+fn reorder_headers(headers: &[u8], names: &[String]) -> Vec<u8> {
+    let mut fields = split_header_fields(headers);
+    // Headers not listed keep their relative order after the listed ones
+    fields.sort_by_key(|field| {
+        let name = header_name(field);
+        names
+            .iter()
+            .position(|n| n.as_bytes().eq_ignore_ascii_case(name))
+            .unwrap_or(names.len())
+    });
+    fields.concat()
+}
+
+fn alignment_mode(alignment: &report::Alignment) -> &'static str {
+    match alignment {
+        report::Alignment::Relaxed => "relaxed",
+        report::Alignment::Strict => "strict",
+        report::Alignment::Unspecified => "",
+    }
+}
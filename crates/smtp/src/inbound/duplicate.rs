@@ -0,0 +1,158 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use common::listener::SessionStream;
+
+use crate::{core::Session, queue::Message};
+
+impl<T: SessionStream> Session<T> {
+    /// Returns `true` when a message with the same Message-ID was already
+    /// delivered to the same set of recipients within the configured window,
+    /// or when the Message-ID was seen in more transactions than allowed.
+    /// Resends to different recipients are tolerated up to the limit.
+    pub async fn is_duplicate_message(&self, message_id: &str) -> bool {
+        let config = &self.core.core.smtp.session.data.duplicate;
+        let store = &self.core.core.storage.lookup;
+
+        let mut rcpts = self
+            .data
+            .rcpt_to
+            .iter()
+            .map(|r| r.address_lcase.as_str())
+            .collect::<Vec<_>>();
+        rcpts.sort_unstable();
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(message_id.as_bytes());
+        let id_hash = hasher.finalize();
+        for rcpt in rcpts {
+            hasher.update(b"\0");
+            hasher.update(rcpt.as_bytes());
+        }
+        let replay_hash = hasher.finalize();
+
+        let mut is_duplicate = false;
+        for (key, max_count) in [
+            (format!("mid:{}", id_hash.to_hex()), config.max_count),
+            (format!("midr:{}", replay_hash.to_hex()), 1),
+        ] {
+            match store
+                .counter_incr(key.into_bytes(), 1, config.window.as_secs().into(), true)
+                .await
+            {
+                Ok(count) => {
+                    is_duplicate |= count > max_count as i64;
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        parent: &self.span,
+                        context = "data",
+                        event = "error",
+                        reason = %err,
+                        "Failed to update duplicate Message-ID counter."
+                    );
+                    return false;
+                }
+            }
+        }
+
+        is_duplicate
+    }
+
+    /// Returns the deduplication key of a message about to be queued, derived
+    /// from the exact message contents, the envelope sender and the sorted
+    /// recipient list. Returns `None` when queue deduplication is disabled.
+    pub async fn queue_dedup_key(&self, message: &Message, raw_message: &[u8]) -> Option<Vec<u8>> {
+        if !self
+            .core
+            .core
+            .eval_if(&self.core.core.smtp.queue.dedup.enable, message)
+            .await
+            .unwrap_or(false)
+        {
+            return None;
+        }
+
+        let mut rcpts = message
+            .recipients
+            .iter()
+            .map(|r| r.address_lcase.as_str())
+            .collect::<Vec<_>>();
+        rcpts.sort_unstable();
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(raw_message);
+        hasher.update(b"\0");
+        hasher.update(message.return_path_lcase.as_bytes());
+        for rcpt in rcpts {
+            hasher.update(b"\0");
+            hasher.update(rcpt.as_bytes());
+        }
+
+        Some(format!("qd:{}", hasher.finalize().to_hex()).into_bytes())
+    }
+
+    /// Returns `true` when an identical message was already queued for the
+    /// same recipients within the configured deduplication window.
+    pub async fn is_duplicate_submission(&self, key: &[u8]) -> bool {
+        match self
+            .core
+            .core
+            .storage
+            .lookup
+            .counter_incr(
+                key.to_vec(),
+                1,
+                self.core.core.smtp.queue.dedup.ttl.as_secs().into(),
+                true,
+            )
+            .await
+        {
+            Ok(count) => count > 1,
+            Err(err) => {
+                tracing::warn!(
+                    parent: &self.span,
+                    context = "queue",
+                    event = "error",
+                    reason = %err,
+                    "Failed to update message deduplication counter."
+                );
+                false
+            }
+        }
+    }
+
+    /// Removes a deduplication key after the message failed to be queued,
+    /// so that a retry of the same submission is not dropped.
+    pub async fn release_dedup_key(&self, key: Option<Vec<u8>>) {
+        if let Some(key) = key {
+            if let Err(err) = self.core.core.storage.lookup.counter_delete(key).await {
+                tracing::warn!(
+                    parent: &self.span,
+                    context = "queue",
+                    event = "error",
+                    reason = %err,
+                    "Failed to remove message deduplication counter."
+                );
+            }
+        }
+    }
+}
@@ -45,6 +45,21 @@ impl<T: SessionStream> Session<T> {
                 return self.write(b"550 5.5.0 Invalid EHLO domain.\r\n").await;
             }
 
+            // Reject hosts without forward-confirmed reverse DNS
+            if self.params.ehlo_require_iprev {
+                self.handle_iprev().await;
+                if let Some(message) = self.iprev_failure() {
+                    tracing::info!(parent: &self.span,
+                        context = "ehlo",
+                        event = "reject",
+                        reason = "iprev",
+                        domain = domain,
+                    );
+
+                    return self.write(message).await;
+                }
+            }
+
             // SPF check
             let prev_helo_domain = std::mem::replace(&mut self.data.helo_domain, domain);
             if self.params.spf_ehlo.verify() {
@@ -128,7 +143,6 @@ impl<T: SessionStream> Session<T> {
         }
         let ec = &self.core.core.smtp.session.extensions;
         let ac = &self.core.core.smtp.session.auth;
-        let dc = &self.core.core.smtp.session.data;
 
         // Pipelining
         if self
@@ -243,12 +257,7 @@ impl<T: SessionStream> Session<T> {
         }
 
         // Size
-        response.size = self
-            .core
-            .core
-            .eval_if(&dc.max_message_size, self)
-            .await
-            .unwrap_or(25 * 1024 * 1024);
+        response.size = self.eval_max_message_size().await;
         if response.size > 0 {
             response.capabilities |= EXT_SIZE;
         }
@@ -268,6 +277,18 @@ impl<T: SessionStream> Session<T> {
             };
         }
 
+        // Do not advertise extensions whose commands are disabled on this listener
+        for (command, capability) in [
+            ("starttls", EXT_START_TLS),
+            ("auth", EXT_AUTH),
+            ("vrfy", EXT_VRFY),
+            ("expn", EXT_EXPN),
+        ] {
+            if self.is_command_disabled(command) {
+                response.capabilities &= !capability;
+            }
+        }
+
         // Generate response
         let mut buf = Vec::with_capacity(64);
         response.write(&mut buf).ok();
@@ -0,0 +1,137 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use common::listener::SessionStream;
+use store::write::now;
+
+use crate::core::Session;
+
+impl<T: SessionStream> Session<T> {
+    /// Returns `true` when the last recipient has to be temporarily rejected
+    /// because its (ip, sender, recipient) triplet was not seen before or was
+    /// first seen less than the configured delay ago. Senders that retry
+    /// after the delay are whitelisted for the configured lifetime.
+    pub async fn is_greylisted(&self) -> bool {
+        let config = &self.core.core.smtp.session.rcpt.greylist;
+        if !self
+            .core
+            .core
+            .eval_if(&config.enable, self)
+            .await
+            .unwrap_or(false)
+        {
+            return false;
+        }
+
+        let store = if let Some(store_id) = &config.store {
+            if let Some(store) = self.core.core.storage.lookups.get(store_id) {
+                store
+            } else {
+                tracing::warn!(
+                    parent: &self.span,
+                    context = "greylist",
+                    event = "error",
+                    store = store_id,
+                    "Greylist store not found."
+                );
+                return false;
+            }
+        } else {
+            &self.core.core.storage.lookup
+        };
+
+        let remote_ip = &self.data.remote_ip_str;
+        let mail_from = self
+            .data
+            .mail_from
+            .as_ref()
+            .map(|f| f.address_lcase.as_str())
+            .unwrap_or_default();
+        let rcpt_to = self
+            .data
+            .rcpt_to
+            .last()
+            .map(|r| r.address_lcase.as_str())
+            .unwrap_or_default();
+        let sender_key = format!("gl:s:{remote_ip}:{mail_from}").into_bytes();
+        let triplet_key = format!("gl:t:{remote_ip}:{mail_from}:{rcpt_to}").into_bytes();
+
+        // Senders that passed greylisting before are not delayed again
+        match store.key_exists(sender_key.clone()).await {
+            Ok(true) => return false,
+            Ok(false) => (),
+            Err(err) => {
+                tracing::warn!(
+                    parent: &self.span,
+                    context = "greylist",
+                    event = "error",
+                    reason = %err,
+                    "Failed to query greylist store."
+                );
+                return false;
+            }
+        }
+
+        let now = now();
+        let result = match store.key_get::<i64>(triplet_key.clone()).await {
+            Ok(Some(first_seen)) => {
+                if now >= (first_seen as u64).saturating_add(config.delay.as_secs()) {
+                    let _ = store.key_delete(triplet_key).await;
+                    store
+                        .key_set(
+                            sender_key,
+                            (now as i64).to_be_bytes().to_vec(),
+                            config.lifetime.as_secs().into(),
+                        )
+                        .await
+                        .map(|_| false)
+                } else {
+                    Ok(true)
+                }
+            }
+            Ok(None) => store
+                .key_set(
+                    triplet_key,
+                    (now as i64).to_be_bytes().to_vec(),
+                    config.window.as_secs().into(),
+                )
+                .await
+                .map(|_| true),
+            Err(err) => Err(err),
+        };
+
+        match result {
+            Ok(is_greylisted) => is_greylisted,
+            Err(err) => {
+                tracing::warn!(
+                    parent: &self.span,
+                    context = "greylist",
+                    event = "error",
+                    reason = %err,
+                    "Failed to update greylist store."
+                );
+                false
+            }
+        }
+    }
+}
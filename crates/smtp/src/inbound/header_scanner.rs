@@ -0,0 +1,139 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use common::listener::SessionStream;
+
+use crate::core::Session;
+
+/// Tracks the size and number of header fields of the message header section
+/// while message data is being received, so that messages with oversized header
+/// sections can be rejected without buffering them entirely.
+#[derive(Debug, Clone)]
+pub struct HeaderScanner {
+    size: usize,
+    count: usize,
+    is_line_start: bool,
+    is_done: bool,
+    limit: Option<HeaderLimit>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderLimit {
+    Size,
+    Count,
+}
+
+impl HeaderScanner {
+    pub fn new() -> Self {
+        HeaderScanner {
+            size: 0,
+            count: 0,
+            is_line_start: true,
+            is_done: false,
+            limit: None,
+        }
+    }
+
+    /// Scans the next chunk of message bytes until the end of the header section,
+    /// returning the limit that was exceeded, if any.
+    pub fn scan(&mut self, bytes: &[u8], max_size: usize, max_count: usize) -> Option<HeaderLimit> {
+        if self.is_done {
+            return None;
+        }
+
+        for &ch in bytes {
+            if self.is_line_start {
+                match ch {
+                    b'\r' | b'\n' => {
+                        // Empty line, end of the header section
+                        self.is_done = true;
+                        return None;
+                    }
+                    b' ' | b'\t' => (),
+                    _ => {
+                        self.count += 1;
+                        if self.count > max_count {
+                            return self.exceeded(HeaderLimit::Count);
+                        }
+                    }
+                }
+            }
+            self.is_line_start = ch == b'\n';
+            self.size += 1;
+            if self.size > max_size {
+                return self.exceeded(HeaderLimit::Size);
+            }
+        }
+
+        None
+    }
+
+    fn exceeded(&mut self, limit: HeaderLimit) -> Option<HeaderLimit> {
+        self.is_done = true;
+        self.limit = Some(limit);
+        self.limit
+    }
+
+    /// Returns the limit exceeded by the header section, if any.
+    pub fn limit(&self) -> Option<HeaderLimit> {
+        self.limit
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+}
+
+impl Default for HeaderScanner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: SessionStream> Session<T> {
+    /// Scans the message bytes received after `offset` for header section limits,
+    /// returning the limit that was exceeded by these bytes, if any.
+    pub fn scan_headers(&mut self, offset: usize) -> Option<HeaderLimit> {
+        let limit = self.data.headers.scan(
+            self.data.message.get(offset..).unwrap_or_default(),
+            self.params.max_header_size,
+            self.params.max_headers,
+        )?;
+
+        tracing::info!(
+            parent: &self.span,
+            context = "data",
+            event = "headers-too-large",
+            limit = ?limit,
+            size = self.data.headers.size(),
+            count = self.data.headers.count(),
+            "Message header section exceeds the configured limits."
+        );
+
+        Some(limit)
+    }
+}
@@ -0,0 +1,393 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::borrow::Cow;
+
+use common::{
+    config::smtp::session::{CircuitBreakerAction, Icap, IcapInfectedAction},
+    listener::SessionStream,
+};
+use rustls_pki_types::ServerName;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::core::Session;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Clean,
+    Infected(String),
+    Modified(Vec<u8>),
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Timeout,
+    TLSInvalidName,
+    ResponseTooLarge,
+    InvalidResponse,
+    Status(u16),
+    Disconnected,
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl<T: SessionStream> Session<T> {
+    /// Streams the message to the configured ICAP servers using RESPMOD and
+    /// returns the modified message, if any of the servers changed it.
+    pub async fn run_icap(&self, message: &[u8]) -> Result<Option<Vec<u8>>, Cow<'static, [u8]>> {
+        let servers = &self.core.core.smtp.session.data.icap;
+        if servers.is_empty() {
+            return Ok(None);
+        }
+
+        let mut edited_message: Option<Vec<u8>> = None;
+        for icap in servers {
+            if !self
+                .core
+                .core
+                .eval_if(&icap.enable, self)
+                .await
+                .unwrap_or(false)
+            {
+                continue;
+            }
+
+            // Skip unavailable servers while the circuit breaker is open
+            if let Some(breaker) = icap
+                .circuit_breaker
+                .as_ref()
+                .filter(|breaker| self.core.is_breaker_open(breaker))
+            {
+                tracing::debug!(
+                    parent: &self.span,
+                    icap.host = &icap.hostname,
+                    icap.port = &icap.port,
+                    context = "icap",
+                    event = "circuit-open",
+                    action = ?breaker.action,
+                    "ICAP server unavailable, circuit breaker is open.");
+                if breaker.action == CircuitBreakerAction::TempFail {
+                    return Err(
+                        (b"451 4.3.5 Unable to accept message at this time.\r\n"[..]).into(),
+                    );
+                }
+                continue;
+            }
+
+            let contents = edited_message.as_deref().unwrap_or(message);
+            let result = self.icap_scan(icap, contents).await;
+            if let Some(breaker) = &icap.circuit_breaker {
+                if result.is_ok() {
+                    self.core.breaker_success(breaker);
+                } else {
+                    self.core.breaker_failure(breaker);
+                }
+            }
+            match result {
+                Ok(Verdict::Clean) => {
+                    tracing::debug!(
+                        parent: &self.span,
+                        icap.host = &icap.hostname,
+                        icap.port = &icap.port,
+                        context = "icap",
+                        event = "clean",
+                        "ICAP server found no threats.");
+                }
+                Ok(Verdict::Infected(threat)) => {
+                    tracing::info!(
+                        parent: &self.span,
+                        icap.host = &icap.hostname,
+                        icap.port = &icap.port,
+                        context = "icap",
+                        event = "infected",
+                        threat = &threat,
+                        action = ?icap.infected_action,
+                        "ICAP server found an infected message.");
+
+                    match icap.infected_action {
+                        IcapInfectedAction::Reject => {
+                            return Err(
+                                (b"550 5.7.1 Message rejected, virus detected.\r\n"[..]).into()
+                            );
+                        }
+                        IcapInfectedAction::Quarantine => {
+                            let mut quarantined =
+                                Vec::with_capacity(contents.len() + threat.len() + 16);
+                            quarantined.extend_from_slice(b"X-Quarantine: ");
+                            quarantined.extend(threat.bytes().map(|ch| {
+                                if ch.is_ascii_control() {
+                                    b' '
+                                } else {
+                                    ch
+                                }
+                            }));
+                            quarantined.extend_from_slice(b"\r\n");
+                            quarantined.extend_from_slice(contents);
+                            edited_message = Some(quarantined);
+                        }
+                    }
+                }
+                Ok(Verdict::Modified(modified)) => {
+                    if modified != contents {
+                        tracing::debug!(
+                            parent: &self.span,
+                            icap.host = &icap.hostname,
+                            icap.port = &icap.port,
+                            context = "icap",
+                            event = "modified",
+                            apply = icap.allow_modifications,
+                            "ICAP server modified message.");
+
+                        if icap.allow_modifications {
+                            edited_message = Some(modified);
+                        }
+                    }
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        parent: &self.span,
+                        icap.host = &icap.hostname,
+                        icap.port = &icap.port,
+                        context = "icap",
+                        event = "error",
+                        reason = ?err,
+                        "ICAP scan failed");
+                    if icap.tempfail_on_error {
+                        return Err(
+                            (b"451 4.3.5 Unable to accept message at this time.\r\n"[..]).into(),
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(edited_message)
+    }
+
+    async fn icap_scan(&self, icap: &Icap, message: &[u8]) -> Result<Verdict, Error> {
+        let stream = tokio::time::timeout(icap.timeout_connect, async {
+            let mut last_err = Error::Disconnected;
+            for addr in &icap.addrs {
+                match TcpStream::connect(addr).await {
+                    Ok(stream) => return Ok(stream),
+                    Err(err) => {
+                        last_err = Error::Io(err);
+                    }
+                }
+            }
+            Err(last_err)
+        })
+        .await
+        .map_err(|_| Error::Timeout)??;
+        let request = build_request(icap, message);
+
+        if !icap.tls {
+            exchange(stream, icap, &request).await
+        } else {
+            let tls_connector = if !icap.tls_allow_invalid_certs {
+                &self.core.inner.connectors.pki_verify
+            } else {
+                &self.core.inner.connectors.dummy_verify
+            };
+            let stream = tokio::time::timeout(
+                icap.timeout_connect,
+                tls_connector.connect(
+                    ServerName::try_from(icap.hostname.as_str())
+                        .map_err(|_| Error::TLSInvalidName)?
+                        .to_owned(),
+                    stream,
+                ),
+            )
+            .await
+            .map_err(|_| Error::Timeout)??;
+            exchange(stream, icap, &request).await
+        }
+    }
+}
+
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    icap: &Icap,
+    request: &[u8],
+) -> Result<Verdict, Error> {
+    tokio::time::timeout(icap.timeout_data, async {
+        stream.write_all(request).await?;
+        stream.flush().await?;
+
+        let mut response = Vec::with_capacity(1024);
+        let mut buf = vec![0u8; 8192];
+        loop {
+            let br = stream.read(&mut buf).await?;
+            if br == 0 {
+                return Err(Error::Disconnected);
+            }
+            response.extend_from_slice(&buf[..br]);
+            if response.len() > icap.max_response_size {
+                return Err(Error::ResponseTooLarge);
+            }
+
+            // Both the ICAP headers and the last chunk end with an empty line
+            if response.ends_with(b"\r\n\r\n") {
+                if let Some(verdict) = parse_response(&response)? {
+                    return Ok(verdict);
+                }
+            }
+        }
+    })
+    .await
+    .map_err(|_| Error::Timeout)?
+}
+
+fn build_request(icap: &Icap, message: &[u8]) -> Vec<u8> {
+    let res_hdr = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: message/rfc822\r\nContent-Length: {}\r\n\r\n",
+        message.len()
+    );
+    let mut request = format!(
+        concat!(
+            "RESPMOD icap://{}:{}/{} ICAP/1.0\r\n",
+            "Host: {}\r\n",
+            "Allow: 204\r\n",
+            "Connection: close\r\n",
+            "Encapsulated: res-hdr=0, res-body={}\r\n\r\n",
+            "{}"
+        ),
+        icap.hostname,
+        icap.port,
+        icap.service,
+        icap.hostname,
+        res_hdr.len(),
+        res_hdr
+    )
+    .into_bytes();
+    request.reserve(message.len() + 16);
+    if !message.is_empty() {
+        request.extend_from_slice(format!("{:x}\r\n", message.len()).as_bytes());
+        request.extend_from_slice(message);
+        request.extend_from_slice(b"\r\n");
+    }
+    request.extend_from_slice(b"0\r\n\r\n");
+    request
+}
+
+fn parse_response(data: &[u8]) -> Result<Option<Verdict>, Error> {
+    let Some(hdr_end) = find(data, b"\r\n\r\n") else {
+        return Ok(None);
+    };
+    let headers = std::str::from_utf8(&data[..hdr_end]).map_err(|_| Error::InvalidResponse)?;
+    let mut lines = headers.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.strip_prefix("ICAP/1.0 "))
+        .and_then(|line| line.get(..3))
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or(Error::InvalidResponse)?;
+
+    let mut threat = None;
+    let mut res_body = None;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "x-infection-found" => {
+                    threat = value
+                        .split(';')
+                        .find_map(|part| part.trim().strip_prefix("Threat="))
+                        .unwrap_or(value)
+                        .trim()
+                        .to_string()
+                        .into();
+                }
+                "x-virus-id" if threat.is_none() => {
+                    threat = value.to_string().into();
+                }
+                "encapsulated" => {
+                    res_body = value.split(',').find_map(|part| {
+                        part.trim()
+                            .strip_prefix("res-body=")
+                            .and_then(|offset| offset.parse::<usize>().ok())
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    match (status, threat) {
+        (200 | 204, Some(threat)) => Ok(Some(Verdict::Infected(threat))),
+        (204, None) => Ok(Some(Verdict::Clean)),
+        (200, None) => {
+            let Some(offset) = res_body else {
+                return Ok(Some(Verdict::Clean));
+            };
+            match data.get(hdr_end + 4 + offset..) {
+                Some(body) => Ok(decode_chunked(body)?.map(|body| {
+                    if !body.is_empty() {
+                        Verdict::Modified(body)
+                    } else {
+                        Verdict::Clean
+                    }
+                })),
+                None => Ok(None),
+            }
+        }
+        (status, _) => Err(Error::Status(status)),
+    }
+}
+
+fn decode_chunked(data: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+    let mut body = Vec::with_capacity(data.len());
+    let mut pos = 0;
+
+    loop {
+        let Some(line_end) = find(&data[pos..], b"\r\n") else {
+            return Ok(None);
+        };
+        let size = std::str::from_utf8(&data[pos..pos + line_end])
+            .ok()
+            .and_then(|line| line.split(';').next())
+            .and_then(|size| usize::from_str_radix(size.trim(), 16).ok())
+            .ok_or(Error::InvalidResponse)?;
+        pos += line_end + 2;
+        if size == 0 {
+            return Ok(Some(body));
+        } else if data.len() - pos < size.saturating_add(2) {
+            return Ok(None);
+        }
+        body.extend_from_slice(&data[pos..pos + size]);
+        pos += size + 2;
+    }
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len())
+        .position(|window| window == needle)
+}
@@ -0,0 +1,214 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::fmt::Write;
+
+use common::listener::SessionStream;
+use mail_builder::{headers::date::Date, mime::make_boundary};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::{Session, SMTP},
+    queue::Message,
+};
+
+/// Journal reports for a message, queued once the message itself is queued
+/// or, when its scan is deferred, once the scan accepts it.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PendingJournal {
+    pub reports: Vec<JournalReport>,
+    pub from: String,
+    pub hostname: String,
+    pub fatal: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JournalReport {
+    pub address: String,
+    // Envelope details listing the recipients covered by this address
+    pub envelope: String,
+}
+
+impl<T: SessionStream> Session<T> {
+    /// Builds the journal reports for a message. The journaling address is
+    /// evaluated for each recipient domain, recipients sharing an address
+    /// are reported together.
+    pub async fn journal_reports(&self, message: &Message) -> Option<PendingJournal> {
+        let config = &self.core.core.smtp.session.data.journal;
+        let mut addresses: Vec<(String, Vec<&str>)> = Vec::new();
+        for rcpt in &message.recipients {
+            let domain = &message.domains[rcpt.domain_idx].domain;
+            let Some(address) = self
+                .core
+                .core
+                .eval_if::<String, _>(
+                    &config.address,
+                    &self.with_rcpt(&rcpt.address_lcase, domain),
+                )
+                .await
+                .filter(|address| address.contains('@'))
+            else {
+                continue;
+            };
+            if let Some((_, rcpts)) = addresses.iter_mut().find(|(a, _)| *a == address) {
+                rcpts.push(&rcpt.address);
+            } else {
+                addresses.push((address, vec![&rcpt.address]));
+            }
+        }
+        if addresses.is_empty() {
+            return None;
+        }
+
+        let reports = addresses
+            .into_iter()
+            .map(|(address, rcpts)| {
+                let mut envelope = String::with_capacity(128);
+                let _ = write!(
+                    envelope,
+                    concat!(
+                        "Sender: {}\r\n",
+                        "Direction: {}\r\n",
+                        "Remote-IP: {}\r\n",
+                        "Queue-ID: {:x}\r\n",
+                    ),
+                    message.return_path,
+                    if self.data.authenticated_as.is_empty() {
+                        "inbound"
+                    } else {
+                        "outbound"
+                    },
+                    self.data.remote_ip,
+                    message.id,
+                );
+                if !self.data.authenticated_as.is_empty() {
+                    let _ = write!(
+                        envelope,
+                        "Authenticated-As: {}\r\n",
+                        self.data.authenticated_as
+                    );
+                }
+                for rcpt in rcpts {
+                    let _ = write!(envelope, "Recipient: {}\r\n", rcpt);
+                }
+                JournalReport { address, envelope }
+            })
+            .collect();
+
+        Some(PendingJournal {
+            reports,
+            from: self
+                .core
+                .core
+                .eval_if(&self.core.core.smtp.queue.dsn.address, message)
+                .await
+                .unwrap_or_else(|| String::from("MAILER-DAEMON@localhost")),
+            hostname: self.hostname.clone(),
+            fatal: self
+                .core
+                .core
+                .eval_if(&config.fatal, self)
+                .await
+                .unwrap_or(false),
+        })
+    }
+}
+
+impl SMTP {
+    /// Queues a copy of a queued message for each journaling address. Returns
+    /// false when journaling failed and failures are configured as fatal.
+    pub async fn queue_journal(
+        &self,
+        journal: &PendingJournal,
+        correlation_id: u64,
+        raw_message: &[&[u8]],
+        span: &tracing::Span,
+    ) -> bool {
+        let mut result = true;
+
+        for report in &journal.reports {
+            let boundary = make_boundary("_");
+            let mut journal_report = format!(
+                concat!(
+                    "From: \"Mail Journal\" <{}>\r\n",
+                    "To: <{}>\r\n",
+                    "Subject: Journal Report\r\n",
+                    "Date: {}\r\n",
+                    "Message-ID: <{}@{}>\r\n",
+                    "Auto-Submitted: auto-generated\r\n",
+                    "X-Journal-Report: Original\r\n",
+                    "MIME-Version: 1.0\r\n",
+                    "Content-Type: multipart/mixed; boundary=\"{}\"\r\n\r\n",
+                    "--{}\r\n",
+                    "Content-Type: text/plain; charset=\"utf-8\"\r\n\r\n",
+                    "{}",
+                    "\r\n--{}\r\nContent-Type: message/rfc822\r\n\r\n",
+                ),
+                journal.from,
+                report.address,
+                Date::now().to_rfc822(),
+                make_boundary("."),
+                journal.hostname,
+                boundary,
+                boundary,
+                report.envelope,
+                boundary,
+            )
+            .into_bytes();
+            for part in raw_message {
+                journal_report.extend_from_slice(part);
+            }
+            if !journal_report.ends_with(b"\r\n") {
+                journal_report.extend_from_slice(b"\r\n");
+            }
+            journal_report.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+
+            // Queue journal report using a null sender, so failures never reach
+            // the original sender.
+            let mut message = self.new_message("", "", "");
+            message.correlation_id = correlation_id;
+            message.add_recipient(report.address.as_str(), self).await;
+            if message.queue(None, &journal_report, self, span).await {
+                tracing::debug!(
+                    parent: span,
+                    context = "journal",
+                    event = "queued",
+                    address = report.address.as_str(),
+                    "Queued message copy for journaling."
+                );
+            } else {
+                tracing::warn!(
+                    parent: span,
+                    context = "journal",
+                    event = "error",
+                    address = report.address.as_str(),
+                    fatal = journal.fatal,
+                    "Failed to queue message copy for journaling."
+                );
+                result = !journal.fatal;
+            }
+        }
+
+        result
+    }
+}
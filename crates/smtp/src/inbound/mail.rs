@@ -23,10 +23,13 @@
 
 use std::time::{Duration, SystemTime};
 
-use common::{listener::SessionStream, scripts::ScriptModification};
+use common::{
+    config::smtp::schedule::TimeWindow, listener::SessionStream, scripts::ScriptModification,
+};
 use mail_auth::{IprevOutput, IprevResult, SpfOutput, SpfResult};
 use smtp_proto::{MailFrom, MtPriority, MAIL_BY_NOTIFY, MAIL_BY_RETURN, MAIL_REQUIRETLS};
-use utils::config::Rate;
+use store::write::now;
+use utils::config::{utils::ParseValue, Rate};
 
 use crate::{
     core::{Session, SessionAddress},
@@ -48,53 +51,23 @@ impl<T: SessionStream> Session<T> {
             return self
                 .write(b"503 5.5.1 Multiple MAIL commands not allowed.\r\n")
                 .await;
+        } else if self.params.mail_require_starttls && !self.stream.is_tls() {
+            return self
+                .write(b"530 5.7.0 Must issue a STARTTLS command first.\r\n")
+                .await;
         } else if self.params.auth_require && self.data.authenticated_as.is_empty() {
             return self
                 .write(b"503 5.5.1 You must authenticate first.\r\n")
                 .await;
-        } else if self.data.iprev.is_none() && self.params.iprev.verify() {
-            let iprev = self
-                .core
-                .core
-                .smtp
-                .resolvers
-                .dns
-                .verify_iprev(self.data.remote_ip)
-                .await;
-
-            tracing::debug!(parent: &self.span,
-                    context = "iprev",
-                    event = "lookup",
-                    result = %iprev.result,
-                    ptr = iprev.ptr.as_ref().and_then(|p| p.first()).map(|p| p.as_str()).unwrap_or_default()
-            );
-
-            self.data.iprev = iprev.into();
+        } else if self.params.iprev.verify() {
+            self.handle_iprev().await;
         }
 
         // In strict mode reject messages from hosts that fail the reverse DNS lookup check
-        if self.params.iprev.is_strict()
-            && !matches!(
-                &self.data.iprev,
-                Some(IprevOutput {
-                    result: IprevResult::Pass,
-                    ..
-                })
-            )
-        {
-            let message = if matches!(
-                &self.data.iprev,
-                Some(IprevOutput {
-                    result: IprevResult::TempError(_),
-                    ..
-                })
-            ) {
-                &b"451 4.7.25 Temporary error validating reverse DNS.\r\n"[..]
-            } else {
-                &b"550 5.7.25 Reverse DNS validation failed.\r\n"[..]
-            };
-
-            return self.write(message).await;
+        if self.params.iprev.is_strict() {
+            if let Some(message) = self.iprev_failure() {
+                return self.write(message).await;
+            }
         }
 
         let (address, address_lcase, domain) = if !from.address.is_empty() {
@@ -126,6 +99,56 @@ impl<T: SessionStream> Session<T> {
         }
         .into();
 
+        // Make sure that the sender is allowed to submit messages at this time
+        if let Some(schedule) = self
+            .core
+            .core
+            .eval_if::<String, _>(&self.core.core.smtp.session.mail.schedule, self)
+            .await
+        {
+            #[cfg(feature = "test_mode")]
+            let now = self.data.clock.unwrap_or_else(now);
+            #[cfg(not(feature = "test_mode"))]
+            let now = now();
+
+            let window = self
+                .core
+                .core
+                .smtp
+                .session
+                .mail
+                .schedule_windows
+                .get(&schedule)
+                .cloned()
+                .map_or_else(|| TimeWindow::parse_value(&schedule), Ok);
+
+            match window {
+                Ok(window) if window.contains(now) => (),
+                Ok(_) => {
+                    tracing::info!(parent: &self.span,
+                        context = "mail-from",
+                        event = "reject",
+                        reason = "schedule",
+                        address = &self.data.mail_from.as_ref().unwrap().address,
+                        schedule = schedule);
+                    self.data.mail_from = None;
+                    return self
+                        .write(b"550 5.7.1 Submission not allowed at this time.\r\n")
+                        .await;
+                }
+                Err(err) => {
+                    tracing::warn!(parent: &self.span,
+                        context = "mail-from",
+                        event = "error",
+                        reason = err);
+                    self.data.mail_from = None;
+                    return self
+                        .write(b"451 4.3.5 Unable to verify the submission schedule.\r\n")
+                        .await;
+                }
+            }
+        }
+
         // Sieve filtering
         if let Some(script) = self
             .core
@@ -186,7 +209,6 @@ impl<T: SessionStream> Session<T> {
 
         // Validate parameters
         let config = &self.core.core.smtp.session.extensions;
-        let config_data = &self.core.core.smtp.session.data;
         if (from.flags & MAIL_REQUIRETLS) != 0
             && !self
                 .core
@@ -251,15 +273,7 @@ impl<T: SessionStream> Session<T> {
                     .await;
             }
         }
-        if from.size > 0
-            && from.size
-                > self
-                    .core
-                    .core
-                    .eval_if(&config_data.max_message_size, self)
-                    .await
-                    .unwrap_or(25 * 1024 * 1024)
-        {
+        if from.size > 0 && from.size > self.eval_max_message_size().await {
             self.data.mail_from = None;
             return self
                 .write(b"552 5.3.4 Message too big for system.\r\n")
@@ -423,4 +437,43 @@ impl<T: SessionStream> Session<T> {
 
         Ok(result)
     }
+
+    pub async fn handle_iprev(&mut self) {
+        if self.data.iprev.is_some() {
+            return;
+        }
+
+        let iprev = self
+            .core
+            .core
+            .smtp
+            .resolvers
+            .dns
+            .verify_iprev(self.data.remote_ip)
+            .await;
+
+        tracing::debug!(parent: &self.span,
+                context = "iprev",
+                event = "lookup",
+                result = %iprev.result,
+                ptr = iprev.ptr.as_ref().and_then(|p| p.first()).map(|p| p.as_str()).unwrap_or_default()
+        );
+
+        self.data.iprev = iprev.into();
+    }
+
+    /// Returns the response for hosts that failed the reverse DNS lookup check.
+    pub fn iprev_failure(&self) -> Option<&'static [u8]> {
+        match &self.data.iprev {
+            Some(IprevOutput {
+                result: IprevResult::Pass,
+                ..
+            }) => None,
+            Some(IprevOutput {
+                result: IprevResult::TempError(_),
+                ..
+            }) => Some(b"451 4.7.25 Temporary error validating reverse DNS.\r\n"),
+            _ => Some(b"550 5.7.25 Reverse DNS validation failed.\r\n"),
+        }
+    }
 }
@@ -23,7 +23,10 @@
 
 use std::borrow::Cow;
 
-use common::{config::smtp::session::Milter, listener::SessionStream};
+use common::{
+    config::smtp::session::{CircuitBreakerAction, Milter},
+    listener::SessionStream,
+};
 use mail_auth::AuthenticatedMessage;
 use smtp_proto::request::parser::Rfc5321Parser;
 use tokio::io::{AsyncRead, AsyncWrite};
@@ -64,7 +67,37 @@ impl<T: SessionStream> Session<T> {
                 continue;
             }
 
-            match self.connect_and_run(milter, message).await {
+            // Skip unavailable milters while the circuit breaker is open
+            if let Some(breaker) = milter
+                .circuit_breaker
+                .as_ref()
+                .filter(|breaker| self.core.is_breaker_open(breaker))
+            {
+                tracing::debug!(
+                    parent: &self.span,
+                    milter.host = &milter.hostname,
+                    milter.port = &milter.port,
+                    context = "milter",
+                    event = "circuit-open",
+                    action = ?breaker.action,
+                    "Milter unavailable, circuit breaker is open.");
+                if breaker.action == CircuitBreakerAction::TempFail {
+                    return Err(
+                        (b"451 4.3.5 Unable to accept message at this time.\r\n"[..]).into(),
+                    );
+                }
+                continue;
+            }
+
+            let result = self.connect_and_run(milter, message).await;
+            if let Some(breaker) = &milter.circuit_breaker {
+                if matches!(result, Err(Rejection::Error(_))) {
+                    self.core.breaker_failure(breaker);
+                } else {
+                    self.core.breaker_success(breaker);
+                }
+            }
+            match result {
                 Ok(new_modifications) => {
                     if !modifications.is_empty() {
                         // The message body can only be replaced once, so we need to remove
@@ -27,14 +27,27 @@ use mail_auth::{
     AuthenticationResults, DkimResult, DmarcResult, IprevResult, SpfResult,
 };
 
+pub mod alternative;
 pub mod auth;
+pub mod breaker;
 pub mod data;
+pub mod duplicate;
 pub mod ehlo;
+pub mod greylist;
+pub mod header_scanner;
+pub mod icap;
+pub mod journal;
 pub mod mail;
 pub mod milter;
+pub mod rate_limit;
 pub mod rcpt;
+pub mod sanitize;
 pub mod session;
 pub mod spawn;
+pub mod spool;
+pub mod terminator;
+pub mod tracking;
+pub mod transcript;
 pub mod vrfy;
 
 pub trait ArcSeal {
@@ -0,0 +1,148 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use common::listener::SessionStream;
+use store::LookupStore;
+use utils::config::Rate;
+
+use crate::{core::Session, queue::Message};
+
+impl<T: SessionStream> Session<T> {
+    /// Returns `true` when the last recipient has already received as many
+    /// messages as allowed by the configured per-recipient rate, which protects
+    /// mailboxes from being flooded regardless of the senders or IPs involved.
+    /// Messages are only counted once accepted, see `count_rcpt_rates`.
+    pub async fn is_rcpt_rate_exceeded(&self) -> bool {
+        let Some(rcpt) = self.data.rcpt_to.last() else {
+            return false;
+        };
+        let Some(rate) = self.rcpt_rate(&rcpt.address_lcase, &rcpt.domain).await else {
+            return false;
+        };
+        let Some(store) = self.rcpt_rate_store() else {
+            return false;
+        };
+
+        match store
+            .is_rate_allowed(&rcpt_rate_key(&rcpt.address_lcase), &rate, true)
+            .await
+        {
+            Ok(Some(_)) => {
+                tracing::info!(
+                    parent: &self.span,
+                    context = "rcpt-rate",
+                    event = "rate-limit-exceeded",
+                    address = rcpt.address_lcase,
+                    max_requests = rate.requests,
+                    max_interval = rate.period.as_secs(),
+                    "Recipient rate limit exceeded."
+                );
+                true
+            }
+            Ok(None) => false,
+            Err(err) => {
+                tracing::warn!(
+                    parent: &self.span,
+                    context = "rcpt-rate",
+                    event = "error",
+                    reason = %err,
+                    "Failed to read rate limit store."
+                );
+                false
+            }
+        }
+    }
+
+    /// Returns the rate limited recipients of a message, which are counted
+    /// with `count_rcpt_rates` once the message has been queued.
+    pub async fn rcpt_rates(&self, message: &Message) -> Vec<(Vec<u8>, Rate)> {
+        let mut rates = Vec::new();
+        if self.core.core.smtp.session.rcpt.rate_limit.rate.is_empty() {
+            return rates;
+        }
+
+        for rcpt in &message.recipients {
+            let domain = message
+                .domains
+                .get(rcpt.domain_idx)
+                .map(|d| d.domain.as_str())
+                .unwrap_or_default();
+            if let Some(rate) = self.rcpt_rate(&rcpt.address_lcase, domain).await {
+                rates.push((rcpt_rate_key(&rcpt.address_lcase), rate));
+            }
+        }
+
+        rates
+    }
+
+    /// Counts an accepted message against the rate of each of its recipients.
+    pub async fn count_rcpt_rates(&self, rates: Vec<(Vec<u8>, Rate)>) {
+        let Some(store) = self.rcpt_rate_store().filter(|_| !rates.is_empty()) else {
+            return;
+        };
+
+        for (key, rate) in rates {
+            if let Err(err) = store.is_rate_allowed(&key, &rate, false).await {
+                tracing::warn!(
+                    parent: &self.span,
+                    context = "rcpt-rate",
+                    event = "error",
+                    reason = %err,
+                    "Failed to update rate limit store."
+                );
+            }
+        }
+    }
+
+    async fn rcpt_rate(&self, address: &str, domain: &str) -> Option<Rate> {
+        self.core
+            .core
+            .eval_if::<Rate, _>(
+                &self.core.core.smtp.session.rcpt.rate_limit.rate,
+                &self.with_rcpt(address, domain),
+            )
+            .await
+    }
+
+    fn rcpt_rate_store(&self) -> Option<&LookupStore> {
+        if let Some(store_id) = &self.core.core.smtp.session.rcpt.rate_limit.store {
+            let store = self.core.core.storage.lookups.get(store_id);
+            if store.is_none() {
+                tracing::warn!(
+                    parent: &self.span,
+                    context = "rcpt-rate",
+                    event = "error",
+                    store = store_id,
+                    "Rate limit store not found."
+                );
+            }
+            store
+        } else {
+            Some(&self.core.core.storage.lookup)
+        }
+    }
+}
+
+fn rcpt_rate_key(address: &str) -> Vec<u8> {
+    format!("rr:{address}").into_bytes()
+}
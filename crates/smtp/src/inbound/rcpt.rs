@@ -75,6 +75,20 @@ impl<T: SessionStream> Session<T> {
             return self.write(b"250 2.1.5 OK\r\n").await;
         }
         self.data.rcpt_to.push(rcpt);
+        let rcpt_count = self.data.rcpt_to.len();
+
+        // Preserve the original recipient before any rewriting takes place
+        if self.data.rcpt_to.last().unwrap().dsn_info.is_none()
+            && self
+                .core
+                .core
+                .eval_if(&self.core.core.smtp.session.rcpt.generate_orcpt, self)
+                .await
+                .unwrap_or(false)
+        {
+            let rcpt = self.data.rcpt_to.last_mut().unwrap();
+            rcpt.dsn_info = rcpt.address.clone().into();
+        }
 
         // Address rewriting and Sieve filtering
         let rcpt_script = self
@@ -100,12 +114,21 @@ impl<T: SessionStream> Session<T> {
                             address = self.data.rcpt_to.last().unwrap().address,
                             modifications = ?modifications);
                             for modification in modifications {
-                                if let ScriptModification::SetEnvelope { name, value } =
-                                    modification
-                                {
-                                    self.data.apply_envelope_modification(name, value);
+                                match modification {
+                                    ScriptModification::SetEnvelope { name, value } => {
+                                        self.data.apply_envelope_modification(name, value);
+                                    }
+                                    ScriptModification::DeleteRecipient { address } => {
+                                        self.data.delete_recipient(&address);
+                                    }
+                                    ScriptModification::AddHeader { .. } => (),
                                 }
                             }
+
+                            // Recipient expanded to no targets
+                            if self.data.rcpt_to.len() < rcpt_count {
+                                return self.write(b"250 2.1.5 OK\r\n").await;
+                            }
                         }
                     }
                     ScriptResult::Reject(message) => {
@@ -227,6 +250,28 @@ impl<T: SessionStream> Session<T> {
             return self.rcpt_error(b"550 5.1.2 Relay not allowed.\r\n").await;
         }
 
+        // Greylisting
+        if self.is_greylisted().await {
+            tracing::debug!(parent: &self.span,
+                context = "rcpt",
+                event = "greylist",
+                address = &self.data.rcpt_to.last().unwrap().address_lcase,
+                "Recipient greylisted.");
+
+            self.data.rcpt_to.pop();
+            return self
+                .write(b"451 4.7.1 Greylisted, please try again later.\r\n")
+                .await;
+        }
+
+        // Per-recipient rate limit
+        if self.is_rcpt_rate_exceeded().await {
+            self.data.rcpt_to.pop();
+            return self
+                .write(b"452 4.2.1 Recipient is receiving too many messages, try again later.\r\n")
+                .await;
+        }
+
         if self.is_allowed().await {
             tracing::debug!(parent: &self.span,
                     context = "rcpt",
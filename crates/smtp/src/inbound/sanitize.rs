@@ -0,0 +1,361 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use common::listener::SessionStream;
+use mail_parser::{MessageParser, PartType};
+
+use crate::core::Session;
+
+use super::tracking::{decode_part, encode_part, replace_parts};
+
+// Elements removed along with their contents
+const REMOVED_ELEMENTS: &[&str] = &["script", "iframe"];
+
+// Elements whose tags are removed while keeping their contents
+const REMOVED_TAGS: &[&str] = &["applet", "embed", "frame", "frameset", "object"];
+
+// Attributes containing URIs
+const URI_ATTRIBUTES: &[&str] = &[
+    "action",
+    "background",
+    "dynsrc",
+    "formaction",
+    "href",
+    "lowsrc",
+    "poster",
+    "src",
+    "xlink:href",
+];
+
+// URI schemes able to run code or embed arbitrary content
+const DANGEROUS_SCHEMES: &[&str] = &["javascript:", "vbscript:", "data:"];
+
+impl<T: SessionStream> Session<T> {
+    /// Removes scripts, frames, event handler attributes and script URIs from
+    /// the HTML parts of a message, returning the rewritten message if any
+    /// active content was removed.
+    pub async fn strip_dangerous_html(&self, raw_message: &[u8]) -> Option<Vec<u8>> {
+        if !self
+            .core
+            .core
+            .eval_if(&self.core.core.smtp.session.data.html_sanitize.enable, self)
+            .await
+            .unwrap_or(false)
+        {
+            return None;
+        }
+
+        let message = MessageParser::new().parse(raw_message)?;
+        let mut replacements = Vec::new();
+        let mut num_removed = 0;
+
+        for part in &message.parts {
+            if !matches!(part.body, PartType::Html(_)) {
+                continue;
+            }
+            let html = decode_part(part, raw_message)?;
+            if let Some((sanitized, removed)) = sanitize_html(&html) {
+                replacements.push((
+                    part.offset_body,
+                    part.offset_end,
+                    encode_part(part, sanitized)?,
+                ));
+                num_removed += removed;
+            }
+        }
+
+        if replacements.is_empty() {
+            return None;
+        }
+
+        tracing::debug!(
+            parent: &self.span,
+            context = "data",
+            event = "html-sanitize",
+            removed = num_removed,
+            "Removed active content from HTML parts."
+        );
+
+        Some(replace_parts(raw_message, replacements))
+    }
+}
+
+/// Neutralizes the active content of an HTML document, returning the sanitized
+/// document and the number of removed elements and attributes, or `None` if the
+/// document had no active content.
+pub fn sanitize_html(html: &[u8]) -> Option<(Vec<u8>, usize)> {
+    let mut output = Vec::with_capacity(html.len());
+    let mut num_removed = 0;
+    let mut last_pos = 0;
+    let mut pos = 0;
+
+    while let Some(start) = html
+        .get(pos..)
+        .and_then(|html| html.iter().position(|&ch| ch == b'<'))
+        .map(|start| start + pos)
+    {
+        // Skip comments
+        if matches!(html.get(start + 1..start + 4), Some(b"!--")) {
+            pos = find_bytes(html, start + 4, b"-->").map_or(html.len(), |end| end + 3);
+            continue;
+        }
+
+        let (is_closing, name) = tag_name(&html[start..]);
+        if name.is_empty() {
+            pos = start + 1;
+            continue;
+        }
+        let Some(end) = tag_end(html, start + 1) else {
+            break;
+        };
+
+        if REMOVED_ELEMENTS.contains(&name.as_str()) {
+            output.extend_from_slice(&html[last_pos..start]);
+            last_pos = if is_closing || html[..end].ends_with(b"/>") {
+                end
+            } else {
+                closing_tag_end(html, end, &name).unwrap_or(html.len())
+            };
+            num_removed += 1;
+        } else if REMOVED_TAGS.contains(&name.as_str()) {
+            output.extend_from_slice(&html[last_pos..start]);
+            last_pos = end;
+            num_removed += 1;
+        } else if !is_closing {
+            let tag = &html[start..end];
+            let attributes = dangerous_attributes(tag);
+            if !attributes.is_empty() {
+                output.extend_from_slice(&html[last_pos..start]);
+                let mut tag_pos = 0;
+                for (attr_start, attr_end) in &attributes {
+                    output.extend_from_slice(&tag[tag_pos..*attr_start]);
+                    tag_pos = *attr_end;
+                }
+                output.extend_from_slice(&tag[tag_pos..]);
+                last_pos = end;
+                num_removed += attributes.len();
+            }
+        }
+        pos = std::cmp::max(end, last_pos);
+    }
+
+    if num_removed > 0 {
+        output.extend_from_slice(&html[last_pos..]);
+        Some((output, num_removed))
+    } else {
+        None
+    }
+}
+
+// Returns whether a tag is a closing tag and its lowercase name
+fn tag_name(tag: &[u8]) -> (bool, String) {
+    let (is_closing, name) = match tag.get(1) {
+        Some(b'/') => (true, &tag[2..]),
+        _ => (false, tag.get(1..).unwrap_or_default()),
+    };
+    if !name.first().map_or(false, |ch| ch.is_ascii_alphabetic()) {
+        return (is_closing, String::new());
+    }
+    let name = name
+        .iter()
+        .take_while(|ch| !ch.is_ascii_whitespace() && !matches!(ch, b'/' | b'>'))
+        .map(|ch| ch.to_ascii_lowercase() as char)
+        .collect();
+    (is_closing, name)
+}
+
+// Returns the position after the end of a tag, ignoring quoted '>' characters
+fn tag_end(html: &[u8], from: usize) -> Option<usize> {
+    let mut quote = None;
+    for (idx, ch) in html.iter().enumerate().skip(from) {
+        match (*ch, quote) {
+            (b'"' | b'\'', None) => quote = Some(*ch),
+            (ch, Some(q)) if ch == q => quote = None,
+            (b'>', None) => return Some(idx + 1),
+            _ => (),
+        }
+    }
+    None
+}
+
+// Returns the position after the closing tag of an element
+fn closing_tag_end(html: &[u8], from: usize, name: &str) -> Option<usize> {
+    let mut pos = from;
+    loop {
+        let start = find_bytes(html, pos, b"</")?;
+        let (_, closing_name) = tag_name(&html[start..]);
+        if closing_name == name {
+            return tag_end(html, start + 2);
+        }
+        pos = start + 2;
+    }
+}
+
+// Returns the byte ranges of the event handler attributes and the attributes
+// containing dangerous URIs, including their leading whitespace
+fn dangerous_attributes(tag: &[u8]) -> Vec<(usize, usize)> {
+    let mut attributes = Vec::new();
+    let len = tag.len();
+
+    // Skip the tag name
+    let mut pos = 1;
+    while pos < len && !tag[pos].is_ascii_whitespace() && !matches!(tag[pos], b'/' | b'>') {
+        pos += 1;
+    }
+
+    loop {
+        let attr_start = pos;
+        while pos < len && (tag[pos].is_ascii_whitespace() || tag[pos] == b'/') {
+            pos += 1;
+        }
+        if pos >= len || tag[pos] == b'>' {
+            break;
+        }
+
+        // Parse attribute name
+        let name_start = pos;
+        while pos < len
+            && !tag[pos].is_ascii_whitespace()
+            && !matches!(tag[pos], b'=' | b'>' | b'/')
+        {
+            pos += 1;
+        }
+        if pos == name_start {
+            pos += 1;
+            continue;
+        }
+        let name = String::from_utf8_lossy(&tag[name_start..pos]).to_ascii_lowercase();
+
+        // Parse attribute value
+        let mut value_pos = pos;
+        while value_pos < len && tag[value_pos].is_ascii_whitespace() {
+            value_pos += 1;
+        }
+        let mut value: &[u8] = b"";
+        if value_pos < len && tag[value_pos] == b'=' {
+            value_pos += 1;
+            while value_pos < len && tag[value_pos].is_ascii_whitespace() {
+                value_pos += 1;
+            }
+            match tag.get(value_pos) {
+                Some(&quote @ (b'"' | b'\'')) => {
+                    let value_start = value_pos + 1;
+                    let value_end = tag[value_start..]
+                        .iter()
+                        .position(|&ch| ch == quote)
+                        .map_or(len - 1, |end| value_start + end);
+                    value = &tag[value_start..value_end];
+                    pos = std::cmp::min(value_end + 1, len - 1);
+                }
+                _ => {
+                    let value_start = value_pos;
+                    while value_pos < len
+                        && !tag[value_pos].is_ascii_whitespace()
+                        && tag[value_pos] != b'>'
+                    {
+                        value_pos += 1;
+                    }
+                    value = &tag[value_start..value_pos];
+                    pos = value_pos;
+                }
+            }
+        }
+
+        if is_dangerous_attribute(&name, value) {
+            attributes.push((attr_start, pos));
+        }
+    }
+
+    attributes
+}
+
+fn is_dangerous_attribute(name: &str, value: &[u8]) -> bool {
+    if name.len() > 2 && name.starts_with("on") {
+        true
+    } else if URI_ATTRIBUTES.contains(&name) {
+        let uri = normalize_uri(value);
+        DANGEROUS_SCHEMES
+            .iter()
+            .any(|scheme| uri.starts_with(scheme))
+    } else if name == "style" {
+        let style = normalize_uri(value);
+        style.contains("expression(") || style.contains("javascript:")
+    } else {
+        false
+    }
+}
+
+// Decodes character references and removes the whitespace and control
+// characters browsers ignore in URIs, returning the lowercase result
+fn normalize_uri(value: &[u8]) -> String {
+    let value = String::from_utf8_lossy(value);
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        let ch = if ch == '&' {
+            let mut entity = String::new();
+            while let Some(&next) = chars.peek() {
+                if next == ';' {
+                    chars.next();
+                    break;
+                } else if next.is_ascii_alphanumeric() || next == '#' {
+                    entity.push(next);
+                    chars.next();
+                    if entity.len() > 10 {
+                        break;
+                    }
+                } else {
+                    break;
+                }
+            }
+            let entity = entity.to_ascii_lowercase();
+            match entity.as_str() {
+                "tab" => '\t',
+                "newline" => '\n',
+                "colon" => ':',
+                _ => entity
+                    .strip_prefix("#x")
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                    .and_then(char::from_u32)
+                    .unwrap_or('&'),
+            }
+        } else {
+            ch
+        };
+
+        if !ch.is_whitespace() && !ch.is_control() {
+            result.push(ch.to_ascii_lowercase());
+        }
+    }
+
+    result
+}
+
+fn find_bytes(html: &[u8], from: usize, needle: &[u8]) -> Option<usize> {
+    html.get(from..)?
+        .windows(needle.len())
+        .position(|w| w.eq_ignore_ascii_case(needle))
+        .map(|pos| pos + from)
+}
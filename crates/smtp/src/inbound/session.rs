@@ -37,10 +37,18 @@ use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 use crate::core::{Session, State};
 
-use super::auth::SaslToken;
+use super::{
+    auth::SaslToken,
+    header_scanner::HeaderScanner,
+    terminator::{ScanResult, TerminatorScanner},
+    AuthResult,
+};
 
 impl<T: SessionStream> Session<T> {
     pub async fn ingest(&mut self, bytes: &[u8]) -> Result<bool, ()> {
+        if let Some(transcript) = &mut self.data.transcript {
+            transcript.client(bytes, &self.state);
+        }
         let mut iter = bytes.iter();
         let mut state = std::mem::replace(&mut self.state, State::None);
 
@@ -48,6 +56,10 @@ impl<T: SessionStream> Session<T> {
             match &mut state {
                 State::Request(receiver) => loop {
                     match receiver.ingest(&mut iter, bytes) {
+                        Ok(request) if self.is_request_disabled(&request) => {
+                            self.write(b"502 5.5.1 Command not implemented.\r\n")
+                                .await?;
+                        }
                         Ok(request) => match request {
                             Request::Rcpt { to } => {
                                 self.handle_rcpt_to(to).await?;
@@ -67,7 +79,9 @@ impl<T: SessionStream> Session<T> {
                                     self.write(b"354 Start mail input; end with <CRLF>.<CRLF>\r\n")
                                         .await?;
                                     self.data.message = Vec::with_capacity(1024);
-                                    state = State::Data(DataReceiver::new());
+                                    self.data.headers = HeaderScanner::new();
+                                    state =
+                                        State::Data(DataReceiver::new(), TerminatorScanner::new());
                                     continue 'outer;
                                 }
                             }
@@ -75,8 +89,8 @@ impl<T: SessionStream> Session<T> {
                                 chunk_size,
                                 is_last,
                             } => {
-                                state = if chunk_size + self.data.message.len()
-                                    < self.params.max_message_size
+                                let message_size = self.data.message.len() + self.spooled_size();
+                                state = if chunk_size + message_size < self.params.max_message_size
                                 {
                                     if self.data.message.is_empty() {
                                         self.data.message = Vec::with_capacity(chunk_size);
@@ -163,10 +177,7 @@ impl<T: SessionStream> Session<T> {
                                 return Err(());
                             }
                             Request::Help { .. } => {
-                                self.write(
-                                    b"250 2.0.0 Help can be found at https://stalw.art/smtp/\r\n",
-                                )
-                                .await?;
+                                self.handle_help().await?;
                             }
                             Request::Helo { host } => {
                                 if self.instance.protocol == ServerProtocol::Smtp {
@@ -230,11 +241,45 @@ impl<T: SessionStream> Session<T> {
                         },
                     }
                 },
-                State::Data(receiver) => {
-                    if self.data.message.len() + bytes.len() < self.params.max_message_size {
-                        if receiver.ingest(&mut iter, &mut self.data.message) {
+                State::Data(receiver, scanner) => {
+                    if scanner.scan(iter.as_slice()) == ScanResult::Ambiguous {
+                        tracing::info!(parent: &self.span,
+                            context = "data",
+                            event = "smuggling",
+                            "Ambiguous end-of-data sequence received.");
+
+                        self.write(b"521 5.5.2 Ambiguous end-of-data sequence received.\r\n")
+                            .await?;
+                        return Err(());
+                    }
+
+                    if self.data.message.len() + self.spooled_size() + bytes.len()
+                        < self.params.max_message_size
+                    {
+                        let offset = self.data.message.len();
+                        let is_done = receiver.ingest(&mut iter, &mut self.data.message);
+                        if self.scan_headers(offset).is_some() {
+                            // Discard the message without buffering the rest of it
+                            self.data.message = Vec::with_capacity(0);
+                            self.data.spool = None;
+                            if is_done {
+                                self.write(b"552 5.3.4 Message too big for system.\r\n")
+                                    .await?;
+                                self.reset();
+                                state = State::default();
+                            } else {
+                                self.reset();
+                                state = State::DataTooLarge(DummyDataReceiver::new_data(receiver));
+                            }
+                        } else if is_done {
                             let num_rcpts = self.data.rcpt_to.len();
-                            let message = self.queue_message().await;
+                            let message = match self.unspool_data().await {
+                                Ok(_reservation) => self.queue_message().await,
+                                Err(()) => {
+                                    (&b"451 4.3.0 Unable to accept message at this time.\r\n"[..])
+                                        .into()
+                                }
+                            };
                             if !message.is_empty() {
                                 if self.instance.protocol == ServerProtocol::Smtp {
                                     self.write(message.as_ref()).await?;
@@ -250,6 +295,7 @@ impl<T: SessionStream> Session<T> {
                                 return Err(());
                             }
                         } else {
+                            self.spool_data().await;
                             break 'outer;
                         }
                     } else {
@@ -257,11 +303,29 @@ impl<T: SessionStream> Session<T> {
                     }
                 }
                 State::Bdat(receiver) => {
-                    if receiver.ingest(&mut iter, &mut self.data.message) {
-                        if self.can_send_data().await? {
+                    let offset = self.data.message.len();
+                    let is_done = receiver.ingest(&mut iter, &mut self.data.message);
+                    self.scan_headers(offset);
+                    if is_done {
+                        if self.data.headers.limit().is_some() {
+                            // Reject all chunks until the end of the transaction
+                            self.data.message = Vec::with_capacity(0);
+                            self.data.spool = None;
+                            self.write(b"552 5.3.4 Message too big for system.\r\n")
+                                .await?;
+                            if receiver.is_last {
+                                self.reset();
+                            }
+                        } else if self.can_send_data().await? {
                             if receiver.is_last {
                                 let num_rcpts = self.data.rcpt_to.len();
-                                let message = self.queue_message().await;
+                                let message = match self.unspool_data().await {
+                                    Ok(_reservation) => self.queue_message().await,
+                                    Err(()) => {
+                                        (&b"451 4.3.0 Unable to accept message at this time.\r\n"[..])
+                                            .into()
+                                    }
+                                };
                                 if !message.is_empty() {
                                     if self.instance.protocol == ServerProtocol::Smtp {
                                         self.write(message.as_ref()).await?;
@@ -276,10 +340,12 @@ impl<T: SessionStream> Session<T> {
                                     return Err(());
                                 }
                             } else {
+                                self.spool_data().await;
                                 self.write(b"250 2.6.0 Chunk accepted.\r\n").await?;
                             }
                         } else {
                             self.data.message = Vec::with_capacity(0);
+                            self.data.spool = None;
                         }
                         state = State::default();
                     } else {
@@ -317,6 +383,7 @@ impl<T: SessionStream> Session<T> {
                         );
 
                         self.data.message = Vec::with_capacity(0);
+                        self.data.spool = None;
                         self.write(b"552 5.3.4 Message too big for system.\r\n")
                             .await?;
                         state = State::default();
@@ -339,6 +406,23 @@ impl<T: SessionStream> Session<T> {
 
         Ok(true)
     }
+
+    pub async fn handle_help(&mut self) -> Result<(), ()> {
+        // An empty help text disables the command
+        let help = self
+            .core
+            .core
+            .eval_if::<String, _>(&self.core.core.smtp.session.connect.help, self)
+            .await
+            .unwrap_or_default()
+            .replace(['\r', '\n'], " ");
+
+        if !help.is_empty() {
+            self.write(format!("250 2.0.0 {help}\r\n").as_bytes()).await
+        } else {
+            self.write(b"502 5.5.1 Command not implemented.\r\n").await
+        }
+    }
 }
 
 impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
@@ -347,6 +431,8 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
         self.data.spf_mail_from = None;
         self.data.rcpt_to.clear();
         self.data.message = Vec::with_capacity(0);
+        self.data.spool = None;
+        self.data.headers = HeaderScanner::new();
         self.data.priority = 0;
         self.data.delivery_by = 0;
         self.data.future_release = 0;
@@ -357,6 +443,9 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
         let err = match self.stream.write_all(bytes).await {
             Ok(_) => match self.stream.flush().await {
                 Ok(_) => {
+                    if let Some(transcript) = &mut self.data.transcript {
+                        transcript.server(bytes);
+                    }
                     tracing::trace!(parent: &self.span,
                             event = "write",
                             data = std::str::from_utf8(bytes).unwrap_or_default() ,
@@ -399,6 +488,37 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
     }
 }
 
+impl<T: SessionStream> Session<T> {
+    /// Returns `true` when the command has been disabled on this listener.
+    pub fn is_command_disabled(&self, command: &str) -> bool {
+        self.core
+            .core
+            .smtp
+            .session
+            .disabled_commands
+            .get(&self.instance.id)
+            .map_or(false, |commands| commands.contains(command))
+    }
+
+    fn is_request_disabled(&self, request: &Request<String>) -> bool {
+        let command = match request {
+            Request::Helo { .. } => "helo",
+            Request::Auth { .. } => "auth",
+            Request::StartTls => "starttls",
+            Request::Vrfy { .. } => "vrfy",
+            Request::Expn { .. } => "expn",
+            Request::Noop { .. } => "noop",
+            Request::Help { .. } => "help",
+            Request::Etrn { .. } => "etrn",
+            Request::Atrn { .. } => "atrn",
+            Request::Burl { .. } => "burl",
+            _ => return false,
+        };
+
+        self.is_command_disabled(command)
+    }
+}
+
 impl<T: SessionStream> ResolveVariable for Session<T> {
     fn resolve_variable(&self, variable: u32) -> expr::Variable<'_> {
         match variable {
@@ -447,7 +567,42 @@ impl<T: SessionStream> ResolveVariable for Session<T> {
             V_TLS => self.stream.is_tls().into(),
             V_PRIORITY => self.data.priority.to_string().into(),
             V_PROTOCOL => self.instance.protocol.as_str().into(),
+            V_IPREV_RESULT => self
+                .data
+                .iprev
+                .as_ref()
+                .map(|iprev| iprev.result.as_str())
+                .unwrap_or_default()
+                .into(),
             _ => expr::Variable::default(),
         }
     }
 }
+
+/// Resolves the session variables for a single recipient, which allows
+/// evaluating per-recipient settings once all recipients have been received.
+pub struct SessionRcpt<'x, T: SessionStream> {
+    pub session: &'x Session<T>,
+    pub address: &'x str,
+    pub domain: &'x str,
+}
+
+impl<T: SessionStream> Session<T> {
+    pub fn with_rcpt<'x>(&'x self, address: &'x str, domain: &'x str) -> SessionRcpt<'x, T> {
+        SessionRcpt {
+            session: self,
+            address,
+            domain,
+        }
+    }
+}
+
+impl<'x, T: SessionStream> ResolveVariable for SessionRcpt<'x, T> {
+    fn resolve_variable(&self, variable: u32) -> expr::Variable<'_> {
+        match variable {
+            V_RECIPIENT => self.address.into(),
+            V_RECIPIENT_DOMAIN => self.domain.into(),
+            _ => self.session.resolve_variable(variable),
+        }
+    }
+}
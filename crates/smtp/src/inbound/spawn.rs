@@ -23,8 +23,13 @@
 
 use std::time::Instant;
 
-use common::listener::{self, SessionManager, SessionStream};
+use common::{
+    config::smtp::session::TlsVersion,
+    expr::V_IPREV_RESULT,
+    listener::{self, SessionManager, SessionStream},
+};
 use tokio_rustls::server::TlsStream;
+use utils::config::utils::ParseValue;
 
 use crate::{
     core::{Session, SessionData, SessionParameters, SmtpSessionManager, State},
@@ -37,13 +42,24 @@ impl SessionManager for SmtpSessionManager {
         self,
         session: listener::SessionData<T>,
     ) -> impl std::future::Future<Output = ()> + Send {
+        // Assign a correlation id to the session
+        let correlation_id = self.inner.inner.snowflake_id.generate().unwrap_or_default();
+        let span = tracing::info_span!(
+            parent: &session.span,
+            "smtp",
+            correlation_id = format!("{correlation_id:x}"),
+        );
+
+        // Track the session so that draining waits for it to finish
+        let drain_in_flight = self.inner.inner.drain.session_in_flight();
+
         // Create session
         let mut session = Session {
             hostname: String::new(),
             core: self.inner.into(),
             instance: session.instance,
             state: State::default(),
-            span: session.span,
+            span,
             stream: session.stream,
             in_flight: vec![session.in_flight],
             data: SessionData::new(
@@ -54,16 +70,21 @@ impl SessionManager for SmtpSessionManager {
             ),
             params: SessionParameters::default(),
         };
+        session.data.correlation_id = correlation_id;
+        session.in_flight.extend(drain_in_flight);
 
         // Enforce throttle
         async {
             if session.is_allowed().await
+                && session.verify_tls_version().await
                 && session.init_conn().await
                 && session.handle_conn().await
                 && session.instance.acceptor.is_tls()
             {
                 if let Ok(mut session) = session.into_tls().await {
-                    session.handle_conn().await;
+                    if session.verify_tls_version().await {
+                        session.handle_conn().await;
+                    }
                 }
             }
         }
@@ -92,7 +113,26 @@ impl SessionManager for SmtpSessionManager {
 
 impl<T: SessionStream> Session<T> {
     pub async fn init_conn(&mut self) -> bool {
+        // Refuse new connections while draining
+        if self.close_if_draining().await {
+            return false;
+        }
+
         self.eval_session_params().await;
+        self.init_transcript().await;
+
+        // Resolve reverse DNS if the greeting depends on it
+        if self
+            .core
+            .core
+            .smtp
+            .session
+            .connect
+            .greeting
+            .has_variable(V_IPREV_RESULT)
+        {
+            self.handle_iprev().await;
+        }
 
         let config = &self.core.core.smtp.session.connect;
 
@@ -151,9 +191,43 @@ impl<T: SessionStream> Session<T> {
         true
     }
 
+    pub async fn verify_tls_version(&mut self) -> bool {
+        if !self.stream.is_tls() {
+            return true;
+        }
+        let Ok(version) = TlsVersion::parse_value(&self.stream.tls_version_and_cipher().0) else {
+            return true;
+        };
+        let min_version = self
+            .core
+            .core
+            .eval_if::<TlsVersion, _>(&self.core.core.smtp.session.connect.tls_min_version, self)
+            .await
+            .unwrap_or_default();
+
+        if version < min_version {
+            tracing::info!(
+                parent: &self.span,
+                context = "tls",
+                event = "reject",
+                remote_ip = %self.data.remote_ip,
+                version = version.as_str(),
+                min_version = min_version.as_str(),
+                "TLS version not allowed for this client."
+            );
+            let _ = self
+                .write(b"421 4.7.0 TLS version not allowed, closing connection.\r\n")
+                .await;
+            false
+        } else {
+            true
+        }
+    }
+
     pub async fn handle_conn(&mut self) -> bool {
         let mut buf = vec![0; 8192];
         let mut shutdown_rx = self.instance.shutdown_rx.clone();
+        let mut drain_rx = self.core.inner.drain.subscribe();
 
         loop {
             tokio::select! {
@@ -166,7 +240,11 @@ impl<T: SessionStream> Session<T> {
                                     if Instant::now() < self.data.valid_until && bytes_read <= self.data.bytes_left  {
                                         self.data.bytes_left -= bytes_read;
                                         match self.ingest(&buf[..bytes_read]).await {
-                                            Ok(true) => (),
+                                            Ok(true) => {
+                                                if self.close_if_draining().await {
+                                                    break;
+                                                }
+                                            }
                                             Ok(false) => {
                                                 return true;
                                             }
@@ -227,6 +305,11 @@ impl<T: SessionStream> Session<T> {
                             }
                         }
                 },
+                _ = drain_rx.changed() => {
+                    if self.close_if_draining().await {
+                        break;
+                    }
+                },
                 _ = shutdown_rx.changed() => {
                     tracing::debug!(
                         parent: &self.span,
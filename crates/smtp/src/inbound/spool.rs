@@ -0,0 +1,227 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::{atomic::Ordering, Arc},
+};
+
+use common::listener::SessionStream;
+use tokio::{
+    fs::{DirBuilder, File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+
+use crate::core::{Inner, Session};
+
+/// Temporary file holding the portion of a message received so far, used
+/// to bound the memory used by sessions transferring large messages.
+/// The file is removed when dropped.
+///
+/// Spooling only covers the time a message spends in transit. Once the
+/// final chunk is received the body is loaded back into memory, as message
+/// parsing, DKIM/ARC verification, Sieve scripts, milters, ICAP and the
+/// queue blob store all operate on a contiguous in-memory buffer. The
+/// memory taken by loaded messages is bounded by `spool-max-memory`.
+pub struct SpoolFile {
+    pub path: PathBuf,
+    pub file: File,
+    pub size: usize,
+    pub failed: bool,
+}
+
+/// Memory taken by a spooled message loaded back for processing, released
+/// when dropped.
+pub struct SpoolReservation {
+    inner: Arc<Inner>,
+    size: usize,
+}
+
+impl SpoolFile {
+    pub async fn new(spool_path: &Path, id: u64) -> std::io::Result<Self> {
+        // Spool files are only accessible by the server
+        let mut builder = DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        builder.mode(0o700);
+        builder.create(spool_path).await?;
+
+        let path = spool_path.join(format!("{id:x}-{:x}.eml", rand::random::<u64>()));
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        Ok(SpoolFile {
+            file: options.open(&path).await?,
+            path,
+            size: 0,
+            failed: false,
+        })
+    }
+
+    pub async fn append(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.file.write_all(bytes).await?;
+        self.size += bytes.len();
+        Ok(())
+    }
+
+    pub async fn read(mut self, tail: Vec<u8>) -> std::io::Result<Vec<u8>> {
+        if self.failed {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "Spool file is incomplete",
+            ));
+        }
+        self.file.flush().await?;
+        self.file.seek(SeekFrom::Start(0)).await?;
+        let mut message = Vec::with_capacity(self.size + tail.len());
+        (&mut self.file)
+            .take(self.size as u64)
+            .read_to_end(&mut message)
+            .await?;
+        message.extend_from_slice(&tail);
+        Ok(message)
+    }
+}
+
+impl Drop for SpoolFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+impl Drop for SpoolReservation {
+    fn drop(&mut self) {
+        self.inner
+            .spool_memory
+            .fetch_sub(self.size, Ordering::Relaxed);
+    }
+}
+
+impl<T: SessionStream> Session<T> {
+    pub async fn spool_data(&mut self) {
+        if self.params.spool_threshold == 0 || self.data.message.len() < self.params.spool_threshold
+        {
+            return;
+        }
+
+        // Create spool file
+        if self.data.spool.is_none() {
+            match SpoolFile::new(
+                &self.core.core.smtp.session.data.spool_path,
+                self.core.inner.snowflake_id.generate().unwrap_or_default(),
+            )
+            .await
+            {
+                Ok(spool) => {
+                    self.data.spool = spool.into();
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        parent: &self.span,
+                        context = "data",
+                        event = "spool-error",
+                        reason = %err,
+                        "Failed to create spool file, keeping message in memory."
+                    );
+                    self.params.spool_threshold = 0;
+                    return;
+                }
+            }
+        }
+
+        // Write buffered data, incomplete spool files are rejected once DATA ends
+        let span = &self.span;
+        if let Some(spool) = self.data.spool.as_mut().filter(|spool| !spool.failed) {
+            if let Err(err) = spool.append(&self.data.message).await {
+                tracing::warn!(
+                    parent: span,
+                    context = "data",
+                    event = "spool-error",
+                    reason = %err,
+                    "Failed to write message to spool file."
+                );
+                spool.failed = true;
+            }
+        }
+        self.data.message.clear();
+    }
+
+    /// Loads a spooled message back into `data.message` before it is
+    /// queued. Fails when the message cannot be read or when loading it
+    /// would exceed the memory reserved for spooled messages.
+    pub async fn unspool_data(&mut self) -> Result<Option<SpoolReservation>, ()> {
+        let Some(spool) = self.data.spool.take() else {
+            return Ok(None);
+        };
+
+        // Bound the memory used by spooled messages being processed, a
+        // single message is always let through to avoid starving large ones.
+        let size = spool.size + self.data.message.len();
+        let max_memory = self.core.core.smtp.session.data.spool_max_memory;
+        let in_use = self
+            .core
+            .inner
+            .spool_memory
+            .fetch_add(size, Ordering::Relaxed);
+        let reservation = SpoolReservation {
+            inner: self.core.inner.clone(),
+            size,
+        };
+        if max_memory > 0 && in_use > 0 && in_use + size > max_memory {
+            tracing::info!(
+                parent: &self.span,
+                context = "data",
+                event = "spool-busy",
+                size = size,
+                in_use = in_use,
+                "Too many spooled messages being processed."
+            );
+            return Err(());
+        }
+
+        match spool.read(std::mem::take(&mut self.data.message)).await {
+            Ok(message) => {
+                self.data.message = message;
+                Ok(Some(reservation))
+            }
+            Err(err) => {
+                tracing::warn!(
+                    parent: &self.span,
+                    context = "data",
+                    event = "spool-error",
+                    reason = %err,
+                    "Failed to read message from spool file."
+                );
+                Err(())
+            }
+        }
+    }
+
+    #[inline(always)]
+    pub fn spooled_size(&self) -> usize {
+        self.data.spool.as_ref().map_or(0, |spool| spool.size)
+    }
+}
@@ -0,0 +1,102 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+/// Tracks the DATA byte stream looking for lines containing a single dot
+/// that are not delimited by CRLF on both sides. Such sequences (for example
+/// `\n.\n` or `\r\n.\r`) are interpreted as the end of data by some servers
+/// and can be used to smuggle additional messages past them.
+#[derive(Debug)]
+pub struct TerminatorScanner {
+    state: ScanState,
+    last: [u8; 2],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScanState {
+    Text,
+    Dot { after_crlf: bool },
+    DotCr { after_crlf: bool },
+    Done,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanResult {
+    Continue,
+    End,
+    Ambiguous,
+}
+
+impl TerminatorScanner {
+    pub fn new() -> Self {
+        // The DATA command line ended with CRLF
+        TerminatorScanner {
+            state: ScanState::Text,
+            last: [b'\r', b'\n'],
+        }
+    }
+
+    /// Scans the next chunk of DATA bytes, stopping at the first
+    /// `\r\n.\r\n` terminator so that pipelined commands are not inspected.
+    pub fn scan(&mut self, bytes: &[u8]) -> ScanResult {
+        for &ch in bytes {
+            self.state = match self.state {
+                ScanState::Text => {
+                    if ch == b'.' && matches!(self.last[1], b'\r' | b'\n') {
+                        ScanState::Dot {
+                            after_crlf: self.last == [b'\r', b'\n'],
+                        }
+                    } else {
+                        ScanState::Text
+                    }
+                }
+                ScanState::Dot { after_crlf } => match ch {
+                    b'\r' => ScanState::DotCr { after_crlf },
+                    b'\n' => return ScanResult::Ambiguous,
+                    _ => ScanState::Text,
+                },
+                ScanState::DotCr { after_crlf } => {
+                    if ch == b'\n' && after_crlf {
+                        self.state = ScanState::Done;
+                        return ScanResult::End;
+                    } else {
+                        return ScanResult::Ambiguous;
+                    }
+                }
+                ScanState::Done => return ScanResult::End,
+            };
+            self.last = [self.last[1], ch];
+        }
+
+        if self.state == ScanState::Done {
+            ScanResult::End
+        } else {
+            ScanResult::Continue
+        }
+    }
+}
+
+impl Default for TerminatorScanner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,298 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use common::{
+    expr::if_block::IfBlock,
+    listener::SessionStream,
+    scripts::functions::html::{get_attribute, html_img_area},
+};
+use mail_builder::encoders::base64::base64_encode;
+use mail_parser::{
+    decoders::{base64::base64_decode, quoted_printable::quoted_printable_decode},
+    Encoding, MessageParser, MessagePart, PartType,
+};
+use sieve::runtime::Variable;
+
+use crate::core::Session;
+
+impl<T: SessionStream> Session<T> {
+    /// Removes tracking pixels from the HTML parts of a message, returning
+    /// the rewritten message if any image was removed. As all recipients
+    /// share the same copy of the message, it is only modified when removal
+    /// is enabled for every recipient.
+    pub async fn strip_tracking_pixels(&self, raw_message: &[u8]) -> Option<Vec<u8>> {
+        let config = &self.core.core.smtp.session.data.tracking_pixels;
+        if !self.is_enabled_for_all_rcpts(&config.enable).await {
+            return None;
+        }
+
+        let message = MessageParser::new().parse(raw_message)?;
+        let mut replacements = Vec::new();
+        let mut num_removed = 0;
+
+        for part in &message.parts {
+            if !matches!(part.body, PartType::Html(_)) {
+                continue;
+            }
+            let html = decode_part(part, raw_message)?;
+
+            // Find and remove tracking images
+            let mut stripped = Vec::with_capacity(html.len());
+            let mut last_pos = 0;
+            for (start, end) in img_tags(&html) {
+                let tag = String::from_utf8_lossy(&html[start..end]).to_lowercase();
+                if self.is_tracking_pixel(&tag).await {
+                    stripped.extend_from_slice(&html[last_pos..start]);
+                    last_pos = end;
+                    num_removed += 1;
+                }
+            }
+            if last_pos == 0 {
+                continue;
+            }
+            stripped.extend_from_slice(&html[last_pos..]);
+
+            // Encode the HTML part using its original transfer encoding
+            replacements.push((
+                part.offset_body,
+                part.offset_end,
+                encode_part(part, stripped)?,
+            ));
+        }
+
+        if replacements.is_empty() {
+            return None;
+        }
+
+        tracing::debug!(
+            parent: &self.span,
+            context = "data",
+            event = "tracking-pixels",
+            removed = num_removed,
+            "Removed tracking pixels from message."
+        );
+
+        Some(replace_parts(raw_message, replacements))
+    }
+
+    pub(super) async fn is_enabled_for_all_rcpts(&self, if_block: &IfBlock) -> bool {
+        for rcpt in &self.data.rcpt_to {
+            if !self
+                .core
+                .core
+                .eval_if(if_block, &self.with_rcpt(&rcpt.address_lcase, &rcpt.domain))
+                .await
+                .unwrap_or(false)
+            {
+                return false;
+            }
+        }
+
+        !self.data.rcpt_to.is_empty()
+    }
+
+    async fn is_tracking_pixel(&self, tag: &str) -> bool {
+        let config = &self.core.core.smtp.session.data.tracking_pixels;
+
+        // Tiny images are considered tracking pixels
+        if html_img_area(&[Variable::from(tag.to_string())]) <= config.max_area {
+            return true;
+        }
+
+        // Images hosted by known trackers
+        if let (Some(store), Some(host)) = (
+            self.core.core.storage.lookups.get(&config.lookup),
+            get_attribute(tag, "src").and_then(url_host),
+        ) {
+            let mut domain = host;
+            loop {
+                if store
+                    .key_exists(domain.as_bytes().to_vec())
+                    .await
+                    .unwrap_or(false)
+                {
+                    return true;
+                }
+                match domain.split_once('.') {
+                    Some((_, parent)) if parent.contains('.') => domain = parent,
+                    _ => break,
+                }
+            }
+        }
+
+        false
+    }
+}
+
+// Returns the byte ranges of all <img> tags in an HTML document
+fn img_tags(html: &[u8]) -> Vec<(usize, usize)> {
+    let mut tags = Vec::new();
+    let mut pos = 0;
+
+    while let Some(start) = html
+        .get(pos..)
+        .and_then(|html| {
+            html.windows(4)
+                .position(|w| w.eq_ignore_ascii_case(b"<img"))
+        })
+        .map(|start| start + pos)
+    {
+        let mut quote = None;
+        let mut end = None;
+        for (idx, ch) in html.iter().enumerate().skip(start + 4) {
+            match (*ch, quote) {
+                (b'"' | b'\'', None) => quote = Some(*ch),
+                (ch, Some(q)) if ch == q => quote = None,
+                (b'>', None) => {
+                    end = Some(idx + 1);
+                    break;
+                }
+                _ => (),
+            }
+        }
+
+        match end {
+            Some(end) => {
+                if html
+                    .get(start + 4)
+                    .map_or(false, |ch| ch.is_ascii_whitespace() || *ch == b'/')
+                {
+                    tags.push((start, end));
+                }
+                pos = end;
+            }
+            None => break,
+        }
+    }
+
+    tags
+}
+
+fn url_host(url: &str) -> Option<&str> {
+    let url = url.trim();
+    let url = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .or_else(|| url.strip_prefix("//"))?;
+    let host = url
+        .split(['/', '?', '#', ':'])
+        .next()
+        .unwrap_or_default()
+        .rsplit('@')
+        .next()
+        .unwrap_or_default();
+    if !host.is_empty() {
+        Some(host)
+    } else {
+        None
+    }
+}
+
+// Returns the transfer decoded body of a part
+pub(super) fn decode_part(part: &MessagePart<'_>, raw_message: &[u8]) -> Option<Vec<u8>> {
+    let raw_body = raw_message.get(part.offset_body..part.offset_end)?;
+    match part.encoding {
+        Encoding::None => raw_body.to_vec().into(),
+        Encoding::Base64 => base64_decode(raw_body),
+        Encoding::QuotedPrintable => quoted_printable_decode(raw_body),
+    }
+}
+
+// Encodes a part body using the original transfer encoding of the part
+pub(super) fn encode_part(part: &MessagePart<'_>, contents: Vec<u8>) -> Option<Vec<u8>> {
+    match part.encoding {
+        Encoding::None => contents.into(),
+        Encoding::Base64 => wrap_lines(&base64_encode(&contents).ok()?).into(),
+        Encoding::QuotedPrintable => quoted_printable_encode(&contents).into(),
+    }
+}
+
+// Rebuilds a message replacing the given byte ranges with new contents
+pub(super) fn replace_parts(
+    raw_message: &[u8],
+    mut replacements: Vec<(usize, usize, Vec<u8>)>,
+) -> Vec<u8> {
+    replacements.sort_unstable_by_key(|(start, _, _)| *start);
+    let mut edited_message = Vec::with_capacity(raw_message.len());
+    let mut last_pos = 0;
+    for (start, end, contents) in replacements {
+        if start < last_pos {
+            continue;
+        }
+        edited_message.extend_from_slice(&raw_message[last_pos..start]);
+        edited_message.extend_from_slice(&contents);
+        last_pos = end;
+    }
+    edited_message.extend_from_slice(&raw_message[last_pos..]);
+    edited_message
+}
+
+fn wrap_lines(encoded: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(encoded.len() + (encoded.len() / 76 + 1) * 2);
+    for (idx, line) in encoded.chunks(76).enumerate() {
+        if idx > 0 {
+            output.extend_from_slice(b"\r\n");
+        }
+        output.extend_from_slice(line);
+    }
+    output
+}
+
+pub(super) fn quoted_printable_encode(input: &[u8]) -> Vec<u8> {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    let mut output = Vec::with_capacity(input.len() + input.len() / 4);
+    let mut line_len = 0;
+    let mut iter = input.iter().peekable();
+
+    while let Some(&ch) = iter.next() {
+        if ch == b'\r' && iter.peek() == Some(&&b'\n') {
+            iter.next();
+            output.extend_from_slice(b"\r\n");
+            line_len = 0;
+            continue;
+        } else if ch == b'\n' {
+            output.extend_from_slice(b"\r\n");
+            line_len = 0;
+            continue;
+        }
+
+        let is_literal = match ch {
+            b'=' => false,
+            b' ' | b'\t' => !matches!(iter.peek().map(|ch| **ch), None | Some(b'\r' | b'\n')),
+            _ => (33..=126).contains(&ch),
+        };
+        let len = if is_literal { 1 } else { 3 };
+        if line_len + len > 75 {
+            output.extend_from_slice(b"=\r\n");
+            line_len = 0;
+        }
+        if is_literal {
+            output.push(ch);
+        } else {
+            output.extend_from_slice(&[b'=', HEX[(ch >> 4) as usize], HEX[(ch & 0x0f) as usize]]);
+        }
+        line_len += len;
+    }
+
+    output
+}
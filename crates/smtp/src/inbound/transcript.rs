@@ -0,0 +1,202 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{collections::VecDeque, net::IpAddr, sync::Arc};
+
+use common::listener::SessionStream;
+use parking_lot::Mutex;
+use store::write::now;
+
+use crate::core::{Session, State};
+
+#[derive(Default)]
+pub struct Transcripts {
+    entries: Mutex<VecDeque<Arc<TranscriptEntry>>>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct TranscriptEntry {
+    #[serde(serialize_with = "serialize_correlation_id")]
+    pub correlation_id: u64,
+    pub remote_ip: IpAddr,
+    pub created: u64,
+    pub transcript: String,
+    pub truncated: bool,
+}
+
+pub struct Transcript {
+    entry: TranscriptEntry,
+    max_size: usize,
+    capacity: usize,
+    transcripts: Arc<Transcripts>,
+}
+
+impl Transcripts {
+    pub fn get(&self, correlation_id: u64) -> Option<Arc<TranscriptEntry>> {
+        self.entries
+            .lock()
+            .iter()
+            .find(|entry| entry.correlation_id == correlation_id)
+            .cloned()
+    }
+
+    pub fn list(&self) -> Vec<Arc<TranscriptEntry>> {
+        self.entries.lock().iter().rev().cloned().collect()
+    }
+
+    fn insert(&self, entry: TranscriptEntry, capacity: usize) {
+        if capacity > 0 {
+            let mut entries = self.entries.lock();
+            while entries.len() >= capacity {
+                entries.pop_front();
+            }
+            entries.push_back(Arc::new(entry));
+        }
+    }
+}
+
+impl Transcript {
+    fn append(&mut self, prefix: &str, line: &str) {
+        if self.entry.truncated {
+            return;
+        }
+        if self.entry.transcript.len() + prefix.len() + line.len() + 2 > self.max_size {
+            self.entry.truncated = true;
+            return;
+        }
+        self.entry.transcript.push_str(prefix);
+        self.entry.transcript.push_str(line);
+        self.entry.transcript.push_str("\r\n");
+    }
+
+    pub fn client(&mut self, bytes: &[u8], state: &State) {
+        match state {
+            State::Request(_) | State::RequestTooLarge(_) => {
+                let text = String::from_utf8_lossy(bytes);
+                let mut offset = 0;
+                let mut is_sasl = false;
+                for line in text.split_inclusive('\n') {
+                    offset += line.len();
+                    if is_sasl {
+                        self.append("C: ", "[REDACTED]");
+                        continue;
+                    }
+                    let line = line.trim_end();
+                    let mut parts = line.splitn(3, ' ');
+                    match (parts.next(), parts.next(), parts.next()) {
+                        // Never record AUTH initial responses nor the SASL
+                        // responses pipelined after the command
+                        (Some(command), mechanism, initial_response)
+                            if command.eq_ignore_ascii_case("AUTH") =>
+                        {
+                            if let (Some(mechanism), Some(_)) = (mechanism, initial_response) {
+                                self.append("C: ", &format!("{command} {mechanism} [REDACTED]"));
+                            } else {
+                                self.append("C: ", line);
+                            }
+                            is_sasl = true;
+                        }
+                        (Some(command), _, _)
+                            if command.eq_ignore_ascii_case("DATA")
+                                || command.eq_ignore_ascii_case("BDAT") =>
+                        {
+                            // Pipelined message contents follow
+                            self.append("C: ", line);
+                            if offset < text.len() {
+                                self.append(
+                                    "C: ",
+                                    &format!("[DATA {} bytes]", text.len() - offset),
+                                );
+                            }
+                            break;
+                        }
+                        _ => self.append("C: ", line),
+                    }
+                }
+            }
+            State::Sasl(_) => self.append("C: ", "[REDACTED]"),
+            _ => self.append("C: ", &format!("[DATA {} bytes]", bytes.len())),
+        }
+    }
+
+    pub fn server(&mut self, bytes: &[u8]) {
+        for line in String::from_utf8_lossy(bytes).lines() {
+            self.append("S: ", line);
+        }
+    }
+}
+
+impl Drop for Transcript {
+    fn drop(&mut self) {
+        self.transcripts.insert(
+            TranscriptEntry {
+                correlation_id: self.entry.correlation_id,
+                remote_ip: self.entry.remote_ip,
+                created: self.entry.created,
+                transcript: std::mem::take(&mut self.entry.transcript),
+                truncated: self.entry.truncated,
+            },
+            self.capacity,
+        );
+    }
+}
+
+impl<T: SessionStream> Session<T> {
+    pub async fn init_transcript(&mut self) {
+        let config = &self.core.core.smtp.session.transcript;
+        if self
+            .core
+            .core
+            .eval_if(&config.enable, self)
+            .await
+            .unwrap_or(false)
+        {
+            tracing::debug!(
+                parent: &self.span,
+                context = "transcript",
+                event = "enable",
+                "Recording session transcript."
+            );
+
+            self.data.transcript = Some(Transcript {
+                entry: TranscriptEntry {
+                    correlation_id: self.data.correlation_id,
+                    remote_ip: self.data.remote_ip,
+                    created: now(),
+                    transcript: String::new(),
+                    truncated: false,
+                },
+                max_size: config.max_size,
+                capacity: config.capacity,
+                transcripts: self.core.inner.transcripts.clone(),
+            });
+        }
+    }
+}
+
+fn serialize_correlation_id<S>(correlation_id: &u64, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_str(&format!("{correlation_id:x}"))
+}
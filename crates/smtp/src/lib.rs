@@ -21,12 +21,12 @@
  * for more details.
 */
 
-use crate::core::{throttle::ThrottleKeyHasherBuilder, TlsConnectors};
+use crate::core::throttle::ThrottleKeyHasherBuilder;
 use core::{Inner, SmtpInstance, SMTP};
 
 use common::SharedCore;
 use dashmap::DashMap;
-use mail_send::smtp::tls::build_tls_connector;
+use inbound::breaker::register_breaker_gauge;
 use queue::manager::SpawnQueue;
 use reporting::scheduler::SpawnReport;
 use tokio::sync::mpsc;
@@ -83,15 +83,19 @@ impl SMTP {
                 .property::<u64>("cluster.node-id")
                 .map(SnowflakeIdGenerator::with_node_id)
                 .unwrap_or_default(),
-            connectors: TlsConnectors {
-                pki_verify: build_tls_connector(false),
-                dummy_verify: build_tls_connector(true),
-            },
+            connectors: Default::default(),
+            transcripts: Default::default(),
+            circuit_breakers: Default::default(),
+            drain: Default::default(),
+            spool_memory: Default::default(),
             #[cfg(feature = "local_delivery")]
             delivery_tx,
         };
         let inner = SmtpInstance::new(core, inner);
 
+        // Report circuit breaker states
+        register_breaker_gauge(&inner.inner);
+
         // Spawn queue manager
         queue_rx.spawn(inner.clone());
 
@@ -0,0 +1,219 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::net::{IpAddr, Ipv4Addr};
+
+use common::{config::smtp::queue::RequireOptional, listener::limiter::InFlight};
+use mail_send::{smtp::AssertReply, SmtpClient};
+use smtp_proto::{EhloResponse, MAIL_REQUIRETLS};
+use store::write::now;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::queue::{Message, QueueEnvelope, Status, MSG_SCAN_PENDING};
+
+use super::session::SessionParams;
+
+impl Message {
+    /// Delivers other messages that are due for the same domain and next hop
+    /// over an already established connection, up to the configured maximum
+    /// number of messages per connection. Each message is locked, delivered
+    /// and saved independently so that a failure on one of them does not
+    /// affect the rest of the batch.
+    pub(super) async fn deliver_batch<T: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        smtp_client: &mut SmtpClient<T>,
+        capabilities: &EhloResponse<String>,
+        params: &SessionParams<'_>,
+    ) {
+        let core = params.core;
+        let mut num_messages = 1;
+
+        for event in core.next_event().await {
+            if num_messages >= params.max_messages {
+                break;
+            } else if event.queue_id == self.id {
+                continue;
+            }
+
+            // Make sure the message is due for delivery to the same destination
+            let mut in_flight = Vec::new();
+            match core.read_message(event.queue_id).await {
+                Some(message) if message.is_batch_candidate(params, &mut in_flight).await => (),
+                _ => continue,
+            }
+
+            // Reset the previous transaction
+            if let Err(err) = smtp_client
+                .cmd(b"RSET\r\n")
+                .await
+                .and_then(|r| r.assert_positive_completion())
+            {
+                tracing::debug!(
+                    parent: params.span,
+                    context = "batch",
+                    event = "rset-failed",
+                    mx = params.hostname,
+                    reason = %err,
+                );
+                break;
+            }
+
+            // Lock the message and fetch its latest version
+            let Some(event) = core.try_lock_event(event).await else {
+                continue;
+            };
+            let Some(mut message) = core.read_message(event.queue_id).await else {
+                continue;
+            };
+            let Some(domain_idx) = message.batch_domain_idx(params) else {
+                continue;
+            };
+
+            let span = tracing::info_span!(
+                parent: params.span,
+                "batch",
+                "id" = message.id,
+                "return_path" = if !message.return_path.is_empty() {
+                    message.return_path.as_ref()
+                } else {
+                    "<>"
+                },
+                "nrcpt" = message.recipients.len(),
+                "size" = message.size
+            );
+            let batch_params = SessionParams {
+                span: &span,
+                ..*params
+            };
+
+            let mut recipients = std::mem::take(&mut message.recipients);
+            let status = match message
+                .deliver_transactions(
+                    smtp_client,
+                    recipients.iter_mut().filter(|r| r.domain_idx == domain_idx),
+                    capabilities,
+                    &batch_params,
+                )
+                .await
+            {
+                Ok(status) | Err(status) => status,
+            };
+            message.recipients = recipients;
+
+            // Update status and save changes
+            let no_ip = IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0));
+            let schedule = core
+                .retry_schedule(&QueueEnvelope {
+                    message: &message,
+                    domain: params.domain,
+                    mx: params.hostname,
+                    remote_ip: no_ip,
+                    local_ip: no_ip,
+                })
+                .await;
+            message.domains[domain_idx].set_status(status, &schedule);
+            core.complete_delivery(message, event, vec![], &span).await;
+            num_messages += 1;
+        }
+    }
+
+    async fn is_batch_candidate(
+        &self,
+        params: &SessionParams<'_>,
+        in_flight: &mut Vec<InFlight>,
+    ) -> bool {
+        let core = params.core;
+        let queue_config = &core.core.smtp.queue;
+
+        // Messages requiring TLS can only be added to encrypted sessions and
+        // messages pending a scan have to go through a regular delivery attempt
+        if self.batch_domain_idx(params).is_none()
+            || ((self.flags & MAIL_REQUIRETLS) != 0 && !params.is_tls)
+            || (self.flags & MSG_SCAN_PENDING) != 0
+        {
+            return false;
+        }
+
+        let no_ip = IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0));
+        let envelope = QueueEnvelope {
+            message: self,
+            domain: params.domain,
+            mx: params.hostname,
+            remote_ip: no_ip,
+            local_ip: no_ip,
+        };
+        if core
+            .core
+            .eval_if::<String, _>(&queue_config.next_hop, &envelope)
+            .await
+            .as_deref()
+            != params.next_hop
+        {
+            return false;
+        }
+
+        // The TLS policy of the session has to match the one this message
+        // would have been delivered with
+        if core
+            .core
+            .eval_if(&queue_config.tls.start, &envelope)
+            .await
+            .unwrap_or(RequireOptional::Optional)
+            != params.starttls
+        {
+            return false;
+        }
+
+        // Apply sender and recipient domain throttles
+        for throttle in &queue_config.throttle.sender {
+            if core
+                .is_allowed(throttle, self, in_flight, params.span)
+                .await
+                .is_err()
+            {
+                return false;
+            }
+        }
+        for throttle in &queue_config.throttle.rcpt {
+            if core
+                .is_allowed(throttle, &envelope, in_flight, params.span)
+                .await
+                .is_err()
+            {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    fn batch_domain_idx(&self, params: &SessionParams<'_>) -> Option<usize> {
+        let now = now();
+        self.domains.iter().position(|domain| {
+            domain.domain == params.domain
+                && domain.expires > now
+                && matches!(&domain.status, Status::Scheduled | Status::TemporaryFailure(_)
+                    if domain.retry.due <= now)
+        })
+    }
+}
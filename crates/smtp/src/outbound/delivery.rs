@@ -23,18 +23,24 @@
 
 use crate::outbound::dane::verify::TlsaVerify;
 use crate::outbound::mta_sts::verify::VerifyPolicy;
-use common::config::{
-    server::ServerProtocol,
-    smtp::{queue::RequireOptional, report::AggregateFrequency},
+use common::{
+    config::{
+        server::ServerProtocol,
+        smtp::{queue::RequireOptional, report::AggregateFrequency, session::TlsVersion},
+    },
+    listener::limiter::ConcurrencyLimiter,
 };
 use mail_auth::{
+    hickory_resolver::proto::op::ResponseCode,
     mta_sts::TlsRpt,
     report::tlsrpt::{FailureDetails, ResultType},
+    IpLookupStrategy,
 };
 use mail_send::SmtpClient;
 use smtp_proto::MAIL_REQUIRETLS;
 use std::{
     net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
     time::Duration,
 };
 use store::write::{now, BatchBuilder, QueueClass, QueueEvent, ValueClass};
@@ -48,16 +54,23 @@ use crate::{
 use super::{
     lookup::ToNextHop,
     mta_sts,
-    session::{read_greeting, say_helo, try_start_tls, SessionParams, StartTlsResult},
+    pinning::verify_tls_pins,
+    session::{
+        read_greeting, say_helo, try_start_tls, verify_tls_version, SessionParams, StartTlsResult,
+    },
     NextHop, TlsStrategy,
 };
 use crate::queue::{
-    throttle, DeliveryAttempt, Domain, Error, Event, OnHold, QueueEnvelope, Status,
+    spool::QueueEventLock, throttle, DeliveryAttempt, Domain, Error, Event, OnHold, QueueEnvelope,
+    Status, MSG_SCAN_PENDING,
 };
 
 impl DeliveryAttempt {
     pub async fn try_deliver(mut self, core: SMTP) {
         tokio::spawn(async move {
+            // Track the delivery so that draining waits for it to finish
+            let _in_flight = core.inner.drain.delivery_in_flight();
+
             // Lock message
             self.event = if let Some(event) = core.try_lock_event(self.event).await {
                 event
@@ -75,13 +88,14 @@ impl DeliveryAttempt {
                     due: self.event.due,
                     queue_id: self.event.queue_id,
                 })));
-                let _ = core.core.storage.data.write(batch.build()).await;
+                let _ = core.core.storage.queue.data.write(batch.build()).await;
                 return;
             };
 
             let span = tracing::info_span!(
                 "delivery",
                 "id" = message.id,
+                "correlation_id" = format!("{:x}", message.correlation_id),
                 "return_path" = if !message.return_path.is_empty() {
                     message.return_path.as_ref()
                 } else {
@@ -92,7 +106,7 @@ impl DeliveryAttempt {
             );
 
             // Check that the message still has recipients to be delivered
-            let has_pending_delivery = message.has_pending_delivery(&span);
+            let has_pending_delivery = message.has_pending_delivery(&core, &span).await;
 
             // Send any due Delivery Status Notifications
             core.send_dsn(&mut message, &span).await;
@@ -120,6 +134,15 @@ impl DeliveryAttempt {
                 return;
             }
 
+            // Run the DATA script on messages that were queued before being scanned
+            if (message.flags & MSG_SCAN_PENDING) != 0
+                && !core.deferred_scan(&mut message, &span).await
+            {
+                core.complete_delivery(message, self.event, vec![], &span)
+                    .await;
+                return;
+            }
+
             // Throttle sender
             for throttle in &core.core.smtp.queue.throttle.sender {
                 if let Err(err) = core
@@ -203,11 +226,13 @@ impl DeliveryAttempt {
                 }
 
                 // Obtain next hop
-                let (mut remote_hosts, is_smtp) = match core
+                let next_hop = core
                     .core
                     .eval_if::<String, _>(&queue_config.next_hop, &envelope)
-                    .await
-                    .and_then(|name| core.core.get_relay_host(&name))
+                    .await;
+                let (mut remote_hosts, is_smtp) = match next_hop
+                    .as_deref()
+                    .and_then(|name| core.core.get_relay_host(name))
                 {
                     #[cfg(feature = "local_delivery")]
                     Some(next_hop) if next_hop.protocol == ServerProtocol::Http => {
@@ -221,14 +246,7 @@ impl DeliveryAttempt {
                             .await;
 
                         // Update status for the current domain and continue with the next one
-                        domain.set_status(
-                            delivery_result,
-                            &core
-                                .core
-                                .eval_if::<Vec<Duration>, _>(&queue_config.retry, &envelope)
-                                .await
-                                .unwrap_or_else(|| vec![Duration::from_secs(60)]),
-                        );
+                        domain.set_status(delivery_result, &core.retry_schedule(&envelope).await);
                         continue 'next_domain;
                     }
                     Some(next_hop) => (
@@ -360,14 +378,7 @@ impl DeliveryAttempt {
                                     "Failed to retrieve MTA-STS policy: {}",
                                     err
                                 );
-                                domain.set_status(
-                                    err,
-                                    &core
-                                        .core
-                                        .eval_if::<Vec<Duration>, _>(&queue_config.retry, &envelope)
-                                        .await
-                                        .unwrap_or_else(|| vec![Duration::from_secs(60)]),
-                                );
+                                domain.set_status(err, &core.retry_schedule(&envelope).await);
                                 continue 'next_domain;
                             } else {
                                 tracing::debug!(
@@ -392,6 +403,10 @@ impl DeliveryAttempt {
                     // Lookup MX
                     mx_list = match core.core.smtp.resolvers.dns.mx_lookup(&domain.domain).await {
                         Ok(mx) => mx,
+                        // The domain exists but has no MX records
+                        Err(mail_auth::Error::DnsRecordNotFound(ResponseCode::NoError)) => {
+                            Arc::new(Vec::new())
+                        }
                         Err(err) => {
                             tracing::info!(
                                 parent: &span,
@@ -399,17 +414,55 @@ impl DeliveryAttempt {
                                 event = "mx-lookup-failed",
                                 reason = %err,
                             );
-                            domain.set_status(
-                                err,
-                                &core
-                                    .core
-                                    .eval_if::<Vec<Duration>, _>(&queue_config.retry, &envelope)
+                            domain.set_status(err, &core.retry_schedule(&envelope).await);
+                            continue 'next_domain;
+                        }
+                    };
+
+                    // Fall back to the domain's address records (implicit MX) when
+                    // allowed by policy, bouncing if the domain has none.
+                    if mx_list.is_empty() {
+                        let reason = if !core
+                            .core
+                            .eval_if(&queue_config.implicit_mx, &envelope)
+                            .await
+                            .unwrap_or(true)
+                        {
+                            Some("Domain has no MX records and implicit MX is not allowed")
+                        } else if match core
+                            .ip_lookup(
+                                &domain.domain,
+                                core.core
+                                    .eval_if(&queue_config.ip_strategy, &envelope)
                                     .await
-                                    .unwrap_or_else(|| vec![Duration::from_secs(60)]),
+                                    .unwrap_or(IpLookupStrategy::Ipv4thenIpv6),
+                                1,
+                            )
+                            .await
+                        {
+                            Ok(addrs) => addrs.is_empty(),
+                            Err(mail_auth::Error::DnsRecordNotFound(_)) => true,
+                            Err(_) => false,
+                        } {
+                            Some("Domain has no MX or address records")
+                        } else {
+                            None
+                        };
+
+                        if let Some(reason) = reason {
+                            tracing::info!(
+                                parent: &span,
+                                context = "dns",
+                                event = "no-mx",
+                                reason = reason,
+                            );
+                            domain.set_status(
+                                Status::PermanentFailure(Error::DnsError(reason.to_string())),
+                                &core.retry_schedule(&envelope).await,
                             );
                             continue 'next_domain;
                         }
-                    };
+                    }
 
                     if let Some(remote_hosts_) = mx_list.to_remote_hosts(
                         &domain.domain,
@@ -430,11 +483,7 @@ impl DeliveryAttempt {
                             Status::PermanentFailure(Error::DnsError(
                                 "Domain does not accept messages (null MX)".to_string(),
                             )),
-                            &core
-                                .core
-                                .eval_if::<Vec<Duration>, _>(&queue_config.retry, &envelope)
-                                .await
-                                .unwrap_or_else(|| vec![Duration::from_secs(60)]),
+                            &core.retry_schedule(&envelope).await,
                         );
                         continue 'next_domain;
                     }
@@ -762,19 +811,50 @@ impl DeliveryAttempt {
                                 .eval_if(&queue_config.timeout.data, &envelope)
                                 .await
                                 .unwrap_or_else(|| Duration::from_secs(5 * 60)),
+                            verp: core
+                                .core
+                                .eval_if(&queue_config.verp, &envelope)
+                                .await
+                                .unwrap_or(false),
+                            domain: envelope.domain,
+                            next_hop: next_hop.as_deref(),
+                            max_messages: core
+                                .core
+                                .eval_if(&queue_config.max_messages_per_connection, &envelope)
+                                .await
+                                .unwrap_or(1),
+                            starttls: tls_strategy.tls,
+                            is_tls: false,
                         };
 
                         // Prepare TLS connector
+                        let tls_pins = core.core.smtp.queue.tls_pins.get(envelope.domain);
                         let is_strict_tls = tls_strategy.is_tls_required()
                             || (message.flags & MAIL_REQUIRETLS) != 0
                             || mta_sts_policy.is_some()
-                            || dane_policy.is_some();
-                        let tls_connector =
-                            if allow_invalid_certs || remote_host.allow_invalid_certs() {
-                                &core.inner.connectors.dummy_verify
-                            } else {
-                                &core.inner.connectors.pki_verify
-                            };
+                            || dane_policy.is_some()
+                            || tls_pins.is_some();
+                        // TLS sessions are not resumed for destinations with DANE, MTA-STS
+                        // or pinned certificates, ensuring that certificates are always validated.
+                        let connectors = &core.inner.connectors;
+                        let allow_invalid_certs =
+                            allow_invalid_certs || remote_host.allow_invalid_certs();
+                        let tls_connector = if mta_sts_policy.is_none()
+                            && dane_policy.is_none()
+                            && tls_pins.is_none()
+                            && core
+                                .core
+                                .eval_if(&queue_config.tls.resumption, &envelope)
+                                .await
+                                .unwrap_or(false)
+                        {
+                            connectors
+                                .resume(allow_invalid_certs, queue_config.tls.resumption_cache_size)
+                        } else if allow_invalid_certs {
+                            connectors.dummy_verify.clone()
+                        } else {
+                            connectors.pki_verify.clone()
+                        };
 
                         let delivery_result = if !remote_host.implicit_tls() {
                             // Read greeting
@@ -823,7 +903,7 @@ impl DeliveryAttempt {
                                     .unwrap_or_else(|| Duration::from_secs(3 * 60));
                                 match try_start_tls(
                                     smtp_client,
-                                    tls_connector,
+                                    &tls_connector,
                                     envelope.mx,
                                     &capabilties,
                                 )
@@ -839,6 +919,23 @@ impl DeliveryAttempt {
                                             cipher = ?smtp_client.tls_connection().negotiated_cipher_suite(),
                                         );
 
+                                        // Verify TLS version
+                                        if let Err(status) = verify_tls_version(
+                                            smtp_client.tls_connection().protocol_version(),
+                                            core.core
+                                                .eval_if::<TlsVersion, _>(
+                                                    &queue_config.tls.min_version,
+                                                    &envelope,
+                                                )
+                                                .await
+                                                .unwrap_or_default(),
+                                            envelope.mx,
+                                            &span,
+                                        ) {
+                                            last_status = status;
+                                            continue 'next_host;
+                                        }
+
                                         // Verify DANE
                                         if let Some(dane_policy) = &dane_policy {
                                             if let Err(status) = dane_policy.verify(
@@ -871,6 +968,19 @@ impl DeliveryAttempt {
                                             }
                                         }
 
+                                        // Verify pinned certificates
+                                        if let Some(tls_pins) = tls_pins {
+                                            if let Err(status) = verify_tls_pins(
+                                                &span,
+                                                envelope.mx,
+                                                tls_pins,
+                                                smtp_client.tls_connection().peer_certificates(),
+                                            ) {
+                                                last_status = status;
+                                                continue 'next_host;
+                                            }
+                                        }
+
                                         // Report TLS success
                                         if let Some(tls_report) = &tls_report {
                                             core.schedule_report(TlsEvent {
@@ -890,7 +1000,10 @@ impl DeliveryAttempt {
                                                 recipients
                                                     .iter_mut()
                                                     .filter(|r| r.domain_idx == domain_idx),
-                                                params,
+                                                SessionParams {
+                                                    is_tls: true,
+                                                    ..params
+                                                },
                                             )
                                             .await
                                     }
@@ -1015,7 +1128,7 @@ impl DeliveryAttempt {
                                 .await
                                 .unwrap_or_else(|| Duration::from_secs(3 * 60));
                             let mut smtp_client =
-                                match smtp_client.into_tls(tls_connector, envelope.mx).await {
+                                match smtp_client.into_tls(&tls_connector, envelope.mx).await {
                                     Ok(smtp_client) => smtp_client,
                                     Err(error) => {
                                         tracing::info!(
@@ -1031,6 +1144,36 @@ impl DeliveryAttempt {
                                     }
                                 };
 
+                            // Verify TLS version
+                            if let Err(status) = verify_tls_version(
+                                smtp_client.tls_connection().protocol_version(),
+                                core.core
+                                    .eval_if::<TlsVersion, _>(
+                                        &queue_config.tls.min_version,
+                                        &envelope,
+                                    )
+                                    .await
+                                    .unwrap_or_default(),
+                                envelope.mx,
+                                &span,
+                            ) {
+                                last_status = status;
+                                continue 'next_host;
+                            }
+
+                            // Verify pinned certificates
+                            if let Some(tls_pins) = tls_pins {
+                                if let Err(status) = verify_tls_pins(
+                                    &span,
+                                    envelope.mx,
+                                    tls_pins,
+                                    smtp_client.tls_connection().peer_certificates(),
+                                ) {
+                                    last_status = status;
+                                    continue 'next_host;
+                                }
+                            }
+
                             // Read greeting
                             smtp_client.timeout = core
                                 .core
@@ -1056,108 +1199,68 @@ impl DeliveryAttempt {
                                 .deliver(
                                     smtp_client,
                                     recipients.iter_mut().filter(|r| r.domain_idx == domain_idx),
-                                    params,
+                                    SessionParams {
+                                        is_tls: true,
+                                        ..params
+                                    },
                                 )
                                 .await
                         };
 
                         // Update status for the current domain and continue with the next one
-                        domain.set_status(
-                            delivery_result,
-                            &core
-                                .core
-                                .eval_if::<Vec<Duration>, _>(&queue_config.retry, &envelope)
-                                .await
-                                .unwrap_or_else(|| vec![Duration::from_secs(60)]),
-                        );
+                        domain.set_status(delivery_result, &core.retry_schedule(&envelope).await);
                         continue 'next_domain;
                     }
                 }
 
                 // Update status
                 domain.disable_tls = disable_tls;
-                domain.set_status(
-                    last_status,
-                    &core
-                        .core
-                        .eval_if::<Vec<Duration>, _>(&queue_config.retry, &envelope)
-                        .await
-                        .unwrap_or_else(|| vec![Duration::from_secs(60)]),
-                );
+                domain.set_status(last_status, &core.retry_schedule(&envelope).await);
             }
             message.domains = domains;
             message.recipients = recipients;
 
-            // Send Delivery Status Notifications
-            core.send_dsn(&mut message, &span).await;
-
-            // Notify queue manager
-            let span = span;
-            let result = if !on_hold.is_empty() {
-                // Save changes to disk
-                let next_due = message.next_event_after(now());
-                message.save_changes(&core, None, None).await;
-
-                tracing::info!(
-                    parent: &span,
-                    context = "queue",
-                    event = "requeue",
-                    reason = "concurrency-limited",
-                    "Too many outbound concurrent connections, message moved to on-hold queue."
-                );
-
-                Event::OnHold(OnHold {
-                    next_due,
-                    limiters: on_hold,
-                    message: self.event,
-                })
-            } else if let Some(due) = message.next_event() {
-                // Save changes to disk
-                message
-                    .save_changes(&core, self.event.due.into(), due.into())
-                    .await;
-
-                tracing::info!(
-                    parent: &span,
-                    context = "queue",
-                    event = "requeue",
-                    reason = "delivery-incomplete",
-                    "Delivery was not possible, message re-queued for delivery."
-                );
-
-                Event::Reload
-            } else {
-                // Delete message from queue
-                message.remove(&core, self.event.due).await;
-
-                tracing::info!(
-                    parent: &span,
-                    context = "queue",
-                    event = "completed",
-                    "Delivery completed."
-                );
-
-                Event::Reload
-            };
-            if core.inner.queue_tx.send(result).await.is_err() {
-                tracing::warn!(
-                    parent: &span,
-                    "Channel closed while trying to notify queue manager."
-                );
-            }
+            core.complete_delivery(message, self.event, on_hold, &span)
+                .await;
         });
     }
 }
 
 impl Message {
     /// Marks as failed all domains that reached their expiration time
-    pub fn has_pending_delivery(&mut self, span: &tracing::Span) -> bool {
+    /// or exhausted their maximum number of delivery attempts
+    pub async fn has_pending_delivery(&mut self, core: &SMTP, span: &tracing::Span) -> bool {
         let now = now();
         let mut has_pending_delivery = false;
 
-        for (idx, domain) in self.domains.iter_mut().enumerate() {
+        // Obtain the maximum number of attempts for each domain
+        let no_ip = IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0));
+        let mut max_attempts = Vec::with_capacity(self.domains.len());
+        for domain in &self.domains {
+            max_attempts.push(
+                core.core
+                    .eval_if::<usize, _>(
+                        &core.core.smtp.queue.max_attempts,
+                        &QueueEnvelope {
+                            message: self,
+                            domain: &domain.domain,
+                            mx: "",
+                            remote_ip: no_ip,
+                            local_ip: no_ip,
+                        },
+                    )
+                    .await,
+            );
+        }
+
+        for ((idx, domain), max_attempts) in self.domains.iter_mut().enumerate().zip(max_attempts) {
+            let is_expired = domain.expires <= now
+                || max_attempts.map_or(false, |max_attempts| {
+                    max_attempts > 0 && domain.retry.inner as usize >= max_attempts
+                });
+
             match &domain.status {
-                Status::TemporaryFailure(err) if domain.expires <= now => {
+                Status::TemporaryFailure(err) if is_expired => {
                     tracing::info!(
                         parent: span,
                         event = "delivery-expired",
@@ -1205,6 +1308,83 @@ impl Message {
     }
 }
 
+impl SMTP {
+    /// Sends any pending DSNs and saves or removes the message after a
+    /// delivery attempt, notifying the queue manager.
+    pub async fn complete_delivery(
+        &self,
+        mut message: Message,
+        event: QueueEventLock,
+        on_hold: Vec<ConcurrencyLimiter>,
+        span: &tracing::Span,
+    ) {
+        // Send Delivery Status Notifications
+        self.send_dsn(&mut message, span).await;
+
+        // Notify queue manager
+        let result = if !on_hold.is_empty() {
+            // Save changes to disk
+            let next_due = message.next_event_after(now());
+            message.save_changes(self, None, None).await;
+
+            tracing::info!(
+                parent: span,
+                context = "queue",
+                event = "requeue",
+                reason = "concurrency-limited",
+                "Too many outbound concurrent connections, message moved to on-hold queue."
+            );
+
+            Event::OnHold(OnHold {
+                next_due,
+                limiters: on_hold,
+                message: event,
+            })
+        } else if let Some(due) = message.next_event() {
+            // Save changes to disk
+            message
+                .save_changes(self, event.due.into(), due.into())
+                .await;
+
+            tracing::info!(
+                parent: span,
+                context = "queue",
+                event = "requeue",
+                reason = "delivery-incomplete",
+                "Delivery was not possible, message re-queued for delivery."
+            );
+
+            Event::Reload
+        } else {
+            // Delete message from queue
+            message.remove(self, event.due).await;
+
+            tracing::info!(
+                parent: span,
+                context = "queue",
+                event = "completed",
+                "Delivery completed."
+            );
+
+            Event::Reload
+        };
+        if self.inner.queue_tx.send(result).await.is_err() {
+            tracing::warn!(
+                parent: span,
+                "Channel closed while trying to notify queue manager."
+            );
+        }
+    }
+
+    /// Returns the retry schedule for a domain.
+    pub async fn retry_schedule(&self, envelope: &QueueEnvelope<'_>) -> Vec<Duration> {
+        self.core
+            .eval_if::<Vec<Duration>, _>(&self.core.smtp.queue.retry, envelope)
+            .await
+            .unwrap_or_else(|| vec![Duration::from_secs(60)])
+    }
+}
+
 impl Domain {
     pub fn set_status(&mut self, status: impl Into<Status<(), Error>>, schedule: &[Duration]) {
         self.status = status.into();
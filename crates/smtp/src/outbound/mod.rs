@@ -34,12 +34,14 @@ use crate::queue::{
     spool::QueueEventLock, DeliveryAttempt, Error, ErrorDetails, HostResponse, Status,
 };
 
+pub mod batch;
 pub mod dane;
 pub mod delivery;
 #[cfg(feature = "local_delivery")]
 pub mod local;
 pub mod lookup;
 pub mod mta_sts;
+pub mod pinning;
 pub mod session;
 
 #[derive(Debug, Clone, Copy, Default)]
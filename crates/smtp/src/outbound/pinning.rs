@@ -0,0 +1,83 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use common::config::smtp::queue::TlsPin;
+use rustls_pki_types::CertificateDer;
+use sha2::{Digest, Sha256};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::queue::{Error, ErrorDetails, Status};
+
+/// Verifies that at least one certificate presented by the remote host matches
+/// one of the fingerprints pinned for the destination domain.
+pub fn verify_tls_pins(
+    span: &tracing::Span,
+    hostname: &str,
+    pins: &[TlsPin],
+    certificates: Option<&[CertificateDer<'_>]>,
+) -> Result<(), Status<(), Error>> {
+    for der_certificate in certificates.unwrap_or_default() {
+        let mut spki_hash = None;
+        let cert_hash = Sha256::digest(der_certificate.as_ref());
+
+        for pin in pins {
+            let hash: &[u8] = if pin.is_spki {
+                let Some(hash) = spki_hash.get_or_insert_with(|| {
+                    X509Certificate::from_der(der_certificate.as_ref())
+                        .ok()
+                        .map(|(_, certificate)| Sha256::digest(certificate.public_key().raw))
+                }) else {
+                    continue;
+                };
+                hash
+            } else {
+                &cert_hash
+            };
+
+            if hash == pin.hash {
+                tracing::debug!(
+                    parent: span,
+                    context = "tls-pin",
+                    event = "authenticated",
+                    mx = hostname,
+                    "Certificate matched pinned fingerprint {:x?}.",
+                    hash
+                );
+                return Ok(());
+            }
+        }
+    }
+
+    tracing::warn!(
+        parent: span,
+        context = "tls-pin",
+        event = "auth-failure",
+        mx = hostname,
+        "No certificates matched the pinned fingerprints."
+    );
+
+    Err(Status::TemporaryFailure(Error::TlsError(ErrorDetails {
+        entity: hostname.to_string(),
+        details: "Certificate does not match any pinned fingerprint".to_string(),
+    })))
+}
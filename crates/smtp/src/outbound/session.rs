@@ -21,7 +21,10 @@
  * for more details.
 */
 
-use common::config::smtp::queue::RequireOptional;
+use common::{
+    addresses::verp_encode,
+    config::smtp::{queue::RequireOptional, session::TlsVersion},
+};
 use mail_send::{smtp::AssertReply, Credentials, SmtpClient};
 use smtp_proto::{
     EhloResponse, Response, Severity, EXT_CHUNKING, EXT_DSN, EXT_REQUIRE_TLS, EXT_SIZE,
@@ -29,7 +32,7 @@ use smtp_proto::{
     RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_NEVER, RCPT_NOTIFY_SUCCESS,
 };
 use std::fmt::Write;
-use std::time::Duration;
+use std::{borrow::Cow, time::Duration};
 use tokio::{
     io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
     net::TcpStream,
@@ -56,6 +59,12 @@ pub struct SessionParams<'x> {
     pub timeout_mail: Duration,
     pub timeout_rcpt: Duration,
     pub timeout_data: Duration,
+    pub verp: bool,
+    pub domain: &'x str,
+    pub next_hop: Option<&'x str>,
+    pub max_messages: usize,
+    pub starttls: RequireOptional,
+    pub is_tls: bool,
 }
 
 impl Message {
@@ -113,9 +122,114 @@ impl Message {
             };*/
         }
 
+        let status = match self
+            .deliver_transactions(&mut smtp_client, recipients, &capabilities, &params)
+            .await
+        {
+            Ok(status) => status,
+            Err(status) => {
+                quit(smtp_client).await;
+                return status;
+            }
+        };
+
+        // Deliver other messages queued for the same domain over this connection
+        if params.max_messages > 1 {
+            self.deliver_batch(&mut smtp_client, &capabilities, &params)
+                .await;
+        }
+
+        quit(smtp_client).await;
+        status
+    }
+
+    pub(super) async fn deliver_transactions<T: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        smtp_client: &mut SmtpClient<T>,
+        recipients: impl Iterator<Item = &mut Recipient>,
+        capabilities: &EhloResponse<String>,
+        params: &SessionParams<'_>,
+    ) -> Result<Status<(), Error>, Status<(), Error>> {
+        // The return path override takes precedence, VERP is then applied to the result.
+        let return_path_override = self.return_path_override(params.core).await;
+        let base_return_path = return_path_override
+            .as_deref()
+            .unwrap_or(self.return_path.as_str());
+
+        // When VERP is enabled, the envelope sender is encoded with each recipient
+        // address, which requires a separate transaction per recipient.
+        let mut recipients = recipients.collect::<Vec<_>>();
+        let mut total_rcpt = 0;
+        let mut total_completed = 0;
+        let mut is_first = true;
+        while !recipients.is_empty() {
+            let (return_path, batch) = if params.verp {
+                let rcpt = recipients.remove(0);
+                let return_path = verp_encode(base_return_path, &rcpt.address)
+                    .map(Cow::Owned)
+                    .unwrap_or(Cow::Borrowed(base_return_path));
+                (return_path, vec![rcpt])
+            } else {
+                (
+                    Cow::Borrowed(base_return_path),
+                    std::mem::take(&mut recipients),
+                )
+            };
+
+            // Reset the previous transaction
+            if !is_first {
+                smtp_client
+                    .cmd(b"RSET\r\n")
+                    .await
+                    .and_then(|r| r.assert_positive_completion())
+                    .map_err(|err| Status::from_smtp_error(params.hostname, "RSET", err))?;
+            }
+            is_first = false;
+
+            let (num_rcpt, num_completed) = self
+                .deliver_transaction(
+                    smtp_client,
+                    return_path.as_ref(),
+                    batch,
+                    capabilities,
+                    params,
+                )
+                .await?;
+            total_rcpt += num_rcpt;
+            total_completed += num_completed;
+        }
+
+        Ok(if total_completed == total_rcpt {
+            Status::Completed(())
+        } else {
+            Status::Scheduled
+        })
+    }
+
+    async fn deliver_transaction<T: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        smtp_client: &mut SmtpClient<T>,
+        return_path: &str,
+        recipients: Vec<&mut Recipient>,
+        capabilities: &EhloResponse<String>,
+        params: &SessionParams<'_>,
+    ) -> Result<(usize, usize), Status<(), Error>> {
+        let mut total_rcpt = 0;
+        let mut total_completed = 0;
+
+        // Skip transactions without pending recipients
+        if recipients.iter().all(|rcpt| {
+            matches!(
+                &rcpt.status,
+                Status::Completed(_) | Status::PermanentFailure(_)
+            )
+        }) {
+            return Ok((recipients.len(), recipients.len()));
+        }
+
         // MAIL FROM
         smtp_client.timeout = params.timeout_mail;
-        let cmd = self.build_mail_from(&capabilities);
+        let cmd = self.build_mail_from(return_path, capabilities);
         if let Err(err) = smtp_client
             .cmd(cmd.as_bytes())
             .await
@@ -128,13 +242,10 @@ impl Message {
                 mx = &params.hostname,
                 reason = %err,
             );
-            quit(smtp_client).await;
-            return Status::from_smtp_error(params.hostname, &cmd, err);
+            return Err(Status::from_smtp_error(params.hostname, &cmd, err));
         }
 
         // RCPT TO
-        let mut total_rcpt = 0;
-        let mut total_completed = 0;
         let mut accepted_rcpts = Vec::new();
         smtp_client.timeout = params.timeout_rcpt;
         for rcpt in recipients {
@@ -147,7 +258,7 @@ impl Message {
                 continue;
             }
 
-            let cmd = self.build_rcpt_to(rcpt, &capabilities);
+            let cmd = self.build_rcpt_to(rcpt, capabilities);
             match smtp_client.cmd(cmd.as_bytes()).await {
                 Ok(response) => match response.severity() {
                     Severity::PositiveCompletion => {
@@ -196,8 +307,7 @@ impl Message {
                     );
 
                     // Something went wrong, abort.
-                    quit(smtp_client).await;
-                    return Status::from_smtp_error(params.hostname, "", err);
+                    return Err(Status::from_smtp_error(params.hostname, "", err));
                 }
             }
         }
@@ -210,7 +320,7 @@ impl Message {
                 None
             };
 
-            if let Err(status) = send_message(&mut smtp_client, self, &bdat_cmd, &params).await {
+            if let Err(status) = send_message(smtp_client, self, &bdat_cmd, params).await {
                 tracing::info!(
                     parent: params.span,
                     context = "message",
@@ -219,13 +329,12 @@ impl Message {
                     reason = %status,
                 );
 
-                quit(smtp_client).await;
-                return status;
+                return Err(status);
             }
 
             if params.is_smtp {
                 // Handle SMTP response
-                match read_smtp_data_respone(&mut smtp_client, params.hostname, &bdat_cmd).await {
+                match read_smtp_data_respone(smtp_client, params.hostname, &bdat_cmd).await {
                     Ok(response) => {
                         // Mark recipients as delivered
                         if response.code() == 250 {
@@ -252,12 +361,11 @@ impl Message {
                                 reason = %response,
                             );
 
-                            quit(smtp_client).await;
-                            return Status::from_smtp_error(
+                            return Err(Status::from_smtp_error(
                                 params.hostname,
                                 bdat_cmd.as_deref().unwrap_or("DATA"),
                                 mail_send::Error::UnexpectedReply(response),
-                            );
+                            ));
                         }
                     }
                     Err(status) => {
@@ -269,18 +377,13 @@ impl Message {
                             reason = %status,
                         );
 
-                        quit(smtp_client).await;
-                        return status;
+                        return Err(status);
                     }
                 }
             } else {
                 // Handle LMTP responses
-                match read_lmtp_data_respone(
-                    &mut smtp_client,
-                    params.hostname,
-                    accepted_rcpts.len(),
-                )
-                .await
+                match read_lmtp_data_respone(smtp_client, params.hostname, accepted_rcpts.len())
+                    .await
                 {
                     Ok(responses) => {
                         for ((rcpt, _), response) in accepted_rcpts.into_iter().zip(responses) {
@@ -341,24 +444,18 @@ impl Message {
                             reason = %status,
                         );
 
-                        quit(smtp_client).await;
-                        return status;
+                        return Err(status);
                     }
                 }
             }
         }
 
-        quit(smtp_client).await;
-        if total_completed == total_rcpt {
-            Status::Completed(())
-        } else {
-            Status::Scheduled
-        }
+        Ok((total_rcpt, total_completed))
     }
 
-    fn build_mail_from(&self, capabilities: &EhloResponse<String>) -> String {
-        let mut mail_from = String::with_capacity(self.return_path.len() + 60);
-        let _ = write!(mail_from, "MAIL FROM:<{}>", self.return_path);
+    fn build_mail_from(&self, return_path: &str, capabilities: &EhloResponse<String>) -> String {
+        let mut mail_from = String::with_capacity(return_path.len() + 60);
+        let _ = write!(mail_from, "MAIL FROM:<{return_path}>");
         if capabilities.has_capability(EXT_SIZE) {
             let _ = write!(mail_from, " SIZE={}", self.size);
         }
@@ -412,6 +509,10 @@ impl Message {
             } else if rcpt.has_flag(RCPT_NOTIFY_NEVER) {
                 rcpt_to.push_str(" NOTIFY=NEVER");
             }
+            if let Some(orcpt) = &rcpt.orcpt {
+                rcpt_to.push_str(" ORCPT=rfc822;");
+                write_xtext(&mut rcpt_to, orcpt);
+            }
         }
         rcpt_to.push_str("\r\n");
         rcpt_to
@@ -474,6 +575,36 @@ pub async fn try_start_tls(
     }
 }
 
+/// Verifies that the TLS version negotiated with a remote host is allowed.
+pub fn verify_tls_version(
+    version: Option<rustls::ProtocolVersion>,
+    min_version: TlsVersion,
+    hostname: &str,
+    span: &tracing::Span,
+) -> Result<(), Status<(), Error>> {
+    let Some(version) = version.and_then(TlsVersion::from_protocol) else {
+        return Ok(());
+    };
+
+    if version < min_version {
+        tracing::info!(
+            parent: span,
+            context = "tls",
+            event = "reject",
+            mx = hostname,
+            version = version.as_str(),
+            min_version = min_version.as_str(),
+            "TLS version not allowed for this host."
+        );
+        Err(Status::TemporaryFailure(Error::TlsError(ErrorDetails {
+            entity: hostname.to_string(),
+            details: format!("TLS version {} is not allowed", version.as_str()),
+        })))
+    } else {
+        Ok(())
+    }
+}
+
 pub async fn read_greeting<T: AsyncRead + AsyncWrite + Unpin>(
     smtp_client: &mut SmtpClient<T>,
     hostname: &str,
@@ -539,6 +670,7 @@ pub async fn send_message<T: AsyncRead + AsyncWrite + Unpin>(
         .core
         .core
         .storage
+        .queue
         .blob
         .get_blob(message.blob_hash.as_slice(), 0..usize::MAX)
         .await
@@ -658,3 +790,15 @@ impl TlsStrategy {
             || self.is_mta_sts_required()
     }
 }
+
+/// Encodes a value as xtext (RFC 3461, section 4), escaping any characters
+/// outside the printable ASCII range as well as `+` and `=`.
+fn write_xtext(buf: &mut String, value: &str) {
+    for byte in value.bytes() {
+        if (b'!'..=b'~').contains(&byte) && byte != b'+' && byte != b'=' {
+            buf.push(byte as char);
+        } else {
+            let _ = write!(buf, "+{byte:02X}");
+        }
+    }
+}
@@ -21,6 +21,7 @@
  * for more details.
 */
 
+use common::config::smtp::queue::DsnCatalog;
 use mail_builder::headers::content_type::ContentType;
 use mail_builder::headers::HeaderType;
 use mail_builder::mime::{make_boundary, BodyPart, MimePart};
@@ -43,16 +44,22 @@ use super::{
 impl SMTP {
     pub async fn send_dsn(&self, message: &mut Message, span: &tracing::Span) {
         if !message.return_path.is_empty() {
-            if let Some(dsn) = message.build_dsn(self, span).await {
+            let return_path = message.return_path_override(self).await;
+            if let Some(dsn) = message.build_dsn(return_path.as_deref(), self, span).await {
                 let mut dsn_message = self.new_message("", "", "");
-                dsn_message
-                    .add_recipient_parts(
-                        &message.return_path,
-                        &message.return_path_lcase,
-                        &message.return_path_domain,
-                        self,
-                    )
-                    .await;
+                dsn_message.correlation_id = message.correlation_id;
+                if let Some(return_path) = return_path {
+                    dsn_message.add_recipient(return_path, self).await;
+                } else {
+                    dsn_message
+                        .add_recipient_parts(
+                            &message.return_path,
+                            &message.return_path_lcase,
+                            &message.return_path_domain,
+                            self,
+                        )
+                        .await;
+                }
 
                 // Sign message
                 let signature = self
@@ -69,7 +76,12 @@ impl SMTP {
 }
 
 impl Message {
-    pub async fn build_dsn(&mut self, core: &SMTP, span: &tracing::Span) -> Option<Vec<u8>> {
+    pub async fn build_dsn(
+        &mut self,
+        return_path: Option<&str>,
+        core: &SMTP,
+        span: &tracing::Span,
+    ) -> Option<Vec<u8>> {
         let config = &core.core.smtp.queue;
         let now = now();
 
@@ -166,36 +178,84 @@ impl Message {
         let has_delay = !txt_delay.is_empty();
         let has_failure = !txt_failed.is_empty();
 
+        // Fetch up to 1024 bytes of message headers
+        let headers = match core
+            .core
+            .storage
+            .queue
+            .blob
+            .get_blob(self.blob_hash.as_slice(), 0..1024)
+            .await
+        {
+            Ok(Some(mut buf)) => {
+                let mut prev_ch = 0;
+                let mut last_lf = buf.len();
+                for (pos, &ch) in buf.iter().enumerate() {
+                    match ch {
+                        b'\n' => {
+                            last_lf = pos + 1;
+                            if prev_ch != b'\n' {
+                                prev_ch = ch;
+                            } else {
+                                break;
+                            }
+                        }
+                        b'\r' => (),
+                        0 => break,
+                        _ => {
+                            prev_ch = ch;
+                        }
+                    }
+                }
+                if last_lf < 1024 {
+                    buf.truncate(last_lf);
+                }
+                String::from_utf8(buf).unwrap_or_default()
+            }
+            Ok(None) => {
+                tracing::error!(
+                    parent: span,
+                    context = "queue",
+                    event = "error",
+                    "Failed to open blob {:?}: not found",
+                    self.blob_hash
+                );
+                String::new()
+            }
+            Err(err) => {
+                tracing::error!(
+                    parent: span,
+                    context = "queue",
+                    event = "error",
+                    "Failed to open blob {:?}: {}",
+                    self.blob_hash,
+                    err
+                );
+                String::new()
+            }
+        };
+
+        // Obtain DSN locale
+        let catalog = self.dsn_catalog(core, &headers).await;
+
         let mut txt = String::with_capacity(txt_len + 128);
-        let (subject, is_mixed) = if has_success && !has_delay && !has_failure {
-            txt.push_str(
-                "Your message has been successfully delivered to the following recipients:\r\n\r\n",
-            );
-            ("Successfully delivered message", false)
+        let (summary, subject, is_mixed) = if has_success && !has_delay && !has_failure {
+            (&catalog.summary_success, &catalog.subject_success, false)
         } else if has_delay && !has_success && !has_failure {
-            txt.push_str("There was a temporary problem delivering your message to the following recipients:\r\n\r\n");
-            ("Warning: Delay in message delivery", false)
+            (&catalog.summary_delay, &catalog.subject_delay, false)
         } else if has_failure && !has_success && !has_delay {
-            txt.push_str(
-                "Your message could not be delivered to the following recipients:\r\n\r\n",
-            );
-            ("Failed to deliver message", false)
+            (&catalog.summary_failure, &catalog.subject_failure, false)
         } else if has_success {
-            txt.push_str("Your message has been partially delivered:\r\n\r\n");
-            ("Partially delivered message", true)
+            (&catalog.summary_partial, &catalog.subject_partial, true)
         } else {
-            txt.push_str("Your message could not be delivered to some recipients:\r\n\r\n");
-            (
-                "Warning: Temporary and permanent failures during message delivery",
-                true,
-            )
+            (&catalog.summary_mixed, &catalog.subject_mixed, true)
         };
+        txt.push_str(summary);
+        txt.push_str("\r\n\r\n");
 
         if has_success {
             if is_mixed {
-                txt.push_str(
-                    "    ----- Delivery to the following addresses was successful -----\r\n",
-                );
+                write_dsn_section(&catalog.section_success, &mut txt);
             }
 
             txt.push_str(&txt_success);
@@ -204,9 +264,7 @@ impl Message {
 
         if has_delay {
             if is_mixed {
-                txt.push_str(
-                    "    ----- There was a temporary problem delivering to these addresses -----\r\n",
-                );
+                write_dsn_section(&catalog.section_delay, &mut txt);
             }
             txt.push_str(&txt_delay);
             txt.push_str("\r\n");
@@ -214,7 +272,7 @@ impl Message {
 
         if has_failure {
             if is_mixed {
-                txt.push_str("    ----- Delivery to the following addresses failed -----\r\n");
+                write_dsn_section(&catalog.section_failure, &mut txt);
             }
             txt.push_str(&txt_failed);
             txt.push_str("\r\n");
@@ -271,69 +329,16 @@ impl Message {
         self.write_dsn_headers(&mut dsn_header, &reporting_mta);
         let dsn = dsn_header + &dsn;
 
-        // Fetch up to 1024 bytes of message headers
-        let headers = match core
-            .core
-            .storage
-            .blob
-            .get_blob(self.blob_hash.as_slice(), 0..1024)
-            .await
-        {
-            Ok(Some(mut buf)) => {
-                let mut prev_ch = 0;
-                let mut last_lf = buf.len();
-                for (pos, &ch) in buf.iter().enumerate() {
-                    match ch {
-                        b'\n' => {
-                            last_lf = pos + 1;
-                            if prev_ch != b'\n' {
-                                prev_ch = ch;
-                            } else {
-                                break;
-                            }
-                        }
-                        b'\r' => (),
-                        0 => break,
-                        _ => {
-                            prev_ch = ch;
-                        }
-                    }
-                }
-                if last_lf < 1024 {
-                    buf.truncate(last_lf);
-                }
-                String::from_utf8(buf).unwrap_or_default()
-            }
-            Ok(None) => {
-                tracing::error!(
-                    parent: span,
-                    context = "queue",
-                    event = "error",
-                    "Failed to open blob {:?}: not found",
-                    self.blob_hash
-                );
-                String::new()
-            }
-            Err(err) => {
-                tracing::error!(
-                    parent: span,
-                    context = "queue",
-                    event = "error",
-                    "Failed to open blob {:?}: {}",
-                    self.blob_hash,
-                    err
-                );
-                String::new()
-            }
-        };
-
         // Build message
         MessageBuilder::new()
             .from((from_name.as_str(), from_addr.as_str()))
-            .header("To", HeaderType::Text(self.return_path.as_str().into()))
+            .header(
+                "To",
+                HeaderType::Text(return_path.unwrap_or(self.return_path.as_str()).into()),
+            )
             .header("Auto-Submitted", HeaderType::Text("auto-generated".into()))
             .message_id(format!("<{}@{}>", make_boundary("."), reporting_mta))
-            .subject(subject)
+            .subject(subject.as_str())
             .body(MimePart::new(
                 ContentType::new("multipart/report").attribute("report-type", "delivery-status"),
                 BodyPart::Multipart(vec![
@@ -353,6 +358,26 @@ impl Message {
             .into()
     }
 
+    async fn dsn_catalog<'x>(&self, core: &'x SMTP, headers: &str) -> &'x DsnCatalog {
+        let config = &core.core.smtp.queue.dsn;
+
+        // Use the language preferred by the sender, if available
+        if config.locale_from_header {
+            if let Some(catalog) = parse_accept_language(headers)
+                .into_iter()
+                .find_map(|tag| config.catalog(tag))
+            {
+                return catalog;
+            }
+        }
+
+        core.core
+            .eval_if::<String, _>(&config.locale, self)
+            .await
+            .and_then(|locale| config.catalog(&locale))
+            .unwrap_or_else(|| &config.catalogs["en"])
+    }
+
     fn handle_double_bounce(&mut self, span: &tracing::Span) {
         let mut is_double_bounce = Vec::with_capacity(0);
 
@@ -399,6 +424,53 @@ impl Message {
     }
 }
 
+fn write_dsn_section(title: &str, txt: &mut String) {
+    let _ = write!(txt, "    ----- {title} -----\r\n");
+}
+
+/// Returns the language tags listed in the Accept-Language (or X-Accept-Language)
+/// header, ordered by preference.
+fn parse_accept_language(headers: &str) -> Vec<&str> {
+    let mut tags = Vec::new();
+    let mut lines = headers.split('\n').peekable();
+
+    while let Some(line) = lines.next() {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        if !name.eq_ignore_ascii_case("Accept-Language")
+            && !name.eq_ignore_ascii_case("X-Accept-Language")
+        {
+            continue;
+        }
+
+        // Collect folded lines
+        let mut values = vec![value];
+        while let Some(next) = lines.next_if(|line| line.starts_with([' ', '\t'])) {
+            values.push(next);
+        }
+
+        for item in values.into_iter().flat_map(|value| value.split(',')) {
+            let mut parts = item.split(';');
+            let tag = parts.next().unwrap_or_default().trim();
+            if tag.is_empty() || tag == "*" {
+                continue;
+            }
+            let quality = parts
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if quality > 0.0 {
+                tags.push((tag, quality));
+            }
+        }
+        break;
+    }
+
+    tags.sort_by(|a, b| b.1.total_cmp(&a.1));
+    tags.into_iter().map(|(tag, _)| tag).collect()
+}
+
 impl HostResponse<String> {
     fn write_dsn_text(&self, addr: &str, dsn: &mut String) {
         let _ = write!(
@@ -611,6 +683,9 @@ impl Status<(), Error> {
             dsn.push_str("Status: ");
             if let Error::UnexpectedResponse(response) = err {
                 response.response.write_dsn_status(dsn);
+            } else if matches!(self, Status::PermanentFailure(Error::DnsError(_))) {
+                // Bad destination system address
+                dsn.push_str("5.1.2");
             } else {
                 dsn.push_str(if matches!(self, Status::PermanentFailure(_)) {
                     "5.0.0"
@@ -33,14 +33,17 @@ use common::{
 };
 use serde::{Deserialize, Serialize};
 use smtp_proto::Response;
-use store::write::now;
+use store::write::{now, Bincode};
 use utils::BlobHash;
 
+use crate::core::SMTP;
+
 use self::spool::QueueEventLock;
 
 pub mod dsn;
 pub mod manager;
 pub mod quota;
+pub mod scan;
 pub mod spool;
 pub mod throttle;
 
@@ -84,6 +87,27 @@ pub struct Message {
 
     pub size: usize,
     pub quota_keys: Vec<QuotaKey>,
+
+    // Identifies the inbound session that queued the message
+    pub correlation_id: u64,
+}
+
+// Queued messages as stored before correlation ids were added
+#[derive(serde::Serialize, serde::Deserialize)]
+struct MessageV1 {
+    id: QueueId,
+    created: u64,
+    blob_hash: BlobHash,
+    return_path: String,
+    return_path_lcase: String,
+    return_path_domain: String,
+    recipients: Vec<Recipient>,
+    domains: Vec<Domain>,
+    flags: u64,
+    env_id: Option<String>,
+    priority: i16,
+    size: usize,
+    quota_keys: Vec<QuotaKey>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
@@ -115,6 +139,9 @@ pub struct Recipient {
 pub const RCPT_DSN_SENT: u64 = 1 << 32;
 pub const RCPT_STATUS_CHANGED: u64 = 2 << 32;
 
+// Message queued before running its DATA script, see `scan::DeferredScan`
+pub const MSG_SCAN_PENDING: u64 = 1 << 32;
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Status<T, E> {
     #[serde(rename = "scheduled")]
@@ -260,6 +287,56 @@ impl<'x> ResolveVariable for QueueEnvelope<'x> {
     }
 }
 
+impl Message {
+    /// Returns the configured address that bounces for this message should be
+    /// directed to instead of the envelope sender. Null senders are never
+    /// overridden, which prevents DSNs from generating further DSNs.
+    pub async fn return_path_override(&self, core: &SMTP) -> Option<String> {
+        if self.return_path.is_empty() {
+            return None;
+        }
+
+        core.core
+            .eval_if::<String, _>(&core.core.smtp.queue.return_path, self)
+            .await
+            .filter(|addr| addr.contains('@'))
+    }
+}
+
+impl store::Deserialize for Message {
+    fn deserialize(bytes: &[u8]) -> store::Result<Self> {
+        <Bincode<Message> as store::Deserialize>::deserialize(bytes)
+            .map(|message| message.inner)
+            .or_else(|err| {
+                // Messages queued by previous versions lack the trailing fields
+                <Bincode<MessageV1> as store::Deserialize>::deserialize(bytes)
+                    .map(|message| message.inner.into())
+                    .map_err(|_| err)
+            })
+    }
+}
+
+impl From<MessageV1> for Message {
+    fn from(message: MessageV1) -> Self {
+        Message {
+            id: message.id,
+            created: message.created,
+            blob_hash: message.blob_hash,
+            return_path: message.return_path,
+            return_path_lcase: message.return_path_lcase,
+            return_path_domain: message.return_path_domain,
+            recipients: message.recipients,
+            domains: message.domains,
+            flags: message.flags,
+            env_id: message.env_id,
+            priority: message.priority,
+            size: message.size,
+            quota_keys: message.quota_keys,
+            correlation_id: 0,
+        }
+    }
+}
+
 impl ResolveVariable for Message {
     fn resolve_variable(&self, variable: u32) -> expr::Variable<'_> {
         match variable {
@@ -109,6 +109,7 @@ impl SMTP {
                 let used_size = self
                     .core
                     .storage
+                    .queue
                     .data
                     .get_counter(ValueKey::from(ValueClass::Queue(QueueClass::QuotaSize(
                         key.as_ref().to_vec(),
@@ -129,6 +130,7 @@ impl SMTP {
                 let total_messages = self
                     .core
                     .storage
+                    .queue
                     .data
                     .get_counter(ValueKey::from(ValueClass::Queue(QueueClass::QuotaCount(
                         key.as_ref().to_vec(),
@@ -0,0 +1,420 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    sync::Arc,
+};
+
+use common::scripts::ScriptModification;
+use mail_auth::common::headers::HeaderWriter;
+use serde::{Deserialize, Serialize};
+use sieve::{runtime::Variable, Envelope};
+use smtp_proto::{Response, MAIL_BY_NOTIFY, MAIL_BY_RETURN, MAIL_RET_FULL, MAIL_RET_HDRS};
+use store::{
+    write::{Bincode, QueueClass, ValueClass},
+    ValueKey,
+};
+
+use crate::{
+    core::SMTP,
+    inbound::{journal::PendingJournal, DkimSign},
+    scripts::{ScriptParameters, ScriptResult, StoredParameters},
+};
+
+use super::{Error, ErrorDetails, HostResponse, Message, QueueEnvelope, Status, MSG_SCAN_PENDING};
+
+const MAX_REASON_LEN: usize = 200;
+
+/// DATA script run on a message after it was accepted and queued. It is
+/// stored next to the message until the queue manager picks it up.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeferredScan {
+    pub script: String,
+    pub quarantine: Option<String>,
+    pub params: StoredParameters,
+    // Length of the headers added during the session
+    pub headers_len: usize,
+    // DKIM signers used to sign the message again if the script replaces it
+    pub signers: Vec<String>,
+    // Journal reports queued once the message is accepted
+    pub journal: Option<PendingJournal>,
+}
+
+impl SMTP {
+    /// Runs the pending DATA script on a queued message. Returns `true` when
+    /// the message can be delivered, otherwise the message was either
+    /// bounced or rescheduled and only needs its changes saved.
+    pub async fn deferred_scan(&self, message: &mut Message, span: &tracing::Span) -> bool {
+        // Fetch the scan parameters
+        let scan = match self
+            .core
+            .storage
+            .queue
+            .data
+            .get_value::<Bincode<DeferredScan>>(ValueKey::from(ValueClass::Queue(
+                QueueClass::MessageScan(message.id),
+            )))
+            .await
+        {
+            Ok(Some(scan)) => scan.inner,
+            Ok(None) => {
+                tracing::warn!(
+                    parent: span,
+                    context = "deferred-scan",
+                    event = "not-found",
+                    "Pending scan not found, delivering message without scanning."
+                );
+                message.flags &= !MSG_SCAN_PENDING;
+                return true;
+            }
+            Err(err) => {
+                tracing::error!(
+                    parent: span,
+                    context = "deferred-scan",
+                    event = "error",
+                    "Failed to read pending scan: {}",
+                    err
+                );
+                self.retry_scan(message).await;
+                return false;
+            }
+        };
+        let script = if let Some(script) = self.core.get_sieve_script(&scan.script) {
+            script.clone()
+        } else {
+            tracing::warn!(
+                parent: span,
+                context = "deferred-scan",
+                event = "not-found",
+                script = scan.script,
+                "Script not found, delivering message without scanning."
+            );
+            if !message.complete_scan(None, self, span).await {
+                self.retry_scan(message).await;
+                return false;
+            }
+            return true;
+        };
+
+        // Fetch the message contents
+        let contents = match self
+            .core
+            .storage
+            .queue
+            .blob
+            .get_blob(message.blob_hash.as_slice(), 0..usize::MAX)
+            .await
+        {
+            Ok(Some(contents)) if contents.len() >= scan.headers_len => contents,
+            Ok(_) => {
+                tracing::error!(
+                    parent: span,
+                    context = "deferred-scan",
+                    event = "error",
+                    "BlobHash {:?} does not exist.",
+                    message.blob_hash
+                );
+                self.retry_scan(message).await;
+                return false;
+            }
+            Err(err) => {
+                tracing::error!(
+                    parent: span,
+                    context = "deferred-scan",
+                    event = "error",
+                    "Failed to fetch blobId {:?}: {}",
+                    message.blob_hash,
+                    err
+                );
+                self.retry_scan(message).await;
+                return false;
+            }
+        };
+        let (headers, raw_message) = contents.split_at(scan.headers_len);
+
+        // Run the script with the envelope as queued
+        let mut params = ScriptParameters::from(scan.params)
+            .with_message(Arc::new(raw_message.to_vec()))
+            .set_envelope(Envelope::From, message.return_path_lcase.clone())
+            .set_envelope(
+                Envelope::To,
+                message
+                    .recipients
+                    .iter()
+                    .map(|rcpt| Variable::from(rcpt.address_lcase.clone()))
+                    .collect::<Vec<_>>(),
+            );
+        if let Some(env_id) = &message.env_id {
+            params = params.set_envelope(Envelope::Envid, env_id.to_lowercase());
+        }
+        if (message.flags & MAIL_RET_FULL) != 0 {
+            params = params.set_envelope(Envelope::Ret, "FULL");
+        } else if (message.flags & MAIL_RET_HDRS) != 0 {
+            params = params.set_envelope(Envelope::Ret, "HDRS");
+        }
+        if (message.flags & MAIL_BY_NOTIFY) != 0 {
+            params = params.set_envelope(Envelope::ByMode, "N");
+        } else if (message.flags & MAIL_BY_RETURN) != 0 {
+            params = params.set_envelope(Envelope::ByMode, "R");
+        }
+
+        let mut scan_headers = Vec::new();
+        let mut is_quarantined = false;
+        let (modifications, replaced_message) = match self.run_script(script, params, span).await {
+            ScriptResult::Accept { modifications } => (modifications, None),
+            ScriptResult::Replace {
+                message,
+                modifications,
+            } => (modifications, Some(message)),
+            ScriptResult::Reject(reason) if reason.starts_with('4') => {
+                tracing::info!(
+                    parent: span,
+                    context = "deferred-scan",
+                    event = "retry",
+                    reason = reason.trim(),
+                    "Post-acceptance scan failed temporarily, message rescheduled."
+                );
+                self.retry_scan(message).await;
+                return false;
+            }
+            result => {
+                let reason = match &result {
+                    ScriptResult::Reject(reason) => sanitize_reason(reason),
+                    _ => "Message discarded by content filter".to_string(),
+                };
+
+                if let Some(quarantine) = scan.quarantine {
+                    tracing::info!(
+                        parent: span,
+                        context = "deferred-scan",
+                        event = "quarantine",
+                        quarantine = quarantine,
+                        reason = reason,
+                        "Message moved to quarantine after post-acceptance scan."
+                    );
+
+                    scan_headers.extend_from_slice(b"X-Quarantine-Reason: ");
+                    scan_headers.extend_from_slice(reason.as_bytes());
+                    scan_headers.extend_from_slice(b"\r\n");
+                    message.recipients.clear();
+                    message.domains.clear();
+                    message.add_recipient(quarantine, self).await;
+                    is_quarantined = true;
+                    (vec![], None)
+                } else {
+                    tracing::info!(
+                        parent: span,
+                        context = "deferred-scan",
+                        event = "bounce",
+                        reason = reason,
+                        "Message bounced after post-acceptance scan."
+                    );
+
+                    message.bounce_scan(reason);
+                    return false;
+                }
+            }
+        };
+
+        // Apply modifications
+        for modification in modifications {
+            match modification {
+                ScriptModification::AddHeader { name, value } => {
+                    scan_headers.extend_from_slice(name.as_bytes());
+                    scan_headers.extend_from_slice(b": ");
+                    scan_headers.extend_from_slice(value.as_bytes());
+                    if !value.ends_with('\n') {
+                        scan_headers.extend_from_slice(b"\r\n");
+                    }
+                }
+                ScriptModification::DeleteRecipient { .. } => {
+                    tracing::debug!(
+                        parent: span,
+                        context = "deferred-scan",
+                        event = "ignored",
+                        "Recipient changes are not applied after acceptance."
+                    );
+                }
+                ScriptModification::SetEnvelope { name, .. } => {
+                    tracing::debug!(
+                        parent: span,
+                        context = "deferred-scan",
+                        event = "ignored",
+                        envelope = ?name,
+                        "Envelope changes are not applied after acceptance."
+                    );
+                }
+            }
+        }
+
+        // Update the queued message
+        let updated = if let Some(replaced_message) = replaced_message {
+            // Signatures added during the session no longer match the new body
+            for signer in &scan.signers {
+                if let Some(signer) = self.core.get_dkim_signer(signer) {
+                    match signer.sign_chained(&[headers, &replaced_message]) {
+                        Ok(signature) => {
+                            signature.write_header(&mut scan_headers);
+                        }
+                        Err(err) => {
+                            tracing::info!(
+                                parent: span,
+                                context = "dkim",
+                                event = "sign-failed",
+                                return_path = message.return_path,
+                                "Failed to sign message: {}",
+                                err
+                            );
+                        }
+                    }
+                }
+            }
+            scan_headers.extend_from_slice(headers);
+            scan_headers.extend_from_slice(&replaced_message);
+            Some(scan_headers)
+        } else if !scan_headers.is_empty() {
+            scan_headers.extend_from_slice(&contents);
+            Some(scan_headers)
+        } else {
+            None
+        };
+
+        // Journal accepted messages, quarantined ones never reach their recipients
+        if let Some(journal) = scan.journal.as_ref().filter(|_| !is_quarantined) {
+            let raw_message = updated.as_deref().unwrap_or(&contents);
+            if !self
+                .queue_journal(journal, message.correlation_id, &[raw_message], span)
+                .await
+            {
+                self.retry_scan(message).await;
+                return false;
+            }
+        }
+
+        if message.complete_scan(updated.as_deref(), self, span).await {
+            true
+        } else {
+            self.retry_scan(message).await;
+            false
+        }
+    }
+
+    async fn retry_scan(&self, message: &mut Message) {
+        let no_ip = IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0));
+        for domain_idx in 0..message.domains.len() {
+            if !matches!(
+                &message.domains[domain_idx].status,
+                Status::Scheduled | Status::TemporaryFailure(_)
+            ) {
+                continue;
+            }
+            let schedule = self
+                .retry_schedule(&QueueEnvelope {
+                    message,
+                    domain: &message.domains[domain_idx].domain,
+                    mx: "",
+                    remote_ip: no_ip,
+                    local_ip: no_ip,
+                })
+                .await;
+            message.domains[domain_idx].set_status(
+                Status::TemporaryFailure(Error::Io("Content scan failed.".to_string())),
+                &schedule,
+            );
+        }
+    }
+}
+
+impl Message {
+    fn bounce_scan(&mut self, reason: String) {
+        for rcpt in &mut self.recipients {
+            if matches!(
+                &rcpt.status,
+                Status::Scheduled | Status::TemporaryFailure(_)
+            ) {
+                rcpt.status = Status::PermanentFailure(HostResponse {
+                    hostname: ErrorDetails {
+                        entity: "localhost".to_string(),
+                        details: format!("RCPT TO:<{}>", rcpt.address),
+                    },
+                    response: Response {
+                        code: 550,
+                        esc: [5, 7, 1],
+                        message: reason.clone(),
+                    },
+                });
+            }
+        }
+        for domain in &mut self.domains {
+            if matches!(
+                &domain.status,
+                Status::Scheduled | Status::TemporaryFailure(_)
+            ) {
+                domain.status = Status::Completed(());
+            }
+        }
+    }
+}
+
+/// Reduces a script reject reason to a single line of printable ASCII
+/// without its SMTP reply codes, so it can be used in headers and DSNs.
+pub fn sanitize_reason(reason: &str) -> String {
+    let mut words = reason.split_ascii_whitespace().peekable();
+    if words
+        .peek()
+        .map_or(false, |word| word.bytes().all(|ch| ch.is_ascii_digit()))
+    {
+        words.next();
+    }
+    if words.peek().map_or(false, |word| {
+        word.split('.')
+            .all(|part| !part.is_empty() && part.bytes().all(|ch| ch.is_ascii_digit()))
+    }) {
+        words.next();
+    }
+
+    let mut result = String::with_capacity(reason.len().min(MAX_REASON_LEN));
+    for word in words {
+        let word = word
+            .chars()
+            .filter(|ch| ch.is_ascii_graphic())
+            .collect::<String>();
+        if word.is_empty() {
+            continue;
+        }
+        if result.len() + word.len() + 1 > MAX_REASON_LEN {
+            break;
+        }
+        if !result.is_empty() {
+            result.push(' ');
+        }
+        result.push_str(&word);
+    }
+
+    if result.is_empty() {
+        "Message rejected by content filter".to_string()
+    } else {
+        result
+    }
+}
@@ -32,7 +32,8 @@ use utils::BlobHash;
 use crate::core::SMTP;
 
 use super::{
-    Domain, Event, Message, QueueId, QuotaKey, Recipient, Schedule, SimpleEnvelope, Status,
+    scan::DeferredScan, Domain, Event, Message, QueueId, QuotaKey, Recipient, Schedule,
+    SimpleEnvelope, Status, MSG_SCAN_PENDING,
 };
 
 pub const LOCK_EXPIRY: u64 = 300;
@@ -70,6 +71,7 @@ impl SMTP {
             size: 0,
             blob_hash: Default::default(),
             quota_keys: Vec::new(),
+            correlation_id: 0,
         }
     }
 
@@ -88,6 +90,7 @@ impl SMTP {
         let result = self
             .core
             .storage
+            .queue
             .data
             .iterate(
                 IterateParams::new(from_key, to_key).ascending(),
@@ -144,7 +147,7 @@ impl SMTP {
             })),
             event.lock_expiry.serialize(),
         );
-        match self.core.storage.data.write(batch.build()).await {
+        match self.core.storage.queue.data.write(batch.build()).await {
             Ok(_) => Some(event),
             Err(store::Error::AssertValueFailed) => {
                 tracing::debug!(
@@ -172,13 +175,12 @@ impl SMTP {
         match self
             .core
             .storage
+            .queue
             .data
-            .get_value::<Bincode<Message>>(ValueKey::from(ValueClass::Queue(QueueClass::Message(
-                id,
-            ))))
+            .get_value::<Message>(ValueKey::from(ValueClass::Queue(QueueClass::Message(id))))
             .await
         {
-            Ok(Some(message)) => Some(message.inner),
+            Ok(Some(message)) => Some(message),
             Ok(None) => None,
             Err(err) => {
                 tracing::error!(
@@ -195,9 +197,21 @@ impl SMTP {
 
 impl Message {
     pub async fn queue(
+        self,
+        raw_headers: Option<&[u8]>,
+        raw_message: &[u8],
+        core: &SMTP,
+        span: &tracing::Span,
+    ) -> bool {
+        self.queue_with_scan(raw_headers, raw_message, None, core, span)
+            .await
+    }
+
+    pub async fn queue_with_scan(
         mut self,
         raw_headers: Option<&[u8]>,
         raw_message: &[u8],
+        scan: Option<DeferredScan>,
         core: &SMTP,
         span: &tracing::Span,
     ) -> bool {
@@ -226,7 +240,7 @@ impl Message {
             },
             0u32.serialize(),
         );
-        if let Err(err) = core.core.storage.data.write(batch.build()).await {
+        if let Err(err) = core.core.storage.queue.data.write(batch.build()).await {
             tracing::error!(
                 parent: span,
                 context = "queue",
@@ -239,6 +253,7 @@ impl Message {
         if let Err(err) = core
             .core
             .storage
+            .queue
             .blob
             .put_blob(self.blob_hash.as_slice(), message.as_ref())
             .await
@@ -285,6 +300,13 @@ impl Message {
                 }
             }
         }
+        if let Some(scan) = scan {
+            self.flags |= MSG_SCAN_PENDING;
+            batch.set(
+                ValueClass::Queue(QueueClass::MessageScan(self.id)),
+                Bincode::new(scan).serialize(),
+            );
+        }
         batch
             .set(
                 ValueClass::Queue(QueueClass::MessageEvent(QueueEvent {
@@ -304,7 +326,7 @@ impl Message {
                 Bincode::new(self).serialize(),
             );
 
-        if let Err(err) = core.core.storage.data.write(batch.build()).await {
+        if let Err(err) = core.core.storage.queue.data.write(batch.build()).await {
             tracing::error!(
                 parent: span,
                 context = "queue",
@@ -420,7 +442,7 @@ impl Message {
             Bincode::new(self).serialize(),
         );
 
-        if let Err(err) = core.core.storage.data.write(batch.build()).await {
+        if let Err(err) = core.core.storage.queue.data.write(batch.build()).await {
             tracing::error!(
                 context = "queue",
                 event = "error",
@@ -433,6 +455,99 @@ impl Message {
         }
     }
 
+    /// Stores the outcome of a post-acceptance scan, replacing the message
+    /// contents when the scan modified them.
+    pub async fn complete_scan(
+        &mut self,
+        contents: Option<&[u8]>,
+        core: &SMTP,
+        span: &tracing::Span,
+    ) -> bool {
+        let mut batch = BatchBuilder::new();
+        let prev_state = (self.blob_hash.clone(), self.size, self.flags);
+
+        if let Some(contents) = contents {
+            // Reserve and write the new blob
+            let blob_hash = BlobHash::from(contents);
+            let mut reserve = BatchBuilder::new();
+            reserve.with_account_id(SPOOL_ACCOUNT_ID).set(
+                BlobOp::Reserve {
+                    hash: blob_hash.clone(),
+                    until: self.next_delivery_event() + BLOB_EXPIRY,
+                },
+                0u32.serialize(),
+            );
+            if let Err(err) = core.core.storage.queue.data.write(reserve.build()).await {
+                tracing::error!(
+                    parent: span,
+                    context = "queue",
+                    event = "error",
+                    "Failed to write to data store: {}",
+                    err
+                );
+                return false;
+            }
+            if let Err(err) = core
+                .core
+                .storage
+                .queue
+                .blob
+                .put_blob(blob_hash.as_slice(), contents)
+                .await
+            {
+                tracing::error!(
+                    parent: span,
+                    context = "queue",
+                    event = "error",
+                    "Failed to write to blob store: {}",
+                    err
+                );
+                return false;
+            }
+
+            // Move the size quota over to the new contents, the previous
+            // blob is purged once its reservation expires.
+            for quota_key in &self.quota_keys {
+                if let QuotaKey::Size { key, .. } = quota_key {
+                    batch.add(
+                        ValueClass::Queue(QueueClass::QuotaSize(key.clone())),
+                        contents.len() as i64 - self.size as i64,
+                    );
+                }
+            }
+            batch.set(
+                BlobOp::Commit {
+                    hash: blob_hash.clone(),
+                },
+                vec![],
+            );
+            self.blob_hash = blob_hash;
+            self.size = contents.len();
+        }
+
+        self.flags &= !MSG_SCAN_PENDING;
+        batch
+            .clear(ValueClass::Queue(QueueClass::MessageScan(self.id)))
+            .set(
+                ValueClass::Queue(QueueClass::Message(self.id)),
+                Bincode::new(self.clone()).serialize(),
+            );
+
+        if let Err(err) = core.core.storage.queue.data.write(batch.build()).await {
+            tracing::error!(
+                parent: span,
+                context = "queue",
+                event = "error",
+                "Failed to update queued message: {}",
+                err
+            );
+            (self.blob_hash, self.size, self.flags) = prev_state;
+            false
+        } else {
+            true
+        }
+    }
+
     pub async fn remove(self, core: &SMTP, prev_event: u64) -> bool {
         let mut batch = BatchBuilder::new();
 
@@ -469,8 +584,11 @@ impl Message {
                 queue_id: self.id,
             })))
             .clear(ValueClass::Queue(QueueClass::Message(self.id)));
+        if (self.flags & MSG_SCAN_PENDING) != 0 {
+            batch.clear(ValueClass::Queue(QueueClass::MessageScan(self.id)));
+        }
 
-        if let Err(err) = core.core.storage.data.write(batch.build()).await {
+        if let Err(err) = core.core.storage.queue.data.write(batch.build()).await {
             tracing::error!(
                 context = "queue",
                 event = "error",
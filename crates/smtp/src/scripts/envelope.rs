@@ -33,6 +33,10 @@ use crate::{
 };
 
 impl SessionData {
+    pub fn delete_recipient(&mut self, address: &str) {
+        self.rcpt_to.retain(|rcpt| rcpt.address_lcase != address);
+    }
+
     pub fn apply_envelope_modification(&mut self, envelope: Envelope, value: String) {
         match envelope {
             Envelope::From => {
@@ -21,12 +21,13 @@
  * for more details.
 */
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Instant};
 
-use common::scripts::plugins::PluginContext;
+use common::{config::scripts::SpamHeaders, scripts::plugins::PluginContext};
 use mail_auth::common::headers::HeaderWriter;
 use sieve::{
     compiler::grammar::actions::action_redirect::{ByMode, ByTime, Notify, NotifyItem, Ret},
+    runtime::Variable,
     Event, Input, MatchAs, Recipient, Sieve,
 };
 use smtp_proto::{
@@ -37,7 +38,10 @@ use tokio::runtime::Handle;
 
 use crate::{core::SMTP, inbound::DkimSign, queue::DomainPart};
 
-use super::{ScriptModification, ScriptParameters, ScriptResult};
+use super::{
+    render_reject_message, ScriptModification, ScriptParameters, ScriptResult,
+    SCRIPT_TIMEOUT_RESPONSE,
+};
 
 impl SMTP {
     pub fn run_script_blocking(
@@ -45,9 +49,11 @@ impl SMTP {
         script: Arc<Sieve>,
         params: ScriptParameters,
         handle: Handle,
+        deadline: Instant,
         span: tracing::Span,
     ) -> ScriptResult {
         // Create filter instance
+        let env_variables = params.variables.clone();
         let mut instance = self
             .core
             .sieve
@@ -66,6 +72,20 @@ impl SMTP {
 
         // Start event loop
         while let Some(result) = instance.run(input) {
+            // Abort scripts exceeding the wall-clock time limit, the caller
+            // stops waiting at the same deadline so no further actions are
+            // executed once the worker has been abandoned
+            if Instant::now() >= deadline {
+                tracing::warn!(
+                    parent: &span,
+                    context = "sieve",
+                    event = "timeout",
+                    timeout = ?self.core.sieve.timeout,
+                    "Script execution exceeded the time limit."
+                );
+                return ScriptResult::Reject(SCRIPT_TIMEOUT_RESPONSE.to_string());
+            }
+
             match result {
                 Ok(event) => match event {
                     Event::IncludeScript { name, optional } => {
@@ -356,6 +376,19 @@ impl SMTP {
             }
         }
 
+        // Add spam headers
+        if let Some(spam_headers) = &self.core.sieve.spam_headers {
+            let score = match instance.global_variable(spam_headers.variable.as_str()) {
+                Some(Variable::Float(score)) => Some(*score),
+                Some(Variable::Integer(score)) => Some(*score as f64),
+                Some(Variable::String(score)) => score.trim().parse().ok(),
+                _ => None,
+            };
+            if let Some(score) = score {
+                add_spam_headers(spam_headers, score, &mut modifications);
+            }
+        }
+
         // Keep id
         // 0 = use original message
         // MAX = implicit keep
@@ -363,7 +396,14 @@ impl SMTP {
 
         if keep_id == 0 {
             ScriptResult::Accept { modifications }
-        } else if let Some(mut reject_reason) = reject_reason {
+        } else if let Some(reject_reason) = reject_reason {
+            // Expand placeholders using session and script variables
+            let mut reject_reason = render_reject_message(&reject_reason, |name| {
+                env_variables
+                    .get(name)
+                    .or_else(|| instance.global_variable(name))
+                    .map(|value| value.to_string().into_owned().into())
+            });
             if !reject_reason.ends_with('\n') {
                 reject_reason.push_str("\r\n");
             }
@@ -391,3 +431,30 @@ impl SMTP {
         }
     }
 }
+
+fn add_spam_headers(config: &SpamHeaders, score: f64, modifications: &mut Vec<ScriptModification>) {
+    let is_spam = score >= config.threshold;
+    for (name, value) in [
+        (
+            &config.status,
+            format!(
+                "{}, score={score:.1} required={:.1}",
+                if is_spam { "Yes" } else { "No" },
+                config.threshold
+            ),
+        ),
+        (&config.score, format!("{score:.1}")),
+        (&config.level, "*".repeat(score.clamp(0.0, 50.0) as usize)),
+    ] {
+        modifications.push(ScriptModification::AddHeader {
+            name: Arc::new(name.clone()),
+            value: value.into(),
+        });
+    }
+    if is_spam {
+        modifications.push(ScriptModification::AddHeader {
+            name: Arc::new(config.flag.clone()),
+            value: Arc::new("YES".to_string()),
+        });
+    }
+}
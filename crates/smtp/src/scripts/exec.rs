@@ -21,7 +21,10 @@
  * for more details.
 */
 
-use std::{sync::Arc, time::SystemTime};
+use std::{
+    sync::Arc,
+    time::{Instant, SystemTime},
+};
 
 use common::listener::SessionStream;
 use mail_auth::common::resolver::ToReverseName;
@@ -29,9 +32,12 @@ use sieve::{runtime::Variable, Envelope, Sieve};
 use smtp_proto::*;
 use tokio::runtime::Handle;
 
-use crate::{core::Session, inbound::AuthResult};
+use crate::{
+    core::{Session, SMTP},
+    inbound::AuthResult,
+};
 
-use super::{ScriptParameters, ScriptResult};
+use super::{ScriptParameters, ScriptResult, SCRIPT_TIMEOUT_RESPONSE};
 
 impl<T: SessionStream> Session<T> {
     pub fn build_script_parameters(&self, stage: &'static str) -> ScriptParameters {
@@ -40,6 +46,7 @@ impl<T: SessionStream> Session<T> {
             .set_variable("remote_ip", self.data.remote_ip.to_string())
             .set_variable("remote_ip.reverse", self.data.remote_ip.to_reverse_name())
             .set_variable("helo_domain", self.data.helo_domain.to_lowercase())
+            .set_variable("correlation_id", format!("{:x}", self.data.correlation_id))
             .set_variable("authenticated_as", self.data.authenticated_as.clone())
             .set_variable(
                 "now",
@@ -137,16 +144,45 @@ impl<T: SessionStream> Session<T> {
     }
 
     pub async fn run_script(&self, script: Arc<Sieve>, params: ScriptParameters) -> ScriptResult {
-        let core = self.core.clone();
-        let span = self.span.clone();
         let params = params.with_envelope(&self.core.core, self).await;
+        self.core.run_script(script, params, &self.span).await
+    }
+}
 
+impl SMTP {
+    pub async fn run_script(
+        &self,
+        script: Arc<Sieve>,
+        params: ScriptParameters,
+        span: &tracing::Span,
+    ) -> ScriptResult {
+        let core = self.clone();
+        let span_ = span.clone();
         let handle = Handle::current();
-        self.core
-            .spawn_worker(move || core.run_script_blocking(script, params, handle, span))
-            .await
-            .unwrap_or(ScriptResult::Accept {
+        let timeout = self.core.sieve.timeout;
+        let deadline = Instant::now() + timeout;
+        match tokio::time::timeout_at(
+            deadline.into(),
+            self.spawn_worker(move || {
+                core.run_script_blocking(script, params, handle, deadline, span_)
+            }),
+        )
+        .await
+        {
+            Ok(result) => result.unwrap_or(ScriptResult::Accept {
                 modifications: vec![],
-            })
+            }),
+            Err(_) => {
+                // The worker is abandoned and aborts the script on its next event
+                tracing::warn!(
+                    parent: span,
+                    context = "sieve",
+                    event = "timeout",
+                    timeout = ?timeout,
+                    "Script execution exceeded the time limit."
+                );
+                ScriptResult::Reject(SCRIPT_TIMEOUT_RESPONSE.to_string())
+            }
+        }
     }
 }
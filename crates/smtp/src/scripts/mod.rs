@@ -31,6 +31,9 @@ pub mod envelope;
 pub mod event_loop;
 pub mod exec;
 
+pub(crate) const SCRIPT_TIMEOUT_RESPONSE: &str =
+    "451 4.3.0 Script execution timed out, please try again later.\r\n";
+
 #[derive(Debug)]
 pub enum ScriptResult {
     Accept {
@@ -103,6 +106,11 @@ impl ScriptParameters {
         self
     }
 
+    pub fn set_envelope(mut self, envelope: Envelope, value: impl Into<Variable>) -> Self {
+        self.envelope.push((envelope, value.into()));
+        self
+    }
+
     #[cfg(feature = "test_mode")]
     pub fn with_expected_variables(
         mut self,
@@ -118,3 +126,167 @@ impl Default for ScriptParameters {
         Self::new()
     }
 }
+
+/// Script variables and envelope settings that outlive the SMTP session,
+/// persisted next to messages that are scanned after being queued.
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct StoredParameters {
+    variables: Vec<(String, StoredVariable)>,
+    from_addr: String,
+    from_name: String,
+    return_path: String,
+    sign: Vec<String>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+enum StoredVariable {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Array(Vec<StoredVariable>),
+}
+
+impl From<&ScriptParameters> for StoredParameters {
+    fn from(params: &ScriptParameters) -> Self {
+        StoredParameters {
+            variables: params
+                .variables
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.into()))
+                .collect(),
+            from_addr: params.from_addr.clone(),
+            from_name: params.from_name.clone(),
+            return_path: params.return_path.clone(),
+            sign: params.sign.clone(),
+        }
+    }
+}
+
+impl From<StoredParameters> for ScriptParameters {
+    fn from(stored: StoredParameters) -> Self {
+        let mut params = ScriptParameters::new();
+        for (name, value) in stored.variables {
+            params.variables.insert(name.into(), value.into());
+        }
+        params.from_addr = stored.from_addr;
+        params.from_name = stored.from_name;
+        params.return_path = stored.return_path;
+        params.sign = stored.sign;
+        params
+    }
+}
+
+impl From<&Variable> for StoredVariable {
+    fn from(value: &Variable) -> Self {
+        match value {
+            Variable::String(value) => StoredVariable::String(value.to_string()),
+            Variable::Integer(value) => StoredVariable::Integer(*value),
+            Variable::Float(value) => StoredVariable::Float(*value),
+            Variable::Array(items) => {
+                StoredVariable::Array(items.iter().map(StoredVariable::from).collect())
+            }
+        }
+    }
+}
+
+impl From<StoredVariable> for Variable {
+    fn from(value: StoredVariable) -> Self {
+        match value {
+            StoredVariable::String(value) => Variable::from(value),
+            StoredVariable::Integer(value) => Variable::Integer(value),
+            StoredVariable::Float(value) => Variable::Float(value),
+            StoredVariable::Array(items) => {
+                Variable::from(items.into_iter().map(Variable::from).collect::<Vec<_>>())
+            }
+        }
+    }
+}
+
+/// Expands `%{name}` placeholders in a reject message using the provided
+/// resolver. Unknown placeholders expand to an empty string and substituted
+/// values have any control characters replaced with spaces so they cannot
+/// terminate or inject additional SMTP response lines.
+pub fn render_reject_message<'x>(
+    template: &str,
+    resolve: impl Fn(&str) -> Option<Cow<'x, str>>,
+) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("%{") {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        if let Some(end) = after.find('}') {
+            if let Some(value) = resolve(after[..end].trim()) {
+                result.extend(
+                    value
+                        .chars()
+                        .map(|ch| if ch.is_control() { ' ' } else { ch }),
+                );
+            }
+            rest = &after[end + 1..];
+        } else {
+            result.push_str(&rest[start..]);
+            rest = "";
+        }
+    }
+    result.push_str(rest);
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+
+    use super::render_reject_message;
+
+    #[test]
+    fn reject_message_placeholders() {
+        let resolve = |name: &str| -> Option<Cow<'static, str>> {
+            match name {
+                "sender" => Some("john@example.org".into()),
+                "rcpt" => Some("jane@example.org\r\n250 2.0.0 OK".into()),
+                "reason" => Some("tab\there\x00null\x7fdel".into()),
+                "empty" => Some("".into()),
+                _ => None,
+            }
+        };
+
+        for (template, expected) in [
+            ("No placeholders", "No placeholders"),
+            ("", ""),
+            (
+                "Sender %{sender} rejected",
+                "Sender john@example.org rejected",
+            ),
+            (
+                "Sender %{ sender } rejected",
+                "Sender john@example.org rejected",
+            ),
+            ("%{sender}%{sender}", "john@example.orgjohn@example.org"),
+            // Line breaks and control characters are replaced with spaces
+            (
+                "Recipient %{rcpt} rejected",
+                "Recipient jane@example.org  250 2.0.0 OK rejected",
+            ),
+            ("Reason: %{reason}", "Reason: tab here null del"),
+            // Unknown and empty placeholders expand to nothing
+            ("Unknown %{unknown} value", "Unknown  value"),
+            ("Empty %{empty} value", "Empty  value"),
+            ("Blank %{} value", "Blank  value"),
+            // Unterminated placeholders are kept as they are
+            ("Unterminated %{sender", "Unterminated %{sender"),
+            ("%{sender} then %{sender", "john@example.org then %{sender"),
+            ("Trailing %{", "Trailing %{"),
+            // Text that is not a placeholder
+            ("100% {sender} %sender }", "100% {sender} %sender }"),
+        ] {
+            assert_eq!(
+                render_reject_message(template, resolve),
+                expected,
+                "{template:?}"
+            );
+        }
+    }
+}
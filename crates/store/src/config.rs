@@ -306,6 +306,48 @@ impl Stores {
                 });
             }
         }
+
+        // Parse queue purge schedules, when the queue uses its own stores
+        if let (Some(store_id), Some(blob_store_id)) = (
+            config
+                .value("queue.storage.data")
+                .filter(|id| Some(*id) != config.value("storage.data")),
+            config
+                .value("queue.storage.blob")
+                .filter(|id| Some(*id) != config.value("storage.blob")),
+        ) {
+            if let (Some(store), Some(blob_store)) = (
+                self.stores.get(store_id),
+                self.blob_stores.get(blob_store_id),
+            ) {
+                let store_id = store_id.to_string();
+                let blob_store_id = blob_store_id.to_string();
+                self.purge_schedules.push(PurgeSchedule {
+                    cron: config
+                        .property_or_default::<SimpleCron>(
+                            ("store", store_id.as_str(), "purge.frequency"),
+                            "0 3 *",
+                        )
+                        .unwrap_or_else(|| SimpleCron::parse_value("0 3 *").unwrap()),
+                    store_id,
+                    store: PurgeStore::Data(store.clone()),
+                });
+                self.purge_schedules.push(PurgeSchedule {
+                    cron: config
+                        .property_or_default::<SimpleCron>(
+                            ("store", blob_store_id.as_str(), "purge.frequency"),
+                            "0 4 *",
+                        )
+                        .unwrap_or_else(|| SimpleCron::parse_value("0 4 *").unwrap()),
+                    store_id: blob_store_id,
+                    store: PurgeStore::Blobs {
+                        store: store.clone(),
+                        blob_store: blob_store.clone(),
+                    },
+                });
+            }
+        }
+
         for (store_id, store) in &self.lookup_stores {
             if matches!(store, LookupStore::Store(_)) {
                 self.purge_schedules.push(PurgeSchedule {
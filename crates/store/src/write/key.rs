@@ -352,6 +352,7 @@ impl<T: AsRef<ValueClass> + Sync + Send> Key for ValueKey<T> {
                     .write(event.seq_id),
                 QueueClass::QuotaCount(key) => serializer.write(55u8).write(key.as_slice()),
                 QueueClass::QuotaSize(key) => serializer.write(56u8).write(key.as_slice()),
+                QueueClass::MessageScan(queue_id) => serializer.write(57u8).write(*queue_id),
             },
             ValueClass::Report(report) => match report {
                 ReportClass::Tls { id, expires } => {
@@ -509,7 +510,7 @@ impl ValueClass {
             },
             ValueClass::IndexEmail { .. } => U64_LEN * 2,
             ValueClass::Queue(q) => match q {
-                QueueClass::Message(_) => U64_LEN,
+                QueueClass::Message(_) | QueueClass::MessageScan(_) => U64_LEN,
                 QueueClass::MessageEvent(_) => U64_LEN * 2,
                 QueueClass::DmarcReportEvent(event) | QueueClass::TlsReportEvent(event) => {
                     event.domain.len() + U64_LEN * 3
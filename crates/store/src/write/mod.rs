@@ -171,6 +171,7 @@ pub enum QueueClass {
     TlsReportEvent(ReportEvent),
     QuotaCount(Vec<u8>),
     QuotaSize(Vec<u8>),
+    MessageScan(u64),
 }
 
 #[derive(Debug, PartialEq, Clone, Eq, Hash)]
@@ -52,6 +52,8 @@ maps = ["spam_config.map",
         "allow_spf_dkim.list", 
         "domains_disposable.list", 
         "domains_free.list", 
+        "domains_trackers.list", 
+        "localparts_role.list", 
         "mime_types.map", 
         "url_redirectors.list"]
 
@@ -71,6 +71,7 @@ pub mod mailbox;
 pub mod push_subscription;
 pub mod quota;
 pub mod sieve_script;
+pub mod spam_learn;
 pub mod stress_test;
 pub mod thread_get;
 pub mod thread_merge;
@@ -309,6 +310,7 @@ pub async fn jmap_tests() {
     email_changes::test(&mut params).await;
     email_query_changes::test(&mut params).await;
     email_copy::test(&mut params).await;
+    spam_learn::test(&mut params).await;
     thread_get::test(&mut params).await;
     thread_merge::test(&mut params).await;
     mailbox::test(&mut params).await;
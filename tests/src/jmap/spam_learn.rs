@@ -0,0 +1,160 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use jmap::{
+    mailbox::{INBOX_ID, JUNK_ID, TRASH_ID},
+    JMAP,
+};
+use jmap_client::client::{Client, Credentials};
+use jmap_proto::types::id::Id;
+use nlp::bayes::Weights;
+
+use crate::jmap::{assert_is_empty, mailbox::destroy_all_mailboxes};
+
+use super::JMAPTest;
+
+const MESSAGE: &str = concat!(
+    "From: bill@example.com\r\n",
+    "To: jdoe@example.com\r\n",
+    "Subject: Cheap watches\r\n",
+    "\r\n",
+    "Buy cheap replica watches today, limited offer!"
+);
+
+pub async fn test(params: &mut JMAPTest) {
+    println!("Running Spam learning tests...");
+    let server = params.server.clone();
+    let inbox_id = Id::from(INBOX_ID).to_string();
+    let junk_id = Id::from(JUNK_ID).to_string();
+    let trash_id = Id::from(TRASH_ID).to_string();
+
+    // Enable spam learning, new connections use the updated core
+    let mut core = server.core.as_ref().clone();
+    core.jmap.spam_learn = true;
+    server.shared_core.store(core.into());
+    let mut client = Client::new()
+        .credentials(Credentials::basic("admin", "secret"))
+        .timeout(Duration::from_secs(3600))
+        .accept_invalid_certs(true)
+        .connect("https://127.0.0.1:8899")
+        .await
+        .unwrap();
+    client.set_default_account_id(Id::new(1));
+    let (spam, ham) = training_counts(&server).await;
+
+    // Messages moved to Junk are learned as spam
+    let email_id = client
+        .email_import(
+            MESSAGE.as_bytes().to_vec(),
+            [&inbox_id],
+            None::<Vec<&str>>,
+            None,
+        )
+        .await
+        .unwrap()
+        .take_id();
+    client
+        .email_set_mailboxes(&email_id, [&junk_id])
+        .await
+        .unwrap();
+    expect_training_counts(&server, (spam + 1, ham)).await;
+
+    // Moving the message back to the Inbox untrains it as spam and learns it as ham
+    client
+        .email_set_mailboxes(&email_id, [&inbox_id])
+        .await
+        .unwrap();
+    expect_training_counts(&server, (spam, ham + 1)).await;
+    client
+        .email_set_mailboxes(&email_id, [&junk_id])
+        .await
+        .unwrap();
+    expect_training_counts(&server, (spam + 1, ham)).await;
+
+    // Messages already learned with the same label are skipped
+    client
+        .email_set_mailboxes(&email_id, [&trash_id])
+        .await
+        .unwrap();
+    client
+        .email_set_mailboxes(&email_id, [&junk_id])
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(training_counts(&server).await, (spam + 1, ham));
+
+    // The same message is learned separately for each account
+    client.set_default_account_id(Id::new(2));
+    let email_id = client
+        .email_import(
+            MESSAGE.as_bytes().to_vec(),
+            [&inbox_id],
+            None::<Vec<&str>>,
+            None,
+        )
+        .await
+        .unwrap()
+        .take_id();
+    client
+        .email_set_mailboxes(&email_id, [&junk_id])
+        .await
+        .unwrap();
+    expect_training_counts(&server, (spam + 2, ham)).await;
+
+    // Restore the original core and empty store
+    server.shared_core.store(server.core.clone());
+    params.client.set_default_account_id(Id::new(1));
+    destroy_all_mailboxes(params).await;
+    params.client.set_default_account_id(Id::new(2));
+    destroy_all_mailboxes(params).await;
+    assert_is_empty(server).await;
+}
+
+async fn training_counts(server: &JMAP) -> (u32, u32) {
+    let weights = Weights::from(
+        server
+            .core
+            .storage
+            .lookup
+            .counter_get(vec![0u8; 16])
+            .await
+            .unwrap(),
+    );
+    (weights.spam, weights.ham)
+}
+
+async fn expect_training_counts(server: &JMAP, expected: (u32, u32)) {
+    for _ in 0..100 {
+        if training_counts(server).await == expected {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!(
+        "Expected training counts {:?}, found {:?}",
+        expected,
+        training_counts(server).await
+    );
+}
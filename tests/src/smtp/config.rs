@@ -26,16 +26,44 @@ use std::{fs, net::IpAddr, path::PathBuf, time::Duration};
 use common::{
     config::{
         server::{Listener, Server, ServerProtocol, Servers},
-        smtp::{throttle::parse_throttle, *},
+        smtp::{queue::QueueConfig, session::SessionConfig, throttle::parse_throttle, *},
     },
     expr::{functions::ResolveVariable, if_block::*, tokenizer::TokenMap, *},
     Core,
 };
+use store::Stores;
 use tokio::net::TcpSocket;
 
 use utils::config::{Config, Rate};
 
-use super::add_test_certs;
+use super::{add_test_certs, TempDir};
+
+const QUEUE_STORAGE_CONFIG: &str = r#"
+[storage]
+data = "data"
+blob = "blob"
+lookup = "data"
+fts = "data"
+
+[store."data"]
+type = "sqlite"
+path = "{TMP}/data.db"
+
+[store."blob"]
+type = "fs"
+path = "{TMP}/blob"
+
+[store."queue-data"]
+type = "sqlite"
+path = "{TMP}/queue-data.db"
+
+[store."queue-blob"]
+type = "fs"
+path = "{TMP}/queue-blob"
+
+[queue.storage]
+{QUEUE_STORAGE}
+"#;
 
 struct TestEnvelope {
     pub local_ip: IpAddr,
@@ -405,6 +433,118 @@ fn parse_servers() {
     }
 }
 
+#[test]
+fn parse_tls_min_version() {
+    for (value, is_valid) in [
+        ("'TLSv1.2'", true),
+        ("'tls1.3'", true),
+        ("'TLSv1.0'", false),
+        ("'SSLv3'", false),
+        ("'TLSv1.1'", false),
+        ("'TLSv9'", false),
+    ] {
+        for (key, condition) in [
+            ("session.connect.tls.min-version", "remote_ip = '10.0.0.1'"),
+            ("queue.outbound.tls.min-version", "mx = 'mx.partner.org'"),
+        ] {
+            // Deprecated versions are rejected both as defaults and as exceptions
+            for if_block in [
+                format!("{key} = \"{value}\"\n"),
+                format!(
+                    "{key} = [{{if = \"{condition}\", then = \"{value}\"}}, {{else = \"'TLSv1.3'\"}}]\n"
+                ),
+            ] {
+                let mut config = Config::new(&if_block).unwrap();
+                let min_version = if key.starts_with("session.") {
+                    SessionConfig::parse(&mut config).connect.tls_min_version
+                } else {
+                    QueueConfig::parse(&mut config).tls.min_version
+                };
+
+                assert_eq!(
+                    config.errors.contains_key(key),
+                    !is_valid,
+                    "failed for {if_block:?}: {:?}",
+                    config.errors
+                );
+                if !is_valid {
+                    assert!(min_version.if_then.is_empty(), "failed for {if_block:?}");
+                    assert_eq!(
+                        min_version.default_string(),
+                        Some("TLSv1.2"),
+                        "failed for {if_block:?}"
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn parse_queue_storage() {
+    let tmp_dir = TempDir::new("smtp_queue_storage_test", true);
+
+    for (test_num, (queue_storage, is_separate)) in [
+        ("data = \"queue-data\"\nblob = \"queue-blob\"", true),
+        ("data = \"data\"\nblob = \"blob\"", false),
+        ("blob = \"queue-blob\"", false),
+        ("data = \"queue-data\"", false),
+        ("data = \"missing\"\nblob = \"queue-blob\"", false),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let mut config = Config::new(
+            QUEUE_STORAGE_CONFIG
+                .replace("{TMP}", tmp_dir.temp_dir.to_str().unwrap())
+                .replace("{QUEUE_STORAGE}", queue_storage),
+        )
+        .unwrap();
+        let stores = Stores::parse_all(&mut config).await;
+        let core = Core::parse(&mut config, stores, Default::default()).await;
+
+        // Half-separate or invalid queue stores are rejected
+        assert_eq!(
+            config
+                .errors
+                .keys()
+                .any(|key| key.starts_with("queue.storage")),
+            !is_separate && queue_storage.contains("queue-"),
+            "failed for {queue_storage:?}: {:?}",
+            config.errors
+        );
+
+        // Queue blobs are only purged when the queue uses its own stores
+        assert_eq!(
+            core.storage
+                .purge_schedules
+                .iter()
+                .any(|schedule| schedule.store_id == "queue-blob"),
+            is_separate,
+            "failed for {queue_storage:?}"
+        );
+
+        // Queue blobs are written to the default blob store unless both stores are separate
+        let blob_key = format!("queue-blob-{test_num}");
+        core.storage
+            .queue
+            .blob
+            .put_blob(blob_key.as_bytes(), b"hello world")
+            .await
+            .unwrap();
+        assert_eq!(
+            core.storage
+                .blob
+                .get_blob(blob_key.as_bytes(), 0..usize::MAX)
+                .await
+                .unwrap()
+                .is_some(),
+            !is_separate,
+            "failed for {queue_storage:?}"
+        );
+    }
+}
+
 #[tokio::test]
 async fn eval_if() {
     let mut file = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -23,7 +23,12 @@ use smtp::{
     scripts::ScriptResult,
 };
 use store::Stores;
-use tokio::runtime::Handle;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    runtime::Handle,
+    sync::watch,
+};
 use utils::config::Config;
 
 use crate::smtp::{build_smtp, session::TestSession, TempDir};
@@ -56,6 +61,7 @@ from-addr = "'sieve@foobar.org'"
 return-path = ""
 hostname = "mx.foobar.org"
 no-capability-check = true
+internal-networks = ["10.0.0.0/8", "192.168.0.0/16"]
 
 [sieve.trusted.limits]
 redirects = 3
@@ -93,13 +99,57 @@ path = "{PATH}/test_antispam.db"
                 "hta" = "BAD|NZ" }
 "spam-trap" = {"spamtrap@*"}
 "spam-allow" = {"stalw.art"}
+"spam-executives" = {"jane doe"}
 
 [resolver]
 public-suffix = "file://{LIST_PATH}/public-suffix.dat"
 
+[sieve.trusted.rdap]
+url = "http://127.0.0.1:9335/domain/"
+
+[sieve.trusted.scoring]
+store = "spamdb"
+
+[sieve.trusted.scoring.weights]
+STATIC = 2.5
+STATIC_NEG = -1.0
+STORED = 1.0
+FALLBACK = 3.0
+
+[sieve.trusted.byte-signatures.malware]
+eicar = 'X5O!P%@AP[4\\PZX54(P^)7CC)7}$EICAR'
+mz-base64 = "TVqQAAMAAAAEAAAA"
+script-tag = "hex:3c 73 63 72 69 70 74 3e"
+
+[sieve.trusted.byte-signatures.phishing]
+verify = "verify your account"
+account = "your account"
+
+[directory."spam-local"]
+type = "memory"
+
+[[directory."spam-local".principals]]
+name = "jane"
+description = "Jane Doe"
+secret = "secret"
+email = ["jane@foobar.org"]
+
 [sieve.trusted.scripts]
 "#;
 
+#[cfg(feature = "yara")]
+const YARA_CONFIG: &str = r#"
+[sieve.trusted.yara.contents]
+eicar = '''
+rule EICAR_Test {
+    strings:
+        $eicar = "EICAR-STANDARD-ANTIVIRUS-TEST-FILE"
+    condition:
+        $eicar
+}
+'''
+"#;
+
 #[tokio::test(flavor = "multi_thread")]
 async fn antispam() {
     /*tracing::subscriber::set_global_default(
@@ -140,6 +190,30 @@ async fn antispam() {
         "reputation",
         "pyzor",
     ];
+    // Scripts exercising functions not used by the shipped spam filter,
+    // loaded from the test resources directory.
+    #[allow(unused_mut)]
+    let mut function_tests = vec![
+        "qr_decode",
+        "domain_age",
+        "role_address",
+        "impersonation",
+        "envelope_mismatch",
+        "weighted_score",
+        "suspicious_headers",
+        "header_injection",
+        "image_text_ratio",
+        "punycode_links",
+        "byte_signatures",
+        "dkim_timestamps",
+        "replyto_risk",
+        "first_contact",
+        "alternative_divergence",
+        "part_order_anomaly",
+        "self_spoof",
+    ];
+    #[cfg(feature = "yara")]
+    function_tests.push("yara_scan");
     let tmp_dir = TempDir::new("smtp_antispam_test", true);
     let base_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
         .parent()
@@ -160,6 +234,7 @@ async fn antispam() {
                 .unwrap(),
         );
     let scores = fs::read_to_string(base_path.join("maps").join("scores.map")).unwrap();
+    let roles = fs::read_to_string(base_path.join("maps").join("localparts_role.list")).unwrap();
     let base_path = base_path.join("scripts");
     let script_config = fs::read_to_string(base_path.join("config.sieve")).unwrap();
     let script_prelude = fs::read_to_string(base_path.join("prelude.sieve")).unwrap();
@@ -185,6 +260,16 @@ async fn antispam() {
             "{test_name}.contents = '''{script_config}\n{script_prelude}\n{script}\n'''\n"
         ));
     }
+    let fixtures_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("resources")
+        .join("smtp")
+        .join("antispam");
+    for test_name in &function_tests {
+        let script = fs::read_to_string(fixtures_path.join(format!("{test_name}.sieve"))).unwrap();
+        config.push_str(&format!(
+            "{test_name}.contents = '''{script_config}\n{script_prelude}\n{script}\n'''\n"
+        ));
+    }
     for test_name in ["composites", "scores", "epilogue"] {
         all_scripts = all_scripts
             + "\n"
@@ -197,6 +282,9 @@ async fn antispam() {
         "combined.contents = '''{all_scripts}\n'''\n[lookup]\n"
     ));
     config.push_str(&scores);
+    config.push_str(&roles);
+    #[cfg(feature = "yara")]
+    config.push_str(YARA_CONFIG);
 
     // Parse config
     let mut config = Config::new(&config).unwrap();
@@ -262,20 +350,23 @@ async fn antispam() {
 
     let core = build_smtp(core, Inner::default());
 
+    // Start mock RDAP server
+    let _rdap_rx = spawn_mock_rdap_server();
+
     // Run tests
-    let base_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-        .join("resources")
-        .join("smtp")
-        .join("antispam");
     let span = tracing::info_span!("sieve_antispam");
-    for &test_name in tests.iter().chain(&["combined"]) {
+    for &test_name in tests
+        .iter()
+        .chain(function_tests.iter())
+        .chain(&["combined"])
+    {
         /*if test_name != "combined" {
             continue;
         }*/
         println!("===== {test_name} =====");
         let script = core.core.sieve.scripts.get(test_name).cloned().unwrap();
 
-        let contents = fs::read_to_string(base_path.join(format!("{test_name}.test"))).unwrap();
+        let contents = fs::read_to_string(fixtures_path.join(format!("{test_name}.test"))).unwrap();
         let mut lines = contents.lines();
         let mut has_more = true;
 
@@ -424,8 +515,11 @@ async fn antispam() {
             let span = span.clone();
             let core_ = core.clone();
             let script = script.clone();
+            let deadline = Instant::now() + core.core.sieve.timeout;
             match core
-                .spawn_worker(move || core_.run_script_blocking(script, params, handle, span))
+                .spawn_worker(move || {
+                    core_.run_script_blocking(script, params, handle, deadline, span)
+                })
                 .await
                 .unwrap()
             {
@@ -470,6 +564,92 @@ async fn antispam() {
     }
 }
 
+pub fn spawn_mock_rdap_server() -> watch::Sender<bool> {
+    let (tx, mut rx) = watch::channel(true);
+
+    tokio::spawn(async move {
+        let listener = TcpListener::bind("127.0.0.1:9335")
+            .await
+            .unwrap_or_else(|e| {
+                panic!("Failed to bind mock RDAP server to 127.0.0.1:9335: {e}");
+            });
+        let mut served = Vec::new();
+        loop {
+            tokio::select! {
+                stream = listener.accept() => {
+                    match stream {
+                        Ok((stream, _)) => {
+                            if let Some(domain) = accept_rdap(stream).await {
+                                // Lookups must be answered from the cache after the first request
+                                assert!(
+                                    !served.contains(&domain),
+                                    "RDAP lookup for {domain} was not cached"
+                                );
+                                served.push(domain);
+                            }
+                        }
+                        Err(err) => {
+                            panic!("Something went wrong: {err}" );
+                        }
+                    }
+                },
+                _ = rx.changed() => {
+                    break;
+                }
+            };
+        }
+    });
+
+    tx
+}
+
+async fn accept_rdap(mut stream: TcpStream) -> Option<String> {
+    let mut request = Vec::new();
+    let mut buf = vec![0u8; 1024];
+    while !request.ends_with(b"\r\n\r\n") {
+        match stream.read(&mut buf).await {
+            Ok(0) | Err(_) => return None,
+            Ok(br) => request.extend_from_slice(&buf[..br]),
+        }
+    }
+    let request = String::from_utf8(request).unwrap();
+    let domain = request
+        .strip_prefix("GET /domain/")
+        .and_then(|r| r.split_once(' '))
+        .map(|(domain, _)| domain.to_string())
+        .unwrap_or_else(|| panic!("Invalid RDAP request: {request}"));
+    let registered = match domain.as_str() {
+        "new-domain.org" => Some(chrono::Utc::now() - chrono::Duration::days(2)),
+        "old-domain.org" => Some(chrono::Utc::now() - chrono::Duration::days(3650)),
+        _ => None,
+    };
+    let response = if let Some(registered) = registered {
+        let body = format!(
+            concat!(
+                "{{\"objectClassName\":\"domain\",\"ldhName\":\"{}\",",
+                "\"events\":[{{\"eventAction\":\"registration\",",
+                "\"eventDate\":\"{}\"}}]}}"
+            ),
+            domain,
+            registered.to_rfc3339()
+        );
+        format!(
+            concat!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/rdap+json\r\n",
+                "Content-Length: {}\r\nConnection: close\r\n\r\n{}"
+            ),
+            body.len(),
+            body
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+    stream.write_all(response.as_bytes()).await.unwrap();
+    stream.flush().await.unwrap();
+
+    Some(domain)
+}
+
 #[test]
 fn html_tokens() {
     for (input, expected) in [
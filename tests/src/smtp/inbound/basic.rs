@@ -21,12 +21,18 @@
  * for more details.
 */
 
-use common::Core;
+use std::sync::Arc;
+
+use common::{config::smtp::session::SessionConfig, listener::ServerInstance, Core};
 use smtp::core::{Inner, Session};
+use utils::config::Config;
 
-use crate::smtp::{
-    build_smtp,
-    session::{TestSession, VerifyResponse},
+use crate::{
+    smtp::{
+        build_smtp,
+        session::{test_server_instance, TestSession, VerifyResponse},
+    },
+    AssertConfig,
 };
 
 #[tokio::test]
@@ -66,3 +72,207 @@ async fn basic_commands() {
     session.ingest(b"QUIT\r\n").await.unwrap_err();
     session.response().assert_code("221");
 }
+
+const CONFIG_HELP: &str = r#"
+[session.connect]
+help = [{if = "listener = 'submission'", then = "'Contact postmaster@example.org'"},
+        {else = "''"}]
+"#;
+
+#[tokio::test]
+async fn help_per_listener() {
+    let mut config = Config::new(CONFIG_HELP).unwrap();
+    let core = Core::parse(&mut config, Default::default(), Default::default()).await;
+    let core = build_smtp(core, Inner::default());
+
+    // HELP is disabled on the MX listener
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session.cmd("HELP", "502 5.5.1").await;
+
+    // The rest of the session must not be affected
+    session.ehlo("mx.foobar.org").await;
+    session.cmd("MAIL FROM:<john@foobar.org>", "250").await;
+    session.cmd("HELP QUIT", "502 5.5.1").await;
+    session.cmd("RSET", "250").await;
+
+    // Submission listener uses a custom help text
+    let mut session = Session::test(core);
+    session.instance = Arc::new(ServerInstance {
+        id: "submission".to_string(),
+        ..test_server_instance()
+    });
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session
+        .cmd("HELP", "250 2.0.0")
+        .await
+        .assert_contains("Contact postmaster@example.org");
+}
+
+#[tokio::test]
+async fn drain_sessions() {
+    let core = build_smtp(Core::default(), Inner::default());
+
+    // Start a transaction before the drain begins
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+    session.cmd("MAIL FROM:<john@foobar.org>", "250").await;
+
+    assert!(core.drain());
+    assert!(!core.drain());
+    assert!(core.inner.drain.is_draining());
+
+    // New connections are refused
+    let mut new_session = Session::test(core.clone());
+    assert!(!new_session.init_conn().await);
+    new_session.response().assert_code("421 4.3.2");
+
+    // Transactions in progress are allowed to complete
+    assert!(!session.close_if_draining().await);
+    session.cmd("RSET", "250").await;
+    assert!(session.close_if_draining().await);
+    session.response().assert_code("421 4.3.2");
+}
+
+const CONFIG_DISABLED_COMMANDS: &str = r#"
+[session.disabled-commands]
+smtp = ["auth", "vrfy", "expn", "starttls"]
+submission = ["helo"]
+"#;
+
+#[tokio::test]
+async fn disabled_commands() {
+    let mut config = Config::new(CONFIG_DISABLED_COMMANDS).unwrap();
+    let mut core = Core::default();
+    core.smtp.session = SessionConfig::parse(&mut config);
+    config.assert_no_errors();
+    let core = build_smtp(core, Inner::default());
+
+    // Disabled commands are neither advertised nor accepted on the MX listener
+    let mut session = Session::test(core.clone());
+    session.stream.tls = false;
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session
+        .ehlo("mx.foobar.org")
+        .await
+        .assert_not_contains("STARTTLS")
+        .assert_not_contains("AUTH");
+    session.cmd("STARTTLS", "502 5.5.1").await;
+    session
+        .cmd("AUTH PLAIN AGpvaG4Ac2VjcmV0", "502 5.5.1")
+        .await;
+    session.cmd("VRFY john", "502 5.5.1").await;
+    session.cmd("EXPN sales", "502 5.5.1").await;
+    session.cmd("HELO mx.foobar.org", "250").await;
+    session.cmd("MAIL FROM:<john@foobar.org>", "250").await;
+    session.cmd("RSET", "250").await;
+
+    // Other listeners are not affected
+    let mut session = Session::test(core);
+    session.instance = Arc::new(ServerInstance {
+        id: "submission".to_string(),
+        ..test_server_instance()
+    });
+    session.stream.tls = false;
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session.cmd("HELO mx.foobar.org", "502 5.5.1").await;
+    session
+        .ehlo("mx.foobar.org")
+        .await
+        .assert_contains("STARTTLS");
+
+    // Mandatory commands cannot be disabled
+    let mut config = Config::new(concat!(
+        "[session.disabled-commands]\n",
+        "smtp = [\"rcpt\", \"quit\", \"noop\"]\n"
+    ))
+    .unwrap();
+    let session = SessionConfig::parse(&mut config);
+    assert_eq!(config.errors.len(), 2, "{:?}", config.errors);
+    assert_eq!(
+        session
+            .disabled_commands
+            .get("smtp")
+            .map(|commands| commands.len()),
+        Some(1)
+    );
+}
+
+const CONFIG_TRANSCRIPT: &str = r#"
+[session.transcript]
+enable = [{if = "remote_ip = '10.0.0.1'", then = true},
+          {else = false}]
+capacity = 2
+"#;
+
+#[tokio::test]
+async fn session_transcript() {
+    let mut config = Config::new(CONFIG_TRANSCRIPT).unwrap();
+    let core = Core::parse(&mut config, Default::default(), Default::default()).await;
+    let core = build_smtp(core, Inner::default());
+
+    // Record the transcript of the targeted sender
+    let mut session = Session::test(core.clone());
+    session.data.correlation_id = 1;
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    assert!(session.init_conn().await);
+    session.ehlo("mx.foobar.org").await;
+    session
+        .ingest(b"AUTH PLAIN AGpvaG4Ac2VjcmV0\r\n")
+        .await
+        .unwrap();
+    session.response();
+    session
+        .ingest(b"AUTH PLAIN\r\nAGJpbGwAcGFzc3dvcmQ=\r\n")
+        .await
+        .unwrap();
+    session.response();
+    session.cmd("MAIL FROM:<john@foobar.org>", "250").await;
+    drop(session);
+
+    let transcript = core.inner.transcripts.get(1).expect("missing transcript");
+    assert!(transcript.transcript.contains("S: 220 "));
+    assert!(transcript.transcript.contains("C: EHLO mx.foobar.org"));
+    assert!(transcript.transcript.contains("C: AUTH PLAIN [REDACTED]"));
+    assert!(transcript
+        .transcript
+        .contains("C: MAIL FROM:<john@foobar.org>"));
+    assert!(!transcript.transcript.contains("AGpvaG4Ac2VjcmV0"));
+    assert!(transcript
+        .transcript
+        .contains("C: AUTH PLAIN\r\nC: [REDACTED]\r\n"));
+    assert!(!transcript.transcript.contains("AGJpbGwAcGFzc3dvcmQ="));
+
+    // Other senders are not recorded
+    let mut session = Session::test(core.clone());
+    session.data.correlation_id = 2;
+    session.data.remote_ip_str = "10.0.0.2".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    assert!(session.init_conn().await);
+    session.ehlo("mx.foobar.org").await;
+    drop(session);
+    assert!(core.inner.transcripts.get(2).is_none());
+
+    // Older transcripts are evicted once the buffer is full
+    for correlation_id in [3, 4] {
+        let mut session = Session::test(core.clone());
+        session.data.correlation_id = correlation_id;
+        session.data.remote_ip_str = "10.0.0.1".to_string();
+        session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+        assert!(session.init_conn().await);
+    }
+    assert!(core.inner.transcripts.get(1).is_none());
+    assert_eq!(core.inner.transcripts.list().len(), 2);
+}
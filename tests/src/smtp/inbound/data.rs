@@ -21,20 +21,41 @@
  * for more details.
 */
 
-use common::Core;
+use std::{
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use common::{
+    addresses::{verp_decode, verp_encode},
+    config::server::ServerProtocol,
+    Core,
+};
+use mail_auth::MX;
+use mail_parser::MessageParser;
 use store::Stores;
 use utils::config::Config;
 
 use crate::{
     smtp::{
         build_smtp,
-        inbound::TestMessage,
+        inbound::{TestMessage, TestQueueEvent},
+        outbound::TestServer,
         session::{load_test_message, TestSession, VerifyResponse},
         TempDir, TestSMTP,
     },
     AssertConfig,
 };
-use smtp::core::{Inner, Session};
+use smtp::{
+    core::{Inner, Session},
+    inbound::{
+        header_scanner::{HeaderLimit, HeaderScanner},
+        sanitize::sanitize_html,
+        spool::SpoolFile,
+        terminator::{ScanResult, TerminatorScanner},
+    },
+    queue::MSG_SCAN_PENDING,
+};
 
 const CONFIG: &str = r#"
 [storage]
@@ -75,9 +96,20 @@ description = "Mike Foobar"
 secret = "p4ssw0rd"
 email = "mike@test.com"
 
+[[directory."local".principals]]
+name = "ann"
+description = "Ann Foobar"
+secret = "p4ssw0rd"
+email = "ann@sanitize.org"
+
 [session.rcpt]
 directory = "'local'"
 
+[session.data]
+bare-line-endings = [{if = "remote_ip = '10.0.0.5'", then = "strict"},
+                     {if = "remote_ip = '10.0.0.6'", then = "lenient"},
+                     {else = "allow"}]
+
 [session.data.limits]
 messages = [{if = "remote_ip = '10.0.0.1'", then = 1},
             {else = 100}]
@@ -90,13 +122,31 @@ received-spf =  [{if = "remote_ip = '10.0.0.3'", then = true},
             {else = false}]
 auth-results =  [{if = "remote_ip = '10.0.0.3'", then = true},
             {else = false}]
-message-id =  [{if = "remote_ip = '10.0.0.3'", then = true},
-               {else = false}]
 date = [{if = "remote_ip = '10.0.0.3'", then = true},
         {else = false}]
 return-path =  [{if = "remote_ip = '10.0.0.3'", then = true},
             {else = false}]
 
+[session.data.add-headers.message-id]
+enable = [{if = "remote_ip = '10.0.0.3'", then = true},
+          {else = false}]
+format = "msg.{unique}.{random:8}@{domain}"
+
+[session.data.tracking-pixels]
+enable = [{if = "remote_ip = '10.0.0.7' && rcpt_domain = 'test.com'", then = true},
+          {else = false}]
+
+[lookup]
+spam-trackers = {"pixel.example.net"}
+
+[session.data.html-sanitize]
+enable = [{if = "rcpt_domain = 'sanitize.org'", then = true},
+          {else = false}]
+
+[session.data.text-alternative]
+enable = [{if = "rcpt_domain = 'example.org'", then = true},
+          {else = false}]
+
 [[queue.quota]]
 match = "sender = 'john@doe.org'"
 key = ['sender']
@@ -198,7 +248,7 @@ async fn data() {
         .assert_contains("To: ")
         .assert_contains("Subject: ")
         .assert_contains("Date: ")
-        .assert_contains("Message-ID: ")
+        .assert_contains("Message-ID: <msg.")
         .assert_contains("Return-Path: ")
         .assert_contains("Received: ")
         .assert_contains("Authentication-Results: ")
@@ -258,11 +308,1059 @@ async fn data() {
         )
         .await;
 
+    // Bare line endings are rejected for 10.0.0.5
+    qr.clear_queue(&core).await;
+    while qr.try_read_event().await.is_some() {}
+    session.data.remote_ip_str = "10.0.0.5".to_string();
+    session.eval_session_params().await;
+    session
+        .send_message(
+            "john@test.org",
+            &["mike@test.com"],
+            "test:no_dkim",
+            "500 5.5.2",
+        )
+        .await;
+    qr.assert_no_events();
+
+    // Bare line endings are normalized for 10.0.0.6
+    session.data.remote_ip_str = "10.0.0.6".to_string();
+    session.eval_session_params().await;
+    session
+        .send_message("john@test.org", &["mike@test.com"], "test:no_dkim", "250")
+        .await;
+    let message = qr.expect_message().await.read_message(&qr).await;
+    assert!(message.contains("\r\n"));
+    assert!(!message.replace("\r\n", "").contains(['\r', '\n']));
+
+    // Tracking pixels and images hosted by known trackers are removed for
+    // 10.0.0.7 when all recipients are at test.com
+    let newsletter = concat!(
+        "From: john@test.org\r\n",
+        "To: mike@test.com\r\n",
+        "Subject: Newsletter\r\n",
+        "Content-Type: text/html; charset=utf-8\r\n",
+        "\r\n",
+        "<html><body><p>Hello</p>",
+        "<img src=\"https://example.org/logo.png\" width=\"120\" height=\"60\">",
+        "<IMG SRC=\"https://tracker.example.com/open?id=1\" WIDTH=\"1\" HEIGHT=\"1\" />",
+        "<img src=\"https://cdn.pixel.example.net/banner.png\" width=\"600\" height=\"90\">",
+        "</body></html>",
+    );
+    session.data.remote_ip_str = "10.0.0.7".to_string();
+    session.eval_session_params().await;
+    session
+        .send_message("john@test.org", &["mike@test.com"], newsletter, "250")
+        .await;
+    let message = qr.expect_message().await.read_message(&qr).await;
+    assert!(message.contains("logo.png"), "{message}");
+    assert!(!message.contains("tracker.example.com"), "{message}");
+    assert!(!message.contains("pixel.example.net"), "{message}");
+
+    // The message is left untouched when a recipient has removal disabled
+    session
+        .send_message(
+            "john@test.org",
+            &["mike@test.com", "bill@foobar.org"],
+            newsletter,
+            "250",
+        )
+        .await;
+    let message = qr.expect_message().await.read_message(&qr).await;
+    assert!(message.contains("tracker.example.com"), "{message}");
+    assert!(message.contains("pixel.example.net"), "{message}");
+
+    // HTML-only messages for example.org get a plain text alternative
+    session
+        .send_message(
+            "john@test.org",
+            &["jdoe@example.org"],
+            concat!(
+                "From: john@test.org\r\n",
+                "To: jdoe@example.org\r\n",
+                "Subject: Newsletter\r\n",
+                "Content-Type: text/html; charset=utf-8\r\n",
+                "\r\n",
+                "<html><body><p>Hello world</p></body></html>\r\n",
+            ),
+            "250",
+        )
+        .await;
+    let message = qr.expect_message().await.read_message(&qr).await;
+    assert!(
+        message.contains("Content-Type: multipart/alternative;"),
+        "{message}"
+    );
+    assert!(
+        message.contains("Content-Type: text/plain; charset=\"utf-8\""),
+        "{message}"
+    );
+    assert!(
+        message.contains("Content-Type: text/html; charset=utf-8"),
+        "{message}"
+    );
+    assert!(message.contains("<p>Hello world</p>"), "{message}");
+    let parsed = MessageParser::new().parse(message.as_bytes()).unwrap();
+    assert_eq!(parsed.text_body_count(), 1);
+    assert_eq!(parsed.html_body_count(), 1);
+    assert_eq!(parsed.body_text(0).unwrap().trim(), "Hello world");
+
+    // Other recipient domains are not modified, including messages that
+    // are also addressed to example.org
+    for rcpts in [
+        &["mike@test.com"][..],
+        &["jdoe@example.org", "mike@test.com"][..],
+    ] {
+        session
+            .send_message(
+                "john@test.org",
+                rcpts,
+                concat!(
+                    "From: john@test.org\r\n",
+                    "To: mike@test.com\r\n",
+                    "Subject: Newsletter\r\n",
+                    "Content-Type: text/html; charset=utf-8\r\n",
+                    "\r\n",
+                    "<html><body><p>Hello world</p></body></html>\r\n",
+                ),
+                "250",
+            )
+            .await;
+        qr.expect_message()
+            .await
+            .read_lines(&qr)
+            .await
+            .assert_not_contains("multipart/alternative");
+    }
+
+    // Active HTML content is removed for sanitize.org
+    session
+        .send_message(
+            "john@test.org",
+            &["ann@sanitize.org"],
+            concat!(
+                "From: john@test.org\r\n",
+                "To: ann@sanitize.org\r\n",
+                "Subject: Invoice\r\n",
+                "Content-Type: text/html; charset=utf-8\r\n",
+                "\r\n",
+                "<html><body onload=\"steal()\"><p>Your invoice</p>",
+                "<script>document.location='https://evil.example.com'</script>",
+                "<a href=\"javascript:steal()\">Pay now</a>",
+                "<iframe src=\"https://evil.example.com\"></iframe>",
+                "</body></html>\r\n",
+            ),
+            "250",
+        )
+        .await;
+    let message = qr.expect_message().await.read_message(&qr).await;
+    assert!(message.contains("<p>Your invoice</p>"), "{message}");
+    assert!(message.contains(">Pay now</a>"), "{message}");
+    for removed in [
+        "onload",
+        "<script",
+        "evil.example.com",
+        "javascript:",
+        "<iframe",
+    ] {
+        assert!(!message.contains(removed), "{removed} in {message}");
+    }
+
     // Make sure store is empty
     qr.clear_queue(&core).await;
     core.core
         .storage
+        .queue
         .data
-        .assert_is_empty(core.core.storage.blob.clone())
+        .assert_is_empty(core.core.storage.queue.blob.clone())
         .await;
 }
+
+#[test]
+fn html_sanitize() {
+    for (html, expected) in [
+        // Event handler attributes
+        (
+            "<img src=\"logo.png\" onerror=\"alert(1)\">",
+            "<img src=\"logo.png\">",
+        ),
+        (
+            "<div ONMOUSEOVER='alert(1)' class=x>Hi</div>",
+            "<div class=x>Hi</div>",
+        ),
+        ("<svg/onload=alert(1)>", "<svg>"),
+        // Script URI schemes
+        ("<a href=\"javascript:alert(1)\">Click</a>", "<a>Click</a>"),
+        ("<a href=\" JaVaScRiPt:alert(1)\">Click</a>", "<a>Click</a>"),
+        (
+            "<a href=\"java&#x09;script&colon;alert(1)\">Click</a>",
+            "<a>Click</a>",
+        ),
+        (
+            "<a href='vbscript:msgbox(1)' title=Link>Click</a>",
+            "<a title=Link>Click</a>",
+        ),
+        (
+            "<img src=\"data:text/html;base64,PHNjcmlwdD4=\" alt=\"x\">",
+            "<img alt=\"x\">",
+        ),
+        (
+            "<form action=javascript:alert(1)><input type=submit></form>",
+            "<form><input type=submit></form>",
+        ),
+        (
+            "<p style=\"width: expression(alert(1))\">Hi</p>",
+            "<p>Hi</p>",
+        ),
+        // Scripts and frames
+        (
+            "<p>Hello</p><script type=\"text/javascript\">if (a > b) alert(1)</script><p>World</p>",
+            "<p>Hello</p><p>World</p>",
+        ),
+        (
+            "<p>Hello</p><SCRIPT src=\"https://evil.example.com/x.js\" /><p>World</p>",
+            "<p>Hello</p><p>World</p>",
+        ),
+        (
+            "<iframe src=\"https://evil.example.com\">Fallback</iframe><p>Hello</p>",
+            "<p>Hello</p>",
+        ),
+        (
+            "<object data=\"movie.swf\"><p>Hello</p></object>",
+            "<p>Hello</p>",
+        ),
+    ] {
+        let (sanitized, _) = sanitize_html(html.as_bytes()).unwrap();
+        assert_eq!(String::from_utf8(sanitized).unwrap(), expected, "{html}");
+    }
+
+    // Safe content is not modified
+    for html in [
+        "<a href=\"https://example.org/?a=1&b=2\" title=\"one\">Link</a>",
+        "<p class=\"online\">a < b and b > c</p><!-- <script>alert(1)</script> -->",
+        "<img src=\"cid:logo@example.org\" alt='data:'>",
+    ] {
+        assert_eq!(sanitize_html(html.as_bytes()), None, "{html}");
+    }
+}
+
+#[tokio::test]
+async fn data_smuggling() {
+    let mut config = Config::new("[session.rcpt]\nrelay = true\n").unwrap();
+    let core = Core::parse(&mut config, Default::default(), Default::default()).await;
+
+    // Alternate end-of-data sequences used in SMTP smuggling attacks
+    for terminator in [
+        "\n.\n", "\n.\r\n", "\r.\n", "\r.\r\n", "\r\n.\n", "\r\n.\r", "\r\n.\rX",
+    ] {
+        let mut session = Session::test(build_smtp(core.clone(), Inner::default()));
+        session.data.remote_ip_str = "10.0.0.1".to_string();
+        session.eval_session_params().await;
+        session.ehlo("mx.doe.org").await;
+        session.mail_from("john@doe.org", "250").await;
+        session.rcpt_to("bill@foobar.org", "250").await;
+        session.ingest(b"DATA\r\n").await.unwrap();
+        session.response().assert_code("354");
+
+        let payload = format!(
+            concat!(
+                "From: john@doe.org\r\nSubject: Hello\r\n\r\nHi{}",
+                "MAIL FROM:<admin@foobar.org>\r\n",
+                "RCPT TO:<bill@foobar.org>\r\n",
+                "DATA\r\n",
+                "From: admin@foobar.org\r\nSubject: Smuggled\r\n\r\nSmuggled\r\n.\r\n"
+            ),
+            terminator
+        );
+        assert!(
+            session.ingest(payload.as_bytes()).await.is_err(),
+            "Terminator {terminator:?} was not rejected"
+        );
+        session.response().assert_code("521 5.5.2");
+    }
+
+    // Dot-stuffed lines and terminators split across packets are accepted
+    let mut scanner = TerminatorScanner::new();
+    for chunk in [
+        &b"From: john@doe.org\r\n\r\n..\r\n.hidden\r\n"[..],
+        &b"Bye\r"[..],
+        &b"\n."[..],
+        &b"\r"[..],
+    ] {
+        assert_eq!(scanner.scan(chunk), ScanResult::Continue);
+    }
+    assert_eq!(scanner.scan(b"\nQUIT\n.\n"), ScanResult::End);
+}
+
+const CONFIG_DEFERRED: &str = r#"
+[session.rcpt]
+relay = true
+
+[session.data]
+script = "'scan'"
+spool-threshold = 100
+
+[session.data.deferred-scan]
+enable = true
+quarantine = [{if = "rcpt_domain = 'foobar.org'", then = "'quarantine@foobar.org'"},
+              {else = false}]
+
+[sieve.trusted.scripts."scan"]
+contents = '''
+require ["reject", "editheader"];
+
+if header :contains "subject" "spam" {
+    reject "550 5.7.1 Spam detected.";
+    stop;
+}
+addheader "X-Scanned" "yes";
+'''
+"#;
+
+const CONFIG_DEFERRED_REMOTE: &str = r#"
+[session.ehlo]
+reject-non-fqdn = false
+
+[session.rcpt]
+relay = true
+"#;
+
+#[tokio::test]
+#[serial_test::serial]
+async fn data_deferred_scan() {
+    // Start test server
+    let mut remote =
+        TestServer::new("smtp_data_deferred_remote", CONFIG_DEFERRED_REMOTE, true).await;
+    let _rx = remote.start(&[ServerProtocol::Smtp]).await;
+    let mut local = TestServer::new("smtp_data_deferred_local", CONFIG_DEFERRED, true).await;
+
+    // Add mock DNS entries
+    let core = local.build_smtp();
+    for domain in ["foobar.org", "foobar.net"] {
+        core.core.smtp.resolvers.dns.mx_add(
+            domain,
+            vec![MX {
+                exchanges: vec![format!("mx.{domain}")],
+                preference: 10,
+            }],
+            Instant::now() + Duration::from_secs(10),
+        );
+        core.core.smtp.resolvers.dns.ipv4_add(
+            format!("mx.{domain}"),
+            vec!["127.0.0.1".parse().unwrap()],
+            Instant::now() + Duration::from_secs(10),
+        );
+    }
+
+    let mut session = local.new_session();
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+
+    // Messages below the spool threshold are still rejected at SMTP time
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            "Subject: spam\r\n\r\nHi",
+            "550 5.7.1",
+        )
+        .await;
+    local.qr.assert_no_events();
+
+    // Larger messages are queued first and scanned before delivery
+    let body = "Lorem ipsum dolor sit amet, consectetur adipiscing elit.\r\n".repeat(4);
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            &format!("Subject: hello\r\n\r\n{body}"),
+            "250",
+        )
+        .await;
+    let message = local.qr.expect_message().await;
+    assert_ne!(message.flags & MSG_SCAN_PENDING, 0);
+    local
+        .qr
+        .delivery_attempt(message.id)
+        .await
+        .try_deliver(core.clone())
+        .await;
+    remote
+        .qr
+        .expect_message()
+        .await
+        .read_lines(&remote.qr)
+        .await
+        .assert_contains("X-Scanned: yes")
+        .assert_contains("Subject: hello");
+    local.qr.read_event().await.assert_reload();
+    local.qr.assert_queue_is_empty().await;
+
+    // Bad verdicts are redirected to the quarantine address
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            &format!("Subject: spam\r\n\r\n{body}"),
+            "250",
+        )
+        .await;
+    local
+        .qr
+        .expect_message_then_deliver()
+        .await
+        .try_deliver(core.clone())
+        .await;
+    let message = remote.qr.expect_message().await;
+    assert_eq!(message.recipients.len(), 1);
+    assert_eq!(message.recipients[0].address, "quarantine@foobar.org");
+    message
+        .read_lines(&remote.qr)
+        .await
+        .assert_contains("X-Quarantine-Reason: Spam detected.")
+        .assert_not_contains("X-Scanned: yes");
+    local.qr.read_event().await.assert_reload();
+    local.qr.assert_queue_is_empty().await;
+
+    // Without a quarantine address the message is bounced
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.net"],
+            &format!("Subject: spam\r\n\r\n{body}"),
+            "250",
+        )
+        .await;
+    local
+        .qr
+        .expect_message_then_deliver()
+        .await
+        .try_deliver(core.clone())
+        .await;
+    let dsn = local.qr.expect_message().await;
+    assert_eq!(dsn.return_path, "");
+    assert_eq!(dsn.recipients[0].address, "john@doe.org");
+    dsn.read_lines(&local.qr)
+        .await
+        .assert_contains("Status: 5.7.1")
+        .assert_contains("Spam detected.");
+    local.qr.read_event().await.assert_reload();
+    remote.qr.assert_no_events();
+}
+
+const CONFIG_SPOOL: &str = r#"
+[storage]
+data = "sqlite"
+lookup = "sqlite"
+blob = "sqlite"
+fts = "sqlite"
+directory = "local"
+
+[store."sqlite"]
+type = "sqlite"
+path = "{TMP}/queue.db"
+
+[directory."local"]
+type = "memory"
+
+[session.rcpt]
+relay = true
+
+[session.data]
+spool-threshold = 100
+spool-path = "{TMP}/spool"
+spool-max-memory = 1000
+"#;
+
+#[tokio::test]
+async fn data_spool() {
+    let mut inner = Inner::default();
+    let tmp_dir = TempDir::new("smtp_data_spool_test", true);
+    let mut config = Config::new(tmp_dir.update_config(CONFIG_SPOOL)).unwrap();
+    let stores = Stores::parse_all(&mut config).await;
+    let core = Core::parse(&mut config, stores, Default::default()).await;
+    config.assert_no_errors();
+    let mut qr = inner.init_test_queue(&core);
+    let spool_path = tmp_dir.temp_dir.join("spool");
+    assert_eq!(core.smtp.session.data.spool_path, spool_path);
+
+    // Spool files are private and never reuse an existing file
+    let spool = SpoolFile::new(&spool_path, 1).await.unwrap();
+    let other_spool = SpoolFile::new(&spool_path, 1).await.unwrap();
+    assert_ne!(spool.path, other_spool.path);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        for (path, mode) in [(&spool_path, 0o700), (&spool.path, 0o600)] {
+            assert_eq!(
+                std::fs::metadata(path).unwrap().permissions().mode() & 0o777,
+                mode
+            );
+        }
+    }
+    let path = spool.path.clone();
+    drop(spool);
+    drop(other_spool);
+    assert!(!path.exists());
+
+    let core = build_smtp(core, inner);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+
+    // Large messages are spooled while being received and queued intact
+    let body = "Lorem ipsum dolor sit amet, consectetur adipiscing elit.\r\n".repeat(4);
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            &format!("Subject: spooled\r\n\r\n{body}"),
+            "250",
+        )
+        .await;
+    qr.expect_message()
+        .await
+        .read_lines(&qr)
+        .await
+        .assert_contains("Subject: spooled")
+        .assert_count("Lorem ipsum", 4);
+    assert_eq!(std::fs::read_dir(&spool_path).unwrap().count(), 0);
+    assert_eq!(core.inner.spool_memory.load(Ordering::Relaxed), 0);
+
+    // Spooled messages are deferred while others exhaust the memory limit
+    core.inner.spool_memory.store(1000, Ordering::Relaxed);
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            &format!("Subject: spooled\r\n\r\n{body}"),
+            "451 4.3.0",
+        )
+        .await;
+    qr.assert_no_events();
+    assert_eq!(std::fs::read_dir(&spool_path).unwrap().count(), 0);
+    assert_eq!(core.inner.spool_memory.load(Ordering::Relaxed), 1000);
+    core.inner.spool_memory.store(0, Ordering::Relaxed);
+}
+
+const CONFIG_JOURNAL: &str = r#"
+[storage]
+data = "sqlite"
+lookup = "sqlite"
+blob = "sqlite"
+fts = "sqlite"
+directory = "local"
+
+[store."sqlite"]
+type = "sqlite"
+path = "{TMP}/queue.db"
+
+[directory."local"]
+type = "memory"
+
+[session.rcpt]
+relay = true
+
+[session.data.journal]
+address = [{if = "rcpt_domain = 'foobar.org'", then = "'journal@archive.org'"},
+           {else = "''"}]
+"#;
+
+#[tokio::test]
+async fn data_journal() {
+    let mut inner = Inner::default();
+    let tmp_dir = TempDir::new("smtp_data_journal_test", true);
+    let mut config = Config::new(tmp_dir.update_config(CONFIG_JOURNAL)).unwrap();
+    let stores = Stores::parse_all(&mut config).await;
+    let core = Core::parse(&mut config, stores, Default::default()).await;
+    config.assert_no_errors();
+    let mut qr = inner.init_test_queue(&core);
+
+    let core = build_smtp(core, inner);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+
+    // A journal report is queued after the original message, listing only
+    // the recipients of journaled domains
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org", "jane@example.org"],
+            "Subject: hello\r\n\r\nHi",
+            "250",
+        )
+        .await;
+    qr.read_event().await.assert_reload();
+    qr.read_event().await.assert_reload();
+    let mut messages = qr.read_queued_messages().await;
+    assert_eq!(messages.len(), 2);
+    let journal = messages.remove(0);
+    let message = messages.remove(0);
+    assert!(message.id < journal.id);
+    assert_eq!(message.return_path, "john@doe.org");
+    assert_eq!(message.recipients.len(), 2);
+    assert_eq!(journal.return_path, "");
+    assert_eq!(journal.recipients[0].address, "journal@archive.org");
+    journal
+        .read_lines(&qr)
+        .await
+        .assert_contains("X-Journal-Report: Original")
+        .assert_contains("Sender: john@doe.org")
+        .assert_contains("Direction: inbound")
+        .assert_contains("Recipient: bill@foobar.org")
+        .assert_not_contains("Recipient: jane@example.org")
+        .assert_contains("Content-Type: message/rfc822")
+        .assert_contains("Subject: hello");
+
+    // Other domains are not journaled
+    session
+        .send_message(
+            "john@doe.org",
+            &["jane@example.org"],
+            "Subject: hello\r\n\r\nHi",
+            "250",
+        )
+        .await;
+    let message = qr.expect_message().await;
+    assert_eq!(message.recipients[0].address, "jane@example.org");
+    qr.assert_no_events();
+}
+
+const CONFIG_DEDUP: &str = r#"
+[storage]
+data = "sqlite"
+lookup = "sqlite"
+blob = "sqlite"
+fts = "sqlite"
+directory = "local"
+
+[store."sqlite"]
+type = "sqlite"
+path = "{TMP}/queue.db"
+
+[directory."local"]
+type = "memory"
+
+[session.rcpt]
+relay = true
+
+[queue.dedup]
+enable = true
+ttl = "1h"
+"#;
+
+#[tokio::test]
+async fn data_queue_dedup() {
+    let mut inner = Inner::default();
+    let tmp_dir = TempDir::new("smtp_data_dedup_test", true);
+    let mut config = Config::new(tmp_dir.update_config(CONFIG_DEDUP)).unwrap();
+    let stores = Stores::parse_all(&mut config).await;
+    let core = Core::parse(&mut config, stores, Default::default()).await;
+    config.assert_no_errors();
+    let mut qr = inner.init_test_queue(&core);
+
+    let core = build_smtp(core, inner);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+
+    // First submission is queued
+    let message = "Message-ID: <abc@doe.org>\r\nSubject: hello\r\n\r\nHi";
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], message, "250")
+        .await;
+    qr.expect_message().await;
+
+    // Identical submission is accepted but dropped
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], message, "250")
+        .await;
+    qr.assert_no_events();
+
+    // Same message to different recipients is queued
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org", "jane@foobar.org"],
+            message,
+            "250",
+        )
+        .await;
+    qr.expect_message().await;
+
+    // Different contents are queued
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            "Message-ID: <abc@doe.org>\r\nSubject: hello again\r\n\r\nHi",
+            "250",
+        )
+        .await;
+    qr.expect_message().await;
+    qr.assert_no_events();
+}
+
+const CONFIG_HEADER_LIMITS: &str = r#"
+[storage]
+data = "sqlite"
+lookup = "sqlite"
+blob = "sqlite"
+fts = "sqlite"
+directory = "local"
+
+[store."sqlite"]
+type = "sqlite"
+path = "{TMP}/queue.db"
+
+[directory."local"]
+type = "memory"
+
+[session.rcpt]
+relay = true
+
+[session.data.limits]
+max-header-size = 300
+max-headers = [{if = "remote_ip = '10.0.0.2'", then = 100},
+               {else = 5}]
+"#;
+
+#[tokio::test]
+async fn data_header_limits() {
+    let mut inner = Inner::default();
+    let tmp_dir = TempDir::new("smtp_data_header_limits_test", true);
+    let mut config = Config::new(tmp_dir.update_config(CONFIG_HEADER_LIMITS)).unwrap();
+    let stores = Stores::parse_all(&mut config).await;
+    let core = Core::parse(&mut config, stores, Default::default()).await;
+    config.assert_no_errors();
+    let mut qr = inner.init_test_queue(&core);
+
+    let core = build_smtp(core, inner);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+
+    let many_headers = (0..6).fold(String::new(), |mut headers, n| {
+        headers.push_str(&format!("X-Header-{n}: value\r\n"));
+        headers
+    }) + "\r\nHi";
+    let large_header = format!("Subject: {}\r\n\r\nHi", "a".repeat(400));
+    let large_body = format!("Subject: hello\r\n\r\n{}", "a".repeat(1000));
+
+    // Too many headers
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], &many_headers, "552")
+        .await;
+    qr.assert_no_events();
+
+    // Header section too large
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], &large_header, "552")
+        .await;
+    qr.assert_no_events();
+
+    // Large bodies are not affected
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], &large_body, "250")
+        .await;
+    qr.expect_message().await;
+
+    // Message and terminator received at once
+    session.mail_from("john@doe.org", "250").await;
+    session.rcpt_to("bill@foobar.org", "250").await;
+    session.ingest(b"DATA\r\n").await.unwrap();
+    session.response().assert_code("354");
+    session
+        .ingest(format!("{many_headers}\r\n.\r\n").as_bytes())
+        .await
+        .unwrap();
+    session.response().assert_code("552");
+    qr.assert_no_events();
+
+    // Limits are enforced on BDAT chunks
+    session.mail_from("john@doe.org", "250").await;
+    session.rcpt_to("bill@foobar.org", "250").await;
+    session
+        .ingest(format!("BDAT {} LAST\r\n{many_headers}", many_headers.len()).as_bytes())
+        .await
+        .unwrap();
+    session.response().assert_code("552");
+    qr.assert_no_events();
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            "Subject: hi\r\n\r\nHi",
+            "250",
+        )
+        .await;
+    qr.expect_message().await;
+
+    // Limits are configurable
+    session.data.remote_ip_str = "10.0.0.2".to_string();
+    session.eval_session_params().await;
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], &many_headers, "250")
+        .await;
+    qr.expect_message().await;
+}
+
+#[test]
+fn header_scanner() {
+    // Folded lines are part of the same header
+    let mut scanner = HeaderScanner::new();
+    for chunk in [
+        &b"Subject: hello\r\n"[..],
+        b" world\r\n",
+        b"To: john",
+        b"@doe.org\r\n",
+    ] {
+        assert_eq!(scanner.scan(chunk, 1000, 2), None);
+    }
+    assert_eq!(scanner.count(), 2);
+
+    // The body is not inspected
+    assert_eq!(scanner.scan(b"\r\nX-Fake: header\r\n", 1000, 2), None);
+    assert_eq!(scanner.count(), 2);
+    assert_eq!(scanner.limit(), None);
+
+    // Limits
+    let mut scanner = HeaderScanner::new();
+    assert_eq!(
+        scanner.scan(b"A: 1\r\nB: 2\r\nC: 3\r\n", 1000, 2),
+        Some(HeaderLimit::Count)
+    );
+    assert_eq!(scanner.limit(), Some(HeaderLimit::Count));
+    let mut scanner = HeaderScanner::new();
+    assert_eq!(
+        scanner.scan(b"Subject: hello world\r\n", 10, 2),
+        Some(HeaderLimit::Size)
+    );
+}
+
+const CONFIG_DUPLICATE: &str = r#"
+[storage]
+data = "sqlite"
+lookup = "sqlite"
+blob = "sqlite"
+fts = "sqlite"
+directory = "local"
+
+[store."sqlite"]
+type = "sqlite"
+path = "{TMP}/queue.db"
+
+[directory."local"]
+type = "memory"
+
+[session.rcpt]
+relay = true
+
+[session.data.duplicate]
+action = [{if = "remote_ip = '10.0.0.1'", then = "reject"},
+          {if = "remote_ip = '10.0.0.2'", then = "tag"},
+          {else = "accept"}]
+max-count = 3
+header = "X-Duplicate"
+"#;
+
+#[tokio::test]
+async fn data_duplicate_message_id() {
+    let mut inner = Inner::default();
+    let tmp_dir = TempDir::new("smtp_data_duplicate_test", true);
+    let mut config = Config::new(tmp_dir.update_config(CONFIG_DUPLICATE)).unwrap();
+    let stores = Stores::parse_all(&mut config).await;
+    let core = Core::parse(&mut config, stores, Default::default()).await;
+    config.assert_no_errors();
+    let mut qr = inner.init_test_queue(&core);
+
+    let core = build_smtp(core, inner);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+
+    // Duplicate Message-IDs are rejected for 10.0.0.1
+    let message = "Message-ID: <abc@doe.org>\r\nSubject: hello\r\n\r\nHi";
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], message, "250")
+        .await;
+    qr.expect_message().await;
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], message, "550 5.7.1")
+        .await;
+    qr.assert_no_events();
+
+    // Resends to other recipients are allowed up to the limit
+    session
+        .send_message("john@doe.org", &["mike@foobar.org"], message, "250")
+        .await;
+    qr.expect_message().await;
+    session
+        .send_message("john@doe.org", &["jane@foobar.org"], message, "550 5.7.1")
+        .await;
+    qr.assert_no_events();
+
+    // Duplicates are tagged using the configured header for 10.0.0.2
+    session.data.remote_ip_str = "10.0.0.2".to_string();
+    session.eval_session_params().await;
+    let message = "Message-ID: <def@doe.org>\r\nSubject: hello\r\n\r\nHi";
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], message, "250")
+        .await;
+    qr.expect_message()
+        .await
+        .read_lines(&qr)
+        .await
+        .assert_not_contains("X-Duplicate: yes");
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], message, "250")
+        .await;
+    qr.expect_message()
+        .await
+        .read_lines(&qr)
+        .await
+        .assert_contains("X-Duplicate: yes");
+    qr.assert_no_events();
+}
+
+const CONFIG_VERP: &str = r#"
+[storage]
+data = "sqlite"
+lookup = "sqlite"
+blob = "sqlite"
+fts = "sqlite"
+directory = "local"
+
+[store."sqlite"]
+type = "sqlite"
+path = "{TMP}/queue.db"
+
+[directory."local"]
+type = "memory"
+
+[[directory."local".principals]]
+name = "bounce"
+secret = "secret"
+email = ["bounce@foobar.org", "list@foobar.org"]
+
+[session.rcpt]
+directory = "'local'"
+relay = true
+
+[session.data]
+script = "'verp'"
+
+[sieve.trusted.scripts."verp"]
+contents = '''
+require ["reject", "vnd.stalwart.expressions"];
+
+if eval "is_intersect(env.verp.recipients, ['jane@example.com'])" {
+    reject "550 5.1.1 Bounce for jane@example.com.";
+    stop;
+}
+if eval "count(env.verp.recipients) > 0" {
+    reject "550 5.1.1 Unexpected bounce recipient.";
+}
+'''
+"#;
+
+#[tokio::test]
+async fn data_verp_bounce() {
+    let mut inner = Inner::default();
+    let tmp_dir = TempDir::new("smtp_data_verp_test", true);
+    let mut config = Config::new(tmp_dir.update_config(CONFIG_VERP)).unwrap();
+    let stores = Stores::parse_all(&mut config).await;
+    let core = Core::parse(&mut config, stores, Default::default()).await;
+    config.assert_no_errors();
+    let mut qr = inner.init_test_queue(&core);
+
+    let core = build_smtp(core, inner);
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+
+    // Bounces to VERP encoded return paths of local domains are attributed
+    let message = "Subject: Undelivered Mail\r\n\r\nBounce";
+    for rcpt in [
+        "bounce+jane=example.com@foobar.org",
+        "list+bounces+jane=example.com@foobar.org",
+    ] {
+        session
+            .send_message("<>", &[rcpt], message, "550 5.1.1 Bounce for")
+            .await;
+        qr.assert_no_events();
+    }
+
+    // Return paths of remote domains are not decoded
+    session
+        .send_message(
+            "<>",
+            &["bounce+jane=example.com@remote.org"],
+            message,
+            "250",
+        )
+        .await;
+    qr.expect_message().await;
+
+    // Only bounces are attributed
+    session
+        .send_message(
+            "john@doe.org",
+            &["bounce+jane=example.com@foobar.org"],
+            message,
+            "250",
+        )
+        .await;
+    qr.expect_message().await;
+    qr.assert_no_events();
+}
+
+#[test]
+fn verp_address_encoding() {
+    for (return_path, rcpt, expected) in [
+        (
+            "bounce@example.org",
+            "jane@example.com",
+            Some("bounce+jane=example.com@example.org"),
+        ),
+        (
+            "list+bounces@example.org",
+            "jane@example.com",
+            Some("list+bounces+jane=example.com@example.org"),
+        ),
+        ("@example.org", "jane@example.com", None),
+        ("bounce@example.org", "example.com", None),
+    ] {
+        let encoded = verp_encode(return_path, rcpt);
+        assert_eq!(encoded.as_deref(), expected, "{return_path} {rcpt}");
+        if let Some(encoded) = encoded {
+            assert_eq!(verp_decode(&encoded).as_deref(), Some(rcpt), "{encoded}");
+        }
+    }
+
+    for (address, expected) in [
+        (
+            "bounce+jane=example.com@example.org",
+            Some("jane@example.com"),
+        ),
+        (
+            "list+bounces+jane=example.com@example.org",
+            Some("jane@example.com"),
+        ),
+        ("bounce+jane=localhost@example.org", None),
+        ("+jane=example.com@example.org", None),
+        ("bounce+=example.com@example.org", None),
+        ("bounce@example.org", None),
+        ("jane=example.com@example.org", None),
+    ] {
+        assert_eq!(verp_decode(address).as_deref(), expected, "{address}");
+    }
+}
@@ -96,7 +96,13 @@ mail-from = [{if = "remote_ip = '10.0.0.2'", then = 'strict'},
              { else = 'relaxed' }]
 
 [auth.dmarc]
-verify = "strict"
+verify = [{if = "remote_ip = '10.0.0.3'", then = 'relaxed'},
+          { else = 'strict' }]
+enforce = "remote_ip = '10.0.0.3'"
+allow-list = "dmarc-allow"
+
+[lookup]
+"dmarc-allow" = {"dmarc-allow.org"}
 
 [auth.arc]
 verify = "strict"
@@ -324,4 +330,162 @@ async fn dmarc() {
         .assert_contains("spf=pass")
         .assert_contains("dmarc=pass")
         .assert_contains("Received-SPF: pass");
+
+    // Enforce p=reject policies when verifying in relaxed mode
+    core.core.smtp.resolvers.dns.txt_add(
+        "_dmarc.dmarc-allow.org",
+        Dmarc::parse(b"v=DMARC1; p=reject;").unwrap(),
+        Instant::now() + Duration::from_secs(5),
+    );
+    session.data.remote_ip_str = "10.0.0.3".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session
+        .send_message(
+            "joe@test.net",
+            &["jdoe@example.com"],
+            "From: joe@example.com\r\nSubject: test\r\n\r\ntest\r\n",
+            "550 5.7.1",
+        )
+        .await;
+
+    // Allow-listed domains are not rejected
+    session
+        .send_message(
+            "joe@test.net",
+            &["jdoe@example.com"],
+            "From: joe@dmarc-allow.org\r\nSubject: test\r\n\r\ntest\r\n",
+            "250",
+        )
+        .await;
+}
+
+const CONFIG_REQUIRE_PASS: &str = r#"
+[storage]
+data = "sqlite"
+lookup = "sqlite"
+blob = "sqlite"
+fts = "sqlite"
+
+[store."sqlite"]
+type = "sqlite"
+path = "{TMP}/queue.db"
+
+[directory."local"]
+type = "memory"
+
+[[directory."local".principals]]
+name = "john"
+description = "John Doe"
+secret = "secret"
+email = ["jdoe@example.com"]
+
+[session.rcpt]
+directory = "'local'"
+
+[auth.spf.verify]
+ehlo = "relaxed"
+mail-from = "relaxed"
+
+[auth.dkim]
+verify = "relaxed"
+
+[auth.dmarc]
+verify = "relaxed"
+
+[auth.require-pass]
+enable = "remote_ip != '10.0.0.9'"
+allow-list = "spf-dkim-allow"
+
+[lookup]
+"spf-dkim-allow" = {"allowed.org"}
+"#;
+
+#[tokio::test]
+async fn dmarc_require_pass() {
+    let mut inner = Inner::default();
+    let tmp_dir = TempDir::new("smtp_require_pass_test", true);
+    let mut config = Config::new(tmp_dir.update_config(CONFIG_REQUIRE_PASS)).unwrap();
+    let stores = Stores::parse_all(&mut config).await;
+    let core = Core::parse(&mut config, stores, Default::default()).await;
+    let mut qr = inner.init_test_queue(&core);
+
+    // Add SPF records
+    for (domain, record) in [
+        ("foobar.org", "v=spf1 -all"),
+        ("allowed.org", "v=spf1 -all"),
+        ("example.net", "v=spf1 ip4:10.0.0.1 -all"),
+    ] {
+        core.smtp.resolvers.dns.txt_add(
+            domain,
+            Spf::parse(record.as_bytes()).unwrap(),
+            Instant::now() + Duration::from_secs(5),
+        );
+    }
+
+    let core = build_smtp(core, inner);
+    let mut session = Session::test(core);
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+
+    // Messages failing all authentication mechanisms are rejected
+    session
+        .send_message(
+            "bill@foobar.org",
+            &["jdoe@example.com"],
+            "From: bill@foobar.org\r\nSubject: test\r\n\r\ntest\r\n",
+            "550 5.7.26",
+        )
+        .await;
+    qr.assert_no_events();
+
+    // Allow-listed domains are accepted
+    session
+        .send_message(
+            "bill@allowed.org",
+            &["jdoe@example.com"],
+            "From: bill@allowed.org\r\nSubject: test\r\n\r\ntest\r\n",
+            "250",
+        )
+        .await;
+    qr.expect_message().await;
+
+    // A single passing mechanism is enough
+    session
+        .send_message(
+            "bill@example.net",
+            &["jdoe@example.com"],
+            "From: bill@example.net\r\nSubject: test\r\n\r\ntest\r\n",
+            "250",
+        )
+        .await;
+    qr.expect_message().await;
+
+    // Authenticated sessions are exempt
+    session.data.authenticated_as = "bill@foobar.org".to_string();
+    session
+        .send_message(
+            "bill@foobar.org",
+            &["jdoe@example.com"],
+            "From: bill@foobar.org\r\nSubject: test\r\n\r\ntest\r\n",
+            "250",
+        )
+        .await;
+    qr.expect_message().await;
+
+    // Exempted networks are not checked
+    session.data.authenticated_as = String::new();
+    session.data.remote_ip_str = "10.0.0.9".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session
+        .send_message(
+            "bill@foobar.org",
+            &["jdoe@example.com"],
+            "From: bill@foobar.org\r\nSubject: test\r\n\r\ntest\r\n",
+            "250",
+        )
+        .await;
+    qr.expect_message().await;
 }
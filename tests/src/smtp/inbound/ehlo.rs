@@ -21,9 +21,12 @@
  * for more details.
 */
 
-use std::time::{Duration, Instant};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use common::Core;
+use common::{listener::ServerInstance, Core};
 use mail_auth::{common::parse::TxtRecordParser, spf::Spf, SpfResult};
 
 use smtp::core::{Inner, Session};
@@ -31,7 +34,7 @@ use utils::config::Config;
 
 use crate::smtp::{
     build_smtp,
-    session::{TestSession, VerifyResponse},
+    session::{test_server_instance, TestSession, VerifyResponse},
 };
 
 const CONFIG: &str = r#"
@@ -114,3 +117,247 @@ async fn ehlo() {
         .assert_not_contains("FUTURERELEASE")
         .assert_not_contains("STARTTLS");
 }
+
+const CONFIG_SIZE: &str = r#"
+[session.data.limits]
+size = [{if = "!is_empty(authenticated_as)", then = 4096},
+        {if = "listener = 'smtp'", then = 1024},
+        {else = 2048}]
+
+[session.auth]
+must-match-sender = false
+"#;
+
+#[tokio::test]
+async fn ehlo_size_per_listener() {
+    let mut config = Config::new(CONFIG_SIZE).unwrap();
+    let core = Core::parse(&mut config, Default::default(), Default::default()).await;
+    let core = build_smtp(core, Inner::default());
+
+    // MX listener
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session
+        .cmd("EHLO mx1.foobar.org", "250")
+        .await
+        .assert_contains("SIZE 1024");
+    session
+        .cmd("MAIL FROM:<john@foobar.org> SIZE=2000", "552 5.3.4")
+        .await;
+
+    // Submission listener
+    let mut session = Session::test(core);
+    session.instance = Arc::new(ServerInstance {
+        id: "submission".to_string(),
+        ..test_server_instance()
+    });
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session
+        .cmd("EHLO mx1.foobar.org", "250")
+        .await
+        .assert_contains("SIZE 2048");
+    session
+        .cmd("MAIL FROM:<john@foobar.org> SIZE=2000", "250")
+        .await;
+    session.rset().await;
+
+    // Authenticated senders
+    session.data.authenticated_as = "john".to_string();
+    session
+        .cmd("EHLO mx1.foobar.org", "250")
+        .await
+        .assert_contains("SIZE 4096");
+    session
+        .cmd("MAIL FROM:<john@foobar.org> SIZE=3000", "250")
+        .await;
+}
+
+const CONFIG_SENDER_SIZE: &str = r#"
+[session.data.limits]
+size = 4096
+sender-size = [{if = "sender_domain = 'foobar.org'", then = 1024},
+               {if = "authenticated_as = 'jane'", then = 2048},
+               {if = "listener = 'submission'", then = 8192},
+               {else = 0}]
+
+[session.auth]
+must-match-sender = false
+"#;
+
+#[tokio::test]
+async fn ehlo_size_per_sender() {
+    let mut config = Config::new(CONFIG_SENDER_SIZE).unwrap();
+    let core = Core::parse(&mut config, Default::default(), Default::default()).await;
+    let core = build_smtp(core, Inner::default());
+
+    // Unrestricted senders are limited by the global size
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session
+        .cmd("EHLO mx1.foobar.org", "250")
+        .await
+        .assert_contains("SIZE 4096");
+    session
+        .cmd("MAIL FROM:<john@example.org> SIZE=3000", "250")
+        .await;
+    session.rset().await;
+
+    // Sender domain limits
+    session
+        .cmd("MAIL FROM:<john@foobar.org> SIZE=2000", "552 5.3.4")
+        .await;
+    session
+        .cmd("MAIL FROM:<john@foobar.org> SIZE=1000", "250")
+        .await;
+    assert_eq!(session.params.max_message_size, 1024);
+    session.rset().await;
+
+    // Authenticated sender limits are advertised
+    session.data.authenticated_as = "jane".to_string();
+    session
+        .cmd("EHLO mx1.foobar.org", "250")
+        .await
+        .assert_contains("SIZE 2048");
+    session
+        .cmd("MAIL FROM:<jane@example.org> SIZE=3000", "552 5.3.4")
+        .await;
+
+    // Sender limits never exceed the global size
+    let mut session = Session::test(core);
+    session.instance = Arc::new(ServerInstance {
+        id: "submission".to_string(),
+        ..test_server_instance()
+    });
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session
+        .cmd("EHLO mx1.foobar.org", "250")
+        .await
+        .assert_contains("SIZE 4096");
+}
+
+const CONFIG_IPREV: &str = r#"
+[session.ehlo]
+require-iprev = [{if = "listener = 'smtp' && remote_ip != '10.0.0.3'", then = true},
+                 {else = false}]
+"#;
+
+#[tokio::test]
+async fn ehlo_require_iprev() {
+    let mut config = Config::new(CONFIG_IPREV).unwrap();
+    let core = Core::parse(&mut config, Default::default(), Default::default()).await;
+    core.smtp.resolvers.dns.ptr_add(
+        "10.0.0.1".parse().unwrap(),
+        vec!["mx1.foobar.org.".to_string()],
+        Instant::now() + Duration::from_secs(5),
+    );
+    core.smtp.resolvers.dns.ipv4_add(
+        "mx1.foobar.org.",
+        vec!["10.0.0.1".parse().unwrap()],
+        Instant::now() + Duration::from_secs(5),
+    );
+    core.smtp.resolvers.dns.ptr_add(
+        "10.0.0.2".parse().unwrap(),
+        vec!["mx2.foobar.org.".to_string()],
+        Instant::now() + Duration::from_secs(5),
+    );
+    let core = build_smtp(core, Inner::default());
+
+    // Forward-confirmed reverse DNS passes
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session.cmd("EHLO mx1.foobar.org", "250").await;
+
+    // PTR record without a matching A record is rejected
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.2".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session.cmd("EHLO mx2.foobar.org", "550 5.7.25").await;
+
+    // Submission listener is exempt
+    let mut session = Session::test(core.clone());
+    session.instance = Arc::new(ServerInstance {
+        id: "submission".to_string(),
+        ..test_server_instance()
+    });
+    session.data.remote_ip_str = "10.0.0.2".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session.cmd("EHLO mx2.foobar.org", "250").await;
+
+    // Trusted networks are exempt
+    let mut session = Session::test(core);
+    session.data.remote_ip_str = "10.0.0.3".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session.cmd("EHLO mx3.foobar.org", "250").await;
+}
+
+const CONFIG_GREETING: &str = r#"
+[session.connect]
+greeting = [{if = "iprev_result = 'pass'", then = "'mx.foobar.org ESMTP ready'"},
+            {if = "listener = 'submission'", then = "'Submission ready'"},
+            {else = "'Service ready'"}]
+"#;
+
+#[tokio::test]
+async fn greeting_iprev() {
+    let mut config = Config::new(CONFIG_GREETING).unwrap();
+    let core = Core::parse(&mut config, Default::default(), Default::default()).await;
+    core.smtp.resolvers.dns.ptr_add(
+        "10.0.0.1".parse().unwrap(),
+        vec!["mx1.foobar.org.".to_string()],
+        Instant::now() + Duration::from_secs(5),
+    );
+    core.smtp.resolvers.dns.ipv4_add(
+        "mx1.foobar.org.",
+        vec!["10.0.0.1".parse().unwrap()],
+        Instant::now() + Duration::from_secs(5),
+    );
+    core.smtp.resolvers.dns.ptr_add(
+        "10.0.0.2".parse().unwrap(),
+        vec!["mx2.foobar.org.".to_string()],
+        Instant::now() + Duration::from_secs(5),
+    );
+    let core = build_smtp(core, Inner::default());
+
+    // Forward-confirmed reverse DNS
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    assert!(session.init_conn().await);
+    session
+        .response()
+        .assert_contains("220 mx.foobar.org ESMTP ready");
+
+    // The cached lookup is reused by later stages
+    assert!(session.data.iprev.is_some());
+
+    // Failed reverse DNS
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.2".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    assert!(session.init_conn().await);
+    session.response().assert_contains("220 Service ready");
+
+    // Other conditions are still evaluated
+    let mut session = Session::test(core);
+    session.instance = Arc::new(ServerInstance {
+        id: "submission".to_string(),
+        ..test_server_instance()
+    });
+    session.data.remote_ip_str = "10.0.0.2".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    assert!(session.init_conn().await);
+    session.response().assert_contains("220 Submission ready");
+}
@@ -0,0 +1,304 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::{Duration, Instant};
+
+use common::Core;
+use smtp::core::{Inner, Session};
+use store::Stores;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::watch,
+};
+use utils::config::Config;
+
+use crate::smtp::{
+    build_smtp,
+    inbound::TestMessage,
+    session::{TestSession, VerifyResponse},
+    TempDir, TestSMTP,
+};
+
+const CONFIG: &str = r#"
+[storage]
+data = "sqlite"
+lookup = "sqlite"
+blob = "sqlite"
+fts = "sqlite"
+
+[store."sqlite"]
+type = "sqlite"
+path = "{TMP}/queue.db"
+
+[session.rcpt]
+relay = true
+
+[session.data.icap."reject"]
+hostname = "127.0.0.1"
+port = 9333
+service = "avscan"
+enable = [{if = "remote_ip = '10.0.0.1'", then = true},
+          {else = false}]
+
+[session.data.icap."quarantine"]
+hostname = "127.0.0.1"
+port = 9333
+service = "avscan"
+enable = [{if = "remote_ip = '10.0.0.2'", then = true},
+          {else = false}]
+options.infected = "quarantine"
+
+[session.data.icap."unreachable"]
+hostname = "127.0.0.1"
+port = 9334
+enable = [{if = "remote_ip = '10.0.0.3'", then = true},
+          {else = false}]
+timeout.connect = "1s"
+
+[session.data.icap."breaker"]
+hostname = "127.0.0.1"
+port = 9334
+enable = [{if = "remote_ip = '10.0.0.4'", then = true},
+          {else = false}]
+timeout.connect = "1s"
+options.circuit-breaker.failures = 2
+options.circuit-breaker.cooldown = "1h"
+options.circuit-breaker.action = "accept"
+"#;
+
+const CLEAN_MESSAGE: &str = "From: john@doe.org\r\nSubject: Hello\r\n\r\nAre you hungry yet?";
+const INFECTED_MESSAGE: &str =
+    "From: john@doe.org\r\nSubject: Invoice\r\n\r\nX5O!P%@AP[4\\PZX54(P^)7CC)7}$EICAR";
+const MODIFIED_MESSAGE: &str =
+    "From: john@doe.org\r\nSubject: Macro\r\n\r\nPlease enable macros: <macro>";
+
+#[tokio::test]
+async fn icap_session() {
+    // Configure tests
+    let tmp_dir = TempDir::new("smtp_icap_test", true);
+    let mut config = Config::new(tmp_dir.update_config(CONFIG)).unwrap();
+    let stores = Stores::parse_all(&mut config).await;
+    let core = Core::parse(&mut config, stores, Default::default()).await;
+    let _rx = spawn_mock_icap_server();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let mut inner = Inner::default();
+    let mut qr = inner.init_test_queue(&core);
+
+    // Build session
+    let mut session = Session::test(build_smtp(core, inner));
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+
+    // Infected messages are rejected
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            INFECTED_MESSAGE,
+            "550 5.7.1",
+        )
+        .await;
+    qr.assert_no_events();
+
+    // Clean messages are accepted unmodified
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            CLEAN_MESSAGE,
+            "250 2.0.0",
+        )
+        .await;
+    qr.expect_message()
+        .await
+        .read_lines(&qr)
+        .await
+        .assert_contains("Are you hungry yet?")
+        .assert_not_contains("X-Quarantine");
+
+    // Modifications returned by the server are applied
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            MODIFIED_MESSAGE,
+            "250 2.0.0",
+        )
+        .await;
+    qr.expect_message()
+        .await
+        .read_lines(&qr)
+        .await
+        .assert_contains("Please enable macros: [removed]")
+        .assert_not_contains("<macro>");
+
+    // Infected messages are quarantined
+    session.data.remote_ip_str = "10.0.0.2".to_string();
+    session.eval_session_params().await;
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            INFECTED_MESSAGE,
+            "250 2.0.0",
+        )
+        .await;
+    qr.expect_message()
+        .await
+        .read_lines(&qr)
+        .await
+        .assert_contains("X-Quarantine: Eicar-Test-Signature");
+
+    // Unreachable servers tempfail the message
+    session.data.remote_ip_str = "10.0.0.3".to_string();
+    session.eval_session_params().await;
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            CLEAN_MESSAGE,
+            "451 4.3.5",
+        )
+        .await;
+    qr.assert_no_events();
+
+    // Messages are accepted without scanning once the circuit breaker opens
+    session.data.remote_ip_str = "10.0.0.4".to_string();
+    session.eval_session_params().await;
+    for _ in 0..2 {
+        session
+            .send_message(
+                "john@doe.org",
+                &["bill@foobar.org"],
+                CLEAN_MESSAGE,
+                "451 4.3.5",
+            )
+            .await;
+    }
+    qr.assert_no_events();
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            CLEAN_MESSAGE,
+            "250 2.0.0",
+        )
+        .await;
+    qr.expect_message()
+        .await
+        .read_lines(&qr)
+        .await
+        .assert_contains("Are you hungry yet?");
+
+    // Breaker states are cleared on reload
+    let states = session.core.breaker_states();
+    assert_eq!(states.len(), 1);
+    assert_eq!(states[0].1.status(Instant::now()), 1);
+    session.core.reset_breakers();
+    assert!(session.core.breaker_states().is_empty());
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            CLEAN_MESSAGE,
+            "451 4.3.5",
+        )
+        .await;
+    qr.assert_no_events();
+}
+
+pub fn spawn_mock_icap_server() -> watch::Sender<bool> {
+    let (tx, mut rx) = watch::channel(true);
+
+    tokio::spawn(async move {
+        let listener = TcpListener::bind("127.0.0.1:9333")
+            .await
+            .unwrap_or_else(|e| {
+                panic!("Failed to bind mock ICAP server to 127.0.0.1:9333: {e}");
+            });
+        loop {
+            tokio::select! {
+                stream = listener.accept() => {
+                    match stream {
+                        Ok((stream, _)) => {
+                            tokio::spawn(accept_icap(stream));
+                        }
+                        Err(err) => {
+                            panic!("Something went wrong: {err}" );
+                        }
+                    }
+                },
+                _ = rx.changed() => {
+                    break;
+                }
+            };
+        }
+    });
+
+    tx
+}
+
+async fn accept_icap(mut stream: TcpStream) {
+    let mut request = Vec::new();
+    let mut buf = vec![0u8; 1024];
+    while !request.ends_with(b"\r\n0\r\n\r\n") {
+        match stream.read(&mut buf).await {
+            Ok(0) | Err(_) => return,
+            Ok(br) => request.extend_from_slice(&buf[..br]),
+        }
+    }
+    let request = String::from_utf8(request).unwrap();
+    assert!(
+        request.starts_with("RESPMOD icap://127.0.0.1:9333/avscan ICAP/1.0\r\n"),
+        "{request}"
+    );
+
+    let response = if request.contains("EICAR") {
+        concat!(
+            "ICAP/1.0 200 OK\r\n",
+            "X-Infection-Found: Type=0; Resolution=2; Threat=Eicar-Test-Signature;\r\n",
+            "Encapsulated: null-body=0\r\n\r\n"
+        )
+        .to_string()
+    } else if request.contains("<macro>") {
+        let body = MODIFIED_MESSAGE.replace("<macro>", "[removed]");
+        let res_hdr = "HTTP/1.1 200 OK\r\nContent-Type: message/rfc822\r\n\r\n";
+        format!(
+            concat!(
+                "ICAP/1.0 200 OK\r\n",
+                "Encapsulated: res-hdr=0, res-body={}\r\n\r\n",
+                "{}{:x}\r\n{}\r\n0\r\n\r\n"
+            ),
+            res_hdr.len(),
+            res_hdr,
+            body.len(),
+            body
+        )
+    } else {
+        "ICAP/1.0 204 No Content\r\n\r\n".to_string()
+    };
+
+    stream.write_all(response.as_bytes()).await.unwrap();
+}
@@ -26,7 +26,7 @@ use std::{
     time::{Duration, Instant, SystemTime},
 };
 
-use common::Core;
+use common::{listener::ServerInstance, Core};
 use mail_auth::{common::parse::TxtRecordParser, spf::Spf, IprevResult, SpfResult};
 use smtp_proto::{MAIL_BY_NOTIFY, MAIL_BY_RETURN, MAIL_REQUIRETLS};
 
@@ -36,7 +36,7 @@ use utils::config::Config;
 
 use crate::smtp::{
     build_smtp,
-    session::{TestSession, VerifyResponse},
+    session::{test_server_instance, TestSession, VerifyResponse},
     TempDir,
 };
 
@@ -328,3 +328,113 @@ async fn mail() {
     session.response().assert_code("501 5.5.4");
     session.rset().await;
 }
+
+const CONFIG_STARTTLS: &str = r#"
+[auth.spf.verify]
+ehlo = 'disable'
+mail-from = 'disable'
+
+[auth.iprev]
+verify = 'disable'
+
+[session.mail]
+require-starttls = [{if = "listener = 'smtp' && remote_ip != '10.0.0.3'", then = true},
+                    {else = false}]
+"#;
+
+#[tokio::test]
+async fn mail_require_starttls() {
+    let mut config = Config::new(CONFIG_STARTTLS).unwrap();
+    let core = build_smtp(
+        Core::parse(&mut config, Default::default(), Default::default()).await,
+        Inner::default(),
+    );
+
+    // Plaintext MAIL FROM is rejected while STARTTLS is still advertised
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session
+        .ehlo("mx.foobar.org")
+        .await
+        .assert_contains("STARTTLS");
+    session.mail_from("john@foobar.org", "530 5.7.0").await;
+
+    // MAIL FROM is accepted once TLS is established
+    session.stream.tls = true;
+    session
+        .ehlo("mx.foobar.org")
+        .await
+        .assert_not_contains("STARTTLS");
+    session.mail_from("john@foobar.org", "250").await;
+
+    // Exempt networks may relay in plaintext
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip_str = "10.0.0.3".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+    session.mail_from("john@foobar.org", "250").await;
+
+    // Other listeners are not affected
+    let mut session = Session::test(core);
+    session.instance = Arc::new(ServerInstance {
+        id: "submission".to_string(),
+        ..test_server_instance()
+    });
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+    session.mail_from("john@foobar.org", "250").await;
+}
+
+const CONFIG_SCHEDULE: &str = r#"
+[session.mail]
+schedule = [{if = "authenticated_as = 'batch@foobar.org'", then = "'mon-fri 01:00-05:00 +02:00'"},
+            {if = "sender_domain = 'broken.org'", then = "'someday 01:00-05:00'"},
+            {else = false}]
+"#;
+
+#[tokio::test]
+async fn mail_schedule() {
+    // 2024-01-01 was a Monday
+    const MONDAY: u64 = 1704067200;
+    const HOUR: u64 = 3600;
+
+    let mut config = Config::new(CONFIG_SCHEDULE).unwrap();
+    let core = Core::parse(&mut config, Default::default(), Default::default()).await;
+    let core = build_smtp(core, Inner::default());
+
+    // Invalid schedules are reported when parsing the configuration
+    assert!(
+        config.errors.contains_key("session.mail.schedule"),
+        "{:?}",
+        config.errors
+    );
+
+    // Senders without a schedule are not affected
+    let mut session = Session::test(core);
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+    session.data.clock = (MONDAY + 8 * HOUR).into();
+    session.mail_from("john@foobar.org", "250").await;
+    session.rset().await;
+
+    // Submissions are only accepted within the window, in the configured timezone
+    session.data.authenticated_as = "batch@foobar.org".to_string();
+    session.data.clock = MONDAY.into();
+    session.mail_from("batch@foobar.org", "250").await;
+    session.rset().await;
+    session.data.clock = (MONDAY + 6 * HOUR).into();
+    session.mail_from("batch@foobar.org", "550 5.7.1").await;
+    session.data.clock = (MONDAY + 5 * 24 * HOUR).into();
+    session.mail_from("batch@foobar.org", "550 5.7.1").await;
+
+    // Invalid schedules are reported as a temporary failure
+    session.data.authenticated_as.clear();
+    session.mail_from("john@broken.org", "451 4.3.5").await;
+}
@@ -419,6 +419,7 @@ async fn milter_client_test() {
             protocol_version: MilterVersion::V6,
             flags_actions: None,
             flags_protocol: None,
+            circuit_breaker: None,
         },
         tracing::span!(tracing::Level::TRACE, "hi"),
     )
@@ -24,7 +24,7 @@
 use std::time::Duration;
 
 use store::{
-    write::{key::DeserializeBigEndian, Bincode, QueueClass, QueueEvent, ReportEvent, ValueClass},
+    write::{key::DeserializeBigEndian, QueueClass, QueueEvent, ReportEvent, ValueClass},
     Deserialize, IterateParams, ValueKey, U64_LEN,
 };
 use tokio::sync::mpsc::error::TryRecvError;
@@ -43,6 +43,7 @@ pub mod basic;
 pub mod data;
 pub mod dmarc;
 pub mod ehlo;
+pub mod icap;
 pub mod limits;
 pub mod mail;
 pub mod milter;
@@ -199,9 +200,9 @@ impl QueueReceiver {
             .iterate(
                 IterateParams::new(from_key, to_key).descending(),
                 |key, value| {
-                    let value = Bincode::<Message>::deserialize(value)?;
-                    assert_eq!(key.deserialize_be_u64(1)?, value.inner.id);
-                    messages.push(value.inner);
+                    let value = <Message as Deserialize>::deserialize(value)?;
+                    assert_eq!(key.deserialize_be_u64(1)?, value.id);
+                    messages.push(value);
                     Ok(true)
                 },
             )
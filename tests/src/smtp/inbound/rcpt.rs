@@ -34,7 +34,7 @@ use smtp::core::{Inner, Session, State};
 use crate::smtp::{
     build_smtp,
     session::{TestSession, VerifyResponse},
-    TempDir,
+    TempDir, TestSMTP,
 };
 
 const CONFIG: &str = r#"
@@ -81,6 +81,8 @@ max-recipients = [{if = "remote_ip = '10.0.0.1'", then = 3},
                 {else = 5}]
 relay = [{if = "remote_ip = '10.0.0.1'", then = false},
          {else = true}]
+generate-orcpt = [{if = "remote_ip = '10.0.0.2'", then = true},
+                  {else = false}]
 
 [session.rcpt.errors]
 total = [{if = "remote_ip = '10.0.0.1'", then = 3},
@@ -88,6 +90,15 @@ total = [{if = "remote_ip = '10.0.0.1'", then = 3},
 wait = [{if = "remote_ip = '10.0.0.1'", then = '5ms'},
         {else = '1s'}]
 
+[session.rcpt.greylist]
+enable = [{if = "remote_ip = '10.0.0.3'", then = true},
+          {else = false}]
+delay = "1s"
+
+[session.rcpt.rate-limit]
+rate = [{if = "remote_ip = '10.0.0.4' && rcpt_domain = 'foobar.org'", then = "[2, 1d]"},
+        {else = false}]
+
 [session.extensions]
 dsn = [{if = "remote_ip = '10.0.0.1'", then = false},
        {else = true}]
@@ -114,9 +125,11 @@ async fn rcpt() {
     let mut config = Config::new(tmp_dir.update_config(CONFIG)).unwrap();
     let stores = Stores::parse_all(&mut config).await;
     let core = Core::parse(&mut config, stores, Default::default()).await;
+    let mut inner = Inner::default();
+    let mut qr = inner.init_test_queue(&core);
 
     // RCPT without MAIL FROM
-    let mut session = Session::test(build_smtp(core, Inner::default()));
+    let mut session = Session::test(build_smtp(core, inner));
     session.data.remote_ip_str = "10.0.0.1".to_string();
     session.eval_session_params().await;
     session.ehlo("mx1.foobar.org").await;
@@ -185,4 +198,62 @@ async fn rcpt() {
     let rcpt = session.data.rcpt_to.last().unwrap();
     assert!((rcpt.flags & (RCPT_NOTIFY_DELAY | RCPT_NOTIFY_SUCCESS | RCPT_NOTIFY_FAILURE)) != 0);
     assert_eq!(rcpt.dsn_info.as_ref().unwrap(), "Jane.Doe@Foobar.org");
+
+    // ORCPT is generated when missing
+    assert_eq!(
+        session
+            .data
+            .rcpt_to
+            .first()
+            .unwrap()
+            .dsn_info
+            .as_ref()
+            .unwrap(),
+        "external@domain.com"
+    );
+
+    // Greylisting is enabled for 10.0.0.3
+    session.data.remote_ip_str = "10.0.0.3".to_string();
+    session.eval_session_params().await;
+    session.rset().await;
+    session.mail_from("john@example.net", "250").await;
+    session.rcpt_to("jane@foobar.org", "451 4.7.1").await;
+    session.rcpt_to("jane@foobar.org", "451 4.7.1").await;
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    session.rcpt_to("jane@foobar.org", "250").await;
+
+    // Sender is whitelisted after passing greylisting
+    session.rcpt_to("bill@foobar.org", "250").await;
+
+    // ORCPT is not generated by default
+    assert!(session.data.rcpt_to.last().unwrap().dsn_info.is_none());
+
+    // Per-recipient rate limit is enabled for 10.0.0.4, recipients of
+    // messages that are not accepted are not counted
+    session.data.remote_ip_str = "10.0.0.4".to_string();
+    session.eval_session_params().await;
+    for _ in 0..3 {
+        session.rset().await;
+        session.mail_from("john@example.net", "250").await;
+        session.rcpt_to("mike@foobar.org", "250").await;
+    }
+    for _ in 0..2 {
+        session.rset().await;
+        session
+            .send_message(
+                "john@example.net",
+                &["mike@foobar.org"],
+                "test:no_dkim",
+                "250",
+            )
+            .await;
+        qr.expect_message().await;
+    }
+    session.rset().await;
+    session.mail_from("bill@example.net", "250").await;
+    session.rcpt_to("Mike@FooBar.org", "452 4.2.1").await;
+
+    // Other recipients and domains are not affected
+    session.rcpt_to("jane@foobar.org", "250").await;
+    session.rcpt_to("external@domain.com", "250").await;
 }
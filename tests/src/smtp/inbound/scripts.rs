@@ -22,7 +22,12 @@
 */
 
 use core::panic;
-use std::{fmt::Write, fs, path::PathBuf};
+use std::{
+    fmt::Write,
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
 use crate::smtp::{
     build_smtp,
@@ -185,8 +190,9 @@ async fn sieve_scripts() {
         let handle = Handle::current();
         let span = span.clone();
         let core_ = core.clone();
+        let deadline = Instant::now() + core.core.sieve.timeout;
         match core
-            .spawn_worker(move || core_.run_script_blocking(script, params, handle, span))
+            .spawn_worker(move || core_.run_script_blocking(script, params, handle, deadline, span))
             .await
             .unwrap()
         {
@@ -400,6 +406,17 @@ async fn sieve_scripts() {
         .assert_contains("From: Joe SixPack <joe@football.example.com>");
     qr.assert_no_events();
 
+    // Expect rejection when the only recipient expands to no targets
+    session
+        .send_message(
+            "test@example.net",
+            &["nobody@foobar.gov"],
+            "test:no_dkim",
+            "554 5.5.1 No valid recipients",
+        )
+        .await;
+    qr.assert_no_events();
+
     // Test pipes
     session.data.remote_ip_str = "10.0.0.123".parse().unwrap();
     session.data.remote_ip = session.data.remote_ip_str.parse().unwrap();
@@ -420,3 +437,172 @@ async fn sieve_scripts() {
         .assert_contains("Authentication-Results");
     qr.assert_no_events();
 }
+
+const CACHE_CONFIG: &str = r#"
+[storage]
+data = "sql"
+lookup = "sql"
+blob = "sql"
+fts = "sql"
+
+[store."sql"]
+type = "sqlite"
+path = "{TMP}/smtp_sieve_cache.db"
+
+[lookup."readonly"]
+"other-key" = "value"
+
+[sieve.trusted.cache]
+store = "{STORE}"
+fail-open = {FAIL_OPEN}
+
+[sieve.trusted.scripts.cache]
+contents = '''
+require ["variables", "vnd.stalwart.expressions", "editheader"];
+let "get" "cache_get('key')";
+let "set" "cache_set('key', 'value', 60)";
+let "incr" "cache_incr('counter', 1, 60)";
+addheader "X-Cache" "${get},${set},${incr}";
+'''
+"#;
+
+#[tokio::test]
+async fn sieve_cache() {
+    // The memory store does not support writes, and missing stores are
+    // unreachable, so both exercise the failure policy
+    for (store, fail_open, expected) in [
+        ("sql", true, [",1,1", "value,1,2"]),
+        ("readonly", true, [",1,0", ",1,0"]),
+        (
+            "readonly",
+            false,
+            [",0,9223372036854775807", ",0,9223372036854775807"],
+        ),
+        ("missing", true, [",1,0", ",1,0"]),
+        (
+            "missing",
+            false,
+            ["1,0,9223372036854775807", "1,0,9223372036854775807"],
+        ),
+    ] {
+        let tmp_dir = TempDir::new("smtp_sieve_cache_test", true);
+        let mut config = Config::new(
+            tmp_dir.update_config(
+                CACHE_CONFIG
+                    .replace("{STORE}", store)
+                    .replace("{FAIL_OPEN}", &fail_open.to_string()),
+            ),
+        )
+        .unwrap();
+        let stores = Stores::parse_all(&mut config).await;
+        let core = Core::parse(&mut config, stores, Default::default()).await;
+        let core = build_smtp(core, Inner::default());
+        let session = Session::test(core.clone());
+        let span = tracing::info_span!("sieve_cache");
+
+        for expected in expected {
+            let script = core.core.sieve.scripts.get("cache").unwrap().clone();
+            let params = session
+                .build_script_parameters("data")
+                .with_envelope(&core.core, &session)
+                .await;
+            let handle = Handle::current();
+            let span = span.clone();
+            let core_ = core.clone();
+            let deadline = Instant::now() + core.core.sieve.timeout;
+            match core
+                .spawn_worker(move || {
+                    core_.run_script_blocking(script, params, handle, deadline, span)
+                })
+                .await
+                .unwrap()
+            {
+                ScriptResult::Replace { message, .. } => {
+                    assert_eq!(
+                        String::from_utf8(message).unwrap(),
+                        format!("X-Cache: {expected}\r\n"),
+                        "store {store:?}, fail-open {fail_open}"
+                    );
+                }
+                result => panic!("Unexpected script result {result:?}"),
+            }
+        }
+    }
+}
+
+const TIMEOUT_CONFIG: &str = r#"
+[sieve.trusted.limits]
+timeout = "1s"
+
+[sieve.trusted.scripts.slow]
+contents = '''
+require ["variables", "vnd.stalwart.expressions", "editheader"];
+if eval "key_exists_http('http://{LISTENER}/list.txt', 'value', ['list'])" {
+    addheader "X-Listed" "true";
+}
+'''
+
+[sieve.trusted.scripts.fast]
+contents = '''
+require ["editheader"];
+addheader "X-Fast" "true";
+'''
+"#;
+
+#[tokio::test]
+async fn sieve_timeout() {
+    // Accept connections and close them without replying after the time
+    // limit, so the script blocks on the lookup
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_secs(3)).await;
+                drop(stream);
+            });
+        }
+    });
+
+    let mut config = Config::new(TIMEOUT_CONFIG.replace("{LISTENER}", &addr.to_string())).unwrap();
+    let core = Core::parse(&mut config, Default::default(), Default::default()).await;
+    let core = build_smtp(core, Inner::default());
+    let session = Session::test(core.clone());
+    let span = tracing::info_span!("sieve_timeout");
+
+    // The caller stops waiting once the time limit is reached
+    let script = core.core.sieve.scripts.get("slow").unwrap().clone();
+    let started = Instant::now();
+    match core
+        .run_script(script, session.build_script_parameters("data"), &span)
+        .await
+    {
+        ScriptResult::Reject(message) => {
+            assert!(message.starts_with("451 4.3.0"), "{message}")
+        }
+        result => panic!("Unexpected script result {result:?}"),
+    }
+    let elapsed = started.elapsed();
+    assert!(
+        elapsed >= Duration::from_secs(1) && elapsed < Duration::from_secs(5),
+        "{elapsed:?}"
+    );
+
+    // The blocking runner aborts on its own once the deadline has passed
+    let script = core.core.sieve.scripts.get("fast").unwrap().clone();
+    let params = session.build_script_parameters("data");
+    let handle = Handle::current();
+    let core_ = core.clone();
+    match core
+        .spawn_worker(move || {
+            core_.run_script_blocking(script, params, handle, Instant::now(), span)
+        })
+        .await
+        .unwrap()
+    {
+        ScriptResult::Reject(message) => {
+            assert!(message.starts_with("451 4.3.0"), "{message}")
+        }
+        result => panic!("Unexpected script result {result:?}"),
+    }
+}
@@ -107,8 +107,8 @@ impl TestSMTP for Inner {
         self.queue_tx = queue_tx;
 
         QueueReceiver {
-            blob_store: core.storage.blob.clone(),
-            store: core.storage.data.clone(),
+            blob_store: core.storage.queue.blob.clone(),
+            store: core.storage.queue.data.clone(),
             queue_rx,
         }
     }
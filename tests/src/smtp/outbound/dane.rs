@@ -276,11 +276,14 @@ async fn dane_test() {
     core.smtp.resolvers = Resolvers {
         dns: Resolver::new_cloudflare().unwrap(),
         dnssec: DnssecResolver {
-            resolver: AsyncResolver::tokio(conf, opts),
+            resolver: AsyncResolver::tokio(conf.clone(), opts.clone()),
         },
+        txt: AsyncResolver::tokio(conf, opts),
         cache: DnsRecordCache {
             tlsa: LruCache::with_capacity(10),
             mta_sts: LruCache::with_capacity(10),
+            dkim_key: LruCache::with_capacity(10),
+            spf_record: LruCache::with_capacity(10),
         },
         psl: PublicSuffix::default(),
     };
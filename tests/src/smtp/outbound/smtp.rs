@@ -262,49 +262,298 @@ async fn smtp_delivery() {
 
     remote.qr.assert_no_events();
 
-    // SMTP smuggling
+    // SMTP smuggling attempts are rejected before being queued
     for separator in ["\n", "\r"].iter() {
+        let mut session = local.new_session();
         session.data.remote_ip_str = "10.0.0.2".to_string();
         session.eval_session_params().await;
         session.ehlo("mx.test.org").await;
+        session.mail_from("john@doe.org", "250").await;
+        session.rcpt_to("bill@foobar.com", "250").await;
+        session.ingest(b"DATA\r\n").await.unwrap();
+        session.response().assert_code("354");
 
         let message = SMUGGLER
             .replace('\r', "")
             .replace('\n', "\r\n")
             .replace("<SEP>", separator);
+        assert!(session.ingest(message.as_bytes()).await.is_err());
+        session.response().assert_code("521 5.5.2");
+    }
+    local.qr.assert_queue_is_empty().await;
+    remote.qr.assert_no_events();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn smtp_delivery_batch() {
+    // Start test server
+    let mut remote = TestServer::new("smtp_delivery_batch_remote", REMOTE, true).await;
+    let _rx = remote.start(&[ServerProtocol::Smtp]).await;
+    let remote_core = remote.build_smtp();
 
+    // Allow up to three messages per connection
+    let mut local = TestServer::new(
+        "smtp_delivery_batch_local",
+        &format!("{LOCAL}\n[queue.outbound.limits]\nmessages-per-connection = 3\n"),
+        true,
+    )
+    .await;
+    let core = local.build_smtp();
+    core.core.smtp.resolvers.dns.mx_add(
+        "foobar.org",
+        vec![MX {
+            exchanges: vec!["mx1.foobar.org".to_string()],
+            preference: 10,
+        }],
+        Instant::now() + Duration::from_secs(10),
+    );
+    core.core.smtp.resolvers.dns.ipv4_add(
+        "mx1.foobar.org",
+        vec!["127.0.0.1".parse().unwrap()],
+        Instant::now() + Duration::from_secs(30),
+    );
+
+    // Queue four messages for the same domain
+    let mut session = local.new_session();
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.test.org").await;
+    let mut queue_ids = Vec::new();
+    for rcpt in [
+        "ok@foobar.org",
+        "fail@foobar.org",
+        "ok2@foobar.org",
+        "ok3@foobar.org",
+    ] {
         session
-            .send_message("john@doe.org", &["bill@foobar.com"], &message, "250")
-            .await;
-        local
-            .qr
-            .expect_message_then_deliver()
-            .await
-            .try_deliver(core.clone())
+            .send_message("john@test.org", &[rcpt], "test:no_dkim", "250")
             .await;
+        queue_ids.push(local.qr.expect_message().await.id);
+    }
+
+    // A single delivery attempt sends three messages over the same connection,
+    // the failed recipient does not prevent the rest of the batch from being delivered
+    local
+        .qr
+        .delivery_attempt(queue_ids[0])
+        .await
+        .try_deliver(core.clone())
+        .await;
+    // Three completed deliveries plus the queued DSN
+    for _ in 0..4 {
         local.qr.read_event().await.assert_reload();
+    }
+    let mut delivered = Vec::new();
+    for _ in 0..2 {
+        delivered.extend(
+            remote
+                .qr
+                .consume_message(&remote_core)
+                .await
+                .recipients
+                .into_iter()
+                .map(|r| r.address),
+        );
+    }
+    remote.qr.assert_no_events();
+    assert!(delivered.contains(&"ok@foobar.org".to_string()));
 
-        let message = remote
-            .qr
-            .consume_message(&remote_core)
-            .await
-            .read_message(&remote.qr)
-            .await;
+    // One message is left in the queue, plus the DSN for the failed recipient
+    let events = core.next_event().await;
+    assert_eq!(events.len(), 2, "{events:?}");
+}
 
-        assert!(
-            message.contains("This is a smuggled message"),
-            "message: {:?}",
-            message
-        );
-        assert!(
-            message.contains("We lost the game."),
-            "message: {:?}",
-            message
+const IMPLICIT_MX: &str = r#"
+[session.rcpt]
+relay = true
+
+[queue.outbound]
+implicit-mx = [{if = "rcpt_domain = 'forbidden.org'", then = false},
+               {else = true}]
+"#;
+
+#[tokio::test]
+#[serial_test::serial]
+async fn smtp_delivery_implicit_mx() {
+    // Start test server
+    let mut remote = TestServer::new("smtp_implicit_mx_remote", REMOTE, true).await;
+    let _rx = remote.start(&[ServerProtocol::Smtp]).await;
+
+    let mut local = TestServer::new("smtp_implicit_mx_local", IMPLICIT_MX, true).await;
+
+    // Add mock DNS entries, none of the domains publish MX records
+    let core = local.build_smtp();
+    for domain in ["implicit.org", "forbidden.org", "nowhere.org"] {
+        core.core.smtp.resolvers.dns.mx_add(
+            domain,
+            vec![],
+            Instant::now() + Duration::from_secs(10),
         );
-        assert!(
-            message.contains(&format!("{separator}..\r\nMAIL FROM:<",)),
-            "message: {:?}",
-            message
+    }
+    for domain in ["implicit.org", "forbidden.org"] {
+        core.core.smtp.resolvers.dns.ipv4_add(
+            domain,
+            vec!["127.0.0.1".parse().unwrap()],
+            Instant::now() + Duration::from_secs(10),
         );
     }
+    core.core.smtp.resolvers.dns.ipv4_add(
+        "nowhere.org",
+        vec![],
+        Instant::now() + Duration::from_secs(10),
+    );
+    core.core.smtp.resolvers.dns.ipv6_add(
+        "nowhere.org",
+        vec![],
+        Instant::now() + Duration::from_secs(10),
+    );
+
+    let mut session = local.new_session();
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.test.org").await;
+
+    // Domains with address records are delivered to their implicit MX
+    session
+        .send_message(
+            "john@test.org",
+            &["bill@implicit.org"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    local
+        .qr
+        .expect_message_then_deliver()
+        .await
+        .try_deliver(core.clone())
+        .await;
+    assert_eq!(
+        remote.qr.expect_message().await.recipients[0].address,
+        "bill@implicit.org"
+    );
+    local.qr.read_event().await.assert_reload();
+
+    // Implicit MX forbidden by policy
+    session
+        .send_message(
+            "john@test.org",
+            &["bill@forbidden.org"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    local
+        .qr
+        .expect_message_then_deliver()
+        .await
+        .try_deliver(core.clone())
+        .await;
+    local
+        .qr
+        .consume_message(&core)
+        .await
+        .read_lines(&local.qr)
+        .await
+        .assert_contains("<bill@forbidden.org>")
+        .assert_contains("implicit MX is not allowed")
+        .assert_contains("Status: 5.1.2");
+    local.qr.read_event().await.assert_reload();
+
+    // Domains without MX or address records are bounced
+    session
+        .send_message(
+            "john@test.org",
+            &["bill@nowhere.org"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    local
+        .qr
+        .expect_message_then_deliver()
+        .await
+        .try_deliver(core.clone())
+        .await;
+    local
+        .qr
+        .consume_message(&core)
+        .await
+        .read_lines(&local.qr)
+        .await
+        .assert_contains("<bill@nowhere.org>")
+        .assert_contains("no MX or address records")
+        .assert_contains("Status: 5.1.2");
+    local.qr.read_event().await.assert_reload();
+    local.qr.assert_queue_is_empty().await;
+    remote.qr.assert_no_events();
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn smtp_delivery_batch_tls_policy() {
+    // Start test server
+    let mut remote = TestServer::new("smtp_delivery_batch_tls_remote", REMOTE, true).await;
+    let _rx = remote.start(&[ServerProtocol::Smtp]).await;
+    let remote_core = remote.build_smtp();
+
+    // Disable STARTTLS for messages sent from plain.org
+    let mut local = TestServer::new(
+        "smtp_delivery_batch_tls_local",
+        &format!(
+            concat!(
+                "{}\n[queue.outbound.limits]\nmessages-per-connection = 3\n",
+                "[queue.outbound.tls]\nstarttls = [{{if = \"sender_domain = 'plain.org'\", ",
+                "then = \"disable\"}}, {{else = \"optional\"}}]\n"
+            ),
+            LOCAL
+        ),
+        true,
+    )
+    .await;
+    let core = local.build_smtp();
+    core.core.smtp.resolvers.dns.mx_add(
+        "foobar.org",
+        vec![MX {
+            exchanges: vec!["mx1.foobar.org".to_string()],
+            preference: 10,
+        }],
+        Instant::now() + Duration::from_secs(10),
+    );
+    core.core.smtp.resolvers.dns.ipv4_add(
+        "mx1.foobar.org",
+        vec!["127.0.0.1".parse().unwrap()],
+        Instant::now() + Duration::from_secs(30),
+    );
+
+    // Queue two messages for the same domain with different TLS policies
+    let mut session = local.new_session();
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.test.org").await;
+    let mut queue_ids = Vec::new();
+    for sender in ["john@test.org", "jane@plain.org"] {
+        session
+            .send_message(sender, &["ok@foobar.org"], "test:no_dkim", "250")
+            .await;
+        queue_ids.push(local.qr.expect_message().await.id);
+    }
+
+    // The message that must not use STARTTLS is not added to the TLS session
+    local
+        .qr
+        .delivery_attempt(queue_ids[0])
+        .await
+        .try_deliver(core.clone())
+        .await;
+    local.qr.read_event().await.assert_reload();
+    assert_eq!(
+        remote.qr.consume_message(&remote_core).await.return_path,
+        "john@test.org"
+    );
+    remote.qr.assert_no_events();
+    let events = core.next_event().await;
+    assert_eq!(events.len(), 1, "{events:?}");
+    assert_eq!(events[0].queue_id, queue_ids[1]);
 }
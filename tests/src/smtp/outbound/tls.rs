@@ -127,3 +127,207 @@ async fn starttls_optional() {
         .await
         .assert_not_contains("using TLSv1.3 with cipher");
 }
+
+const LOCAL_PINNED: &str = r#"
+[session.rcpt]
+relay = true
+
+[queue.outbound.tls.pin]
+"foobar.org" = ["cert:0000000000000000000000000000000000000000000000000000000000000000"]
+"foobar.net" = ["cert:0000000000000000000000000000000000000000000000000000000000000000",
+                "spki:49ba2c6a0dc664b4002c9ebc0fc327c63dfed7ed641a0f9bdbeb784080ac1100"]
+"#;
+
+const REMOTE_PINNED: &str = r#"
+[session.rcpt]
+relay = true
+
+[session.ehlo]
+reject-non-fqdn = false
+
+[session.data.add-headers]
+received = true
+"#;
+
+#[tokio::test]
+#[serial_test::serial]
+async fn tls_pinning() {
+    // Start test server
+    let mut remote = TestServer::new("smtp_tls_pin_remote", REMOTE_PINNED, true).await;
+    let _rx = remote.start(&[ServerProtocol::Smtp]).await;
+    let mut local = TestServer::new("smtp_tls_pin_local", LOCAL_PINNED, true).await;
+
+    // Add mock DNS entries
+    let core = local.build_smtp();
+    for domain in ["foobar.org", "foobar.net"] {
+        core.core.smtp.resolvers.dns.mx_add(
+            domain,
+            vec![MX {
+                exchanges: vec!["mx.foobar.org".to_string()],
+                preference: 10,
+            }],
+            Instant::now() + Duration::from_secs(10),
+        );
+    }
+    core.core.smtp.resolvers.dns.ipv4_add(
+        "mx.foobar.org",
+        vec!["127.0.0.1".parse().unwrap()],
+        Instant::now() + Duration::from_secs(10),
+    );
+
+    // Certificates not matching the pinned fingerprints are refused
+    let mut session = local.new_session();
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.test.org").await;
+    session
+        .send_message("john@test.org", &["bill@foobar.org"], "test:no_dkim", "250")
+        .await;
+    local
+        .qr
+        .expect_message_then_deliver()
+        .await
+        .try_deliver(core.clone())
+        .await;
+    let message = local.qr.expect_message().await;
+    let status = message.domains[0].status.to_string();
+    assert!(
+        status.contains("Certificate does not match any pinned fingerprint"),
+        "{status}"
+    );
+    local.qr.clear_queue(&core).await;
+
+    // A matching SPKI fingerprint allows delivery
+    session
+        .send_message("john@test.org", &["jane@foobar.net"], "test:no_dkim", "250")
+        .await;
+    local
+        .qr
+        .expect_message_then_deliver()
+        .await
+        .try_deliver(core.clone())
+        .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    remote
+        .qr
+        .expect_message()
+        .await
+        .read_lines(&remote.qr)
+        .await
+        .assert_contains("using TLSv1.3 with cipher");
+}
+
+const LOCAL_RESUME: &str = r#"
+[session.rcpt]
+relay = true
+
+[queue.outbound.tls.resumption]
+enable = [{if = "rcpt_domain = 'foobar.com'", then = false},
+          {else = true}]
+cache-size = 16
+
+[queue.outbound.tls.pin]
+"foobar.net" = ["spki:49ba2c6a0dc664b4002c9ebc0fc327c63dfed7ed641a0f9bdbeb784080ac1100"]
+"#;
+
+#[tokio::test]
+#[serial_test::serial]
+async fn tls_resumption() {
+    // Start test server
+    let mut remote = TestServer::new("smtp_tls_resume_remote", REMOTE_PINNED, true).await;
+    let _rx = remote.start(&[ServerProtocol::Smtp]).await;
+    let mut local = TestServer::new("smtp_tls_resume_local", LOCAL_RESUME, true).await;
+
+    // Add mock DNS entries
+    let core = local.build_smtp();
+    for domain in ["foobar.org", "foobar.net", "foobar.com"] {
+        core.core.smtp.resolvers.dns.mx_add(
+            domain,
+            vec![MX {
+                exchanges: vec!["mx.foobar.org".to_string()],
+                preference: 10,
+            }],
+            Instant::now() + Duration::from_secs(10),
+        );
+    }
+    core.core.smtp.resolvers.dns.ipv4_add(
+        "mx.foobar.org",
+        vec!["127.0.0.1".parse().unwrap()],
+        Instant::now() + Duration::from_secs(10),
+    );
+    let mut session = local.new_session();
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.test.org").await;
+
+    // Sessions are not resumed for pinned destinations or when resumption is
+    // disabled, resumed otherwise.
+    for (rcpt, cache_size) in [
+        ("jane@foobar.net", None),
+        ("bill@foobar.com", None),
+        ("john@foobar.org", Some(16)),
+    ] {
+        session
+            .send_message("john@test.org", &[rcpt], "test:no_dkim", "250")
+            .await;
+        local
+            .qr
+            .expect_message_then_deliver()
+            .await
+            .try_deliver(core.clone())
+            .await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let message = remote.qr.expect_message().await;
+        assert_eq!(message.recipients[0].address, rcpt);
+        message
+            .read_lines(&remote.qr)
+            .await
+            .assert_contains("using TLSv1.3 with cipher");
+        assert_eq!(
+            core.inner
+                .connectors
+                .resume
+                .lock()
+                .as_ref()
+                .map(|resume| resume.cache_size),
+            cache_size,
+            "{rcpt}"
+        );
+    }
+
+    // Changing the cache size on reload replaces the resume connectors
+    let mut reloaded = core.core.as_ref().clone();
+    reloaded.smtp.queue.tls.resumption_cache_size = 32;
+    local.instance.core.store(reloaded.into());
+    let core = local.build_smtp();
+    let mut session = local.new_session();
+    session.data.remote_ip_str = "10.0.0.1".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.test.org").await;
+    session
+        .send_message("john@test.org", &["jane@foobar.org"], "test:no_dkim", "250")
+        .await;
+    local
+        .qr
+        .expect_message_then_deliver()
+        .await
+        .try_deliver(core.clone())
+        .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    remote
+        .qr
+        .expect_message()
+        .await
+        .read_lines(&remote.qr)
+        .await
+        .assert_contains("using TLSv1.3 with cipher");
+    assert_eq!(
+        core.inner
+            .connectors
+            .resume
+            .lock()
+            .as_ref()
+            .map(|resume| resume.cache_size),
+        Some(32)
+    );
+}
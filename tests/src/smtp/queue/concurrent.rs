@@ -115,7 +115,8 @@ async fn concurrent_queue() {
     // Make sure local store is queue
     core.core
         .storage
+        .queue
         .data
-        .assert_is_empty(core.core.storage.blob.clone())
+        .assert_is_empty(core.core.storage.queue.blob.clone())
         .await;
 }
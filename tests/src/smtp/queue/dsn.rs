@@ -103,6 +103,7 @@ async fn generate_dsn() {
         priority: 0,
         blob_hash: BlobHash::from(dsn_original.as_bytes()),
         quota_keys: vec![],
+        correlation_id: 0,
     };
     let span = tracing::span!(tracing::Level::INFO, "hi");
 
@@ -175,6 +176,215 @@ async fn generate_dsn() {
     assert_eq!(queue.len(), 4);
 }
 
+const CONFIG_LOCALE: &str = r#"
+[report]
+submitter = "'mx.example.org'"
+
+[report.dsn]
+from-name = "'Mail Delivery Subsystem'"
+from-address = "'MAILER-DAEMON@example.org'"
+sign = "['rsa']"
+locale = [{if = "sender_domain = 'foobar.es'", then = "'es'"},
+          {else = "'en'"}]
+locale-from-header = true
+
+[report.dsn.catalog.pt]
+subject.failure = "Falha na entrega da mensagem"
+summary.failure = "Sua mensagem nao pode ser entregue aos seguintes destinatarios:"
+
+"#;
+
+#[tokio::test]
+async fn generate_dsn_locale() {
+    let mut message = Message {
+        size: 0,
+        id: 0,
+        created: now(),
+        return_path: "sender@foobar.es".to_string(),
+        return_path_lcase: "sender@foobar.es".to_string(),
+        return_path_domain: "foobar.es".to_string(),
+        recipients: vec![],
+        domains: vec![Domain {
+            domain: "example.org".to_string(),
+            retry: Schedule::now(),
+            notify: Schedule::now(),
+            expires: now() + 10,
+            status: Status::Scheduled,
+            disable_tls: false,
+        }],
+        flags: 0,
+        env_id: None,
+        priority: 0,
+        blob_hash: Default::default(),
+        quota_keys: vec![],
+        correlation_id: 0,
+    };
+    let span = tracing::span!(tracing::Level::INFO, "hi");
+
+    // Load config
+    let mut local = TestServer::new(
+        "smtp_dsn_locale_test",
+        CONFIG_LOCALE.to_string() + SIGNATURES,
+        true,
+    )
+    .await;
+    let core = local.build_smtp();
+    let qr = &mut local.qr;
+
+    for (original, expected_subject, expected_summary) in [
+        (
+            "From: sender@foobar.es\r\nSubject: Test\r\n\r\nTest\r\n",
+            "Subject: No se pudo entregar el mensaje",
+            "Su mensaje no pudo ser entregado a los siguientes destinatarios:",
+        ),
+        (
+            concat!(
+                "From: sender@foobar.es\r\nAccept-Language: fi;q=0.9, pt-BR,\r\n",
+                " es;q=0.5\r\nSubject: Test\r\n\r\nTest\r\n"
+            ),
+            "Subject: Falha na entrega da mensagem",
+            "Sua mensagem nao pode ser entregue aos seguintes destinatarios:",
+        ),
+    ] {
+        message.blob_hash = BlobHash::from(original.as_bytes());
+        message.size = original.len();
+        qr.blob_store
+            .put_blob(message.blob_hash.as_slice(), original.as_bytes())
+            .await
+            .unwrap();
+        message.recipients = vec![Recipient {
+            domain_idx: 0,
+            address: "foobar@example.org".to_string(),
+            address_lcase: "foobar@example.org".to_string(),
+            status: Status::PermanentFailure(HostResponse {
+                hostname: ErrorDetails {
+                    entity: "mx.example.org".to_string(),
+                    details: "RCPT TO:<foobar@example.org>".to_string(),
+                },
+                response: Response {
+                    code: 550,
+                    esc: [5, 1, 2],
+                    message: "User does not exist".to_string(),
+                },
+            }),
+            flags: RCPT_NOTIFY_FAILURE,
+            orcpt: None,
+        }];
+
+        core.send_dsn(&mut message, &span).await;
+        let dsn_message = qr.expect_message().await;
+        let dsn = String::from_utf8(
+            qr.blob_store
+                .get_blob(dsn_message.blob_hash.as_slice(), 0..usize::MAX)
+                .await
+                .unwrap()
+                .unwrap(),
+        )
+        .unwrap();
+        assert!(dsn.contains(expected_subject), "{dsn}");
+        assert!(dsn.contains(expected_summary), "{dsn}");
+        assert!(dsn.contains("Action: failed\r\n"), "{dsn}");
+    }
+}
+
+const CONFIG_RETURN_PATH: &str = r#"
+[report]
+submitter = "'mx.example.org'"
+
+[report.dsn]
+from-name = "'Mail Delivery Subsystem'"
+from-address = "'MAILER-DAEMON@example.org'"
+sign = "['rsa']"
+
+[queue.outbound]
+return-path = [{if = "sender_domain = 'foobar.org'", then = "'bounces@example.org'"},
+               {else = "''"}]
+"#;
+
+#[tokio::test]
+async fn generate_dsn_return_path() {
+    let original = "From: sender@foobar.org\r\nSubject: Test\r\n\r\nTest\r\n";
+    let span = tracing::span!(tracing::Level::INFO, "hi");
+
+    // Load config
+    let mut local = TestServer::new(
+        "smtp_dsn_return_path_test",
+        CONFIG_RETURN_PATH.to_string() + SIGNATURES,
+        true,
+    )
+    .await;
+    let core = local.build_smtp();
+    let qr = &mut local.qr;
+    qr.blob_store
+        .put_blob(
+            BlobHash::from(original.as_bytes()).as_slice(),
+            original.as_bytes(),
+        )
+        .await
+        .unwrap();
+
+    for (return_path, expected_rcpt) in [
+        ("sender@foobar.org", "bounces@example.org"),
+        ("sender@foobar.net", "sender@foobar.net"),
+    ] {
+        let mut message = Message {
+            size: original.len(),
+            id: 0,
+            created: now(),
+            return_path: return_path.to_string(),
+            return_path_lcase: return_path.to_string(),
+            return_path_domain: return_path.rsplit_once('@').unwrap().1.to_string(),
+            recipients: vec![Recipient {
+                domain_idx: 0,
+                address: "foobar@example.org".to_string(),
+                address_lcase: "foobar@example.org".to_string(),
+                status: Status::PermanentFailure(HostResponse {
+                    hostname: ErrorDetails {
+                        entity: "mx.example.org".to_string(),
+                        details: "RCPT TO:<foobar@example.org>".to_string(),
+                    },
+                    response: Response {
+                        code: 550,
+                        esc: [5, 1, 2],
+                        message: "User does not exist".to_string(),
+                    },
+                }),
+                flags: RCPT_NOTIFY_FAILURE,
+                orcpt: None,
+            }],
+            domains: vec![Domain {
+                domain: "example.org".to_string(),
+                retry: Schedule::now(),
+                notify: Schedule::now(),
+                expires: now() + 10,
+                status: Status::Scheduled,
+                disable_tls: false,
+            }],
+            flags: 0,
+            env_id: None,
+            priority: 0,
+            blob_hash: BlobHash::from(original.as_bytes()),
+            quota_keys: vec![],
+            correlation_id: 0,
+        };
+
+        // DSNs are addressed to the return path override
+        core.send_dsn(&mut message, &span).await;
+        let dsn_message = qr.expect_message().await;
+        assert_eq!(dsn_message.recipients.len(), 1);
+        assert_eq!(dsn_message.recipients[0].address, expected_rcpt);
+        let dsn = String::from_utf8(
+            qr.blob_store
+                .get_blob(dsn_message.blob_hash.as_slice(), 0..usize::MAX)
+                .await
+                .unwrap()
+                .unwrap(),
+        )
+        .unwrap();
+        assert!(dsn.contains(&format!("To: {expected_rcpt}\r\n")), "{dsn}");
+    }
+}
+
 impl QueueReceiver {
     async fn compare_dsn(&self, message: Message, test: &str) {
         let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
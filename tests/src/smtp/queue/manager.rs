@@ -25,8 +25,12 @@ use std::time::Duration;
 
 use mail_auth::hickory_resolver::proto::op::ResponseCode;
 
-use smtp::queue::{Domain, Message, Schedule, Status};
-use store::write::now;
+use smtp::queue::{Domain, Message, QuotaKey, Recipient, Schedule, Status};
+use store::{
+    write::{now, Bincode},
+    Deserialize, Serialize,
+};
+use utils::BlobHash;
 
 use crate::smtp::outbound::TestServer;
 
@@ -140,6 +144,57 @@ fn delivery_events() {
     assert!(message.next_event().is_none());
 }
 
+#[test]
+fn queue_legacy_message() {
+    let mut message = new_message(7);
+    message.domains.push(domain("a", 1, 4, 5));
+    message.correlation_id = 1234;
+
+    // Current messages round trip
+    let bytes = Bincode::new(message.clone()).serialize();
+    assert_eq!(
+        <Message as Deserialize>::deserialize(&bytes).unwrap(),
+        message
+    );
+
+    // Messages queued before correlation ids were added are still readable
+    let legacy: (
+        u64,
+        u64,
+        BlobHash,
+        String,
+        String,
+        String,
+        Vec<Recipient>,
+        Vec<Domain>,
+        u64,
+        Option<String>,
+        i16,
+        usize,
+        Vec<QuotaKey>,
+    ) = (
+        message.id,
+        message.created,
+        message.blob_hash.clone(),
+        message.return_path.clone(),
+        message.return_path_lcase.clone(),
+        message.return_path_domain.clone(),
+        message.recipients.clone(),
+        message.domains.clone(),
+        message.flags,
+        message.env_id.clone(),
+        message.priority,
+        message.size,
+        message.quota_keys.clone(),
+    );
+    let bytes = Bincode::new(legacy).serialize();
+    message.correlation_id = 0;
+    assert_eq!(
+        <Message as Deserialize>::deserialize(&bytes).unwrap(),
+        message
+    );
+}
+
 pub fn new_message(id: u64) -> Message {
     Message {
         size: 0,
@@ -154,6 +209,7 @@ pub fn new_message(id: u64) -> Message {
         env_id: None,
         priority: 0,
         quota_keys: vec![],
+        correlation_id: 0,
         blob_hash: Default::default(),
     }
 }
@@ -43,11 +43,15 @@ deliver-by = "1h"
 future-release = "1h"
 
 [queue.schedule]
-retry = "[1s, 2s, 3s]"
+retry = [{if = "rcpt_domain = '_dns_error.net'", then = "1s"},
+         {else = "[1s, 2s, 3s]"}]
 notify = [{if = "sender_domain = 'test.org'", then = "[1s, 2s]"},
            {else = ['15h', '22h']}]
-expire = [{if = "sender_domain = 'test.org'", then = "6s"},
+expire = [{if = "rcpt_domain = '_dns_error.net'", then = "1d"},
+          {if = "sender_domain = 'test.org'", then = "6s"},
           {else = '1d'}]
+max-attempts = [{if = "rcpt_domain = '_dns_error.net'", then = 3},
+                {else = false}]
 "#;
 
 #[tokio::test]
@@ -169,6 +173,66 @@ async fn queue_retry() {
         .assert_contains("Final-Recipient: rfc822;jane@_dns_error.org")
         .assert_contains("Action: failed");
 
+    // Domains with a maximum number of attempts fail once all attempts are
+    // exhausted, long before the message expires.
+    session
+        .send_message(
+            "john@test.org",
+            &["jane@_dns_error.net"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    let attempt = qr.expect_message_then_deliver().await;
+    let expires = core
+        .read_message(attempt.event.queue_id)
+        .await
+        .unwrap()
+        .domains[0]
+        .expires;
+    let mut dsn = Vec::new();
+    let mut attempts = Vec::new();
+    attempt.try_deliver(core.clone()).await;
+    loop {
+        match qr.try_read_event().await {
+            Some(Event::Reload) => {}
+            Some(Event::OnHold(_)) => unreachable!(),
+            None | Some(Event::Stop) => break,
+        }
+
+        let now = now();
+        let events = core.next_event().await;
+        if events.is_empty() {
+            break;
+        }
+        for event in events {
+            if event.due > now {
+                tokio::time::sleep(Duration::from_secs(event.due - now)).await;
+            }
+
+            let message = core.read_message(event.queue_id).await.unwrap();
+            if message.return_path.is_empty() {
+                message.clone().remove(&core, event.due).await;
+                dsn.push(message);
+            } else {
+                attempts.push(message.domains[0].retry.inner);
+                DeliveryAttempt::new(event).try_deliver(core.clone()).await;
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+    qr.assert_queue_is_empty().await;
+    // The domain is failed when the next event finds all attempts used
+    assert_eq!(attempts.last(), Some(&3), "{attempts:?}");
+    assert!(now() < expires);
+    dsn.pop()
+        .unwrap()
+        .read_lines(qr)
+        .await
+        .assert_contains("<jane@_dns_error.net> (failed to lookup '_dns_error.net'")
+        .assert_contains("Final-Recipient: rfc822;jane@_dns_error.net")
+        .assert_contains("Action: failed");
+
     // Test FUTURERELEASE + DELIVERBY (RETURN)
     session.data.remote_ip_str = "10.0.0.2".to_string();
     session.eval_session_params().await;